@@ -0,0 +1,110 @@
+//! Benchmarks of the emulation core on synthetic ROMs, one emulated frame per iteration.
+//! Each ROM stresses one part: CPU instruction loop, PPU rendering or APU channels.
+
+use std::cell::Cell;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use eyre::Result;
+use rnes::console::{apu::Apu, controller::Controller, video::Frame, Console, Frontend};
+
+const PRG_LEN: usize = 0x4000;
+const CHR_LEN: usize = 0x2000;
+
+/// Stops the console after every frame so each benchmark iteration runs exactly one
+struct FrameStepper {
+    quit: Cell<bool>,
+}
+
+impl Frontend for FrameStepper {
+    fn handle_io(&mut self, _frame: &Frame, _apu: &Apu, _controller: &mut Controller) {
+        self.quit.set(true);
+    }
+
+    fn handle_audio(&mut self, _apu: &Apu) -> Result<()> {
+        Ok(())
+    }
+
+    fn quit_requested(&self) -> bool {
+        self.quit.replace(false)
+    }
+}
+
+// Mapper 0 ROM running `program` from $8000, NMI and IRQ vectors point to an RTI
+fn rom(program: &[u8]) -> Vec<u8> {
+    let mut prg = vec![0xEA; PRG_LEN];
+    prg[..program.len()].copy_from_slice(program);
+    prg[0x3FF0] = 0x40; // RTI at $BFF0
+    prg[0x3FFA..].copy_from_slice(&[0xF0, 0xBF, 0x00, 0x80, 0xF0, 0xBF]);
+
+    // Every tile row non-zero so the renderer has real pixels to mix
+    let chr: Vec<u8> = (0..CHR_LEN).map(|i| (i * 37) as u8).collect();
+
+    let mut rom = vec![0x4E, 0x45, 0x53, 0x1A, 1, 1, 0, 0];
+    rom.resize(16, 0);
+    rom.extend(prg);
+    rom.extend(chr);
+    rom
+}
+
+#[rustfmt::skip]
+const CPU_LOOP: &[u8] = &[
+    0xA2, 0x00,       // LDX #$00
+    0xA9, 0x00,       // LDA #$00
+    0x18,             // loop: CLC
+    0x75, 0x00,       // ADC $00,X
+    0x9D, 0x00, 0x02, // STA $0200,X
+    0xE8,             // INX
+    0xD0, 0xF7,       // BNE loop
+    0x4C, 0x04, 0x80, // JMP loop
+];
+
+#[rustfmt::skip]
+const PPU_RENDER: &[u8] = &[
+    0xA9, 0x1E,       // LDA #$1E, background and sprites on
+    0x8D, 0x01, 0x20, // STA $2001
+    0x4C, 0x05, 0x80, // JMP *
+];
+
+#[rustfmt::skip]
+const APU_CHANNELS: &[u8] = &[
+    0xA9, 0x0F, 0x8D, 0x15, 0x40, // Enable pulse 1 & 2, triangle and noise
+    0xA9, 0xBF, 0x8D, 0x00, 0x40, // Pulse 1 halted length, constant volume 15
+    0xA9, 0xFF, 0x8D, 0x02, 0x40,
+    0xA9, 0x08, 0x8D, 0x03, 0x40,
+    0xA9, 0xBF, 0x8D, 0x04, 0x40, // Pulse 2
+    0xA9, 0x80, 0x8D, 0x06, 0x40,
+    0xA9, 0x08, 0x8D, 0x07, 0x40,
+    0xA9, 0xFF, 0x8D, 0x08, 0x40, // Triangle
+    0xA9, 0x40, 0x8D, 0x0A, 0x40,
+    0xA9, 0x08, 0x8D, 0x0B, 0x40,
+    0xA9, 0x3F, 0x8D, 0x0C, 0x40, // Noise
+    0xA9, 0x03, 0x8D, 0x0E, 0x40,
+    0xA9, 0x08, 0x8D, 0x0F, 0x40,
+    0x4C, 0x41, 0x80,             // JMP *
+];
+
+fn bench_rom(c: &mut Criterion, name: &str, program: &[u8]) {
+    let rom = rom(program);
+    let mut frontend = FrameStepper {
+        quit: Cell::new(false),
+    };
+    let mut console = Console::new(&rom, &mut frontend).expect("synthetic ROM should load");
+    c.bench_function(name, |b| {
+        b.iter(|| console.run_with_callback(|_| {}).expect("frame should run"));
+    });
+}
+
+fn cpu(c: &mut Criterion) {
+    bench_rom(c, "cpu_instruction_loop", CPU_LOOP);
+}
+
+fn ppu(c: &mut Criterion) {
+    bench_rom(c, "ppu_render_frame", PPU_RENDER);
+}
+
+fn apu(c: &mut Criterion) {
+    bench_rom(c, "apu_channels_frame", APU_CHANNELS);
+}
+
+criterion_group!(benches, cpu, ppu, apu);
+criterion_main!(benches);
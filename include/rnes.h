@@ -0,0 +1,100 @@
+#ifndef RNES_H
+#define RNES_H
+
+/* Generated with cbindgen from src/ffi.rs, do not edit */
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#define SCREEN_WIDTH 256
+
+#define SCREEN_HEIGHT 240
+
+/**
+ * Bytes in the RGBA framebuffer
+ */
+#define RNES_FRAMEBUFFER_LEN ((SCREEN_WIDTH * SCREEN_HEIGHT) * 4)
+
+/**
+ * A console and the frontend it borrows, opaque to C
+ */
+typedef struct RnesConsole RnesConsole;
+
+/**
+ * Powers on a console with an iNES image. Returns null on failure, see
+ * `rnes_last_error`.
+ *
+ * # Safety
+ * `rom` must point to `len` readable bytes.
+ */
+struct RnesConsole *rnes_console_create(const uint8_t *rom, size_t len);
+
+/**
+ * Frees a console from `rnes_console_create`, null is ignored
+ *
+ * # Safety
+ * `console` must be null or a console that hasn't been destroyed yet.
+ */
+void rnes_console_destroy(struct RnesConsole *console);
+
+/**
+ * Replaces the ROM and powers the console on again
+ *
+ * # Safety
+ * `console` must be valid and `rom` must point to `len` readable bytes.
+ */
+bool rnes_console_load_rom(struct RnesConsole *console, const uint8_t *rom, size_t len);
+
+/**
+ * Emulates until the next frame is complete
+ *
+ * # Safety
+ * `console` must be valid.
+ */
+bool rnes_console_run_frame(struct RnesConsole *console);
+
+/**
+ * Saves the console state into `buffer`, which holds `*len` bytes. The state's
+ * size is stored in `*len`; if `buffer` is null only the size is stored, and if
+ * it is too small the call fails.
+ *
+ * # Safety
+ * `console` and `len` must be valid and `buffer` must be null or point to `*len`
+ * writable bytes.
+ */
+bool rnes_console_save_state(struct RnesConsole *console, uint8_t *buffer, size_t *len);
+
+/**
+ * Continues from a state saved with `rnes_console_save_state` for the same ROM.
+ * On failure the console is left as it was.
+ *
+ * # Safety
+ * `console` must be valid and `state` must point to `len` readable bytes.
+ */
+bool rnes_console_load_state(struct RnesConsole *console, const uint8_t *state, size_t len);
+
+/**
+ * The last completed frame, `RNES_FRAMEBUFFER_LEN` bytes of 256x240 RGBA pixels.
+ * Stays valid until the console is destroyed.
+ *
+ * # Safety
+ * `console` must be valid.
+ */
+const uint8_t *rnes_console_framebuffer(const struct RnesConsole *console);
+
+/**
+ * Sets controller 1 buttons, one bit each: A, B, Select, Start, Up, Down, Left, Right
+ * from bit 0
+ *
+ * # Safety
+ * `console` must be valid.
+ */
+void rnes_console_set_input(struct RnesConsole *console, uint8_t buttons);
+
+/**
+ * Message of the last failed call on this thread, valid until the next failure
+ */
+const char *rnes_last_error(void);
+
+#endif  /* RNES_H */
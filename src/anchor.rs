@@ -0,0 +1,148 @@
+use std::fmt::Write;
+use std::path::Path;
+
+use eyre::{eyre, Result, WrapErr};
+
+use crate::checksum::crc32;
+use crate::console::debug::StateHashes;
+use crate::movie::Movie;
+
+/// A named point in a run, reached by replaying the recorded input from power-on.
+///
+/// Stored as text: a `ranchor 1` header, `name`, `rom` (CRC32) and `state` (the
+/// CPU, RAM, PPU and APU hashes at the anchor) lines, then the input as a movie.
+pub struct Anchor {
+    pub name: String,
+    rom_crc32: u32,
+    hashes: StateHashes,
+    movie: Movie,
+}
+
+impl Anchor {
+    const HEADER: &'static str = "ranchor 1";
+
+    /// Anchor after `movie.len()` frames, `movie` holding the input recorded so far.
+    /// `rom_crc32` covers the whole ROM file, header included.
+    pub fn new(name: &str, rom_crc32: u32, hashes: StateHashes, movie: Movie) -> Self {
+        Self {
+            name: name.to_owned(),
+            rom_crc32,
+            hashes,
+            movie,
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("Failed to open anchor file {}", path.display()))?;
+        Self::parse(&text).wrap_err_with(|| format!("Invalid anchor file {}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, self.to_text())
+            .wrap_err_with(|| format!("Failed to write anchor file {}", path.display()))
+    }
+
+    fn parse(text: &str) -> Result<Self> {
+        let mut lines = text.lines();
+        if lines.next().map(str::trim) != Some(Self::HEADER) {
+            return Err(eyre!("Missing '{}' header", Self::HEADER));
+        }
+        let mut field = |key: &str| {
+            lines
+                .next()
+                .and_then(|line| line.strip_prefix(key)?.strip_prefix(' '))
+                .ok_or_else(|| eyre!("Missing {key} line"))
+        };
+        let name = field("name")?.to_owned();
+        let rom_crc32 = u32::from_str_radix(field("rom")?, 16).wrap_err("Invalid ROM CRC32")?;
+        let hashes: Vec<u64> = field("state")?
+            .split_whitespace()
+            .map(|hash| u64::from_str_radix(hash, 16))
+            .collect::<Result<_, _>>()
+            .wrap_err("Invalid state hash")?;
+        let hashes = <[u64; 4]>::try_from(hashes).map_err(|_| eyre!("Expected 4 state hashes"))?;
+        let movie = Movie::parse(&lines.collect::<Vec<_>>().join("\n"))?;
+        Ok(Self {
+            name,
+            rom_crc32,
+            hashes: StateHashes::from_values(hashes),
+            movie,
+        })
+    }
+
+    fn to_text(&self) -> String {
+        let mut text = format!(
+            "{}\nname {}\nrom {:08X}\nstate",
+            Self::HEADER,
+            self.name,
+            self.rom_crc32
+        );
+        for hash in self.hashes.values() {
+            let _ = write!(text, " {hash:016X}");
+        }
+        text.push('\n');
+        text.push_str(&self.movie.to_text());
+        text
+    }
+
+    /// Fails unless the anchor was made with this ROM
+    pub fn check_rom(&self, rom: &[u8]) -> Result<()> {
+        let crc = crc32(rom);
+        if crc != self.rom_crc32 {
+            return Err(eyre!(
+                "Anchor {} was made with ROM {:08X}, not {crc:08X}",
+                self.name,
+                self.rom_crc32
+            ));
+        }
+        Ok(())
+    }
+
+    /// Frames to run from power-on to reach the anchor
+    pub fn frames(&self) -> usize {
+        self.movie.len()
+    }
+
+    pub const fn movie(&self) -> &Movie {
+        &self.movie
+    }
+
+    /// Compares the state after replaying to the anchor with the recorded one
+    pub fn verify(&self, hashes: &StateHashes) -> Result<()> {
+        if *hashes != self.hashes {
+            return Err(eyre!(
+                "State at anchor {} differs in {}, the replay went out of sync",
+                self.name,
+                self.hashes.differences(hashes).join(", ")
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_text_round_trip() {
+        let mut movie = Movie::default();
+        movie.push(0x01);
+        movie.push(0x80);
+        let hashes = StateHashes::from_values([1, 2, 3, u64::MAX]);
+        let anchor = Anchor::new("boss", crc32(b"rom"), hashes, movie);
+        let parsed = Anchor::parse(&anchor.to_text()).unwrap();
+        assert_eq!(parsed.name, "boss");
+        assert_eq!(parsed.frames(), 2);
+        assert_eq!(parsed.movie().frame(1), Some(0x80));
+        parsed.check_rom(b"rom").unwrap();
+        assert!(parsed.check_rom(b"other").is_err());
+        parsed.verify(&hashes).unwrap();
+        assert!(parsed
+            .verify(&StateHashes::from_values([1, 2, 0, u64::MAX]))
+            .is_err());
+        assert!(Anchor::parse("ranchor 1\nname x\n").is_err());
+    }
+}
@@ -0,0 +1,130 @@
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+
+use eyre::{eyre, Result};
+
+use crate::console::video::diff::{self, FrameDiff};
+use crate::console::{
+    apu::Apu, controller::Controller, video::palette::Palette, video::Frame, Console, Frontend,
+};
+
+/// Input applied to the second console for the next frame
+#[derive(Clone, Copy)]
+pub struct CompareInput {
+    pub buttons: u8,
+    pub reset: bool,
+}
+
+/// A frame from the second console
+pub struct CompareFrame {
+    pub indices: Vec<u16>,
+    pub rgba: Vec<u8>,
+}
+
+/// Frontend of the second console, hands each frame over and waits for the input
+/// of the next one so both consoles advance in lockstep
+struct Lockstep {
+    frames: Sender<CompareFrame>,
+    inputs: Receiver<CompareInput>,
+    quit: bool,
+}
+
+impl Frontend for Lockstep {
+    fn handle_io(&mut self, frame: &Frame, _apu: &Apu, controller: &mut Controller) {
+        let frame = CompareFrame {
+            indices: frame.indices.to_vec(),
+            rgba: frame.rgba.to_vec(),
+        };
+        // Either side hanging up ends the comparison
+        if self.frames.send(frame).is_err() {
+            self.quit = true;
+            return;
+        }
+        match self.inputs.recv() {
+            Ok(input) => {
+                controller.set_buttons(input.buttons);
+                if input.reset {
+                    controller.reset();
+                }
+            }
+            Err(_) => self.quit = true,
+        }
+    }
+
+    fn handle_audio(&mut self, _apu: &Apu) -> Result<()> {
+        Ok(())
+    }
+
+    fn quit_requested(&self) -> bool {
+        self.quit
+    }
+}
+
+/// Runs a second console on its own thread, fed with the input of the main one
+pub struct Comparison {
+    frames: Receiver<CompareFrame>,
+    inputs: Sender<CompareInput>,
+    _thread: JoinHandle<()>,
+}
+
+impl Comparison {
+    pub fn spawn(rom: Vec<u8>, palette: Palette) -> Self {
+        let (frame_tx, frames) = channel();
+        let (inputs, input_rx) = channel();
+        let thread = thread::spawn(move || {
+            let mut frontend = Lockstep {
+                frames: frame_tx,
+                inputs: input_rx,
+                quit: false,
+            };
+            let result = Console::new(&rom, &mut frontend).and_then(|mut console| {
+                console.set_palette(palette);
+                console.run_with_callback(|_| {})
+            });
+            if let Err(e) = result {
+                println!("Comparison console stopped: {e}");
+            }
+        });
+        Self {
+            frames,
+            inputs,
+            _thread: thread,
+        }
+    }
+
+    /// Waits for the second console to finish the frame matching the main one
+    pub fn next_frame(&self) -> Result<CompareFrame> {
+        self.frames
+            .recv()
+            .map_err(|_| eyre!("Comparison console is no longer running"))
+    }
+
+    pub fn send_input(&self, input: CompareInput) -> Result<()> {
+        self.inputs
+            .send(input)
+            .map_err(|_| eyre!("Comparison console is no longer running"))
+    }
+}
+
+/// Dims the second console's picture and marks pixels that differ from the main one
+pub fn highlight_diff(main: &[u16], other: &mut CompareFrame) -> FrameDiff {
+    let diff = diff::compare_indices(main, &other.indices);
+    diff.highlight(&mut other.rgba);
+    diff
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_highlight_diff() {
+        let mut frame = CompareFrame {
+            indices: vec![1, 2, 3],
+            rgba: vec![100; 12],
+        };
+        assert_eq!(highlight_diff(&[1, 5, 3], &mut frame).count, 1);
+        assert_eq!(&frame.rgba[..4], &[50, 50, 50, 100]);
+        assert_eq!(&frame.rgba[4..8], &diff::DIFF_COLOR);
+    }
+}
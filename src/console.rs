@@ -1,16 +1,132 @@
+pub mod access_trace;
 pub mod apu;
+pub mod apu_log;
 mod bus;
 mod cartridge;
 pub mod controller;
+pub mod coverage;
 pub mod cpu;
+pub mod debug;
+pub mod events;
 pub mod ppu;
+pub mod state;
+pub mod time;
+pub mod video;
 
-use eyre::Result;
+use eyre::{eyre, Result};
+use std::panic::{self, AssertUnwindSafe};
 
-use crate::emulator::Emulator;
+use access_trace::{AccessFilter, AccessTrace};
+use apu::Apu;
+use apu_log::ApuLog;
 use bus::Bus;
 use cartridge::Cartridge;
-use cpu::Cpu;
+use controller::Controller;
+use coverage::Coverage;
+use cpu::{Cpu, JamBehavior};
+use debug::{CpuRegs, DebugSnapshot, DebugWrite, PpuRegs};
+use events::{ConsoleEvent, EventListener, InstructionHook};
+use state::{SaveState, StateRequest};
+use time::EmulatedTime;
+use video::{palette::Palette, Frame};
+
+pub use bus::{Accuracy, Alignment};
+pub use cartridge::{ines_image, Region};
+
+/// Receives video, audio and input traffic from the console.
+/// Implemented by the SDL emulator window and by the headless runner.
+pub trait Frontend {
+    /// Called once per frame when the PPU enters vblank
+    fn handle_io(&mut self, frame: &Frame, apu: &Apu, controller: &mut Controller);
+
+    /// Called whenever the APU has filled its output buffer
+    fn handle_audio(&mut self, apu: &Apu) -> Result<()>;
+
+    /// Samples the APU collects before each `handle_audio` call, asked once at power-on
+    fn audio_chunk_len(&self) -> usize {
+        Apu::DEFAULT_CHUNK_LEN
+    }
+
+    /// Whether the APU should generate audio, checked once per frame. Timing and
+    /// IRQs are emulated either way.
+    fn wants_audio(&self) -> bool {
+        true
+    }
+
+    /// Called at the start of every scanline, -1 being the pre-render line
+    fn scanline_started(&mut self, _scanline: i16, _rendering: bool) {}
+
+    /// Whether `debug_snapshot` should be called, capturing state costs a little time
+    fn wants_debug(&self) -> bool {
+        false
+    }
+
+    /// Called once per frame before `handle_io` if `wants_debug` returns true
+    fn debug_snapshot(&mut self, _snapshot: &DebugSnapshot) {}
+
+    /// Register and memory changes to make, polled once per frame
+    fn take_debug_writes(&mut self) -> Vec<DebugWrite> {
+        Vec::new()
+    }
+
+    /// Called for every console event, before other subscribers
+    fn console_event(&mut self, _event: ConsoleEvent) {}
+
+    /// Whether frames should say which layer each pixel came from, checked once per frame
+    fn wants_pixel_sources(&self) -> bool {
+        false
+    }
+
+    /// Called when the game strobes the controller, to sample input mid-frame
+    fn poll_input(&mut self, _controller: &mut Controller) {}
+
+    /// ROM to hot-swap in, if the user asked for it
+    fn take_reloaded_rom(&mut self) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// The ROM from `take_reloaded_rom` couldn't be loaded, the old one keeps running
+    fn reload_failed(&mut self, _error: &eyre::Report) {}
+
+    /// Save state to take or load, polled once per frame
+    fn take_state_request(&mut self) -> Option<StateRequest> {
+        None
+    }
+
+    /// Receives the state taken for `StateRequest::Save`, see `Console::save_state`
+    fn state_saved(&mut self, _state: Vec<u8>) {}
+
+    /// A `StateRequest::Load` failed and the console kept running from where it was.
+    /// Successful loads send `ConsoleEvent::StateLoaded`.
+    fn state_load_failed(&mut self, _error: &eyre::Report) {}
+
+    /// Whether the user asked for the CPU trace ring to be dumped
+    fn take_trace_dump_request(&mut self) -> bool {
+        false
+    }
+
+    /// Palette to switch to, e.g. after the palette file was edited
+    fn take_palette(&mut self) -> Option<Palette> {
+        None
+    }
+
+    /// Whether battery backed RAM should be saved, checked once per frame
+    fn battery_save_due(&mut self) -> bool {
+        false
+    }
+
+    /// Receives battery backed PRG RAM when it is due and when emulation stops
+    fn save_battery_ram(&mut self, _ram: &[u8]) {}
+
+    fn set_region(&mut self, _region: Region) {}
+
+    /// The CPU executed a jam opcode at `addr` and hangs until reset
+    fn cpu_jammed(&mut self, _addr: u16) {}
+
+    fn quit_requested(&self) -> bool {
+        false
+    }
+}
 
 pub struct Console<'a> {
     cpu: Cpu<'a>,
@@ -20,17 +136,198 @@ pub const SCREEN_WIDTH: usize = 256;
 pub const SCREEN_HEIGHT: usize = 240;
 
 impl<'a> Console<'a> {
-    pub fn new(rom: &[u8], emulator: &'a mut Emulator) -> Result<Self> {
-        let bus = Bus::new(Cartridge::new(rom)?, emulator);
+    pub fn new(rom: &[u8], frontend: &'a mut dyn Frontend) -> Result<Self> {
+        Ok(Self::with_cartridge(Cartridge::new(rom)?, frontend))
+    }
+
+    /// Like `new`, but runs games with an unsupported mapper as NROM instead of
+    /// failing, see `Cartridge::load`. The frontend gets a `ConsoleEvent::MapperFallback`.
+    pub fn with_mapper_fallback(rom: &[u8], frontend: &'a mut dyn Frontend) -> Result<Self> {
+        Ok(Self::with_cartridge(Cartridge::load(rom, true)?, frontend))
+    }
+
+    fn with_cartridge(cartridge: Cartridge, frontend: &'a mut dyn Frontend) -> Self {
+        frontend.set_region(cartridge.region);
+        if let Some(mapper) = cartridge.fallback_from {
+            frontend.console_event(ConsoleEvent::MapperFallback(mapper));
+        }
+        let bus = Bus::new(cartridge, frontend);
         let cpu = Cpu::new(bus);
 
+        Self { cpu }
+    }
+
+    /// Console with nothing but a CPU and 64 kB of RAM holding `image` at `load_addr`,
+    /// for running CPU test suites. Starts at `start` and stops on BRK.
+    pub fn raw_prg(
+        image: &[u8],
+        load_addr: u16,
+        start: u16,
+        frontend: &'a mut dyn Frontend,
+    ) -> Result<Self> {
+        let end = load_addr as usize + image.len();
+        if end > 0x10000 {
+            return Err(eyre!(
+                "{} byte program doesn't fit in memory at ${load_addr:04X}",
+                image.len()
+            ));
+        }
+        let mut memory = vec![0; 0x10000];
+        memory[load_addr as usize..end].copy_from_slice(image);
+        let mut cpu = Cpu::new(Bus::flat(memory, frontend)?);
+        cpu.program_counter = start;
+        // Where the power-on reset would leave it
+        cpu.stack_pointer = 0xFD;
+        cpu.set_quit_on_brk(true);
         Ok(Self { cpu })
     }
 
+    /// Memory of a console made with `raw_prg`
+    pub fn flat_memory(&self) -> Option<&[u8]> {
+        self.cpu.bus.flat_memory()
+    }
+
+    /// Replaces the running cartridge with the given ROM without resetting the console.
+    /// RAM and CPU state are kept, so the new ROM should be compatible with the old one.
+    pub fn swap_cartridge(&mut self, rom: &[u8]) -> Result<()> {
+        self.cpu.bus.swap_cartridge(Cartridge::new(rom)?);
+        Ok(())
+    }
+
+    /// Restores battery backed PRG RAM, e.g. from a save file
+    pub fn load_battery_ram(&mut self, data: &[u8]) {
+        self.cpu.bus.load_battery_ram(data);
+    }
+
+    /// Adds a listener for console events such as completed frames and resets
+    pub fn subscribe(&mut self, listener: Box<dyn EventListener + 'a>) {
+        self.cpu.bus.events.subscribe(listener);
+    }
+
+    /// Sets the palette used for the RGB frame handed to the frontend
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.cpu.bus.video.set_palette(palette);
+    }
+
+    /// Logs bus accesses matching the given filters
+    pub fn set_access_trace(&mut self, filters: Vec<AccessFilter>) {
+        self.cpu.bus.access_trace = Some(AccessTrace::new(filters));
+    }
+
+    /// Calls the hook with every instruction the CPU executes, e.g. for profilers or
+    /// coverage tools outside the emulator. None removes it, costing nothing again.
+    pub fn set_instruction_hook(&mut self, hook: Option<InstructionHook<'a>>) {
+        self.cpu.set_instruction_hook(hook);
+    }
+
+    pub fn set_jam_behavior(&mut self, behavior: JamBehavior) {
+        self.cpu.jam_behavior = behavior;
+    }
+
+    /// Replays loops waiting for NMI with rendering off instead of interpreting them,
+    /// which saves host CPU time on title screens without changing emulated timing
+    pub fn set_idle_skip(&mut self, enabled: bool) {
+        self.cpu.set_idle_skip(enabled);
+    }
+
+    /// Must be called before running, the alignment is set at power-on
+    pub fn set_alignment(&mut self, alignment: Alignment) {
+        self.cpu.bus.set_alignment(alignment);
+    }
+
+    /// Must be called before running, see `Accuracy`
+    pub fn set_accuracy(&mut self, accuracy: Accuracy) {
+        self.cpu.bus.set_accuracy(accuracy);
+    }
+
+    /// Lets DMC sample fetches corrupt controller reads like on hardware
+    pub fn set_dpcm_conflicts(&mut self, enabled: bool) {
+        self.cpu.bus.dpcm_conflicts = enabled;
+    }
+
+    /// Starts counting accesses to each PRG ROM byte
+    pub fn enable_coverage(&mut self) {
+        self.cpu.bus.enable_coverage();
+    }
+
+    /// Starts logging APU register writes and DMC sample fetches
+    pub fn enable_apu_log(&mut self) {
+        self.cpu.bus.apu_log = Some(ApuLog::new(self.time().cpu_cycles));
+    }
+
+    pub const fn time(&self) -> EmulatedTime {
+        self.cpu.bus.time()
+    }
+
+    /// CPU registers between instructions
+    pub fn cpu_regs(&self) -> CpuRegs {
+        self.cpu.regs()
+    }
+
+    pub fn ppu_regs(&self) -> PpuRegs {
+        self.cpu.bus.ppu_regs()
+    }
+
+    /// Last values written to $4000-$4017
+    pub fn apu_registers(&self) -> [u8; Apu::REGISTERS] {
+        self.cpu.bus.apu_registers()
+    }
+
+    /// Changes registers or memory, e.g. from a script between frames
+    pub fn apply_debug_write(&mut self, write: DebugWrite) -> Result<()> {
+        match write {
+            DebugWrite::CpuRegs(regs) => self.cpu.set_regs(regs),
+            _ => self.cpu.bus.apply_debug_write(write)?,
+        }
+        Ok(())
+    }
+
+    pub const fn coverage(&self) -> Option<&Coverage> {
+        self.cpu.bus.coverage.as_ref()
+    }
+
+    pub const fn apu_log(&self) -> Option<&ApuLog> {
+        self.cpu.bus.apu_log.as_ref()
+    }
+
+    /// Runs to the end of the next frame without drawing pixels or mixing audio, e.g. to
+    /// re-simulate frames for rollback. The game sees exactly what it would in a
+    /// normal frame. The frontend gets input polls and console events, but no
+    /// `handle_io`, `handle_audio` or other end of frame calls. The picture left
+    /// in the PPU is stale until the next normal frame.
+    pub fn run_frame_silent(&mut self) -> Result<()> {
+        self.cpu.run_frame_silent()
+    }
+
+    /// Everything needed to continue emulation exactly from this point, in the
+    /// format described in `state`. While running, the frontend gets the same
+    /// through `StateRequest::Save`.
+    pub fn save_state(&mut self) -> Vec<u8> {
+        self.cpu.snapshot().encode()
+    }
+
+    /// Continues from a state taken with `save_state`, of the same game. If the
+    /// state can't be loaded the console is left as it was.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<()> {
+        self.cpu.restore(&SaveState::decode(data)?)
+    }
+
+    /// Runs until the frontend asks to quit, then hands battery backed RAM to the
+    /// frontend. A panic dumps the CPU trace ring before it is passed on.
     pub fn run_with_callback<F>(&mut self, callback: F) -> Result<()>
     where
         F: FnMut(&mut Cpu),
     {
-        self.cpu.run_with_callback(callback)
+        let cpu = &mut self.cpu;
+        match panic::catch_unwind(AssertUnwindSafe(|| cpu.run_with_callback(callback))) {
+            Ok(result) => {
+                self.cpu.bus.save_battery_ram();
+                result
+            }
+            Err(payload) => {
+                self.cpu.dump_trace("Emulator panicked");
+                panic::resume_unwind(payload)
+            }
+        }
     }
 }
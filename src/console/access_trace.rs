@@ -0,0 +1,96 @@
+use eyre::{eyre, Result, WrapErr};
+
+/// An address range whose reads and/or writes are logged
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct AccessFilter {
+    start: u16,
+    end: u16,
+    reads: bool,
+    writes: bool,
+}
+
+impl AccessFilter {
+    /// Parses `r:`, `w:` or `rw:` followed by a hex address or range, e.g. `w:2000-2007`
+    pub fn parse(spec: &str) -> Result<Self> {
+        let (kind, range) = spec
+            .split_once(':')
+            .ok_or_else(|| eyre!("Access filter '{spec}' should look like w:2000-2007"))?;
+        let (reads, writes) = match kind {
+            "r" => (true, false),
+            "w" => (false, true),
+            "rw" => (true, true),
+            _ => return Err(eyre!("Access filter kind must be r, w or rw, got '{kind}'")),
+        };
+        let (start, end) = range.split_once('-').unwrap_or((range, range));
+        let parse_addr = |addr: &str| {
+            u16::from_str_radix(addr.trim_start_matches('$'), 16)
+                .wrap_err_with(|| format!("Invalid address '{addr}' in access filter"))
+        };
+        let (start, end) = (parse_addr(start)?, parse_addr(end)?);
+        if start > end {
+            return Err(eyre!("Access filter range {range} is reversed"));
+        }
+        Ok(Self {
+            start,
+            end,
+            reads,
+            writes,
+        })
+    }
+
+    /// Parses a comma separated list of filters
+    pub fn parse_list(specs: &str) -> Result<Vec<Self>> {
+        specs.split(',').map(|s| Self::parse(s.trim())).collect()
+    }
+
+    const fn matches(self, addr: u16, write: bool) -> bool {
+        addr >= self.start && addr <= self.end && if write { self.writes } else { self.reads }
+    }
+}
+
+/// Logs CPU bus accesses matching any of the filters, stamped with PPU position and CPU cycle
+pub struct AccessTrace {
+    filters: Vec<AccessFilter>,
+}
+
+impl AccessTrace {
+    pub fn new(filters: Vec<AccessFilter>) -> Self {
+        Self { filters }
+    }
+
+    pub fn log(&self, addr: u16, data: u8, write: bool, scanline: isize, dot: usize, cycle: u64) {
+        if self.filters.iter().any(|f| f.matches(addr, write)) {
+            let (kind, arrow) = if write { ("W", "<-") } else { ("R", "->") };
+            println!(
+                "{kind} ${addr:04X} {arrow} ${data:02X}  line {scanline:3} dot {dot:3} cycle {cycle}"
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_match() {
+        let filters = AccessFilter::parse_list("w:2000-2007, rw:$8000-FFFF,r:4016").unwrap();
+        assert_eq!(filters.len(), 3);
+        assert!(filters[0].matches(0x2005, true));
+        assert!(!filters[0].matches(0x2005, false));
+        assert!(!filters[0].matches(0x2008, true));
+        assert!(filters[1].matches(0xC000, false));
+        assert!(filters[1].matches(0xFFFF, true));
+        assert!(filters[2].matches(0x4016, false));
+        assert!(!filters[2].matches(0x4017, false));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(AccessFilter::parse("2000-2007").is_err());
+        assert!(AccessFilter::parse("x:2000").is_err());
+        assert!(AccessFilter::parse("w:2007-2000").is_err());
+        assert!(AccessFilter::parse("w:zz").is_err());
+    }
+}
@@ -1,16 +1,24 @@
 mod common;
 mod dmc;
+pub mod mixer;
 mod noise;
 mod pulse;
+pub mod scope;
 mod triangle;
 
 use dmc::Dmc;
+use mixer::Sample;
 use noise::Noise;
 use pulse::Pulse;
+pub use pulse::PulseMute;
+use scope::ChannelScope;
 use triangle::Triangle;
 
-use super::cartridge::Cartridge;
+use super::debug::Fnv1a;
+use super::state::{Snapshot, StateField};
+use std::borrow::Cow;
 
+#[allow(clippy::struct_excessive_bools)]
 pub struct Apu {
     pulse1: Pulse,
     pulse2: Pulse,
@@ -18,8 +26,18 @@ pub struct Apu {
     noise: Noise,
     dmc: Dmc,
 
-    pub output: Vec<f32>,
+    pub output: Vec<Sample>,
     output_idx: usize,
+    /// Cartridge expansion audio, mixed into `output`
+    pub expansion: Sample,
+    /// Generate waveforms and fill `output`. When off only the frame counter, length
+    /// counters, IRQs and DMC DMA keep running, which is much cheaper.
+    pub synthesize: bool,
+    /// Run the channels without mixing or writing samples, for frames nobody will hear
+    pub silent: bool,
+
+    /// Recent output of pulse 1, pulse 2, triangle, noise and DMC channels
+    pub scopes: [ChannelScope; 5],
 
     cycle: usize,
 
@@ -28,16 +46,44 @@ pub struct Apu {
 
     framec_cycle: usize,
     framec_mode: bool,
+    // Last value written to $4017, written again on reset
+    framec_last_write: u8,
+    registers: [u8; Self::REGISTERS],
 }
 
-fn divide(dividend: f32, divisor: f32, zero_result: f32) -> f32 {
-    if divisor == 0.0 {
-        return zero_result;
+impl Snapshot for Apu {
+    fn state_fields(&mut self) -> Vec<(&'static str, &mut dyn StateField)> {
+        let mut fields: Vec<(&'static str, &mut dyn StateField)> = vec![
+            ("cycle", &mut self.cycle),
+            ("irq_disable", &mut self.irq_disable),
+            ("irq", &mut self.irq),
+            ("framec_cycle", &mut self.framec_cycle),
+            ("framec_mode", &mut self.framec_mode),
+            ("framec_last_write", &mut self.framec_last_write),
+            ("registers", &mut self.registers),
+        ];
+        fields.extend(self.pulse1.state_fields());
+        fields.extend(self.pulse2.state_fields());
+        fields.extend(self.triangle.state_fields());
+        fields.extend(self.noise.state_fields());
+        fields.extend(self.dmc.state_fields());
+        fields
     }
-    dividend / divisor
 }
 
 impl Apu {
+    // Scope buffers are fed every 40 APU cycles, ~44.7 kHz
+    const SCOPE_DECIMATION: usize = 40;
+    const SCOPE_LEN: usize = 2048;
+
+    /// $4000-$4017, the channel, status and frame counter registers
+    pub const REGISTERS: usize = 0x18;
+
+    /// Samples per `handle_audio` call unless the frontend asks otherwise, ~8.3 ms
+    pub const DEFAULT_CHUNK_LEN: usize = crate::APU_FREQ / 120;
+
+    pub const CHANNEL_NAMES: [&'static str; 5] = ["Pulse 1", "Pulse 2", "Triangle", "Noise", "DMC"];
+
     pub fn new() -> Self {
         Self {
             pulse1: Pulse::new(0),
@@ -45,22 +91,42 @@ impl Apu {
             triangle: Triangle::default(),
             noise: Noise::default(),
             dmc: Dmc::default(),
-            output: vec![0.0; crate::APU_FREQ / 120],
+            output: vec![Sample::default(); Self::DEFAULT_CHUNK_LEN],
             output_idx: 0,
+            expansion: Sample::default(),
+            synthesize: true,
+            silent: false,
+            scopes: std::array::from_fn(|_| ChannelScope::new(Self::SCOPE_LEN)),
             cycle: 0,
             irq_disable: false,
             irq: false,
             framec_cycle: 0,
             framec_mode: false,
+            framec_last_write: 0,
+            registers: [0; Self::REGISTERS],
         }
     }
 
+    /// Sets how many samples `output` collects before it's handed to the frontend
+    pub fn set_chunk_len(&mut self, len: usize) {
+        self.output = vec![Sample::default(); len.max(1)];
+        self.output_idx = 0;
+    }
+
+    /// Soft reset: channels are silenced and the frame counter restarts with the
+    /// last written mode. Triangle phase and most channel registers are kept.
     pub fn reset(&mut self) {
         self.set_enable(0);
+        self.dmc.reset();
+        self.irq = false;
+        self.write_frame_counter(self.framec_last_write);
     }
 
     pub fn write(&mut self, addr: u16, data: u8) {
         // println!("Writing {:2X} to {:4X}", data, addr);
+        if let Some(reg) = self.registers.get_mut(addr.wrapping_sub(0x4000) as usize) {
+            *reg = data;
+        }
         match addr {
             0x4000 => self.pulse1.write_r0(data),
             0x4001 => self.pulse1.write_r1(data),
@@ -87,18 +153,26 @@ impl Apu {
 
             0x4015 => self.set_enable(data),
 
-            0x4017 => {
-                self.irq_disable = data & 0x40 != 0;
-                self.irq = if self.irq_disable { false } else { self.irq };
-                self.framec_mode = data & 0x80 != 0;
-                if self.framec_mode {
-                    self.cycle = 18639;
-                }
-            }
+            0x4017 => self.write_frame_counter(data),
             _ => (),
         }
     }
 
+    fn write_frame_counter(&mut self, data: u8) {
+        self.framec_last_write = data;
+        self.irq_disable = data & 0x40 != 0;
+        self.irq = if self.irq_disable { false } else { self.irq };
+        self.framec_mode = data & 0x80 != 0;
+        // Sequencer restarts, and 5-step mode clocks all units immediately. That clock
+        // comes a few cycles after the write, so it doesn't race earlier writes.
+        self.framec_cycle = 0;
+        if self.framec_mode {
+            self.settle_length_writes();
+            self.tick_quarter_frame();
+            self.tick_half_frame();
+        }
+    }
+
     fn set_enable(&mut self, data: u8) {
         self.pulse1.set_enable(data & 0x01 != 0);
         self.pulse2.set_enable(data & 0x02 != 0);
@@ -110,14 +184,15 @@ impl Apu {
     pub fn read(&mut self, addr: u16) -> u8 {
         match addr {
             0x4015 => {
-                let mut val = (self.pulse1.length_counter > 0) as u8;
-                val |= ((self.pulse2.length_counter > 0) as u8) << 1;
-                val |= ((self.triangle.length_counter > 0) as u8) << 2;
-                val |= ((self.noise.length_counter > 0) as u8) << 3;
+                let mut val = self.pulse1.length.active() as u8;
+                val |= (self.pulse2.length.active() as u8) << 1;
+                val |= (self.triangle.length.active() as u8) << 2;
+                val |= (self.noise.length.active() as u8) << 3;
                 val |= ((self.dmc.bytes_remaining > 0) as u8) << 4;
                 val |= (self.irq as u8) << 6;
                 val |= (self.dmc.irq as u8) << 7;
 
+                // Only the frame interrupt is acknowledged by reading, DMC needs a $4015 write
                 self.irq = false;
 
                 val
@@ -126,22 +201,88 @@ impl Apu {
         }
     }
 
+    /// Hash of the registers, length counters, frame counter and IRQ flags. Output
+    /// samples are left out, they depend on the chunk length and on `synthesize`.
+    pub fn state_hash(&self) -> u64 {
+        let mut hasher = Fnv1a::default();
+        hasher.write(&self.registers);
+        hasher.write(&[
+            self.pulse1.length.active().into(),
+            self.pulse2.length.active().into(),
+            self.triangle.length.active().into(),
+            self.noise.length.active().into(),
+        ]);
+        hasher.write(&self.dmc.bytes_remaining.to_le_bytes());
+        hasher.write(&(self.cycle as u64).to_le_bytes());
+        hasher.write(&(self.framec_cycle as u64).to_le_bytes());
+        hasher.write(&[
+            self.framec_mode.into(),
+            self.irq.into(),
+            self.irq_disable.into(),
+            self.dmc.irq.into(),
+        ]);
+        hasher.finish()
+    }
+
+    /// The last filled output buffer as floating point samples, converted from
+    /// fixed point with the `fixed-audio` feature
+    pub fn output_f32(&self) -> Cow<'_, [f32]> {
+        #[cfg(not(feature = "fixed-audio"))]
+        return Cow::Borrowed(&self.output);
+        #[cfg(feature = "fixed-audio")]
+        return Cow::Owned(self.output.iter().map(|&s| mixer::to_f32(s)).collect());
+    }
+
+    /// Why each pulse channel is silent, for the debugger
+    pub const fn pulse_mutes(&self) -> [Option<PulseMute>; 2] {
+        [self.pulse1.mute_reason(), self.pulse2.mute_reason()]
+    }
+
+    pub const fn frame_irq(&self) -> bool {
+        self.irq
+    }
+
+    pub const fn dmc_irq(&self) -> bool {
+        self.dmc.irq
+    }
+
     pub const fn irq_active(&self) -> bool {
         self.irq | self.dmc.irq
     }
 
-    pub fn tick(&mut self, cartridge: &mut Cartridge) -> bool {
+    /// Last values written to each register, most of which can't be read back
+    pub const fn registers(&self) -> [u8; Self::REGISTERS] {
+        self.registers
+    }
+
+    /// Address the DMC wants a sample byte fetched from, stalling the CPU
+    pub const fn dmc_dma_request(&self) -> Option<u16> {
+        self.dmc.dma_request()
+    }
+
+    pub fn dmc_dma_done(&mut self, data: u8) {
+        self.dmc.load_sample(data);
+    }
+
+    pub fn tick(&mut self) -> bool {
         self.cycle += 1;
 
         self.tick_frame_counter();
+        self.settle_length_writes();
 
+        self.dmc.tick();
+        if !self.synthesize {
+            return false;
+        }
         self.triangle.tick();
-        self.dmc.tick(cartridge);
         if self.cycle % 2 == 0 {
             self.pulse1.tick();
             self.pulse2.tick();
             self.noise.tick();
         }
+        if self.silent {
+            return false;
+        }
 
         // let pulse1_out = 0.0;
         // let pulse2_out = 0.0;
@@ -149,20 +290,22 @@ impl Apu {
         // let noise_out = 0.0;
         // let dmc_out = 0.0;
 
-        let pulse1_out = self.pulse1.output as f32;
-        let pulse2_out = self.pulse2.output as f32;
-        let tri_out = self.triangle.output as f32;
-        let noise_out = self.noise.output as f32;
-        let dmc_out = self.dmc.output as f32;
-        let total_pulse_out = divide(
-            95.88,
-            divide(8128.0, pulse1_out + pulse2_out, -100.0) + 100.0,
-            0.0,
+        if self.cycle % Self::SCOPE_DECIMATION == 0 {
+            self.scopes[0].push(self.pulse1.output);
+            self.scopes[1].push(self.pulse2.output);
+            self.scopes[2].push(self.triangle.output);
+            self.scopes[3].push(self.noise.output);
+            self.scopes[4].push(self.dmc.output);
+        }
+
+        let mix = mixer::mix(
+            self.pulse1.output,
+            self.pulse2.output,
+            self.triangle.output,
+            self.noise.output,
+            self.dmc.output,
         );
-        let tnd_tmp = tri_out / 8227.0 + noise_out / 12241.0 + dmc_out / 22638.0;
-        let tnd_out = divide(159.79, divide(1.0, tnd_tmp, -100.0) + 100.0, 0.0);
-        let output = total_pulse_out + tnd_out - 0.5;
-        self.output[self.output_idx] = output * 0.5;
+        self.output[self.output_idx] = mixer::add(mix, self.expansion);
 
         self.output_idx += 1;
         if self.output_idx >= self.output.len() {
@@ -201,6 +344,18 @@ impl Apu {
         }
     }
 
+    // Length counter writes racing a clock in this cycle have been resolved
+    fn settle_length_writes(&mut self) {
+        for length in [
+            &mut self.pulse1.length,
+            &mut self.pulse2.length,
+            &mut self.triangle.length,
+            &mut self.noise.length,
+        ] {
+            length.end_cycle();
+        }
+    }
+
     fn tick_quarter_frame(&mut self) {
         self.pulse1.tick_quarter_frame();
         self.pulse2.tick_quarter_frame();
@@ -220,3 +375,179 @@ impl Apu {
 const LENGTH_VALUES: [u8; 32] = 
     [10, 254, 20, 2,  40, 4,  80, 6,  160, 8,  60, 10, 14, 12, 26, 14,
      12, 16,  24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30];
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn run(apu: &mut Apu, cycles: usize) {
+        for _ in 0..cycles {
+            apu.tick();
+        }
+    }
+
+    #[test]
+    fn test_status_reports_length_counters() {
+        let mut apu = Apu::new();
+        assert_eq!(apu.read(0x4015), 0);
+
+        apu.write(0x4015, 0x0F);
+        apu.write(0x4003, 0x08);
+        apu.write(0x400B, 0x08);
+        assert_eq!(apu.read(0x4015), 0x05);
+
+        apu.write(0x4015, 0x00);
+        assert_eq!(apu.read(0x4015), 0x00);
+    }
+
+    #[test]
+    fn test_length_not_loaded_when_disabled() {
+        let mut apu = Apu::new();
+        apu.write(0x4007, 0x08);
+        assert_eq!(apu.read(0x4015) & 0x02, 0);
+    }
+
+    #[test]
+    fn test_pulse_mute_reasons() {
+        let mut apu = Apu::new();
+        assert_eq!(apu.pulse_mutes()[0], Some(PulseMute::Disabled));
+        apu.write(0x4015, 0x01);
+        assert_eq!(apu.pulse_mutes()[0], Some(PulseMute::LengthZero));
+        // Constant volume 15, period 4
+        apu.write(0x4000, 0x3F);
+        apu.write(0x4002, 0x04);
+        apu.write(0x4003, 0x08);
+        assert_eq!(apu.pulse_mutes()[0], Some(PulseMute::PeriodTooLow));
+        // Period $600 sweeps up to $780 with a shift of 2, or past $7FF with 1
+        apu.write(0x4003, 0x0E);
+        apu.write(0x4002, 0x00);
+        apu.write(0x4001, 0x02);
+        assert_eq!(apu.pulse_mutes()[0], None);
+        apu.write(0x4001, 0x01);
+        assert_eq!(apu.pulse_mutes()[0], Some(PulseMute::SweepOverflow));
+        apu.write(0x4001, 0x00);
+        apu.write(0x4002, 0x00);
+        apu.write(0x4003, 0x09);
+        apu.write(0x4000, 0x30);
+        assert_eq!(apu.pulse_mutes()[0], Some(PulseMute::VolumeZero));
+    }
+
+    #[test]
+    fn test_frame_irq_cleared_by_read() {
+        let mut apu = Apu::new();
+        run(&mut apu, 30_000);
+        assert!(apu.irq_active());
+        assert_eq!(apu.read(0x4015) & 0x40, 0x40);
+        assert!(!apu.irq_active());
+        assert_eq!(apu.read(0x4015) & 0x40, 0);
+    }
+
+    #[test]
+    fn test_frame_irq_inhibit() {
+        let mut apu = Apu::new();
+        apu.write(0x4017, 0x40);
+        run(&mut apu, 30_000);
+        assert!(!apu.irq_active());
+    }
+
+    #[test]
+    fn test_five_step_write_clocks_length_immediately() {
+        let mut apu = Apu::new();
+        apu.write(0x4015, 0x01);
+        // Length index 3 loads a count of 2
+        apu.write(0x4003, 0x18);
+        apu.write(0x4017, 0x80);
+        apu.write(0x4017, 0x80);
+        assert_eq!(apu.read(0x4015) & 0x01, 0);
+    }
+
+    #[test]
+    fn test_reset_silences_and_keeps_frame_counter_mode() {
+        let mut apu = Apu::new();
+        apu.write(0x4017, 0x40);
+        apu.write(0x4015, 0x0F);
+        apu.write(0x4003, 0x08);
+        apu.reset();
+        assert_eq!(apu.read(0x4015), 0);
+
+        // IRQ inhibit survives the reset
+        run(&mut apu, 30_000);
+        assert!(!apu.irq_active());
+    }
+
+    // CPU cycles before the one the first half frame clock lands on
+    const BEFORE_HALF_FRAME: usize = 14911;
+
+    // Length of pulse 1 after a $4003 write of length index 3 (2) on `offset` cycles
+    // from the first half frame clock, with a count of 4 loaded beforehand if `loaded`
+    fn reload_near_clock(offset: isize, loaded: bool) -> u8 {
+        let mut apu = Apu::new();
+        apu.write(0x4015, 0x01);
+        if loaded {
+            // Length index 5 loads a count of 4
+            apu.write(0x4003, 0x28);
+        }
+        run(&mut apu, BEFORE_HALF_FRAME.saturating_add_signed(offset));
+        apu.write(0x4003, 0x18);
+        run(&mut apu, 10);
+        apu.pulse1.length.value
+    }
+
+    #[test]
+    fn test_length_reload_timing() {
+        // Before the clock the reload is clocked, after it the reload stays
+        assert_eq!(reload_near_clock(-1, true), 1);
+        assert_eq!(reload_near_clock(1, true), 2);
+        // On the clock a reload only takes when the counter was zero
+        assert_eq!(reload_near_clock(0, false), 2);
+        assert_eq!(reload_near_clock(0, true), 3);
+    }
+
+    #[test]
+    fn test_length_halt_timing() {
+        for (halt_before, expected) in [(false, 3), (true, 4)] {
+            let mut apu = Apu::new();
+            apu.write(0x4015, 0x01);
+            apu.write(0x4000, if halt_before { 0x20 } else { 0x00 });
+            apu.write(0x4003, 0x28);
+            run(&mut apu, BEFORE_HALF_FRAME);
+            // The clock on this cycle still sees the old halt flag
+            apu.write(0x4000, if halt_before { 0x00 } else { 0x20 });
+            run(&mut apu, 1);
+            assert_eq!(apu.pulse1.length.value, expected);
+        }
+    }
+
+    #[test]
+    fn test_timing_without_synthesis() {
+        let mut apu = Apu::new();
+        apu.synthesize = false;
+        apu.write(0x4015, 0x01);
+        // Length index 3 loads a count of 2, gone after two half frames
+        apu.write(0x4003, 0x18);
+        let mut filled = false;
+        for _ in 0..30_000 {
+            filled |= apu.tick();
+        }
+        assert!(!filled);
+        assert_eq!(apu.read(0x4015) & 0x41, 0x40);
+    }
+
+    #[test]
+    fn test_chunk_len() {
+        let mut apu = Apu::new();
+        apu.set_chunk_len(100);
+        let mut other = Apu::new();
+        other.synthesize = false;
+        for apu in [&mut apu, &mut other] {
+            apu.write(0x4015, 0x01);
+            apu.write(0x4003, 0x18);
+        }
+        let filled = (0..1000).filter(|_| apu.tick()).count();
+        run(&mut other, 1000);
+        assert_eq!(filled, 10);
+        assert_eq!(apu.output.len(), 100);
+        // Neither the chunk length nor synthesis changes the state
+        assert_eq!(apu.state_hash(), other.state_hash());
+    }
+}
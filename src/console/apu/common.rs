@@ -1,3 +1,7 @@
+use eyre::Result;
+
+use crate::console::state::StateField;
+
 #[derive(Default)]
 pub struct Envelope {
     pub divider: u8,
@@ -25,3 +29,95 @@ impl Envelope {
         }
     }
 }
+
+/// Length counter with the hardware's write races. When a length clock lands on the
+/// same cycle as a register write, the clock still sees the old halt flag, and a
+/// reload is ignored unless the counter was already zero.
+#[derive(Default)]
+pub struct LengthCounter {
+    pub value: u8,
+    halt: bool,
+    // Values replaced by writes in the current cycle, cleared by `end_cycle`
+    halt_before_write: Option<bool>,
+    value_before_reload: Option<u8>,
+}
+
+impl LengthCounter {
+    pub const fn active(&self) -> bool {
+        self.value > 0
+    }
+
+    pub fn set_halt(&mut self, halt: bool) {
+        self.halt_before_write.get_or_insert(self.halt);
+        self.halt = halt;
+    }
+
+    /// Loads the count for a length index written to the channel's last register
+    pub fn reload(&mut self, length_idx: u8) {
+        self.value_before_reload.get_or_insert(self.value);
+        self.value = super::LENGTH_VALUES[length_idx as usize];
+    }
+
+    /// Clears the counter when the channel is disabled through $4015
+    pub fn clear(&mut self) {
+        self.value = 0;
+        self.value_before_reload = None;
+    }
+
+    /// Half frame clock
+    pub fn clock(&mut self) {
+        let halt = self.halt_before_write.unwrap_or(self.halt);
+        match self.value_before_reload.take() {
+            // Reloading a silent channel wins over the clock
+            Some(0) => (),
+            // Otherwise the clock wins and the reload is lost
+            Some(before) => self.value = before - u8::from(!halt),
+            None if !halt && self.value > 0 => self.value -= 1,
+            None => (),
+        }
+    }
+
+    /// Called after the frame counter on every APU cycle
+    pub fn end_cycle(&mut self) {
+        self.halt_before_write = None;
+        self.value_before_reload = None;
+    }
+}
+
+impl StateField for Envelope {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.divider.save(out);
+        self.value.save(out);
+        self.reset.save(out);
+        self.divider_start.save(out);
+        self.looping.save(out);
+    }
+    fn load(&mut self, input: &mut &[u8]) -> Result<()> {
+        self.divider.load(input)?;
+        self.value.load(input)?;
+        self.reset.load(input)?;
+        self.divider_start.load(input)?;
+        self.looping.load(input)
+    }
+    fn describe(&self) -> String {
+        format!("{}/{}", self.value, self.divider)
+    }
+}
+
+impl StateField for LengthCounter {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.value.save(out);
+        self.halt.save(out);
+        self.halt_before_write.save(out);
+        self.value_before_reload.save(out);
+    }
+    fn load(&mut self, input: &mut &[u8]) -> Result<()> {
+        self.value.load(input)?;
+        self.halt.load(input)?;
+        self.halt_before_write.load(input)?;
+        self.value_before_reload.load(input)
+    }
+    fn describe(&self) -> String {
+        format!("{}{}", self.value, if self.halt { " halted" } else { "" })
+    }
+}
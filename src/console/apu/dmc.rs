@@ -1,6 +1,5 @@
-use crate::macros::bit_bool;
-
-use crate::console::cartridge::Cartridge;
+use crate::console::state::Snapshot;
+use crate::macros::{bit_bool, state_fields};
 
 #[allow(clippy::struct_excessive_bools)]
 #[derive(Default)]
@@ -27,34 +26,37 @@ pub struct Dmc {
     sample_len: u16,
 }
 
+impl Snapshot for Dmc {
+    state_fields!(
+        enable,
+        timer,
+        silence,
+        irq,
+        sample_buffer,
+        start_sample,
+        sample_addr,
+        bytes_remaining,
+        shift_register,
+        bits_remaining,
+        output,
+        rate,
+        dmc_loop,
+        irq_enable,
+        next_sample_addr,
+        sample_len,
+    );
+}
+
 impl Dmc {
     const RATE: [u16; 16] = [
         428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
     ];
 
-    pub fn tick(&mut self, cartridge: &mut Cartridge) {
+    pub fn tick(&mut self) {
         if !self.enable {
             return;
         }
 
-        if self.sample_buffer == None && self.bytes_remaining > 0 {
-            self.sample_buffer = Some(cartridge.read_cpu(self.sample_addr));
-
-            // Sample address always starts from
-            self.sample_addr = if self.sample_addr == 0xFFFF {
-                0x8000
-            } else {
-                self.sample_addr + 1
-            };
-
-            self.bytes_remaining -= 1;
-            if self.bytes_remaining == 0 && self.dmc_loop {
-                self.start_sample = true;
-            } else if self.bytes_remaining == 0 {
-                self.irq = self.irq_enable;
-            }
-        }
-
         if self.start_sample {
             self.sample_addr = 0xC000 | (self.next_sample_addr << 6);
             self.bytes_remaining = self.sample_len;
@@ -91,6 +93,39 @@ impl Dmc {
         }
     }
 
+    /// Address of the next sample byte while the empty sample buffer waits for DMA
+    pub const fn dma_request(&self) -> Option<u16> {
+        if self.enable && self.sample_buffer.is_none() && self.bytes_remaining > 0 {
+            Some(self.sample_addr)
+        } else {
+            None
+        }
+    }
+
+    /// Fills the sample buffer with the byte fetched by DMA
+    pub fn load_sample(&mut self, data: u8) {
+        self.sample_buffer = Some(data);
+
+        // Sample address wraps around to $8000
+        self.sample_addr = if self.sample_addr == 0xFFFF {
+            0x8000
+        } else {
+            self.sample_addr + 1
+        };
+
+        self.bytes_remaining -= 1;
+        if self.bytes_remaining == 0 && self.dmc_loop {
+            self.start_sample = true;
+        } else if self.bytes_remaining == 0 {
+            self.irq = self.irq_enable;
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.irq = false;
+        self.output &= 0x01;
+    }
+
     pub fn set_enable(&mut self, enable: bool) {
         self.enable = enable;
         self.irq = false;
@@ -0,0 +1,103 @@
+// Mixes the channel outputs into one sample, see https://www.nesdev.org/wiki/APU_Mixer
+
+/// Mixed output sample, 16 bit fixed point with the `fixed-audio` feature
+#[cfg(not(feature = "fixed-audio"))]
+pub type Sample = f32;
+#[cfg(feature = "fixed-audio")]
+pub type Sample = i16;
+
+#[cfg(not(feature = "fixed-audio"))]
+fn divide(dividend: f32, divisor: f32, zero_result: f32) -> f32 {
+    if divisor == 0.0 {
+        return zero_result;
+    }
+    dividend / divisor
+}
+
+/// Nonlinear mix of the channel DAC levels, centered around zero
+#[cfg(not(feature = "fixed-audio"))]
+pub fn mix(pulse1: u8, pulse2: u8, triangle: u8, noise: u8, dmc: u8) -> Sample {
+    let total_pulse_out = divide(
+        95.88,
+        divide(8128.0, pulse1 as f32 + pulse2 as f32, -100.0) + 100.0,
+        0.0,
+    );
+    let tnd_tmp = triangle as f32 / 8227.0 + noise as f32 / 12241.0 + dmc as f32 / 22638.0;
+    let tnd_out = divide(159.79, divide(1.0, tnd_tmp, -100.0) + 100.0, 0.0);
+    (total_pulse_out + tnd_out - 0.5) * 0.5
+}
+
+#[cfg(not(feature = "fixed-audio"))]
+pub const fn to_f32(sample: Sample) -> f32 {
+    sample
+}
+
+#[cfg(not(feature = "fixed-audio"))]
+pub const fn from_f32(level: f32) -> Sample {
+    level
+}
+
+/// Adds expansion audio to a mixed sample
+#[cfg(not(feature = "fixed-audio"))]
+pub fn add(sample: Sample, expansion: Sample) -> Sample {
+    sample + expansion
+}
+
+// Q15 levels from the lookup table approximation, which sums the triangle, noise
+// and DMC channels with weights 3, 2 and 1 before the nonlinearity
+#[cfg(feature = "fixed-audio")]
+const PULSE_TABLE: [i32; 31] = {
+    let mut table = [0; 31];
+    let mut n = 1;
+    while n < table.len() {
+        table[n] = (95.52 / (8128.0 / n as f64 + 100.0) * 32768.0) as i32;
+        n += 1;
+    }
+    table
+};
+#[cfg(feature = "fixed-audio")]
+const TND_TABLE: [i32; 203] = {
+    let mut table = [0; 203];
+    let mut n = 1;
+    while n < table.len() {
+        table[n] = (163.67 / (24329.0 / n as f64 + 100.0) * 32768.0) as i32;
+        n += 1;
+    }
+    table
+};
+
+/// Table based mix without floating point math, for targets without an FPU
+#[cfg(feature = "fixed-audio")]
+pub const fn mix(pulse1: u8, pulse2: u8, triangle: u8, noise: u8, dmc: u8) -> Sample {
+    let pulse = PULSE_TABLE[(pulse1 + pulse2) as usize];
+    let tnd = TND_TABLE[3 * triangle as usize + 2 * noise as usize + dmc as usize];
+    ((pulse + tnd - 16384) / 2) as Sample
+}
+
+#[cfg(feature = "fixed-audio")]
+pub fn to_f32(sample: Sample) -> f32 {
+    sample as f32 / 32768.0
+}
+
+#[cfg(feature = "fixed-audio")]
+pub fn from_f32(level: f32) -> Sample {
+    (level * 32768.0) as Sample
+}
+
+#[cfg(feature = "fixed-audio")]
+pub const fn add(sample: Sample, expansion: Sample) -> Sample {
+    sample.saturating_add(expansion)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_mix_range() {
+        let silent = to_f32(mix(0, 0, 0, 0, 0));
+        let loudest = to_f32(mix(15, 15, 15, 15, 127));
+        assert!((silent + 0.25).abs() < 1e-3, "{silent}");
+        assert!(loudest > 0.2 && loudest < 0.251, "{loudest}");
+    }
+}
@@ -1,6 +1,7 @@
-use crate::macros::bit_bool;
+use crate::console::state::Snapshot;
+use crate::macros::{bit_bool, state_fields};
 
-use super::common::Envelope;
+use super::common::{Envelope, LengthCounter};
 
 #[allow(clippy::struct_excessive_bools)]
 pub struct Noise {
@@ -8,14 +9,13 @@ pub struct Noise {
     enable: bool,
     shift_register: u16,
 
-    pub length_counter: u8,
+    pub length: LengthCounter,
     env: Envelope,
 
     pub output: u8,
 
     volume: u8,
     const_vol: bool,
-    counter_halt: bool,
     mode: bool,
     period: u16,
 }
@@ -26,18 +26,32 @@ impl Default for Noise {
             shift_register: 1,
             timer: 0,
             enable: false,
-            length_counter: 0,
+            length: LengthCounter::default(),
             env: Envelope::default(),
             output: 0,
             volume: 0,
             const_vol: false,
-            counter_halt: false,
             mode: false,
             period: 0,
         }
     }
 }
 
+impl Snapshot for Noise {
+    state_fields!(
+        timer,
+        enable,
+        shift_register,
+        length,
+        env,
+        output,
+        volume,
+        const_vol,
+        mode,
+        period,
+    );
+}
+
 impl Noise {
     const TIMER_VALUES: [u16; 16] = [
         4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
@@ -68,7 +82,7 @@ impl Noise {
             self.env.value
         };
 
-        if self.shift_register & 0x1 == 0 && self.length_counter > 0 {
+        if self.shift_register & 0x1 == 0 && self.length.active() {
             self.output = volume;
         } else {
             self.output = 0;
@@ -76,9 +90,7 @@ impl Noise {
     }
 
     pub fn tick_half_frame(&mut self) {
-        if !self.counter_halt && self.length_counter > 0 {
-            self.length_counter -= 1;
-        }
+        self.length.clock();
     }
 
     pub fn tick_quarter_frame(&mut self) {
@@ -88,7 +100,7 @@ impl Noise {
     pub fn set_enable(&mut self, enable: bool) {
         self.enable = enable;
         if !enable {
-            self.length_counter = 0;
+            self.length.clear();
         }
     }
 
@@ -96,7 +108,7 @@ impl Noise {
         self.volume = data & 0xF;
         self.env.divider_start = self.volume;
         self.env.looping = bit_bool!(data, 5);
-        self.counter_halt = bit_bool!(data, 5);
+        self.length.set_halt(bit_bool!(data, 5));
         self.const_vol = bit_bool!(data, 4);
     }
 
@@ -107,9 +119,8 @@ impl Noise {
     }
 
     pub fn write_r3(&mut self, data: u8) {
-        let counter_load = data >> 3;
         if self.enable {
-            self.length_counter = super::LENGTH_VALUES[counter_load as usize];
+            self.length.reload(data >> 3);
         };
         self.env.reset = true;
     }
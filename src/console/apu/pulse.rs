@@ -1,6 +1,33 @@
-use crate::macros::bit_bool;
+use crate::console::state::Snapshot;
+use crate::macros::{bit_bool, state_fields};
+
+use super::common::{Envelope, LengthCounter};
+
+/// Why a pulse channel outputs nothing
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PulseMute {
+    /// Its bit in $4015 is clear
+    Disabled,
+    LengthZero,
+    /// Periods below 8 would be ultrasonic
+    PeriodTooLow,
+    /// The sweep target period is above $7FF, which mutes even with the sweep off
+    SweepOverflow,
+    /// Constant volume 0 or a decayed envelope
+    VolumeZero,
+}
 
-use super::common::Envelope;
+impl PulseMute {
+    pub const fn describe(self) -> &'static str {
+        match self {
+            Self::Disabled => "disabled in $4015",
+            Self::LengthZero => "length counter is zero",
+            Self::PeriodTooLow => "period is below 8",
+            Self::SweepOverflow => "sweep target period is above $7FF",
+            Self::VolumeZero => "volume is zero",
+        }
+    }
+}
 
 #[allow(clippy::struct_excessive_bools)]
 #[derive(Default)]
@@ -15,13 +42,12 @@ pub struct Pulse {
     enable: bool,
 
     env: Envelope,
-    pub length_counter: u8,
+    pub length: LengthCounter,
 
     pub output: u8,
 
     volume: u8,
     const_vol: bool,
-    counter_halt: bool,
     duty: usize,
     sw_shift: u8,
     sw_negate: bool,
@@ -30,6 +56,29 @@ pub struct Pulse {
     timer_start: u16,
 }
 
+impl Snapshot for Pulse {
+    state_fields!(
+        timer,
+        period,
+        target_period,
+        sequencer,
+        sweep_period,
+        sw_reload,
+        enable,
+        env,
+        length,
+        output,
+        volume,
+        const_vol,
+        duty,
+        sw_shift,
+        sw_negate,
+        sw_period,
+        sw_enable,
+        timer_start,
+    );
+}
+
 impl Pulse {
     const DUTY_TABLES: [[u8; 8]; 4] = [
         [0, 0, 0, 0, 0, 0, 0, 1],
@@ -51,23 +100,11 @@ impl Pulse {
             return;
         }
 
-        let period_shifted = self.period >> self.sw_shift;
-        self.target_period = if self.sw_negate {
-            if self.idx == 0 {
-                self.period - period_shifted - 1
-            } else {
-                self.period - period_shifted
-            }
-        } else {
-            self.period + period_shifted
-        };
-
-        let volume = if self.length_counter == 0 || self.period < 8 || self.target_period > 0x7FF {
+        self.target_period = self.sweep_target();
+        let volume = if !self.length.active() || self.period < 8 || self.target_period > 0x7FF {
             0
-        } else if self.const_vol {
-            self.volume
         } else {
-            self.env.value
+            self.volume()
         };
 
         if self.timer == 0 {
@@ -83,6 +120,44 @@ impl Pulse {
         self.output = volume * Self::DUTY_TABLES[self.duty][self.sequencer];
     }
 
+    // Pulse 1 negates with ones' complement, so it subtracts one more. A negative
+    // target wraps above $7FF, but the period is below 8 then, which mutes anyway.
+    const fn sweep_target(&self) -> u16 {
+        let period_shifted = self.period >> self.sw_shift;
+        if !self.sw_negate {
+            self.period + period_shifted
+        } else if self.idx == 0 {
+            self.period.wrapping_sub(period_shifted + 1)
+        } else {
+            self.period - period_shifted
+        }
+    }
+
+    const fn volume(&self) -> u8 {
+        if self.const_vol {
+            self.volume
+        } else {
+            self.env.value
+        }
+    }
+
+    /// Why the channel is silent, if it is
+    pub const fn mute_reason(&self) -> Option<PulseMute> {
+        if !self.enable {
+            Some(PulseMute::Disabled)
+        } else if !self.length.active() {
+            Some(PulseMute::LengthZero)
+        } else if self.period < 8 {
+            Some(PulseMute::PeriodTooLow)
+        } else if self.sweep_target() > 0x7FF {
+            Some(PulseMute::SweepOverflow)
+        } else if self.volume() == 0 {
+            Some(PulseMute::VolumeZero)
+        } else {
+            None
+        }
+    }
+
     pub fn tick_half_frame(&mut self) {
         // Sweep divider always updated no matter if enabled
         self.sweep_period -= 1;
@@ -100,9 +175,7 @@ impl Pulse {
             self.sweep_period = self.sw_period as i8;
         }
 
-        if !self.counter_halt && self.length_counter > 0 {
-            self.length_counter -= 1;
-        }
+        self.length.clock();
     }
 
     pub fn tick_quarter_frame(&mut self) {
@@ -112,7 +185,7 @@ impl Pulse {
     pub fn set_enable(&mut self, enable: bool) {
         self.enable = enable;
         if !enable {
-            self.length_counter = 0;
+            self.length.clear();
         }
     }
 
@@ -122,7 +195,7 @@ impl Pulse {
         self.volume = data & 0xF;
 
         self.const_vol = bit_bool!(data, 4);
-        self.counter_halt = bit_bool!(data, 5);
+        self.length.set_halt(bit_bool!(data, 5));
         self.env.divider_start = self.volume;
         self.env.looping = bit_bool!(data, 5);
         self.duty = (data >> 6) as usize;
@@ -146,8 +219,7 @@ impl Pulse {
         self.period = self.timer_start;
         self.sequencer = 0;
         if self.enable {
-            let length_idx = data >> 3;
-            self.length_counter = super::LENGTH_VALUES[length_idx as usize];
+            self.length.reload(data >> 3);
         };
         self.env.reset = true;
     }
@@ -0,0 +1,66 @@
+/// Ring buffer of recent output levels of a single APU channel, for scope views
+pub struct ChannelScope {
+    samples: Vec<u8>,
+    pos: usize,
+}
+
+impl ChannelScope {
+    pub fn new(len: usize) -> Self {
+        Self {
+            samples: vec![0; len],
+            pos: 0,
+        }
+    }
+
+    pub fn push(&mut self, sample: u8) {
+        self.samples[self.pos] = sample;
+        self.pos = (self.pos + 1) % self.samples.len();
+    }
+
+    /// All buffered samples, oldest first
+    pub fn samples(&self) -> impl Iterator<Item = u8> + '_ {
+        self.samples[self.pos..]
+            .iter()
+            .chain(self.samples[..self.pos].iter())
+            .copied()
+    }
+
+    /// Latest `len` samples starting from a rising edge, so that periodic waveforms
+    /// stay in place between frames like on a triggered oscilloscope.
+    /// Falls back to the most recent samples if no edge is found.
+    pub fn synced(&self, len: usize) -> Vec<u8> {
+        let samples: Vec<u8> = self.samples().collect();
+        let len = len.min(samples.len());
+        let last_start = samples.len() - len;
+
+        let trigger = (1..=last_start)
+            .rev()
+            .find(|&idx| samples[idx - 1] < samples[idx]);
+        let start = trigger.unwrap_or(last_start);
+        samples[start..start + len].to_vec()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_samples_in_order_after_wrap() {
+        let mut scope = ChannelScope::new(4);
+        for sample in 1..=6 {
+            scope.push(sample);
+        }
+        assert_eq!(scope.samples().collect::<Vec<_>>(), vec![3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn test_synced_starts_at_rising_edge() {
+        let mut scope = ChannelScope::new(8);
+        for sample in [0, 5, 5, 0, 0, 5, 5, 0] {
+            scope.push(sample);
+        }
+        assert_eq!(scope.synced(3), vec![5, 5, 0]);
+        assert_eq!(scope.synced(8), vec![0, 5, 5, 0, 0, 5, 5, 0]);
+    }
+}
@@ -1,4 +1,7 @@
-use crate::macros::bit_bool;
+use crate::console::state::Snapshot;
+use crate::macros::{bit_bool, state_fields};
+
+use super::common::LengthCounter;
 
 #[allow(clippy::struct_excessive_bools)]
 #[derive(Default)]
@@ -7,7 +10,7 @@ pub struct Triangle{
     timer: u16,
     enable: bool,
 
-    pub length_counter: u8,
+    pub length: LengthCounter,
 
     pub wave_ptr: usize,
     linear_counter: u8,
@@ -17,10 +20,24 @@ pub struct Triangle{
 
     linear_counter_start: u8,
     control: bool,
-    counter_halt: bool,
     timer_start: u16,
 }
 
+impl Snapshot for Triangle {
+    state_fields!(
+        timer,
+        enable,
+        length,
+        wave_ptr,
+        linear_counter,
+        reload_linear,
+        output,
+        linear_counter_start,
+        control,
+        timer_start,
+    );
+}
+
 
 impl Triangle {
 
@@ -31,7 +48,7 @@ impl Triangle {
     ];
 
     pub fn tick(&mut self) {
-        if !self.enable || self.length_counter == 0 || self.linear_counter == 0 {
+        if !self.enable || !self.length.active() || self.linear_counter == 0 {
             return;
         }
         
@@ -49,9 +66,7 @@ impl Triangle {
     }
 
     pub fn tick_half_frame(&mut self) {
-        if !self.counter_halt && self.length_counter > 0 {
-            self.length_counter -= 1;
-        }
+        self.length.clock();
     }
 
     pub fn tick_quarter_frame(&mut self) {
@@ -70,7 +85,7 @@ impl Triangle {
     pub fn set_enable(&mut self, enable: bool) {
         self.enable = enable;
         if !enable {
-            self.length_counter = 0;
+            self.length.clear();
         }
     }
     
@@ -78,7 +93,7 @@ impl Triangle {
     pub fn write_r0(&mut self, data: u8) {
         self.linear_counter_start = data & 0x7F;
         self.control = bit_bool!(data, 7);
-        self.counter_halt = bit_bool!(data, 7);
+        self.length.set_halt(bit_bool!(data, 7));
     }
 
     pub fn write_r2(&mut self, data: u8) {
@@ -88,8 +103,7 @@ impl Triangle {
     pub fn write_r3(&mut self, data: u8) {
         self.timer_start = self.timer_start & 0x00FF | (((data & 0x7) as u16) << 8);
         if self.enable {
-            let length_idx = data >> 3;
-            self.length_counter = super::LENGTH_VALUES[length_idx as usize];
+            self.length.reload(data >> 3);
         };
         self.reload_linear = true;
     }
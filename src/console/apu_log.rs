@@ -0,0 +1,192 @@
+use std::fmt::Write as _;
+use std::path::Path;
+
+use eyre::{Result, WrapErr};
+
+// VGM 1.61 with the NES APU clock at 0x84, commands start after the 256 byte header
+const VGM_VERSION: u32 = 0x161;
+const VGM_HEADER_LEN: usize = 0x100;
+const VGM_SAMPLE_RATE: u64 = 44_100;
+const VGM_APU_WRITE: u8 = 0xB4;
+const VGM_WAIT: u8 = 0x61;
+const VGM_WAIT_NTSC_FRAME: u8 = 0x62;
+const VGM_WAIT_PAL_FRAME: u8 = 0x63;
+const VGM_WAIT_SHORT: u8 = 0x70;
+const VGM_DATA_BLOCK: u8 = 0x67;
+// Data block type that writes to the APU's view of $C000-$FFFF, for DMC samples
+const VGM_DPCM_RAM: u8 = 0xC2;
+const VGM_END: u8 = 0x66;
+
+const DPCM_START: u16 = 0xC000;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct RegisterWrite {
+    cycle: u64,
+    addr: u16,
+    data: u8,
+}
+
+/// Every write to the APU registers with the CPU cycle it happened on, plus the DMC
+/// sample bytes fetched, for exporting game audio to chiptune tools
+pub struct ApuLog {
+    start_cycle: u64,
+    writes: Vec<RegisterWrite>,
+    /// Sample bytes by address from $C000, with bank switching the last fetch wins
+    dpcm: Vec<Option<u8>>,
+}
+
+impl ApuLog {
+    pub fn new(start_cycle: u64) -> Self {
+        Self {
+            start_cycle,
+            writes: Vec::new(),
+            dpcm: vec![None; 0x4000],
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, data: u8, cycle: u64) {
+        self.writes.push(RegisterWrite { cycle, addr, data });
+    }
+
+    pub fn sample_fetched(&mut self, addr: u16, data: u8) {
+        if let Some(byte) = addr
+            .checked_sub(DPCM_START)
+            .and_then(|offset| self.dpcm.get_mut(offset as usize))
+        {
+            *byte = Some(data);
+        }
+    }
+
+    fn samples_at(&self, cycle: u64) -> u64 {
+        (cycle - self.start_cycle) * VGM_SAMPLE_RATE / crate::CPU_FREQ as u64
+    }
+
+    /// VGM file playing the log up to `end_cycle`, with the DMC samples loaded first
+    pub fn to_vgm(&self, end_cycle: u64) -> Vec<u8> {
+        let mut vgm = vec![0; VGM_HEADER_LEN];
+        self.push_dpcm_blocks(&mut vgm);
+        let mut position = 0;
+        for write in &self.writes {
+            let target = self.samples_at(write.cycle);
+            push_wait(&mut vgm, target - position);
+            position = target;
+            vgm.extend([VGM_APU_WRITE, (write.addr - 0x4000) as u8, write.data]);
+        }
+        let total_samples = self.samples_at(end_cycle.max(self.start_cycle));
+        push_wait(&mut vgm, total_samples.saturating_sub(position));
+        vgm.push(VGM_END);
+
+        let eof_offset = vgm.len() as u32 - 4;
+        vgm[..4].copy_from_slice(b"Vgm ");
+        vgm[0x04..0x08].copy_from_slice(&eof_offset.to_le_bytes());
+        vgm[0x08..0x0C].copy_from_slice(&VGM_VERSION.to_le_bytes());
+        vgm[0x18..0x1C].copy_from_slice(&(total_samples as u32).to_le_bytes());
+        vgm[0x34..0x38].copy_from_slice(&(VGM_HEADER_LEN as u32 - 0x34).to_le_bytes());
+        vgm[0x84..0x88].copy_from_slice(&(crate::CPU_FREQ as u32).to_le_bytes());
+        vgm
+    }
+
+    // One block per run of fetched bytes
+    fn push_dpcm_blocks(&self, vgm: &mut Vec<u8>) {
+        let mut offset = 0;
+        while offset < self.dpcm.len() {
+            let run: Vec<u8> = self.dpcm[offset..].iter().map_while(|b| *b).collect();
+            if run.is_empty() {
+                offset += 1;
+                continue;
+            }
+            let addr = DPCM_START + offset as u16;
+            vgm.extend([VGM_DATA_BLOCK, VGM_END, VGM_DPCM_RAM]);
+            vgm.extend((run.len() as u32 + 2).to_le_bytes());
+            vgm.extend(addr.to_le_bytes());
+            vgm.extend(&run);
+            offset += run.len();
+        }
+    }
+
+    /// One `<cpu cycle> <address> <value>` line per write, cycles counted from the start
+    pub fn to_text(&self) -> String {
+        let mut text = String::from("# cycle addr value\n");
+        for write in &self.writes {
+            let _ = writeln!(
+                text,
+                "{} {:04X} {:02X}",
+                write.cycle - self.start_cycle,
+                write.addr,
+                write.data
+            );
+        }
+        text
+    }
+
+    /// Writes a VGM file, or the text log if the file name doesn't end in .vgm
+    pub fn export(&self, file: &str, end_cycle: u64) -> Result<()> {
+        let is_vgm = Path::new(file)
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("vgm"));
+        let data = if is_vgm {
+            self.to_vgm(end_cycle)
+        } else {
+            self.to_text().into_bytes()
+        };
+        std::fs::write(file, data).wrap_err_with(|| format!("Failed to write APU log {file}"))
+    }
+}
+
+fn push_wait(vgm: &mut Vec<u8>, mut samples: u64) {
+    while samples > 0 {
+        let step = samples.min(u16::MAX as u64);
+        match step {
+            735 => vgm.push(VGM_WAIT_NTSC_FRAME),
+            882 => vgm.push(VGM_WAIT_PAL_FRAME),
+            1..=16 => vgm.push(VGM_WAIT_SHORT + step as u8 - 1),
+            _ => {
+                vgm.push(VGM_WAIT);
+                vgm.extend((step as u16).to_le_bytes());
+            }
+        }
+        samples -= step;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_push_wait() {
+        let mut vgm = Vec::new();
+        for samples in [0, 5, 735, 70_000] {
+            push_wait(&mut vgm, samples);
+        }
+        assert_eq!(vgm, [0x74, 0x62, 0x61, 0xFF, 0xFF, 0x61, 0x71, 0x11]);
+    }
+
+    #[test]
+    fn test_vgm() {
+        let mut log = ApuLog::new(1000);
+        log.write(0x4015, 0x1F, 1000);
+        // 735 samples, one NTSC frame
+        log.write(0x4000, 0xBF, 1000 + 29_781);
+        log.sample_fetched(0xC040, 0xAA);
+        log.sample_fetched(0xC041, 0x55);
+        log.sample_fetched(0x8000, 0x00);
+
+        let vgm = log.to_vgm(1000 + crate::CPU_FREQ as u64);
+        assert_eq!(&vgm[..4], b"Vgm ");
+        assert_eq!(vgm[0x04..0x08], (vgm.len() as u32 - 4).to_le_bytes());
+        assert_eq!(vgm[0x18..0x1C], 44_100u32.to_le_bytes());
+        assert_eq!(
+            vgm[VGM_HEADER_LEN..],
+            [
+                0x67, 0x66, 0xC2, 4, 0, 0, 0, 0x40, 0xC0, 0xAA, 0x55, // DMC samples
+                0xB4, 0x15, 0x1F, 0x62, 0xB4, 0x00, 0xBF, // register writes
+                0x61, 0x65, 0xA9, 0x66, // wait for the remaining 43365 samples
+            ]
+        );
+        assert_eq!(
+            log.to_text(),
+            "# cycle addr value\n0 4015 1F\n29781 4000 BF\n"
+        );
+    }
+}
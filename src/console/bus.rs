@@ -1,83 +1,794 @@
-use crate::emulator::Emulator;
-
-use super::{apu::Apu, cartridge::Cartridge, controller::Controller, ppu::Ppu};
-use eyre::Result;
+use super::cartridge::mappers::MapperEvent;
+use super::{
+    access_trace::AccessTrace,
+    apu::Apu,
+    apu_log::ApuLog,
+    cartridge::Cartridge,
+    controller::Controller,
+    coverage::Coverage,
+    debug::{
+        find_return_addrs, CpuRegs, DebugSnapshot, DebugWrite, Fnv1a, FrameStats, InterruptCause,
+        InterruptLog, IrqEdge, IrqHistory, PpuRegs, StateHashes, TakenInterrupt,
+    },
+    events::{ConsoleEvent, EventBus},
+    ppu::Ppu,
+    state::{self, SaveState, Snapshot, StateRequest},
+    time::EmulatedTime,
+    video::Video,
+    Frontend,
+};
+use crate::macros::state_fields;
+use eyre::{eyre, Result, WrapErr};
+use rand::{rngs::StdRng, Rng, SeedableRng};
 
+#[allow(clippy::struct_excessive_bools)]
 pub struct Bus<'a> {
     ram: [u8; 0x800],
+    /// 64 kB of RAM replacing everything else, see `Bus::flat`
+    flat_memory: Option<Vec<u8>>,
     ppu: Ppu,
     apu: Apu,
-    cycles: usize,
+    time: EmulatedTime,
     controller: Controller,
     cartridge: Cartridge,
+    /// Last value on the CPU data bus, read back from addresses nothing drives
+    last_data: u8,
+    pub coverage: Option<Coverage>,
+    pub apu_log: Option<ApuLog>,
+    pub video: Video,
+    pub access_trace: Option<AccessTrace>,
+    /// Kept up to date by the CPU for the debugger
+    pub cpu_regs: CpuRegs,
+    /// Registers the debugger set, loaded by the CPU before its next instruction
+    pub pending_cpu_regs: Option<CpuRegs>,
+    /// Cycles and open bus value of each tick, kept while the CPU records an idle loop
+    pub tick_log: Option<Vec<(u8, u8)>>,
+    /// Save state the frontend asked for, handled by the CPU before its next instruction
+    pub state_request: Option<StateRequest>,
+    frame_stats: FrameStats,
+    irq_history: IrqHistory,
+    interrupt_log: InterruptLog,
+    /// Set when the frontend asks for the CPU trace ring to be written out
+    pub trace_dump_requested: bool,
+    /// Emulate DMC DMA clocking the controller an extra time when it lands on a read
+    pub dpcm_conflicts: bool,
+    /// Running a frame without output, see `start_silent_frame`
+    silent: bool,
+    /// The silent frame ended, the CPU stops at the end of the instruction
+    silent_frame_done: bool,
+    /// The cartridge's CHR ROM write was reported, see `ConsoleEvent::ChrRomWrite`
+    chr_rom_write_reported: bool,
+    accuracy: Accuracy,
+    /// NMI line rose too late in the last instruction for the CPU to poll it
+    nmi_late: bool,
+    /// PPU dots the CPU has run ahead by, see `catch_up_ppu`
+    ppu_lag: u32,
+    /// Dots the PPU may lag behind before the CPU could notice
+    ppu_quiet: u32,
+    /// Subscribers besides the frontend
+    pub events: EventBus<'a>,
 
-    emulator: &'a mut Emulator,
+    frontend: &'a mut dyn Frontend,
 }
 
 const RAM_START: u16 = 0x0000;
 const RAM_END: u16 = 0x1FFF;
 const PPU_REGISTERS_START: u16 = 0x2000;
 const PPU_REGISTERS_END: u16 = 0x3FFF;
+const APU_CHANNELS_START: u16 = 0x4000;
+const APU_CHANNELS_END: u16 = 0x4013;
 const OAM_DMA_ADDR: u16 = 0x4014;
+const APU_STATUS_ADDR: u16 = 0x4015;
+// $4016 writes strobe both controllers, reads return controller 1
 const CONTROLLER1_ADDR: u16 = 0x4016;
+// $4017 reads return controller 2, writes go to the APU frame counter
 const CONTROLLER2_ADDR: u16 = 0x4017;
+const APU_FRAME_COUNTER_ADDR: u16 = 0x4017;
+
+// CPU internal registers, disabled while OAM DMA drives the bus
+const DMA_INVISIBLE_END: u16 = 0x401F;
+
+// CPU cycles a DMC sample fetch halts the CPU for
+const DMC_DMA_CYCLES: u8 = 4;
 
 const RAM_ADDR_MIRROR_MASK: u16 = 0x07FF;
 
+// Whole CPU address space
+const FLAT_MEMORY_LEN: usize = 0x10000;
+
+// PPU dots per CPU cycle, and so the number of distinct power-on alignments
+const DOTS_PER_CPU_CYCLE: u8 = 3;
+
+/// Phase of the PPU relative to the CPU at power-on. Real consoles start up in one
+/// of several alignments, which some timing sensitive test ROMs can tell apart.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Alignment {
+    /// PPU starts this many dots ahead of the CPU
+    Fixed(u8),
+    /// Picked at random, from the given seed or a fresh one
+    Random(Option<u64>),
+}
+
+impl Alignment {
+    /// Parses `0`-`2`, `random` or `random:<seed>`
+    pub fn parse(spec: &str) -> Result<Self> {
+        if spec == "random" {
+            return Ok(Self::Random(None));
+        }
+        if let Some(seed) = spec.strip_prefix("random:") {
+            let seed = seed
+                .parse()
+                .wrap_err_with(|| format!("Invalid alignment seed '{seed}'"))?;
+            return Ok(Self::Random(Some(seed)));
+        }
+        match spec.parse() {
+            Ok(dots) if dots < DOTS_PER_CPU_CYCLE => Ok(Self::Fixed(dots)),
+            _ => Err(eyre!(
+                "Alignment must be 0-{}, random or random:<seed>, got '{spec}'",
+                DOTS_PER_CPU_CYCLE - 1
+            )),
+        }
+    }
+
+    /// PPU dot offset to start with, logging the seed of random alignments
+    fn dots(self) -> u8 {
+        match self {
+            Self::Fixed(dots) => dots,
+            Self::Random(seed) => {
+                let seed = seed.unwrap_or_else(rand::random);
+                let dots = StdRng::seed_from_u64(seed).gen_range(0..DOTS_PER_CPU_CYCLE);
+                println!("Power-on alignment {dots} (random:{seed})");
+                dots
+            }
+        }
+    }
+}
+
+// Written the way `Alignment::parse` reads it
+impl std::fmt::Display for Alignment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Fixed(dots) => write!(f, "{dots}"),
+            Self::Random(None) => write!(f, "random"),
+            Self::Random(Some(seed)) => write!(f, "random:{seed}"),
+        }
+    }
+}
+
+/// Hardware quirks that can be switched. Most are off by default and needed by some
+/// test ROMs and games.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Accuracy {
+    /// The PPU ignores $2000, $2001, $2005 and $2006 writes for about 29658 CPU
+    /// cycles after power-on, and after a reset until the end of vblank
+    pub ppu_warmup: bool,
+    /// An NMI raised on the last cycle of an instruction, or enabled through $2000
+    /// while the vblank flag is set, is taken one instruction later
+    pub nmi_delay: bool,
+    /// UNROM and CNROM see the bitwise AND of a write and the ROM byte at its address.
+    /// On by default, without NES 2.0 submappers there's no telling whether the
+    /// board avoids them, and games for either board work with them.
+    pub bus_conflicts: bool,
+}
+
+impl Default for Accuracy {
+    fn default() -> Self {
+        Self {
+            ppu_warmup: false,
+            nmi_delay: false,
+            bus_conflicts: true,
+        }
+    }
+}
+
+impl Accuracy {
+    /// Parses a comma separated list of `ppu-warmup`, `nmi-delay` and
+    /// `no-bus-conflicts`, or `all` or `none`
+    pub fn parse(list: &str) -> Result<Self> {
+        let mut accuracy = Self::default();
+        for quirk in list.split(',').map(str::trim) {
+            match quirk {
+                "none" => (),
+                "all" => {
+                    accuracy.ppu_warmup = true;
+                    accuracy.nmi_delay = true;
+                }
+                "ppu-warmup" => accuracy.ppu_warmup = true,
+                "nmi-delay" => accuracy.nmi_delay = true,
+                "no-bus-conflicts" => accuracy.bus_conflicts = false,
+                _ => {
+                    return Err(eyre!(
+                        "Unknown accuracy quirk '{quirk}', expected ppu-warmup, nmi-delay, \
+                         no-bus-conflicts or all"
+                    ))
+                }
+            }
+        }
+        Ok(accuracy)
+    }
+}
+
+// Written the way `Accuracy::parse` reads it
+impl std::fmt::Display for Accuracy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let quirks: Vec<_> = [
+            ("ppu-warmup", self.ppu_warmup),
+            ("nmi-delay", self.nmi_delay),
+            ("no-bus-conflicts", !self.bus_conflicts),
+        ]
+        .into_iter()
+        .filter_map(|(name, on)| on.then_some(name))
+        .collect();
+        if quirks.is_empty() {
+            write!(f, "none")
+        } else {
+            write!(f, "{}", quirks.join(","))
+        }
+    }
+}
+
+// The PPU, APU and mapper get sections of their own, see `save_sections`
+impl Snapshot for Bus<'_> {
+    state_fields!(ram, last_data, time, controller, nmi_late);
+}
+
 impl<'a> Bus<'a> {
-    pub fn new(cartridge: Cartridge, emulator: &'a mut Emulator) -> Self {
+    pub fn new(cartridge: Cartridge, frontend: &'a mut dyn Frontend) -> Self {
+        let mut apu = Apu::new();
+        apu.set_chunk_len(frontend.audio_chunk_len());
+        let mut ppu = Ppu::new();
+        ppu.a12_watched = cartridge.watches_a12();
+        ppu.chr_reads_watched = cartridge.watches_chr_reads();
         Self {
             ram: [0; 0x800],
-            ppu: Ppu::new(),
-            apu: Apu::new(),
+            flat_memory: None,
+            ppu,
+            apu,
             controller: Controller::new(),
-            cycles: 0,
+            time: EmulatedTime::default(),
             cartridge,
-            emulator,
+            last_data: 0,
+            coverage: None,
+            apu_log: None,
+            video: Video::new(),
+            access_trace: None,
+            cpu_regs: CpuRegs::default(),
+            pending_cpu_regs: None,
+            tick_log: None,
+            state_request: None,
+            frame_stats: FrameStats::default(),
+            irq_history: IrqHistory::default(),
+            interrupt_log: InterruptLog::default(),
+            trace_dump_requested: false,
+            dpcm_conflicts: false,
+            silent: false,
+            silent_frame_done: false,
+            chr_rom_write_reported: false,
+            accuracy: Accuracy::default(),
+            nmi_late: false,
+            ppu_lag: 0,
+            ppu_quiet: 0,
+            events: EventBus::default(),
+            frontend,
+        }
+    }
+
+    /// Bus with nothing but 64 kB of RAM, for running CPU test programs. The PPU, APU
+    /// and cartridge are never clocked or accessed.
+    pub fn flat(memory: Vec<u8>, frontend: &'a mut dyn Frontend) -> Result<Self> {
+        if memory.len() != FLAT_MEMORY_LEN {
+            return Err(eyre!(
+                "Flat memory must be 64 kB, got {} bytes",
+                memory.len()
+            ));
+        }
+        let mut bus = Self::new(Cartridge::blank(0)?, frontend);
+        bus.flat_memory = Some(memory);
+        // The caller decides where the program starts instead of the reset vector
+        bus.controller.reset_triggered();
+        Ok(bus)
+    }
+
+    pub fn flat_memory(&self) -> Option<&[u8]> {
+        self.flat_memory.as_deref()
+    }
+
+    /// Replaces the cartridge, keeping RAM and CPU state intact. Battery backed RAM
+    /// carries over too, a reloaded ROM would otherwise autosave over the game's saves.
+    pub fn swap_cartridge(&mut self, mut cartridge: Cartridge) {
+        if cartridge.battery {
+            cartridge.load_prg_ram(&self.cartridge.prg_ram());
+        }
+        self.catch_up_ppu();
+        self.cartridge = cartridge;
+        self.chr_rom_write_reported = false;
+        self.ppu.invalidate_chr();
+        self.ppu.a12_watched = self.cartridge.watches_a12();
+        self.ppu.chr_reads_watched = self.cartridge.watches_chr_reads();
+        self.ppu_quiet = self.ppu.quiet_dots();
+        self.cartridge
+            .set_bus_conflicts(self.accuracy.bus_conflicts);
+        self.frontend.set_region(self.cartridge.region);
+        if self.coverage.is_some() {
+            self.enable_coverage();
+        }
+        self.emit(ConsoleEvent::RomSwapped);
+    }
+
+    /// Adds the sections of everything on the bus to a save state
+    pub fn save_sections(&mut self, state: &mut SaveState) {
+        self.catch_up_ppu();
+        state.put(&state::BUS, self.save_state());
+        state.put(&state::PPU, self.ppu.save_state());
+        state.put(&state::APU, self.apu.save_state());
+        state.put(&state::MAPPER, self.cartridge.mapper.save_state());
+    }
+
+    /// Restores everything on the bus from a save state. A failure can leave it
+    /// partly restored, see `Cpu::restore`.
+    pub fn load_sections(&mut self, state: &SaveState) -> Result<()> {
+        let section = |format: &state::SectionFormat| {
+            let name = String::from_utf8_lossy(&format.tag).trim_end().to_owned();
+            move |e: eyre::Report| eyre!("Failed to load the {name} section: {e}")
+        };
+        self.load_state(&state.require(&state::BUS)?)
+            .map_err(section(&state::BUS))?;
+        self.cartridge
+            .mapper
+            .load_state(&state.require(&state::MAPPER)?)
+            .map_err(section(&state::MAPPER))?;
+        // Patterns may have changed with CHR RAM and banks
+        self.ppu.invalidate_chr();
+        self.ppu
+            .load_state(&state.require(&state::PPU)?)
+            .map_err(section(&state::PPU))?;
+        self.apu
+            .load_state(&state.require(&state::APU)?)
+            .map_err(section(&state::APU))?;
+        self.ppu_lag = 0;
+        self.ppu_quiet = self.ppu.quiet_dots();
+        Ok(())
+    }
+
+    pub fn state_saved(&mut self, state: Vec<u8>) {
+        self.frontend.state_saved(state);
+    }
+
+    pub fn state_loaded(&mut self) {
+        self.emit(ConsoleEvent::StateLoaded);
+    }
+
+    pub fn state_load_failed(&mut self, error: &eyre::Report) {
+        self.frontend.state_load_failed(error);
+    }
+
+    pub fn load_battery_ram(&mut self, data: &[u8]) {
+        self.cartridge.load_prg_ram(data);
+    }
+
+    pub fn save_battery_ram(&mut self) {
+        if self.cartridge.battery {
+            self.frontend.save_battery_ram(&self.cartridge.prg_ram());
+        }
+    }
+
+    fn emit(&mut self, event: ConsoleEvent) {
+        self.frontend.console_event(event);
+        self.events.publish(event);
+    }
+
+    pub fn enable_coverage(&mut self) {
+        self.coverage = Some(Coverage::new(self.cartridge.prg_rom_len()));
+    }
+
+    /// Marks the bytes of an instruction as executed for coverage tracking
+    pub fn mark_executed(&mut self, addr: u16, bytes: u8) {
+        self.frame_stats.executed(addr);
+        if let Some(coverage) = self.coverage.as_mut() {
+            for i in 0..bytes as u16 {
+                if let Some(offset) = self.cartridge.prg_rom_offset(addr.wrapping_add(i)) {
+                    coverage.mark_executed(offset);
+                }
+            }
         }
     }
 
     pub fn tick(&mut self, cycles: u8) -> Result<()> {
-        self.cycles += cycles as usize;
+        if let Some(log) = self.tick_log.as_mut() {
+            log.push((cycles, self.last_data));
+        }
+        // A sample fetch the DMC asked for during the last instruction halts the CPU
+        let cycles = match self.apu.dmc_dma_request() {
+            Some(addr) => {
+                let data = self.read(addr);
+                if let Some(log) = self.apu_log.as_mut() {
+                    log.sample_fetched(addr, data);
+                }
+                self.apu.dmc_dma_done(data);
+                cycles + DMC_DMA_CYCLES
+            }
+            None => cycles,
+        };
+        self.time.cpu_cycles += cycles as u64;
+        if self.flat_memory.is_some() {
+            return Ok(());
+        }
         for _ in 0..cycles {
-            if self.apu.tick(&mut self.cartridge) {
-                self.emulator.handle_audio(&self.apu)?;
+            self.cartridge.trigger_event(MapperEvent::CpuTick);
+            self.apu.expansion = self.cartridge.audio_output();
+            if self.apu.tick() {
+                self.frontend.handle_audio(&self.apu)?;
             }
         }
-        for _ in 0..3 * cycles {
-            if self.ppu.tick(&mut self.cartridge) {
-                self.emulator.handle_io(&self.ppu, &mut self.controller);
+        // The PPU only runs once it's about to start a scanline or move the NMI line,
+        // register and cartridge accesses catch it up in between
+        self.ppu_lag += 3 * cycles as u32;
+        if self.ppu_lag > self.ppu_quiet {
+            self.run_ppu()?;
+        }
+        // CPU cycle driven counters are only seen once per instruction
+        self.sample_irq();
+        Ok(())
+    }
+
+    /// Ticks as recorded in `tick_log`, with the open bus value the CPU left
+    pub fn replay_tick(&mut self, cycles: u8, open_bus: u8) -> Result<()> {
+        self.last_data = open_bus;
+        self.tick(cycles)
+    }
+
+    /// Whether a loop waiting for NMI may be replayed without executing it: NMI is on,
+    /// rendering is off and nothing is watching individual accesses
+    pub fn idle_skip_allowed(&self) -> bool {
+        self.flat_memory.is_none()
+            && self.coverage.is_none()
+            && self.access_trace.is_none()
+            && self.ppu.waiting_for_nmi()
+    }
+
+    /// Runs the PPU up to the CPU, handling scanline starts and the end of the frame
+    fn run_ppu(&mut self) -> Result<()> {
+        let dots = std::mem::take(&mut self.ppu_lag);
+        let last_cycle_start = dots.saturating_sub(3);
+        let mut nmi_before_last_cycle = self.ppu.nmi_up;
+        let mut dot = 0;
+        while dot < dots {
+            if dot == last_cycle_start {
+                nmi_before_last_cycle = self.ppu.nmi_up;
+            }
+            // Lines without rendering are run in bulk up to their last dot
+            let limit = if dot < last_cycle_start {
+                last_cycle_start
+            } else {
+                dots
+            };
+            let skipped = self.ppu.skip_idle_dots(limit - dot);
+            if skipped > 0 {
+                dot += skipped;
+                continue;
+            }
+            dot += 1;
+            let frame_done = self.ppu.tick(&mut self.cartridge);
+            if let Some((scanline, rendering)) = self.ppu.take_scanline_start() {
+                self.cartridge.trigger_event(MapperEvent::ScanlineTick {
+                    scanline,
+                    rendering,
+                });
+                self.sample_irq();
+                self.frontend.scanline_started(scanline, rendering);
+            }
+            if frame_done && self.silent {
+                self.time.frames += 1;
+                self.silent_frame_done = true;
+                self.emit(ConsoleEvent::VblankStarted);
+                self.emit(ConsoleEvent::FrameCompleted(self.time));
+            } else if frame_done {
+                self.time.frames += 1;
+                self.emit(ConsoleEvent::VblankStarted);
+                if self.frontend.wants_debug() {
+                    let snapshot = self.debug_snapshot();
+                    self.frontend.debug_snapshot(&snapshot);
+                }
+                self.frame_stats = FrameStats::default();
+                let mut frame = self.video.convert(&self.ppu.frame);
+                frame.sources = self.ppu.pixel_sources();
+                self.frontend
+                    .handle_io(&frame, &self.apu, &mut self.controller);
+                self.ppu
+                    .set_pixel_sources(self.frontend.wants_pixel_sources());
+                self.apu.synthesize = self.frontend.wants_audio();
+                self.trace_dump_requested |= self.frontend.take_trace_dump_request();
+                for write in self.frontend.take_debug_writes() {
+                    self.apply_debug_write(write)?;
+                }
+                if self.frontend.battery_save_due() {
+                    self.save_battery_ram();
+                }
+                if let Some(palette) = self.frontend.take_palette() {
+                    self.video.set_palette(palette);
+                }
+                self.state_request = self.frontend.take_state_request();
+                if let Some(rom) = self.frontend.take_reloaded_rom() {
+                    match Cartridge::load(&rom, self.cartridge.fallback_from.is_some()) {
+                        Ok(cartridge) => self.swap_cartridge(cartridge),
+                        Err(e) => self.frontend.reload_failed(&e),
+                    }
+                }
+                self.emit(ConsoleEvent::FrameCompleted(self.time));
             }
         }
+        self.nmi_late |= self.accuracy.nmi_delay && !nmi_before_last_cycle && self.ppu.nmi_up;
+        self.ppu_quiet = self.ppu.quiet_dots();
         Ok(())
     }
 
+    /// Runs the dots the PPU lags behind the CPU. They are all quiet, so no scanline
+    /// or frame ends on the way.
+    fn catch_up_ppu(&mut self) {
+        let mut dots = std::mem::take(&mut self.ppu_lag);
+        while dots > 0 {
+            match self.ppu.skip_idle_dots(dots) {
+                0 => {
+                    self.ppu.tick(&mut self.cartridge);
+                    dots -= 1;
+                }
+                skipped => dots -= skipped,
+            }
+        }
+        self.ppu_quiet = self.ppu.quiet_dots();
+    }
+
+    // Scanline counters are sampled when they are clocked, register writes right after
+    fn sample_irq(&mut self) {
+        if self.cartridge.irq_active() != self.irq_history.line() {
+            // Edges are logged with the PPU position
+            self.catch_up_ppu();
+        }
+        let (scanline, dot) = self.ppu.position();
+        let time = self.time;
+        self.irq_history
+            .sample(self.cartridge.irq_active(), |asserted| IrqEdge {
+                asserted,
+                frame: time.frames,
+                scanline,
+                dot,
+                cpu_cycle: time.cpu_cycles,
+            });
+    }
+
+    pub fn ppu_regs(&self) -> PpuRegs {
+        self.ppu.regs()
+    }
+
+    pub const fn apu_registers(&self) -> [u8; Apu::REGISTERS] {
+        self.apu.registers()
+    }
+
+    pub fn apply_debug_write(&mut self, write: DebugWrite) -> Result<()> {
+        match write {
+            DebugWrite::CpuRegs(regs) => self.pending_cpu_regs = Some(regs),
+            DebugWrite::PpuCtrl(data) => {
+                self.catch_up_ppu();
+                self.ppu.set_ctrl(data);
+                self.ppu_quiet = self.ppu.quiet_dots();
+            }
+            DebugWrite::PpuScroll { x, y } => {
+                self.catch_up_ppu();
+                self.ppu.set_scroll(x, y);
+            }
+            DebugWrite::Memory { addr, data } => self.write(addr, data)?,
+        }
+        Ok(())
+    }
+
+    fn debug_snapshot(&mut self) -> DebugSnapshot {
+        let mut stack = [0; 256];
+        stack.copy_from_slice(&self.ram[0x100..0x200]);
+        let return_addrs = find_return_addrs(&stack, self.cpu_regs.sp, |addr| self.peek(addr));
+        let empty_prg_windows = (0x8000..=0xE000)
+            .step_by(0x2000)
+            .filter(|&start: &u16| {
+                let first = self.peek(start);
+                (start..=start + 0x1FFF).all(|addr| self.peek(addr) == first)
+            })
+            .collect();
+        DebugSnapshot {
+            regs: self.cpu_regs,
+            stack,
+            return_addrs,
+            ppu: self.ppu.debug_state(),
+            ppu_regs: self.ppu.regs(),
+            apu_regs: self.apu.registers(),
+            frame_stats: self.frame_stats,
+            irq_edges: self.irq_history.edges(),
+            interrupts: self.interrupt_log.taken(),
+            empty_prg_windows,
+            hashes: self.state_hashes(),
+            tile_map: self.ppu.tile_map(&mut self.cartridge),
+            ram: self.ram,
+            prg_ram: (0x6000..=0x7FFF)
+                .filter_map(|addr| self.peek(addr))
+                .collect(),
+        }
+    }
+
+    fn state_hashes(&self) -> StateHashes {
+        let regs = self.cpu_regs;
+        let mut cpu = Fnv1a::default();
+        cpu.write(&[regs.a, regs.x, regs.y, regs.p, regs.sp]);
+        cpu.write(&regs.pc.to_le_bytes());
+        cpu.write(&self.time.cpu_cycles.to_le_bytes());
+        StateHashes {
+            cpu: cpu.finish(),
+            ram: Fnv1a::hash(&self.ram),
+            ppu: self.ppu.state_hash(),
+            apu: self.apu.state_hash(),
+        }
+    }
+
+    /// Reads RAM, PRG RAM or PRG ROM without side effects, registers return `None`
+    pub fn peek(&mut self, addr: u16) -> Option<u8> {
+        if let Some(memory) = &self.flat_memory {
+            return Some(memory[addr as usize]);
+        }
+        match addr {
+            RAM_START..=RAM_END => Some(self.ram[(addr & RAM_ADDR_MIRROR_MASK) as usize]),
+            0x6000.. if self.cartridge.cpu_read_driven(addr) => Some(self.cartridge.read_cpu(addr)),
+            _ => None,
+        }
+    }
+
+    /// Runs the PPU ahead of the CPU to the given power-on alignment
+    pub fn set_alignment(&mut self, alignment: Alignment) {
+        for _ in 0..alignment.dots() {
+            self.ppu.tick(&mut self.cartridge);
+        }
+    }
+
+    /// Must be called before running, the PPU warm-up starts at power-on
+    pub fn set_accuracy(&mut self, accuracy: Accuracy) {
+        self.accuracy = accuracy;
+        self.ppu.set_warmup(accuracy.ppu_warmup);
+        self.cartridge.set_bus_conflicts(accuracy.bus_conflicts);
+    }
+
+    pub const fn time(&self) -> EmulatedTime {
+        self.time
+    }
+
     pub fn nmi_active(&mut self) -> bool {
         self.ppu.nmi_up
     }
 
+    /// True once if the NMI line rose after the CPU polled for interrupts
+    pub fn take_nmi_late(&mut self) -> bool {
+        std::mem::take(&mut self.nmi_late)
+    }
+
+    /// Sources holding the IRQ line, as an interrupt cause for the log
+    pub fn irq_cause(&mut self) -> InterruptCause {
+        InterruptCause::Irq {
+            apu_frame: self.apu.frame_irq(),
+            dmc: self.apu.dmc_irq(),
+            mapper: self.cartridge.irq_active(),
+        }
+    }
+
+    /// Logs an interrupt taken by the CPU at the current position
+    pub fn log_interrupt(&mut self, cause: InterruptCause) {
+        self.catch_up_ppu();
+        let (scanline, dot) = self.ppu.position();
+        self.interrupt_log.push(TakenInterrupt {
+            cause,
+            frame: self.time.frames,
+            scanline,
+            dot,
+            cpu_cycle: self.time.cpu_cycles,
+        });
+    }
+
     pub fn irq_active(&mut self) -> bool {
         self.cartridge.irq_active() | self.apu.irq_active()
     }
 
+    /// Presses the console's reset button, the CPU resets before its next instruction
+    pub fn press_reset(&mut self) {
+        self.controller.reset();
+    }
+
     pub fn reset_triggered(&mut self) -> bool {
         self.controller.reset_triggered()
     }
 
+    pub fn cpu_jammed(&mut self, addr: u16) {
+        self.frontend.cpu_jammed(addr);
+    }
+
+    pub fn quit_requested(&self) -> bool {
+        self.frontend.quit_requested()
+    }
+
+    /// Runs without drawing pixels or mixing audio until the frame ends. The frontend
+    /// only gets input polls and console events meanwhile.
+    pub fn start_silent_frame(&mut self) {
+        self.silent = true;
+        self.silent_frame_done = false;
+        self.ppu.output = false;
+        self.apu.silent = true;
+    }
+
+    /// True once the silent frame has ended
+    pub const fn silent_frame_done(&self) -> bool {
+        self.silent_frame_done
+    }
+
+    pub fn end_silent_frame(&mut self) {
+        self.silent = false;
+        self.silent_frame_done = false;
+        self.ppu.output = true;
+        self.apu.silent = false;
+    }
+
     pub fn reset(&mut self) {
-        self.ppu.reset();
+        self.catch_up_ppu();
+        self.ppu.reset(self.accuracy.ppu_warmup);
+        self.ppu_quiet = self.ppu.quiet_dots();
         self.apu.reset();
+        self.emit(ConsoleEvent::Reset);
     }
 
     pub fn read(&mut self, addr: u16) -> u8 {
+        let data = self.read_mapped(addr);
+        self.last_data = data;
+        self.trace_access(addr, data, false);
+        data
+    }
+
+    fn trace_access(&mut self, addr: u16, data: u8, write: bool) {
+        if self.access_trace.is_some() {
+            self.catch_up_ppu();
+        }
+        if let Some(trace) = self.access_trace.as_ref() {
+            let (scanline, dot) = self.ppu.position();
+            trace.log(addr, data, write, scanline, dot, self.time.cpu_cycles);
+        }
+    }
+
+    fn read_mapped(&mut self, addr: u16) -> u8 {
+        if let Some(memory) = &self.flat_memory {
+            return memory[addr as usize];
+        }
         match addr {
             RAM_START..=RAM_END => self.ram[(addr & RAM_ADDR_MIRROR_MASK) as usize],
-            PPU_REGISTERS_START..=PPU_REGISTERS_END => self.ppu.read(addr, &mut self.cartridge),
-            CONTROLLER1_ADDR => self.controller.read(),
-            CONTROLLER2_ADDR => 0,
-            0x4000..=0x4017 => self.apu.read(addr),
+            PPU_REGISTERS_START..=PPU_REGISTERS_END => {
+                if addr & 0x7 == 0x2 {
+                    self.frame_stats.status_reads += 1;
+                }
+                self.catch_up_ppu();
+                let data = self.ppu.read(addr, &mut self.cartridge);
+                self.ppu_quiet = self.ppu.quiet_dots();
+                data
+            }
+            APU_STATUS_ADDR => self.apu.read(addr),
+            CONTROLLER1_ADDR => {
+                // The DMA halt makes the CPU repeat the read, so a bit gets skipped
+                if self.dpcm_conflicts && self.apu.dmc_dma_request().is_some() {
+                    self.controller.read();
+                }
+                self.controller.read()
+            }
+            // Write-only APU and DMA registers, and no controller 2 attached
+            APU_CHANNELS_START..=OAM_DMA_ADDR | CONTROLLER2_ADDR => 0,
 
-            0x4020.. => self.cartridge.read_cpu(addr),
+            0x4020.. if !self.cartridge.cpu_read_driven(addr) => self.last_data,
+            0x4020.. => {
+                if let Some(coverage) = self.coverage.as_mut() {
+                    if let Some(offset) = self.cartridge.prg_rom_offset(addr) {
+                        coverage.mark_read(offset);
+                    }
+                }
+                self.cartridge.read_cpu(addr)
+            }
 
             _ => {
                 println!("Read from unknown address 0x{:X}", addr);
@@ -93,31 +804,454 @@ impl<'a> Bus<'a> {
     }
 
     pub fn write(&mut self, addr: u16, data: u8) -> Result<()> {
+        self.last_data = data;
+        self.trace_access(addr, data, true);
+        if let Some(memory) = &mut self.flat_memory {
+            memory[addr as usize] = data;
+            return Ok(());
+        }
         match addr {
             RAM_START..=RAM_END => self.ram[(addr & RAM_ADDR_MIRROR_MASK) as usize] = data,
             PPU_REGISTERS_START..=PPU_REGISTERS_END => {
+                self.catch_up_ppu();
+                let nmi_was_up = self.ppu.nmi_output();
                 self.ppu.write(addr, data, &mut self.cartridge);
+                self.ppu_quiet = self.ppu.quiet_dots();
+                if let (Some(chr_addr), false) =
+                    (self.cartridge.chr_rom_write, self.chr_rom_write_reported)
+                {
+                    self.chr_rom_write_reported = true;
+                    self.emit(ConsoleEvent::ChrRomWrite(chr_addr));
+                }
+                self.nmi_late |= self.accuracy.nmi_delay && !nmi_was_up && self.ppu.nmi_output();
             }
 
             OAM_DMA_ADDR => self.oam_dma(data)?,
-            CONTROLLER1_ADDR => self.controller.write(data),
-            0x4000..=0x4017 => self.apu.write(addr, data),
+            CONTROLLER1_ADDR => {
+                // Sample host input right when the game latches the controller
+                if data & 0x1 != 0 {
+                    self.frontend.poll_input(&mut self.controller);
+                }
+                self.controller.write(data);
+            }
+            APU_CHANNELS_START..=APU_CHANNELS_END | APU_STATUS_ADDR | APU_FRAME_COUNTER_ADDR => {
+                if let Some(log) = self.apu_log.as_mut() {
+                    log.write(addr, data, self.time.cpu_cycles);
+                }
+                self.apu.write(addr, data);
+            }
 
-            0x4020.. => self.cartridge.write_cpu(addr, data),
+            0x4020.. => {
+                if let Some(coverage) = self.coverage.as_mut() {
+                    if let Some(offset) = self.cartridge.prg_rom_offset(addr) {
+                        coverage.mark_written(offset);
+                    }
+                }
+                // Bank and mirroring changes must not reach dots drawn before the write
+                self.catch_up_ppu();
+                self.cartridge.write_cpu(addr, data);
+                self.sample_irq();
+            }
 
             _ => println!("Write to unknown address 0x{:X}", addr),
         }
         Ok(())
     }
 
+    /// Copies a page to OAM through $2004, starting at the current OAM address and
+    /// wrapping around. Reads go through the bus, so a page in $2000-$3FFF reads the
+    /// PPU registers with their side effects.
     fn oam_dma(&mut self, page: u8) -> Result<()> {
-        // println!("Performing OAM DMA to address {:x}", self.ppu.oam_addr);
+        // One cycle to halt the CPU, and another to align to a read cycle
+        let align_cycles = 1 + (self.time.cpu_cycles % 2) as u8;
+        self.tick(align_cycles)?;
+
         let start_addr = (page as u16) << 8;
+        // The data bus still holds the page number written to $4014
+        let mut data = page;
         for i in 0..256 {
-            let oam_data = self.read(start_addr + i);
-            self.write(0x2004, oam_data)?;
+            data = self.dma_read(start_addr + i, data);
+            self.write(0x2004, data)?;
             self.tick(2)?;
         }
-        self.tick(1)
+        Ok(())
+    }
+
+    // DMA doesn't see the CPU's internal registers, reading them leaves the bus floating
+    fn dma_read(&mut self, addr: u16, open_bus: u8) -> u8 {
+        match addr {
+            APU_CHANNELS_START..=DMA_INVISIBLE_END => open_bus,
+            _ => self.read(addr),
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod test {
+    use super::*;
+    use crate::console::apu::Apu;
+    use crate::console::cartridge::ines_image;
+    use crate::console::controller::Button;
+    use crate::console::events::EventListener;
+    use crate::console::video::Frame;
+
+    struct NullFrontend;
+
+    impl Frontend for NullFrontend {
+        fn handle_io(&mut self, _frame: &Frame, _apu: &Apu, _controller: &mut Controller) {}
+
+        fn handle_audio(&mut self, _apu: &Apu) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_4016_write_strobes_controller() {
+        let mut frontend = NullFrontend;
+        let mut bus = Bus::new(Cartridge::blank(0).unwrap(), &mut frontend);
+        bus.controller.set_button_state(Button::A, true);
+        bus.write(CONTROLLER1_ADDR, 1).unwrap();
+        bus.write(CONTROLLER1_ADDR, 0).unwrap();
+        bus.controller.set_button_state(Button::A, false);
+
+        assert_eq!(bus.read(CONTROLLER1_ADDR), 1);
+        assert_eq!(bus.read(CONTROLLER1_ADDR), 0);
+    }
+
+    #[test]
+    fn test_4017_write_goes_to_frame_counter_only() {
+        let mut frontend = NullFrontend;
+        let mut bus = Bus::new(Cartridge::blank(0).unwrap(), &mut frontend);
+        bus.write(CONTROLLER1_ADDR, 1).unwrap();
+        // Frame IRQ inhibit, must not release the controller strobe
+        bus.write(APU_FRAME_COUNTER_ADDR, 0x40).unwrap();
+
+        bus.controller.set_button_state(Button::A, true);
+        assert_eq!(bus.read(CONTROLLER1_ADDR), 1);
+
+        for _ in 0..10_000 {
+            bus.tick(3).unwrap();
+        }
+        assert!(!bus.irq_active());
+    }
+
+    fn read_oam(bus: &mut Bus, addr: u8) -> u8 {
+        bus.write(0x2003, addr).unwrap();
+        bus.read(0x2004)
+    }
+
+    #[test]
+    fn test_oam_dma_wraps_from_oam_addr() {
+        let mut frontend = NullFrontend;
+        let mut bus = Bus::new(Cartridge::blank(0).unwrap(), &mut frontend);
+        for i in 0..=255 {
+            bus.write(0x0200 + i as u16, i).unwrap();
+        }
+        bus.write(0x2003, 0x10).unwrap();
+        let cycles = bus.time.cpu_cycles;
+        bus.write(OAM_DMA_ADDR, 0x02).unwrap();
+        assert!(matches!(bus.time.cpu_cycles - cycles, 513 | 514));
+
+        assert_eq!(read_oam(&mut bus, 0x10), 0x00);
+        assert_eq!(read_oam(&mut bus, 0xFF), 0xEF);
+        assert_eq!(read_oam(&mut bus, 0x0F), 0xFF);
+    }
+
+    #[test]
+    fn test_oam_dma_from_io_page_reads_open_bus() {
+        let mut frontend = NullFrontend;
+        let mut bus = Bus::new(Cartridge::blank(0).unwrap(), &mut frontend);
+        bus.write(0x2003, 0).unwrap();
+        bus.write(OAM_DMA_ADDR, 0x40).unwrap();
+        for addr in [0x00, 0x15, 0x16, 0x1F] {
+            assert_eq!(read_oam(&mut bus, addr), 0x40);
+        }
+    }
+
+    #[test]
+    fn test_missing_prg_ram_reads_open_bus() {
+        let mut rom = ines_image(0, &[0; 0x4000], &[0; 0x2000]);
+        // Flags the board as having no PRG RAM
+        rom[10] = 0x10;
+        let cartridge = Cartridge::new(&rom).unwrap();
+        let mut frontend = NullFrontend;
+        let mut bus = Bus::new(cartridge, &mut frontend);
+        bus.write(0x0010, 0x5A).unwrap();
+        bus.write(0x6000, 0x11).unwrap();
+        assert_eq!(bus.read(0x0010), 0x5A);
+        assert_eq!(bus.read(0x6000), 0x5A);
+        assert_eq!(bus.read(0x5000), 0x5A);
+        assert_eq!(bus.peek(0x6000), None);
+    }
+
+    #[test]
+    fn test_oam_dma_from_ppu_page_reads_registers() {
+        let mut frontend = NullFrontend;
+        let mut bus = Bus::new(Cartridge::blank(0).unwrap(), &mut frontend);
+        // VRAM address increment of 1
+        bus.write(0x2000, 0).unwrap();
+        bus.write(0x2006, 0x24).unwrap();
+        bus.write(0x2006, 0x00).unwrap();
+        for data in [0xAA, 0xBB] {
+            bus.write(0x2007, data).unwrap();
+        }
+        bus.write(0x2006, 0x24).unwrap();
+        bus.write(0x2006, 0x00).unwrap();
+
+        // Every eighth byte is a $2007 mirror, read through the PPU's read buffer
+        bus.write(0x2003, 0).unwrap();
+        bus.write(OAM_DMA_ADDR, 0x20).unwrap();
+        assert_eq!(read_oam(&mut bus, 0x07), 0x00);
+        assert_eq!(read_oam(&mut bus, 0x0F), 0xAA);
+        assert_eq!(read_oam(&mut bus, 0x17), 0xBB);
+    }
+
+    #[test]
+    fn test_nmi_enabled_in_vblank_is_late() {
+        assert_eq!(
+            Accuracy::parse("nmi-delay").unwrap(),
+            Accuracy {
+                ppu_warmup: false,
+                nmi_delay: true,
+                bus_conflicts: true,
+            }
+        );
+        let accuracy = Accuracy::parse("nmi-delay,no-bus-conflicts").unwrap();
+        assert!(!accuracy.bus_conflicts);
+        assert_eq!(Accuracy::parse(&accuracy.to_string()).unwrap(), accuracy);
+        assert!(Accuracy::parse("bogus").is_err());
+
+        let mut frontend = NullFrontend;
+        let mut bus = Bus::new(Cartridge::blank(0).unwrap(), &mut frontend);
+        bus.set_accuracy(Accuracy::parse("all").unwrap());
+        while !bus.ppu.nmi_output() {
+            bus.write(0x2000, 0x00).unwrap();
+            bus.tick(1).unwrap();
+            bus.write(0x2000, 0x80).unwrap();
+        }
+        assert!(bus.take_nmi_late());
+        assert!(!bus.take_nmi_late());
+    }
+
+    #[test]
+    fn test_alignment() {
+        assert_eq!(Alignment::parse("2").unwrap(), Alignment::Fixed(2));
+        assert_eq!(
+            Alignment::parse("random:42").unwrap(),
+            Alignment::Random(Some(42))
+        );
+        assert!(Alignment::parse("3").is_err());
+        assert_eq!(
+            Alignment::Random(Some(42)).dots(),
+            Alignment::Random(Some(42)).dots()
+        );
+
+        let mut frontend = NullFrontend;
+        let mut bus = Bus::new(Cartridge::blank(0).unwrap(), &mut frontend);
+        let start = bus.ppu.position();
+        bus.set_alignment(Alignment::Fixed(2));
+        assert_eq!(bus.ppu.position(), (start.0, start.1 + 2));
+    }
+
+    #[test]
+    fn test_ppu_catches_up_on_access() {
+        let mut frontend = NullFrontend;
+        let mut bus = Bus::new(Cartridge::blank(0).unwrap(), &mut frontend);
+        bus.tick(1).unwrap();
+        assert_eq!(bus.ppu.position(), (0, 3));
+        bus.tick(10).unwrap();
+        assert_eq!(bus.ppu.position(), (0, 3));
+        bus.read(0x2002);
+        assert_eq!(bus.ppu.position(), (0, 33));
+
+        // Runs on its own to start the next scanline
+        while bus.time.cpu_cycles < 120 {
+            bus.tick(1).unwrap();
+            if bus.ppu.position().0 == 1 {
+                break;
+            }
+        }
+        assert_eq!(
+            bus.ppu.position(),
+            (1, 3 * bus.time.cpu_cycles as usize - 341)
+        );
+    }
+
+    // Runs the CPU until the PPU has reached the given position
+    fn run_to(bus: &mut Bus, scanline: isize, dot: usize) {
+        while bus.ppu.position() < (scanline, dot) {
+            bus.tick(1).unwrap();
+            bus.catch_up_ppu();
+        }
+    }
+
+    #[test]
+    fn test_mid_frame_mask_write() {
+        let mut frontend = NullFrontend;
+        let mut bus = Bus::new(Cartridge::blank(0).unwrap(), &mut frontend);
+        // Backdrop color, and a background of blank tiles
+        for data in [0x3F, 0x00] {
+            bus.write(0x2006, data).unwrap();
+        }
+        bus.write(0x2007, 0x21).unwrap();
+        bus.write(0x2001, 0x0A).unwrap();
+
+        // Greyscale and all emphasis bits from the middle of line 100
+        run_to(&mut bus, 100, 120);
+        bus.write(0x2001, 0xEB).unwrap();
+        let (_, dot) = bus.ppu.position();
+        run_to(&mut bus, 101, 0);
+        // Emphasis and rendering off from the end of line 101, lines without
+        // rendering are filled in bulk
+        run_to(&mut bus, 101, 300);
+        bus.write(0x2001, 0x01).unwrap();
+        run_to(&mut bus, 103, 0);
+
+        let (old, new, plain_grey) = (0x21, 0x20 | 0b111 << 6, 0x20);
+        let line = |line: usize| &bus.ppu.frame[line * 256..(line + 1) * 256];
+        assert!(line(99).iter().all(|&p| p == old));
+        assert!(line(100)[..dot].iter().all(|&p| p == old));
+        assert!(line(100)[dot..].iter().all(|&p| p == new));
+        assert!(line(101).iter().all(|&p| p == new));
+        assert!(line(102).iter().all(|&p| p == plain_grey));
+    }
+
+    struct EventLog<'a>(&'a std::cell::RefCell<Vec<ConsoleEvent>>);
+
+    impl EventListener for EventLog<'_> {
+        fn on_event(&mut self, event: ConsoleEvent) {
+            self.0.borrow_mut().push(event);
+        }
+    }
+
+    #[test]
+    fn test_events() {
+        let log = std::cell::RefCell::new(Vec::new());
+        let mut frontend = NullFrontend;
+        let mut bus = Bus::new(Cartridge::blank(0).unwrap(), &mut frontend);
+        bus.events.subscribe(Box::new(EventLog(&log)));
+
+        while bus.time().frames == 0 {
+            bus.tick(1).unwrap();
+        }
+        bus.reset();
+        bus.swap_cartridge(Cartridge::blank(0).unwrap());
+        drop(bus);
+        let log = log.into_inner();
+        assert!(matches!(
+            log[..],
+            [
+                ConsoleEvent::VblankStarted,
+                ConsoleEvent::FrameCompleted(EmulatedTime { frames: 1, .. }),
+                ConsoleEvent::Reset,
+                ConsoleEvent::RomSwapped
+            ]
+        ));
+    }
+
+    #[test]
+    fn test_chr_rom_write_reported_once() {
+        let log = std::cell::RefCell::new(Vec::new());
+        let mut frontend = NullFrontend;
+        let mut bus = Bus::new(Cartridge::blank(0).unwrap(), &mut frontend);
+        bus.events.subscribe(Box::new(EventLog(&log)));
+        let write_chr = |bus: &mut Bus| {
+            bus.write(0x2006, 0x01).unwrap();
+            bus.write(0x2006, 0x23).unwrap();
+            bus.write(0x2007, 0xAA).unwrap();
+            bus.write(0x2007, 0xBB).unwrap();
+        };
+
+        write_chr(&mut bus);
+        assert_eq!(bus.cartridge.read_ppu(0x0123), 0);
+        bus.swap_cartridge(Cartridge::blank(0).unwrap());
+        write_chr(&mut bus);
+        drop(bus);
+        assert_eq!(
+            log.into_inner(),
+            [
+                ConsoleEvent::ChrRomWrite(0x0123),
+                ConsoleEvent::RomSwapped,
+                ConsoleEvent::ChrRomWrite(0x0123)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dmc_dma_stalls_and_conflicts_with_controller_read() {
+        for (conflicts, expected) in [(false, 1), (true, 0)] {
+            let mut frontend = NullFrontend;
+            let mut bus = Bus::new(Cartridge::blank(0).unwrap(), &mut frontend);
+            bus.dpcm_conflicts = conflicts;
+            bus.controller.set_button_state(Button::A, true);
+            bus.write(CONTROLLER1_ADDR, 1).unwrap();
+            bus.write(CONTROLLER1_ADDR, 0).unwrap();
+
+            // One byte sample, the fetch is requested on the next APU cycle
+            bus.write(0x4013, 0).unwrap();
+            bus.write(APU_STATUS_ADDR, 0x10).unwrap();
+            bus.tick(1).unwrap();
+            assert_eq!(bus.read(CONTROLLER1_ADDR), expected);
+
+            let start = bus.time().cpu_cycles;
+            bus.tick(1).unwrap();
+            assert_eq!(bus.time().cpu_cycles - start, 1 + DMC_DMA_CYCLES as u64);
+            assert_eq!(bus.apu.dmc_dma_request(), None);
+        }
+    }
+
+    #[test]
+    fn test_debug_writes() {
+        let mut frontend = NullFrontend;
+        let mut bus = Bus::new(Cartridge::blank(0).unwrap(), &mut frontend);
+        bus.write(0x2005, 0x10).unwrap();
+        bus.apply_debug_write(DebugWrite::PpuCtrl(0x81)).unwrap();
+        bus.apply_debug_write(DebugWrite::PpuScroll { x: 0x20, y: 0x30 })
+            .unwrap();
+        bus.apply_debug_write(DebugWrite::Memory {
+            addr: 0x4002,
+            data: 0xAB,
+        })
+        .unwrap();
+        let regs = CpuRegs {
+            a: 1,
+            pc: 0x8000,
+            ..CpuRegs::default()
+        };
+        bus.apply_debug_write(DebugWrite::CpuRegs(regs)).unwrap();
+
+        let snapshot = bus.debug_snapshot();
+        assert_eq!(snapshot.ppu_regs.ctrl, 0x81);
+        assert_eq!(
+            (snapshot.ppu_regs.scroll_x, snapshot.ppu_regs.scroll_y),
+            (0x20, 0x30)
+        );
+        assert_eq!(snapshot.apu_regs[2], 0xAB);
+        assert_eq!(bus.pending_cpu_regs, Some(regs));
+
+        // The scroll write latch is left as the game set it, expecting Y next
+        bus.write(0x2005, 0x40).unwrap();
+        assert_eq!(bus.ppu.regs().scroll_y, 0x40);
+    }
+
+    #[test]
+    fn test_irq_edges_are_timestamped() {
+        let cartridge = Cartridge::new(&ines_image(19, &vec![0; 0x8000], &[0; 0x2000])).unwrap();
+        let mut frontend = NullFrontend;
+        let mut bus = Bus::new(cartridge, &mut frontend);
+        bus.tick(10).unwrap();
+        // Namco 163 counter two cycles away from firing
+        bus.write(0x5000, 0xFD).unwrap();
+        bus.write(0x5800, 0xFF).unwrap();
+        bus.tick(2).unwrap();
+        let fired = bus.time;
+        bus.write(0x5000, 0).unwrap();
+
+        let edges = bus.debug_snapshot().irq_edges;
+        assert_eq!(edges.len(), 2);
+        assert!(edges[0].asserted && !edges[1].asserted);
+        assert_eq!(edges[0].cpu_cycle, fired.cpu_cycles);
+        assert_eq!((edges[0].frame, edges[0].dot), (0, 36));
     }
 }
@@ -3,21 +3,56 @@ pub mod mappers;
 use eyre::eyre;
 use eyre::Result;
 
-use mappers::{get_mapper, Mapper, Mirroring};
+use super::apu::mixer::Sample;
+use mappers::{get_mapper, is_supported, Mapper, MapperEvent, Mirroring};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Region {
+    Ntsc,
+    Pal,
+}
+
+impl std::fmt::Display for Region {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Ntsc => write!(f, "NTSC"),
+            Self::Pal => write!(f, "PAL"),
+        }
+    }
+}
 
 pub struct Cartridge {
     pub mapper: Box<dyn Mapper>,
+    pub region: Region,
+    /// PRG RAM is battery backed and should outlive the session
+    pub battery: bool,
+    /// First pattern table address the game tried to write while it's ROM
+    pub chr_rom_write: Option<u16>,
+    /// Unsupported mapper the cartridge is run as NROM instead of, see `Cartridge::load`
+    pub fallback_from: Option<u8>,
 }
 
 impl Cartridge {
     const INES_TAG: [u8; 4] = [0x4E, 0x45, 0x53, 0x1A];
+    const HEADER_LEN: usize = 16;
+    const TRAINER_LEN: usize = 512;
     const PRG_ROM_BANK_SIZE: usize = 0x4000;
     const CHR_ROM_BANK_SIZE: usize = 0x2000;
-    const _PRG_RAM_BANK_SIZE: usize = 0x2000;
+    const PRG_RAM_BANK_SIZE: usize = 0x2000;
     const CHR_RAM_BANK_SIZE: usize = 0x2000;
 
+    /// Parses an iNES image. Malformed files give an error instead of a panic, and
+    /// data past the declared ROM sizes is ignored.
     pub fn new(rom: &[u8]) -> Result<Self> {
-        if rom[0..4] != Self::INES_TAG {
+        Self::load(rom, false)
+    }
+
+    /// Like `new`, but with `mapper_fallback` an unsupported mapper is run as NROM
+    /// with the last 32 kB of PRG ROM and the first 8 kB of CHR ROM. Most boards keep
+    /// the reset code in their last bank, so simple games sometimes get far enough to
+    /// tell whether the mapper is worth adding.
+    pub fn load(rom: &[u8], mapper_fallback: bool) -> Result<Self> {
+        if rom.len() < Self::HEADER_LEN || rom[0..4] != Self::INES_TAG {
             return Err(eyre!("File is not in iNES file format"));
         }
 
@@ -35,25 +70,75 @@ impl Cartridge {
             (false, false) => Mirroring::Horizontal,
         };
 
+        // iNES 1.0 TV system flag, rarely set correctly but the best we have
+        let region = if rom[9] & 0b1 == 0 {
+            Region::Ntsc
+        } else {
+            Region::Pal
+        };
+
+        let battery = rom[6] & 0b10 != 0;
+        // A zero size means 8 kB in iNES 1.0, only the unofficial flag in byte 10 can
+        // say a board has no PRG RAM at all
+        let prg_ram_size = if rom[10] & 0x10 != 0 {
+            0
+        } else {
+            (rom[8] as usize).max(1) * Self::PRG_RAM_BANK_SIZE
+        };
         let skip_trainer = rom[6] & 0b100 != 0;
 
-        let prg_rom_start = 16 + if skip_trainer { 512 } else { 0 };
+        let prg_rom_start = Self::HEADER_LEN + if skip_trainer { Self::TRAINER_LEN } else { 0 };
         let prg_rom_len = rom[4] as usize * Self::PRG_ROM_BANK_SIZE;
-        let prg_rom = rom[prg_rom_start..(prg_rom_start + prg_rom_len)].to_vec();
+        if prg_rom_len == 0 {
+            return Err(eyre!("Header declares no PRG ROM"));
+        }
+        let prg_rom = Self::section(rom, prg_rom_start, prg_rom_len, "PRG ROM")?;
 
         let chr_rom_start = prg_rom_start + prg_rom_len;
         let chr_rom_len = rom[5] as usize * Self::CHR_ROM_BANK_SIZE;
-        let chr_rom = rom[chr_rom_start..(chr_rom_start + chr_rom_len)].to_vec();
+        let mut chr_rom = Self::section(rom, chr_rom_start, chr_rom_len, "CHR ROM")?;
 
+        let fallback_from = (mapper_fallback && !is_supported(mapper)).then_some(mapper);
+        let (mapper, prg_rom) = if fallback_from.is_some() {
+            chr_rom.truncate(Self::CHR_ROM_BANK_SIZE);
+            (0, prg_rom[prg_rom.len().saturating_sub(0x8000)..].to_vec())
+        } else {
+            (mapper, prg_rom)
+        };
         let mapper = get_mapper(
             mapper,
             prg_rom,
             chr_rom,
             (chr_rom_len == 0) as usize * Self::CHR_RAM_BANK_SIZE,
+            prg_ram_size,
             mirroring,
         )?;
 
-        Ok(Self { mapper })
+        Ok(Self {
+            mapper,
+            region,
+            battery,
+            chr_rom_write: None,
+            fallback_from,
+        })
+    }
+
+    /// Blank NROM-sized board with the given mapper, for a console without a game
+    pub fn blank(mapper: u8) -> Result<Self> {
+        let prg = [0; Self::PRG_ROM_BANK_SIZE];
+        Self::new(&ines_image(mapper, &prg, &[0; Self::CHR_ROM_BANK_SIZE]))
+    }
+
+    fn section(rom: &[u8], start: usize, len: usize, name: &str) -> Result<Vec<u8>> {
+        rom.get(start..start + len)
+            .map(<[u8]>::to_vec)
+            .ok_or_else(|| {
+                eyre!(
+                    "File is truncated, header declares {} kB of {name} but only {} bytes follow",
+                    len / 1024,
+                    rom.len().saturating_sub(start)
+                )
+            })
     }
 
     pub fn read_cpu(&mut self, addr: u16) -> u8 {
@@ -69,6 +154,9 @@ impl Cartridge {
     }
 
     pub fn write_ppu(&mut self, addr: u16, data: u8) {
+        if addr < 0x2000 && !self.mapper.chr_writable() {
+            self.chr_rom_write.get_or_insert(addr);
+        }
         self.mapper.write_ppu(addr, data);
     }
 
@@ -76,7 +164,194 @@ impl Cartridge {
         self.mapper.mirror_vram(addr)
     }
 
+    pub fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        self.mapper.prg_rom_offset(addr)
+    }
+
+    pub fn prg_rom_len(&self) -> usize {
+        self.mapper.prg_rom_len()
+    }
+
+    pub fn chr_offset(&self, addr: u16) -> Option<usize> {
+        self.mapper.chr_offset(addr)
+    }
+
+    pub fn chr_len(&self) -> usize {
+        self.mapper.chr_len()
+    }
+
+    pub fn cpu_read_driven(&self, addr: u16) -> bool {
+        self.mapper.cpu_read_driven(addr)
+    }
+
+    pub fn trigger_event(&mut self, event: MapperEvent) {
+        self.mapper.trigger_event(event);
+    }
+
     pub fn irq_active(&self) -> bool {
         self.mapper.irq_active()
     }
+
+    pub fn set_bus_conflicts(&mut self, enabled: bool) {
+        self.mapper.set_bus_conflicts(enabled);
+    }
+
+    pub fn watches_a12(&self) -> bool {
+        self.mapper.watches_a12()
+    }
+
+    pub fn watches_chr_reads(&self) -> bool {
+        self.mapper.watches_chr_reads()
+    }
+
+    pub fn audio_output(&self) -> Sample {
+        self.mapper.audio_output()
+    }
+
+    pub fn prg_ram(&self) -> Vec<u8> {
+        self.mapper.prg_ram()
+    }
+
+    pub fn load_prg_ram(&mut self, data: &[u8]) {
+        self.mapper.load_prg_ram(data);
+    }
+}
+
+/// iNES 1.0 image of a vertically mirrored board with the given ROM, CHR RAM if `chr`
+/// is empty. For tests and tools that make images on the fly.
+pub fn ines_image(mapper: u8, prg: &[u8], chr: &[u8]) -> Vec<u8> {
+    let mut rom = Cartridge::INES_TAG.to_vec();
+    rom.push((prg.len() / Cartridge::PRG_ROM_BANK_SIZE) as u8);
+    rom.push((chr.len() / Cartridge::CHR_ROM_BANK_SIZE) as u8);
+    rom.extend([mapper << 4 | 0b1, mapper & 0xF0]);
+    rom.resize(Cartridge::HEADER_LEN, 0);
+    rom.extend_from_slice(prg);
+    rom.extend_from_slice(chr);
+    rom
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod test {
+    use super::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    fn image(mapper: u8, prg_banks: u8, chr_banks: u8) -> Vec<u8> {
+        let prg_len = prg_banks as usize * Cartridge::PRG_ROM_BANK_SIZE;
+        let len = prg_len + chr_banks as usize * Cartridge::CHR_ROM_BANK_SIZE;
+        let data: Vec<u8> = (0..len).map(|i| i as u8).collect();
+        ines_image(mapper, &data[..prg_len], &data[prg_len..])
+    }
+
+    fn error(rom: &[u8]) -> String {
+        Cartridge::new(rom).err().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_truncated_images() {
+        let rom = image(0, 2, 1);
+        assert!(Cartridge::new(&rom).is_ok());
+        assert_eq!(error(&rom[..3]), "File is not in iNES file format");
+        assert_eq!(error(&rom[..10]), "File is not in iNES file format");
+        assert_eq!(
+            error(&rom[..16 + 0x4000]),
+            "File is truncated, header declares 32 kB of PRG ROM but only 16384 bytes follow"
+        );
+        assert_eq!(
+            error(&rom[..rom.len() - 1]),
+            "File is truncated, header declares 8 kB of CHR ROM but only 8191 bytes follow"
+        );
+
+        // A trainer that isn't there pushes the ROM past the end of the file
+        let mut trainer = rom.clone();
+        trainer[6] |= 0b100;
+        assert!(Cartridge::new(&trainer).is_err());
+
+        let mut no_prg = rom;
+        no_prg[4] = 0;
+        assert_eq!(error(&no_prg), "Header declares no PRG ROM");
+    }
+
+    #[test]
+    fn test_mapper_fallback() {
+        let rom = image(5, 8, 4);
+        assert_eq!(error(&rom), "Unsupported mapper 5");
+        let mut cartridge = Cartridge::load(&rom, true).unwrap();
+        assert_eq!(cartridge.fallback_from, Some(5));
+        assert_eq!(cartridge.prg_rom_len(), 0x8000);
+        // Bytes count up from the start of PRG ROM, which is 128 kB here
+        assert_eq!(cartridge.read_cpu(0x8001), 1);
+        assert_eq!(cartridge.read_cpu(0xFFFF), 0xFF);
+        assert_eq!(cartridge.read_ppu(0x0003), 3);
+
+        let cartridge = Cartridge::load(&image(1, 8, 0), true).unwrap();
+        assert_eq!(cartridge.fallback_from, None);
+        assert_eq!(cartridge.prg_rom_len(), 8 * 0x4000);
+    }
+
+    #[test]
+    fn test_battery_ram() {
+        let mut rom = image(1, 1, 0);
+        assert!(!Cartridge::new(&rom).unwrap().battery);
+        rom[6] |= 0b10;
+        let mut cartridge = Cartridge::new(&rom).unwrap();
+        assert!(cartridge.battery);
+
+        cartridge.load_prg_ram(&[1, 2, 3]);
+        assert_eq!(cartridge.read_cpu(0x6001), 2);
+        cartridge.write_cpu(0x7FFF, 0xAA);
+        let ram = cartridge.prg_ram();
+        assert_eq!((&ram[..3], ram[0x1FFF]), (&[1, 2, 3][..], 0xAA));
+    }
+
+    #[test]
+    fn test_prg_ram_presence() {
+        let mut rom = image(0, 1, 1);
+        let mut cartridge = Cartridge::new(&rom).unwrap();
+        assert!(cartridge.cpu_read_driven(0x7FFF));
+        assert!(!cartridge.cpu_read_driven(0x5000));
+        cartridge.write_cpu(0x6001, 0x42);
+        assert_eq!(cartridge.read_cpu(0x6001), 0x42);
+
+        rom[10] |= 0x10;
+        let cartridge = Cartridge::new(&rom).unwrap();
+        assert!(!cartridge.cpu_read_driven(0x6000));
+        assert!(cartridge.cpu_read_driven(0x8000));
+    }
+
+    #[test]
+    fn test_oversized_image_ignores_extra_data() {
+        let mut rom = image(1, 1, 0);
+        rom.extend(vec![0xFF; 5000]);
+        let mut cartridge = Cartridge::new(&rom).unwrap();
+        assert_eq!(cartridge.prg_rom_len(), 0x4000);
+        assert_eq!(cartridge.read_cpu(0xC001), 1);
+    }
+
+    // Every prefix and random register writes must be handled without panicking
+    #[test]
+    fn test_malformed_images_do_not_panic() {
+        let mut rng = StdRng::seed_from_u64(2202);
+        for mapper in [0, 1, 2, 3, 4, 9, 10, 19, 73, 75, 85, 210] {
+            for (prg_banks, chr_banks) in [(1, 0), (1, 1), (3, 2)] {
+                let rom = image(mapper, prg_banks, chr_banks);
+                for len in (0..rom.len()).step_by(997) {
+                    assert!(Cartridge::new(&rom[..len]).is_err());
+                }
+
+                let mut four_screen = rom.clone();
+                four_screen[6] |= 0b1000;
+                let mut cartridge = Cartridge::new(&four_screen).unwrap();
+                for _ in 0..5000 {
+                    let addr = rng.gen_range(0x4020..=0xFFFF);
+                    cartridge.write_cpu(addr, rng.gen());
+                    cartridge.read_cpu(rng.gen_range(0x4020..=0xFFFF));
+                    let ppu_addr = rng.gen_range(0..0x2000);
+                    cartridge.read_ppu(ppu_addr);
+                    cartridge.write_ppu(ppu_addr, rng.gen());
+                    cartridge.mirror_vram_addr(rng.gen_range(0x2000..0x3000));
+                }
+            }
+        }
+    }
 }
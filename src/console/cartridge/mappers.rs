@@ -1,7 +1,63 @@
 use eyre::eyre;
 use eyre::Result;
 
-pub enum MapperEvent {}
+use crate::console::apu::mixer::Sample;
+use crate::console::state::{take, Snapshot, StateField};
+use crate::macros::state_fields;
+
+pub enum MapperEvent {
+    /// One CPU cycle has passed
+    CpuTick,
+    /// The PPU started a new scanline, -1 being the pre-render line
+    ScanlineTick { scanline: i16, rendering: bool },
+    /// PPU address line A12 rose after being low for a few CPU cycles, see
+    /// `Mapper::watches_a12`
+    A12Rise,
+}
+
+impl StateField for Mirroring {
+    fn save(&self, out: &mut Vec<u8>) {
+        let value: u8 = match self {
+            Self::Vertical => 0,
+            Self::Horizontal => 1,
+            Self::FourScreen => 2,
+            Self::SingleScreenLower => 3,
+            Self::SingleScreenUpper => 4,
+        };
+        out.push(value);
+    }
+    fn load(&mut self, input: &mut &[u8]) -> Result<()> {
+        *self = match take(input, 1)?[0] {
+            0 => Self::Vertical,
+            1 => Self::Horizontal,
+            2 => Self::FourScreen,
+            3 => Self::SingleScreenLower,
+            4 => Self::SingleScreenUpper,
+            v => return Err(eyre!("Invalid mirroring value {}", v)),
+        };
+        Ok(())
+    }
+    fn describe(&self) -> String {
+        match self {
+            Self::Vertical => "Vertical",
+            Self::Horizontal => "Horizontal",
+            Self::FourScreen => "Four screen",
+            Self::SingleScreenLower => "Single screen (lower)",
+            Self::SingleScreenUpper => "Single screen (upper)",
+        }
+        .to_owned()
+    }
+}
+
+mod discrete;
+mod konami;
+mod namco;
+mod nintendo;
+
+use discrete::{Mapper002, Mapper003};
+use konami::{Mapper073, Mapper075, Mapper085};
+use namco::{Mapper019, Mapper210, Namco210Chip};
+use nintendo::{Mapper004, Mapper009, Mapper010};
 
 pub enum Mirroring {
     Vertical,
@@ -17,20 +73,92 @@ impl Default for Mirroring {
     }
 }
 
-pub trait Mapper {
+/// Board logic of a cartridge. Save states and the inspector both go through
+/// `Snapshot::state_fields`, so a field listed there is persisted and shown.
+pub trait Mapper: Snapshot {
     fn read_cpu(&mut self, addr: u16) -> u8;
     fn write_cpu(&mut self, addr: u16, data: u8);
     fn read_ppu(&mut self, addr: u16) -> u8;
     fn write_ppu(&mut self, addr: u16, data: u8);
     fn mirror_vram(&self, addr: u16) -> usize;
 
-    fn trigger_event(&mut self, _event: MapperEvent) {
-        todo!("No cartridge event support yet")
-    }
+    fn trigger_event(&mut self, _event: MapperEvent) {}
 
     fn irq_active(&self) -> bool {
         false
     }
+
+    /// Whether the mapper counts `MapperEvent::A12Rise`. The PPU then catches up with
+    /// the CPU at every pattern fetch, so the IRQ is raised on time.
+    fn watches_a12(&self) -> bool {
+        false
+    }
+
+    /// Whether `read_ppu` changes the mapper's state, like the MMC2 latches. The PPU
+    /// then reads every pattern fetch from the cartridge instead of its tile cache.
+    fn watches_chr_reads(&self) -> bool {
+        false
+    }
+
+    /// False where nothing on the board answers a CPU read, so the data bus keeps
+    /// its last value instead of what `read_cpu` returns. Boards with registers or
+    /// RAM below $8000 override it.
+    fn cpu_read_driven(&self, addr: u16) -> bool {
+        addr >= 0x8000
+    }
+
+    /// Turns bus conflicts on or off on boards that have them, see `Accuracy::bus_conflicts`
+    fn set_bus_conflicts(&mut self, _enabled: bool) {}
+
+    /// Expansion audio, added to the APU mix every CPU cycle
+    fn audio_output(&self) -> Sample {
+        Sample::default()
+    }
+
+    /// Offset into PRG ROM that the given CPU address currently maps to, if any
+    fn prg_rom_offset(&self, _addr: u16) -> Option<usize> {
+        None
+    }
+
+    fn prg_rom_len(&self) -> usize {
+        0
+    }
+
+    /// Offset into CHR memory that the given PPU address currently maps to, if any.
+    /// Lets the PPU cache decoded tiles across bank switches.
+    fn chr_offset(&self, _addr: u16) -> Option<usize> {
+        None
+    }
+
+    fn chr_len(&self) -> usize {
+        0
+    }
+
+    /// False when the pattern tables are ROM, so PPU writes to them are ignored
+    fn chr_writable(&self) -> bool {
+        true
+    }
+
+    /// Contents of PRG RAM, saved between sessions for battery backed boards
+    fn prg_ram(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    /// Restores PRG RAM from a save file, ignoring data that doesn't fit
+    fn load_prg_ram(&mut self, _data: &[u8]) {}
+
+    /// Human readable name/value pairs for the debugger inspector
+    fn inspect(&mut self) -> Vec<(&'static str, String)> {
+        self.state_fields()
+            .into_iter()
+            .map(|(name, field)| (name, field.describe()))
+            .collect()
+    }
+}
+
+/// Mapper numbers `get_mapper` has an implementation for
+pub const fn is_supported(mapper: u8) -> bool {
+    matches!(mapper, 0 | 1 | 2 | 3 | 4 | 9 | 10 | 19 | 73 | 75 | 85 | 210)
 }
 
 pub fn get_mapper(
@@ -38,6 +166,7 @@ pub fn get_mapper(
     prg_rom: Vec<u8>,
     chr_rom: Vec<u8>,
     chr_ram_size: usize,
+    prg_ram_size: usize,
     mirroring: Mirroring,
 ) -> Result<Box<dyn Mapper>> {
     println!("Using mapper {}", mapper);
@@ -47,6 +176,7 @@ pub fn get_mapper(
             prg_rom,
             chr_rom,
             chr_ram_size,
+            prg_ram_size,
             mirroring,
         ))),
         1 => Ok(Box::new(Mapper001::new(
@@ -55,10 +185,132 @@ pub fn get_mapper(
             chr_ram_size,
             mirroring,
         ))),
+        // Bus conflicts are on until `Accuracy::bus_conflicts` turns them off
+        2 => Ok(Box::new(Mapper002::new(
+            prg_rom,
+            chr_rom,
+            chr_ram_size,
+            mirroring,
+            true,
+        ))),
+        3 => Ok(Box::new(Mapper003::new(
+            prg_rom,
+            chr_rom,
+            chr_ram_size,
+            mirroring,
+            true,
+        ))),
+        4 => Ok(Box::new(Mapper004::new(
+            prg_rom,
+            chr_rom,
+            chr_ram_size,
+            mirroring,
+        ))),
+        9 => Ok(Box::new(Mapper009::new(
+            prg_rom,
+            chr_rom,
+            chr_ram_size,
+            mirroring,
+        ))),
+        10 => Ok(Box::new(Mapper010::new(
+            prg_rom,
+            chr_rom,
+            chr_ram_size,
+            mirroring,
+        ))),
+        19 => Ok(Box::new(Mapper019::new(prg_rom, chr_rom, chr_ram_size))),
+        73 => Ok(Box::new(Mapper073::new(
+            prg_rom,
+            chr_rom,
+            chr_ram_size,
+            mirroring,
+        ))),
+        75 => Ok(Box::new(Mapper075::new(
+            prg_rom,
+            chr_rom,
+            chr_ram_size,
+            mirroring,
+        ))),
+        85 => Ok(Box::new(Mapper085::new(
+            prg_rom,
+            chr_rom,
+            chr_ram_size,
+            mirroring,
+        ))),
+        // Without NES 2.0 submappers the 175 and 340 can't be told apart, 175 is more common
+        210 => Ok(Box::new(Mapper210::new(
+            Namco210Chip::N175,
+            prg_rom,
+            chr_rom,
+            chr_ram_size,
+            mirroring,
+        ))),
         _ => Err(eyre!("Unsupported mapper {}", mapper)),
     }
 }
 
+// Save files from other emulators may be sized for a different board
+fn load_ram(ram: &mut [u8], data: &[u8]) {
+    let len = ram.len().min(data.len());
+    ram[..len].copy_from_slice(&data[..len]);
+}
+
+// Banks of the given size each filled with their own index
+#[cfg(test)]
+fn banked(banks: u8, size: usize) -> Vec<u8> {
+    (0..banks).flat_map(|b| vec![b; size]).collect()
+}
+
+/// CHR ROM, or CHR RAM on boards without it
+struct Chr {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+}
+
+impl Chr {
+    fn new(rom: Vec<u8>, ram_size: usize) -> Self {
+        Self {
+            rom,
+            ram: vec![0; ram_size],
+        }
+    }
+
+    fn is_ram(&self) -> bool {
+        !self.ram.is_empty()
+    }
+
+    fn len(&self) -> usize {
+        self.rom.len().max(self.ram.len())
+    }
+
+    fn read(&self, offset: usize) -> u8 {
+        if self.is_ram() {
+            self.ram[offset]
+        } else {
+            self.rom[offset]
+        }
+    }
+
+    fn write(&mut self, offset: usize, data: u8) {
+        if self.is_ram() {
+            self.ram[offset] = data;
+        }
+    }
+}
+
+// ROM never changes
+impl StateField for Chr {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.ram.save(out);
+    }
+    fn load(&mut self, input: &mut &[u8]) -> Result<()> {
+        self.ram.load(input)
+    }
+    fn describe(&self) -> String {
+        self.ram.describe()
+    }
+}
+
 // Horizontal mirroring - first two 1kB areas map to first 1kB of VRAM
 const fn mirror_horizontal(addr: u16) -> usize {
     if addr & 0x800 == 0 {
@@ -92,33 +344,77 @@ pub struct Mapper000 {
 }
 
 impl Mapper000 {
-    fn new(prg_rom: Vec<u8>, chr_rom: Vec<u8>, chr_ram_size: usize, mirroring: Mirroring) -> Self {
+    fn new(
+        prg_rom: Vec<u8>,
+        chr_rom: Vec<u8>,
+        chr_ram_size: usize,
+        prg_ram_size: usize,
+        mirroring: Mirroring,
+    ) -> Self {
         Self {
             prg_rom,
             chr_rom,
-            prg_ram: vec![0; 0x2000],
+            // Family Basic has RAM at $6000, plain NROM boards have nothing there
+            prg_ram: vec![0; prg_ram_size.min(0x2000)],
             chr_ram: vec![0; chr_ram_size],
             mirroring,
         }
     }
 }
 
+impl Snapshot for Mapper000 {
+    state_fields!(prg_ram, chr_ram);
+}
+
 impl Mapper for Mapper000 {
-    fn trigger_event(&mut self, _event: MapperEvent) {
-        todo!("No cartridge event support yet")
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        match addr {
+            0x8000.. => Some((addr - 0x8000) as usize % self.prg_rom.len()),
+            _ => None,
+        }
+    }
+
+    fn prg_rom_len(&self) -> usize {
+        self.prg_rom.len()
+    }
+
+    fn chr_offset(&self, addr: u16) -> Option<usize> {
+        match addr {
+            0..=0x1FFF => Some(addr as usize),
+            _ => None,
+        }
+    }
+
+    fn chr_len(&self) -> usize {
+        self.chr_ram.len().max(self.chr_rom.len())
+    }
+
+    fn chr_writable(&self) -> bool {
+        !self.chr_ram.is_empty()
+    }
+
+    fn cpu_read_driven(&self, addr: u16) -> bool {
+        match addr {
+            0x6000..=0x7FFF => !self.prg_ram.is_empty(),
+            0x8000.. => true,
+            _ => false,
+        }
     }
 
     fn read_cpu(&mut self, addr: u16) -> u8 {
         match addr {
-            0x6000..=0x7FFF => self.prg_ram[(addr - 0x6000) as usize],
+            0x6000..=0x7FFF if !self.prg_ram.is_empty() => {
+                self.prg_ram[(addr - 0x6000) as usize % self.prg_ram.len()]
+            }
             0x8000.. => self.prg_rom[(addr - 0x8000) as usize % self.prg_rom.len()],
             _ => 0,
         }
     }
 
     fn write_cpu(&mut self, addr: u16, data: u8) {
-        if let 0x6000..=0x7FFF = addr {
-            self.prg_ram[(addr - 0x6000) as usize] = data;
+        if let (0x6000..=0x7FFF, false) = (addr, self.prg_ram.is_empty()) {
+            let len = self.prg_ram.len();
+            self.prg_ram[(addr - 0x6000) as usize % len] = data;
         }
     }
 
@@ -137,7 +433,7 @@ impl Mapper for Mapper000 {
 
         match addr {
             0..=0x1FFF if use_chr_ram => self.chr_ram[addr as usize] = data,
-            0..=0x1FFF => self.chr_rom[addr as usize] = data,
+            0..=0x1FFF => (),
             _ => panic!("PPU writing to address {:X}", addr),
         }
     }
@@ -145,10 +441,10 @@ impl Mapper for Mapper000 {
     /// Translates given VRAM address to actual VRAM location
     /// This includes removing address offset and mirroring based on current mirroring scheme
     fn mirror_vram(&self, addr: u16) -> usize {
+        // Four screen VRAM isn't emulated, and NROM can't select single screen
         match self.mirroring {
-            Mirroring::Vertical => mirror_vertical(addr),
             Mirroring::Horizontal => mirror_horizontal(addr),
-            _ => panic!("Unsupported mirroring mode for mappper 000!"),
+            _ => mirror_vertical(addr),
         }
     }
 }
@@ -156,7 +452,7 @@ impl Mapper for Mapper000 {
 pub struct Mapper001 {
     prg_banks: Vec<Vec<u8>>,
     prg_ram_banks: Vec<Vec<u8>>,
-    chr_banks: Vec<Vec<u8>>,
+    chr: Mapper001Chr,
     mirroring: Mirroring,
 
     buffer: usize,
@@ -179,6 +475,72 @@ enum Mapper001PrgMode {
     FixLast,
 }
 
+impl StateField for Mapper001PrgMode {
+    fn save(&self, out: &mut Vec<u8>) {
+        let value: u8 = match self {
+            Self::SwitchBoth => 0,
+            Self::FixFirst => 1,
+            Self::FixLast => 2,
+        };
+        out.push(value);
+    }
+    fn load(&mut self, input: &mut &[u8]) -> Result<()> {
+        *self = match take(input, 1)?[0] {
+            0 => Self::SwitchBoth,
+            1 => Self::FixFirst,
+            2 => Self::FixLast,
+            v => return Err(eyre!("Invalid MMC1 PRG mode {}", v)),
+        };
+        Ok(())
+    }
+    fn describe(&self) -> String {
+        match self {
+            Self::SwitchBoth => "32k switch",
+            Self::FixFirst => "Fix first",
+            Self::FixLast => "Fix last",
+        }
+        .to_owned()
+    }
+}
+
+/// CHR banks of the MMC1, RAM on boards without CHR ROM
+struct Mapper001Chr {
+    banks: Vec<Vec<u8>>,
+    is_ram: bool,
+}
+
+// ROM never changes, so only RAM is kept. States saved before that held the ROM
+// banks too, those are skipped.
+impl StateField for Mapper001Chr {
+    fn save(&self, out: &mut Vec<u8>) {
+        if self.is_ram {
+            self.banks.save(out);
+        } else {
+            0u32.save(out);
+        }
+    }
+    fn load(&mut self, input: &mut &[u8]) -> Result<()> {
+        if self.is_ram {
+            return self.banks.load(input);
+        }
+        let mut banks = 0u32;
+        banks.load(input)?;
+        for _ in 0..banks {
+            let mut len = 0u32;
+            len.load(input)?;
+            take(input, len as usize)?;
+        }
+        Ok(())
+    }
+    fn describe(&self) -> String {
+        if self.is_ram {
+            self.banks.describe()
+        } else {
+            "ROM".to_owned()
+        }
+    }
+}
+
 impl Mapper001 {
     const PRG_ROM_BANK_SIZE: usize = 16 * 1024;
     const CHR_ROM_BANK_SIZE: usize = 8 * 1024;
@@ -191,18 +553,19 @@ impl Mapper001 {
             .map(<[u8]>::to_vec)
             .collect();
 
-        let mut chr_banks = chr_rom
+        let mut banks = chr_rom
             .chunks(Self::CHR_ROM_BANK_SIZE)
             .map(<[u8]>::to_vec)
             .collect::<Vec<Vec<u8>>>();
 
-        if chr_banks.is_empty() {
-            chr_banks = vec![vec![0; Self::CHR_ROM_BANK_SIZE]; 16];
+        let is_ram = banks.is_empty();
+        if is_ram {
+            banks = vec![vec![0; Self::CHR_ROM_BANK_SIZE]; 16];
         }
 
         Self {
             prg_banks,
-            chr_banks,
+            chr: Mapper001Chr { banks, is_ram },
             prg_ram_banks: vec![vec![0; Self::PRG_RAM_BANK_SIZE]; Self::PRG_RAM_BANKS],
             mirroring,
             buffer: 0,
@@ -253,60 +616,136 @@ impl Mapper001 {
         self.chr_independent_banks = data & 0x10 != 0;
     }
 
-    fn get_chr_ref(&mut self, addr: u16) -> &mut u8 {
-        let idx = addr as usize % Self::CHR_ROM_BANK_SIZE;
+    // Bank currently mapped to given PPU address
+    fn chr_bank(&self, addr: u16) -> usize {
         let bank = addr as usize / Self::CHR_ROM_BANK_SIZE;
-        let banks = self.chr_banks.len();
+        let banks = self.chr.banks.len();
         if bank == 0 {
-            &mut self.chr_banks[self.chr_bank0 % banks][idx]
+            self.chr_bank0 % banks
         } else if !self.chr_independent_banks {
-            &mut self.chr_banks[(self.chr_bank0 + 1) % banks][idx]
+            (self.chr_bank0 + 1) % banks
         } else {
-            &mut self.chr_banks[self.chr_bank1 % banks][idx]
+            self.chr_bank1 % banks
         }
     }
 
-    fn get_prg_ref(&mut self, addr: u16) -> &mut u8 {
-        let idx = addr as usize % Self::PRG_ROM_BANK_SIZE;
+    fn get_chr_ref(&mut self, addr: u16) -> &mut u8 {
+        let idx = addr as usize % Self::CHR_ROM_BANK_SIZE;
+        let bank = self.chr_bank(addr);
+        &mut self.chr.banks[bank][idx]
+    }
+
+    // Bank currently mapped to given CPU address
+    fn prg_bank(&self, addr: u16) -> usize {
         let bank = (addr - 0x8000) as usize / Self::PRG_ROM_BANK_SIZE;
         let banks = self.prg_banks.len();
 
         if bank == 0 && self.prg_mode == Mapper001PrgMode::FixFirst {
-            &mut self.prg_banks[0][idx]
+            0
         } else if bank == 0 {
-            &mut self.prg_banks[self.prg_bank0 % banks][idx]
+            self.prg_bank0 % banks
         } else if self.prg_mode == Mapper001PrgMode::SwitchBoth {
-            &mut self.prg_banks[(self.prg_bank0 + 1) % banks][idx]
+            (self.prg_bank0 + 1) % banks
         } else if self.prg_mode == Mapper001PrgMode::FixLast {
-            &mut self.prg_banks[banks - 1][idx]
+            banks - 1
         } else {
-            &mut self.prg_banks[self.prg_bank1 % banks][idx]
+            self.prg_bank1 % banks
         }
     }
+
+    // Bank numbers can come from a save state, so they are masked like the others
+    fn get_prg_ram_ref(&mut self, addr: u16) -> &mut u8 {
+        let bank = self.prg_ram_bank % Self::PRG_RAM_BANKS;
+        &mut self.prg_ram_banks[bank][addr as usize % Self::PRG_RAM_BANK_SIZE]
+    }
+
+    fn get_prg_ref(&mut self, addr: u16) -> &mut u8 {
+        let idx = addr as usize % Self::PRG_ROM_BANK_SIZE;
+        let bank = self.prg_bank(addr);
+        &mut self.prg_banks[bank][idx]
+    }
+}
+
+impl Snapshot for Mapper001 {
+    state_fields!(
+        prg_ram_banks,
+        chr,
+        mirroring,
+        buffer,
+        bit_idx,
+        prg_bank0,
+        prg_bank1,
+        prg_ram_bank,
+        chr_bank0,
+        chr_bank1,
+        chr_independent_banks,
+        prg_mode,
+    );
 }
 
 impl Mapper for Mapper001 {
-    fn trigger_event(&mut self, _event: MapperEvent) {
-        todo!("No cartridge event support yet")
+    fn cpu_read_driven(&self, addr: u16) -> bool {
+        addr >= 0x6000
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        match addr {
+            0x8000.. => Some(
+                self.prg_bank(addr) * Self::PRG_ROM_BANK_SIZE
+                    + addr as usize % Self::PRG_ROM_BANK_SIZE,
+            ),
+            _ => None,
+        }
+    }
+
+    fn prg_rom_len(&self) -> usize {
+        self.prg_banks.len() * Self::PRG_ROM_BANK_SIZE
+    }
+
+    fn chr_offset(&self, addr: u16) -> Option<usize> {
+        match addr {
+            0..=0x1FFF => Some(
+                self.chr_bank(addr) * Self::CHR_ROM_BANK_SIZE
+                    + addr as usize % Self::CHR_ROM_BANK_SIZE,
+            ),
+            _ => None,
+        }
+    }
+
+    fn chr_len(&self) -> usize {
+        self.chr.banks.len() * Self::CHR_ROM_BANK_SIZE
+    }
+
+    fn chr_writable(&self) -> bool {
+        self.chr.is_ram
+    }
+
+    fn prg_ram(&self) -> Vec<u8> {
+        self.prg_ram_banks.concat()
+    }
+
+    fn load_prg_ram(&mut self, data: &[u8]) {
+        for (bank, data) in self
+            .prg_ram_banks
+            .iter_mut()
+            .zip(data.chunks(Self::PRG_RAM_BANK_SIZE))
+        {
+            load_ram(bank, data);
+        }
     }
 
     fn read_cpu(&mut self, addr: u16) -> u8 {
         match addr {
-            0x6000..=0x7FFF => {
-                self.prg_ram_banks[self.prg_ram_bank][(addr as usize) % Self::PRG_RAM_BANK_SIZE]
-            }
+            0x6000..=0x7FFF => *self.get_prg_ram_ref(addr),
             0x8000.. => *self.get_prg_ref(addr),
-            _ => panic!("Unexpected CPU read from address {:X}", addr),
+            _ => 0,
         }
     }
 
     fn write_cpu(&mut self, addr: u16, data: u8) {
         // println!("Write {:X} to mapper address {:X}", data, addr);
         match addr {
-            0x6000..=0x7FFF => {
-                self.prg_ram_banks[self.prg_ram_bank][(addr as usize) % Self::PRG_RAM_BANK_SIZE] =
-                    data;
-            }
+            0x6000..=0x7FFF => *self.get_prg_ram_ref(addr) = data,
             0x8000.. => {
                 if data & 0x80 == 0 {
                     self.buffer |= (data as usize & 0x01) << self.bit_idx;
@@ -321,7 +760,7 @@ impl Mapper for Mapper001 {
                     self.prg_mode = Mapper001PrgMode::FixLast;
                 }
             }
-            _ => panic!("Unexpected CPU read from address {:X}", addr),
+            _ => (),
         }
     }
 
@@ -334,18 +773,157 @@ impl Mapper for Mapper001 {
 
     fn write_ppu(&mut self, addr: u16, data: u8) {
         match addr {
-            0..=0x1FFF => *self.get_chr_ref(addr) = data,
+            0..=0x1FFF if self.chr.is_ram => *self.get_chr_ref(addr) = data,
+            0..=0x1FFF => (),
             _ => panic!("PPU writing to address {:X}", addr),
         }
     }
 
     fn mirror_vram(&self, addr: u16) -> usize {
         match self.mirroring {
-            Mirroring::Vertical => mirror_vertical(addr),
+            // Four screen is only possible from the header until the control register is set
+            Mirroring::Vertical | Mirroring::FourScreen => mirror_vertical(addr),
             Mirroring::Horizontal => mirror_horizontal(addr),
             Mirroring::SingleScreenLower => mirror_single(addr, false),
             Mirroring::SingleScreenUpper => mirror_single(addr, true),
-            Mirroring::FourScreen => panic!("Unsupported mirroring for Mapper001"),
         }
     }
 }
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_supported_mappers() {
+        for mapper in 0..=255 {
+            let result = get_mapper(
+                mapper,
+                vec![0; 0x8000],
+                vec![0; 0x2000],
+                0,
+                0x2000,
+                Mirroring::Vertical,
+            );
+            assert_eq!(result.is_ok(), is_supported(mapper), "mapper {mapper}");
+        }
+    }
+
+    #[test]
+    fn test_open_bus_below_8000() {
+        let board = |mapper| {
+            get_mapper(
+                mapper,
+                vec![0; 0x8000],
+                vec![0; 0x2000],
+                0,
+                0x2000,
+                Mirroring::Vertical,
+            )
+            .unwrap()
+        };
+        for mapper in [1, 4, 10, 19, 73] {
+            assert!(board(mapper).cpu_read_driven(0x6000), "mapper {mapper}");
+        }
+        for mapper in [2, 3, 9, 75, 85, 210] {
+            assert!(!board(mapper).cpu_read_driven(0x6000), "mapper {mapper}");
+        }
+        for mapper in [0, 1, 2, 3, 4, 9, 10, 19, 73, 75, 85, 210] {
+            assert!(!board(mapper).cpu_read_driven(0x4020), "mapper {mapper}");
+            assert!(board(mapper).cpu_read_driven(0x8000), "mapper {mapper}");
+        }
+        assert!(board(19).cpu_read_driven(0x5000));
+
+        // PRG RAM enabled through VRC7 $E000 and N175 $C000
+        let mut vrc7 = board(85);
+        vrc7.write_cpu(0xE000, 0x80);
+        assert!(vrc7.cpu_read_driven(0x7FFF));
+        let mut n175 = board(210);
+        n175.write_cpu(0xC000, 1);
+        assert!(n175.cpu_read_driven(0x6000));
+    }
+
+    #[test]
+    fn test_mmc1_state_round_trip() {
+        let mut mapper = get_mapper(
+            1,
+            vec![0; 0x8000],
+            vec![],
+            0x2000,
+            0x2000,
+            Mirroring::Vertical,
+        )
+        .unwrap();
+        // Switch to horizontal mirroring, 16k fix-first mode through the serial port
+        for bit in 0..5 {
+            mapper.write_cpu(0x8000, (0b01011 >> bit) & 1);
+        }
+        mapper.write_cpu(0x6123, 0x42);
+        let state = mapper.save_state();
+
+        let mut restored = get_mapper(
+            1,
+            vec![0; 0x8000],
+            vec![],
+            0x2000,
+            0x2000,
+            Mirroring::Vertical,
+        )
+        .unwrap();
+        restored.load_state(&state).unwrap();
+        assert_eq!(restored.read_cpu(0x6123), 0x42);
+        assert_eq!(restored.save_state(), state);
+        assert_eq!(restored.inspect(), mapper.inspect());
+    }
+
+    #[test]
+    fn test_mmc1_state_skips_chr_rom() {
+        let mmc1 = |chr| {
+            get_mapper(
+                1,
+                vec![0; 0x8000],
+                vec![chr; 0x4000],
+                0,
+                0x2000,
+                Mirroring::Vertical,
+            )
+            .unwrap()
+        };
+        let mut mapper = mmc1(0x11);
+        mapper.write_cpu(0x6000, 0x42);
+        let state = mapper.save_state();
+        // No CHR banks follow the four PRG RAM banks
+        let chr_start = 4 + 4 * (4 + 0x2000);
+        assert_eq!(state[chr_start..chr_start + 4], [0; 4]);
+
+        // ROM stays as loaded from the cartridge
+        let mut restored = mmc1(0x22);
+        restored.load_state(&state).unwrap();
+        assert_eq!(restored.read_cpu(0x6000), 0x42);
+        assert_eq!(restored.read_ppu(0x0000), 0x22);
+        assert_eq!(restored.save_state(), state);
+
+        // Older states held the ROM banks as well
+        let mut old = state[..chr_start].to_vec();
+        vec![vec![0x11; 0x2000]; 2].save(&mut old);
+        old.extend_from_slice(&state[chr_start + 4..]);
+        restored.load_state(&old).unwrap();
+        assert_eq!(restored.read_ppu(0x0000), 0x22);
+    }
+
+    #[test]
+    fn test_truncated_state_fails() {
+        let mut mapper = get_mapper(
+            0,
+            vec![0; 0x4000],
+            vec![0; 0x2000],
+            0,
+            0x2000,
+            Mirroring::Vertical,
+        )
+        .unwrap();
+        let state = mapper.save_state();
+        assert!(mapper.load_state(&state[..state.len() - 1]).is_err());
+    }
+}
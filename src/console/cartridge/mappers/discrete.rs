@@ -0,0 +1,269 @@
+// Discrete logic boards, where a latch takes the bank number from any write to ROM.
+// The ROM drives the data bus at the same time, so unless the board keeps it from
+// doing so the latch sees the written value ANDed with the ROM byte at that address.
+
+use super::{mirror_horizontal, mirror_vertical, Chr, Mapper, Mirroring, Snapshot};
+use crate::macros::state_fields;
+
+const PRG_BANK_SIZE: usize = 16 * 1024;
+const CHR_BANK_SIZE: usize = 8 * 1024;
+
+// Value a latch sees when `data` is written to PRG ROM holding `rom`
+const fn latched(data: u8, rom: u8, bus_conflicts: bool) -> u8 {
+    if bus_conflicts {
+        data & rom
+    } else {
+        data
+    }
+}
+
+// Four screen VRAM isn't emulated and the mirroring is soldered on the board
+fn mirror(mirroring: &Mirroring, addr: u16) -> usize {
+    match mirroring {
+        Mirroring::Horizontal => mirror_horizontal(addr),
+        _ => mirror_vertical(addr),
+    }
+}
+
+/// UNROM and UOROM: 16 kB PRG bank switched at $8000, the last one fixed at $C000
+pub struct Mapper002 {
+    prg_rom: Vec<u8>,
+    chr: Chr,
+    mirroring: Mirroring,
+    bus_conflicts: bool,
+    prg_bank: u8,
+}
+
+impl Mapper002 {
+    pub fn new(
+        prg_rom: Vec<u8>,
+        chr_rom: Vec<u8>,
+        chr_ram_size: usize,
+        mirroring: Mirroring,
+        bus_conflicts: bool,
+    ) -> Self {
+        Self {
+            prg_rom,
+            chr: Chr::new(chr_rom, chr_ram_size),
+            mirroring,
+            bus_conflicts,
+            prg_bank: 0,
+        }
+    }
+
+    fn prg_offset(&self, addr: u16) -> usize {
+        let banks = (self.prg_rom.len() / PRG_BANK_SIZE).max(1);
+        let bank = match addr {
+            0x8000..=0xBFFF => self.prg_bank as usize % banks,
+            _ => banks - 1,
+        };
+        (bank * PRG_BANK_SIZE + addr as usize % PRG_BANK_SIZE) % self.prg_rom.len()
+    }
+}
+
+impl Snapshot for Mapper002 {
+    state_fields!(chr, prg_bank);
+}
+
+impl Mapper for Mapper002 {
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        match addr {
+            0x8000.. => Some(self.prg_offset(addr)),
+            _ => None,
+        }
+    }
+
+    fn prg_rom_len(&self) -> usize {
+        self.prg_rom.len()
+    }
+
+    fn chr_offset(&self, addr: u16) -> Option<usize> {
+        match addr {
+            0..=0x1FFF => Some(addr as usize % self.chr.len()),
+            _ => None,
+        }
+    }
+
+    fn chr_len(&self) -> usize {
+        self.chr.len()
+    }
+
+    fn chr_writable(&self) -> bool {
+        self.chr.is_ram()
+    }
+
+    fn set_bus_conflicts(&mut self, enabled: bool) {
+        self.bus_conflicts = enabled;
+    }
+
+    fn read_cpu(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x8000.. => self.prg_rom[self.prg_offset(addr)],
+            _ => 0,
+        }
+    }
+
+    fn write_cpu(&mut self, addr: u16, data: u8) {
+        if addr >= 0x8000 {
+            let rom = self.prg_rom[self.prg_offset(addr)];
+            self.prg_bank = latched(data, rom, self.bus_conflicts);
+        }
+    }
+
+    fn read_ppu(&mut self, addr: u16) -> u8 {
+        let offset = addr as usize % CHR_BANK_SIZE % self.chr.len();
+        self.chr.read(offset)
+    }
+
+    fn write_ppu(&mut self, addr: u16, data: u8) {
+        let offset = addr as usize % CHR_BANK_SIZE % self.chr.len();
+        self.chr.write(offset, data);
+    }
+
+    fn mirror_vram(&self, addr: u16) -> usize {
+        mirror(&self.mirroring, addr)
+    }
+}
+
+/// CNROM: fixed PRG ROM and an 8 kB CHR bank switched by writes to $8000-$FFFF
+pub struct Mapper003 {
+    prg_rom: Vec<u8>,
+    chr: Chr,
+    mirroring: Mirroring,
+    bus_conflicts: bool,
+    chr_bank: u8,
+}
+
+impl Mapper003 {
+    pub fn new(
+        prg_rom: Vec<u8>,
+        chr_rom: Vec<u8>,
+        chr_ram_size: usize,
+        mirroring: Mirroring,
+        bus_conflicts: bool,
+    ) -> Self {
+        Self {
+            prg_rom,
+            chr: Chr::new(chr_rom, chr_ram_size),
+            mirroring,
+            bus_conflicts,
+            chr_bank: 0,
+        }
+    }
+
+    fn chr_index(&self, addr: u16) -> usize {
+        let banks = (self.chr.len() / CHR_BANK_SIZE).max(1);
+        let bank = self.chr_bank as usize % banks;
+        (bank * CHR_BANK_SIZE + addr as usize % CHR_BANK_SIZE) % self.chr.len()
+    }
+}
+
+impl Snapshot for Mapper003 {
+    state_fields!(chr, chr_bank);
+}
+
+impl Mapper for Mapper003 {
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        match addr {
+            0x8000.. => Some((addr - 0x8000) as usize % self.prg_rom.len()),
+            _ => None,
+        }
+    }
+
+    fn prg_rom_len(&self) -> usize {
+        self.prg_rom.len()
+    }
+
+    fn chr_offset(&self, addr: u16) -> Option<usize> {
+        match addr {
+            0..=0x1FFF => Some(self.chr_index(addr)),
+            _ => None,
+        }
+    }
+
+    fn chr_len(&self) -> usize {
+        self.chr.len()
+    }
+
+    fn chr_writable(&self) -> bool {
+        self.chr.is_ram()
+    }
+
+    fn set_bus_conflicts(&mut self, enabled: bool) {
+        self.bus_conflicts = enabled;
+    }
+
+    fn read_cpu(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x8000.. => self.prg_rom[(addr - 0x8000) as usize % self.prg_rom.len()],
+            _ => 0,
+        }
+    }
+
+    fn write_cpu(&mut self, addr: u16, data: u8) {
+        if addr >= 0x8000 {
+            let rom = self.prg_rom[(addr - 0x8000) as usize % self.prg_rom.len()];
+            self.chr_bank = latched(data, rom, self.bus_conflicts);
+        }
+    }
+
+    fn read_ppu(&mut self, addr: u16) -> u8 {
+        self.chr.read(self.chr_index(addr))
+    }
+
+    fn write_ppu(&mut self, addr: u16, data: u8) {
+        let offset = self.chr_index(addr);
+        self.chr.write(offset, data);
+    }
+
+    fn mirror_vram(&self, addr: u16) -> usize {
+        mirror(&self.mirroring, addr)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::banked;
+    use super::*;
+
+    #[test]
+    fn test_unrom_banking() {
+        let prg = banked(8, PRG_BANK_SIZE);
+        let mut mapper = Mapper002::new(prg, vec![], 0x2000, Mirroring::Vertical, false);
+        assert_eq!(mapper.read_cpu(0xC000), 7);
+        mapper.write_cpu(0x8000, 3);
+        assert_eq!(mapper.read_cpu(0x8000), 3);
+        assert_eq!(mapper.read_cpu(0xFFFF), 7);
+        mapper.write_ppu(0x0010, 0x42);
+        assert_eq!(mapper.read_ppu(0x0010), 0x42);
+    }
+
+    #[test]
+    fn test_bus_conflicts() {
+        // Bank 0 is all zeros, so with conflicts writes there always select bank 0
+        let prg = banked(8, PRG_BANK_SIZE);
+        let mut mapper = Mapper002::new(prg.clone(), vec![], 0x2000, Mirroring::Vertical, true);
+        mapper.write_cpu(0x8000, 3);
+        assert_eq!(mapper.read_cpu(0x8000), 0);
+        mapper.write_cpu(0xC000, 0x0E);
+        assert_eq!(mapper.read_cpu(0x8000), 6);
+        let mut mapper = Mapper002::new(prg, vec![], 0x2000, Mirroring::Vertical, false);
+        mapper.write_cpu(0x8000, 3);
+        assert_eq!(mapper.read_cpu(0x8000), 3);
+
+        let mut prg = vec![0xFF; 0x8000];
+        prg[0x10] = 0x01;
+        let chr = banked(4, CHR_BANK_SIZE);
+        let mut mapper = Mapper003::new(prg, chr, 0, Mirroring::Horizontal, true);
+        mapper.write_cpu(0x8010, 0x03);
+        assert_eq!(mapper.read_ppu(0x0000), 1);
+        mapper.write_cpu(0x8011, 0x03);
+        assert_eq!(mapper.read_ppu(0x1FFF), 3);
+        // CHR ROM can't be written
+        mapper.write_ppu(0x0000, 0x42);
+        assert_eq!(mapper.read_ppu(0x0000), 3);
+        mapper.set_bus_conflicts(false);
+        mapper.write_cpu(0x8010, 0x02);
+        assert_eq!(mapper.read_ppu(0x0000), 2);
+    }
+}
@@ -0,0 +1,686 @@
+// Konami VRC boards. VRC1 and VRC7 switch three 8 kB PRG banks at $8000, $A000 and
+// $C000 with the last 8 kB fixed, VRC3 switches 16 kB at $8000. VRC7 has the IRQ
+// counter also found on VRC4 and VRC6, and an FM synthesizer.
+
+mod opll;
+
+use eyre::Result;
+
+use super::{
+    load_ram, mirror_horizontal, mirror_single, mirror_vertical, Chr, Mapper, MapperEvent,
+    Mirroring, Snapshot, StateField,
+};
+use crate::console::apu::mixer::Sample;
+use crate::macros::state_fields;
+use opll::Opll;
+
+const PRG_BANK_SIZE: usize = 8 * 1024;
+
+/// Offset into PRG ROM for boards with three 8 kB banks and the last one fixed
+fn prg_offset(prg_rom: &[u8], banks: [u8; 3], addr: u16) -> usize {
+    let count = prg_rom.len() / PRG_BANK_SIZE;
+    let bank = match addr {
+        0x8000..=0xDFFF => banks[(addr as usize - 0x8000) / PRG_BANK_SIZE] as usize,
+        _ => count - 1,
+    };
+    (bank % count) * PRG_BANK_SIZE + addr as usize % PRG_BANK_SIZE
+}
+
+/// IRQ counter of VRC4, VRC6 and VRC7. Counts up to $FF either every CPU cycle or
+/// once per scanline, timed with a prescaler of 341 PPU dots.
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Default)]
+struct VrcIrq {
+    latch: u8,
+    counter: u8,
+    prescaler: u16,
+    enable: bool,
+    enable_after_ack: bool,
+    cycle_mode: bool,
+    irq: bool,
+}
+
+impl VrcIrq {
+    fn write_control(&mut self, data: u8) {
+        self.enable_after_ack = data & 0x01 != 0;
+        self.enable = data & 0x02 != 0;
+        self.cycle_mode = data & 0x04 != 0;
+        if self.enable {
+            self.counter = self.latch;
+            self.prescaler = 341;
+        }
+        self.irq = false;
+    }
+
+    fn acknowledge(&mut self) {
+        self.irq = false;
+        self.enable = self.enable_after_ack;
+    }
+
+    fn tick(&mut self) {
+        if !self.enable {
+            return;
+        }
+        if !self.cycle_mode {
+            if self.prescaler > 3 {
+                self.prescaler -= 3;
+                return;
+            }
+            self.prescaler += 338;
+        }
+        if self.counter == 0xFF {
+            self.counter = self.latch;
+            self.irq = true;
+        } else {
+            self.counter += 1;
+        }
+    }
+}
+
+impl StateField for VrcIrq {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.latch.save(out);
+        self.counter.save(out);
+        self.prescaler.save(out);
+        self.enable.save(out);
+        self.enable_after_ack.save(out);
+        self.cycle_mode.save(out);
+        self.irq.save(out);
+    }
+    fn load(&mut self, input: &mut &[u8]) -> Result<()> {
+        self.latch.load(input)?;
+        self.counter.load(input)?;
+        self.prescaler.load(input)?;
+        self.enable.load(input)?;
+        self.enable_after_ack.load(input)?;
+        self.cycle_mode.load(input)?;
+        self.irq.load(input)
+    }
+    fn describe(&self) -> String {
+        format!(
+            "{:02X}/{:02X} {}{}",
+            self.counter,
+            self.latch,
+            if self.enable { "on" } else { "off" },
+            if self.cycle_mode { " cycle" } else { "" }
+        )
+    }
+}
+
+/// Mapper 73, VRC3. 16 bit IRQ counter clocked by the CPU, CHR RAM only.
+#[allow(clippy::struct_excessive_bools)]
+pub struct Mapper073 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Chr,
+    mirroring: Mirroring,
+    prg_bank: u8,
+
+    irq_latch: u16,
+    irq_counter: u16,
+    irq_enable: bool,
+    irq_enable_after_ack: bool,
+    /// Only the low 8 bits count and reload
+    irq_8bit: bool,
+    irq: bool,
+}
+
+impl Mapper073 {
+    const PRG_BANK_SIZE: usize = 16 * 1024;
+
+    pub fn new(
+        prg_rom: Vec<u8>,
+        chr_rom: Vec<u8>,
+        chr_ram_size: usize,
+        mirroring: Mirroring,
+    ) -> Self {
+        Self {
+            prg_rom,
+            prg_ram: vec![0; 0x2000],
+            chr: Chr::new(chr_rom, chr_ram_size),
+            mirroring,
+            prg_bank: 0,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_enable: false,
+            irq_enable_after_ack: false,
+            irq_8bit: false,
+            irq: false,
+        }
+    }
+
+    fn prg_offset(&self, addr: u16) -> usize {
+        let count = self.prg_rom.len() / Self::PRG_BANK_SIZE;
+        let bank = match addr {
+            0x8000..=0xBFFF => self.prg_bank as usize % count,
+            _ => count - 1,
+        };
+        bank * Self::PRG_BANK_SIZE + addr as usize % Self::PRG_BANK_SIZE
+    }
+}
+
+impl Snapshot for Mapper073 {
+    state_fields!(
+        prg_ram,
+        chr,
+        prg_bank,
+        irq_latch,
+        irq_counter,
+        irq_enable,
+        irq_enable_after_ack,
+        irq_8bit,
+        irq,
+    );
+}
+
+impl Mapper for Mapper073 {
+    fn cpu_read_driven(&self, addr: u16) -> bool {
+        addr >= 0x6000
+    }
+
+    fn trigger_event(&mut self, event: MapperEvent) {
+        if !matches!(event, MapperEvent::CpuTick) || !self.irq_enable {
+            return;
+        }
+        if self.irq_8bit {
+            let low = (self.irq_counter as u8).wrapping_add(1);
+            if low == 0 {
+                self.irq_counter = (self.irq_counter & 0xFF00) | (self.irq_latch & 0xFF);
+                self.irq = true;
+            } else {
+                self.irq_counter = (self.irq_counter & 0xFF00) | low as u16;
+            }
+        } else if self.irq_counter == 0xFFFF {
+            self.irq_counter = self.irq_latch;
+            self.irq = true;
+        } else {
+            self.irq_counter += 1;
+        }
+    }
+
+    fn irq_active(&self) -> bool {
+        self.irq
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        match addr {
+            0x8000.. => Some(self.prg_offset(addr)),
+            _ => None,
+        }
+    }
+
+    fn prg_rom_len(&self) -> usize {
+        self.prg_rom.len()
+    }
+
+    fn chr_offset(&self, addr: u16) -> Option<usize> {
+        match addr {
+            0..=0x1FFF => Some(addr as usize % self.chr.len()),
+            _ => None,
+        }
+    }
+
+    fn chr_len(&self) -> usize {
+        self.chr.len()
+    }
+
+    fn chr_writable(&self) -> bool {
+        self.chr.is_ram()
+    }
+
+    fn prg_ram(&self) -> Vec<u8> {
+        self.prg_ram.clone()
+    }
+
+    fn load_prg_ram(&mut self, data: &[u8]) {
+        load_ram(&mut self.prg_ram, data);
+    }
+
+    fn read_cpu(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF => self.prg_ram[addr as usize - 0x6000],
+            0x8000.. => self.prg_rom[self.prg_offset(addr)],
+            _ => 0,
+        }
+    }
+
+    fn write_cpu(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7FFF => self.prg_ram[addr as usize - 0x6000] = data,
+            // Latch is written a nibble at a time, lowest first
+            0x8000..=0xBFFF => {
+                let shift = (addr - 0x8000) / 0x1000 * 4;
+                self.irq_latch = (self.irq_latch & !(0xF << shift)) | (data as u16 & 0xF) << shift;
+            }
+            0xC000..=0xCFFF => {
+                self.irq_enable_after_ack = data & 0x01 != 0;
+                self.irq_enable = data & 0x02 != 0;
+                self.irq_8bit = data & 0x04 != 0;
+                if self.irq_enable {
+                    self.irq_counter = self.irq_latch;
+                }
+                self.irq = false;
+            }
+            0xD000..=0xDFFF => {
+                self.irq = false;
+                self.irq_enable = self.irq_enable_after_ack;
+            }
+            0xF000.. => self.prg_bank = data & 0x07,
+            _ => (),
+        }
+    }
+
+    fn read_ppu(&mut self, addr: u16) -> u8 {
+        self.chr.read(addr as usize % self.chr.len())
+    }
+
+    fn write_ppu(&mut self, addr: u16, data: u8) {
+        let offset = addr as usize % self.chr.len();
+        self.chr.write(offset, data);
+    }
+
+    fn mirror_vram(&self, addr: u16) -> usize {
+        match self.mirroring {
+            Mirroring::Horizontal => mirror_horizontal(addr),
+            _ => mirror_vertical(addr),
+        }
+    }
+}
+
+/// Mapper 75, VRC1. Two 4 kB CHR banks, no PRG RAM or IRQ.
+pub struct Mapper075 {
+    prg_rom: Vec<u8>,
+    chr: Chr,
+    prg_banks: [u8; 3],
+    chr_banks: [u8; 2],
+    mirroring: Mirroring,
+}
+
+impl Mapper075 {
+    const CHR_BANK_SIZE: usize = 4 * 1024;
+
+    pub fn new(
+        prg_rom: Vec<u8>,
+        chr_rom: Vec<u8>,
+        chr_ram_size: usize,
+        mirroring: Mirroring,
+    ) -> Self {
+        Self {
+            prg_rom,
+            chr: Chr::new(chr_rom, chr_ram_size),
+            prg_banks: [0, 1, 2],
+            chr_banks: [0, 1],
+            mirroring,
+        }
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        let bank = self.chr_banks[addr as usize / Self::CHR_BANK_SIZE] as usize;
+        (bank * Self::CHR_BANK_SIZE + addr as usize % Self::CHR_BANK_SIZE) % self.chr.len()
+    }
+}
+
+impl Snapshot for Mapper075 {
+    state_fields!(chr, prg_banks, chr_banks, mirroring);
+}
+
+impl Mapper for Mapper075 {
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        match addr {
+            0x8000.. => Some(prg_offset(&self.prg_rom, self.prg_banks, addr)),
+            _ => None,
+        }
+    }
+
+    fn prg_rom_len(&self) -> usize {
+        self.prg_rom.len()
+    }
+
+    fn chr_offset(&self, addr: u16) -> Option<usize> {
+        match addr {
+            0..=0x1FFF => Some(self.chr_offset(addr)),
+            _ => None,
+        }
+    }
+
+    fn chr_len(&self) -> usize {
+        self.chr.len()
+    }
+
+    fn chr_writable(&self) -> bool {
+        self.chr.is_ram()
+    }
+
+    fn read_cpu(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x8000.. => self.prg_rom[prg_offset(&self.prg_rom, self.prg_banks, addr)],
+            _ => 0,
+        }
+    }
+
+    fn write_cpu(&mut self, addr: u16, data: u8) {
+        match addr & 0xF000 {
+            0x8000 => self.prg_banks[0] = data & 0x0F,
+            // Mirroring and the high bits of both CHR banks
+            0x9000 => {
+                self.mirroring = if data & 0x01 == 0 {
+                    Mirroring::Vertical
+                } else {
+                    Mirroring::Horizontal
+                };
+                self.chr_banks[0] = (self.chr_banks[0] & 0x0F) | (data & 0x02) << 3;
+                self.chr_banks[1] = (self.chr_banks[1] & 0x0F) | (data & 0x04) << 2;
+            }
+            0xA000 => self.prg_banks[1] = data & 0x0F,
+            0xC000 => self.prg_banks[2] = data & 0x0F,
+            0xE000 => self.chr_banks[0] = (self.chr_banks[0] & 0x10) | (data & 0x0F),
+            0xF000 => self.chr_banks[1] = (self.chr_banks[1] & 0x10) | (data & 0x0F),
+            _ => (),
+        }
+    }
+
+    fn read_ppu(&mut self, addr: u16) -> u8 {
+        self.chr.read(self.chr_offset(addr))
+    }
+
+    fn write_ppu(&mut self, addr: u16, data: u8) {
+        let offset = self.chr_offset(addr);
+        self.chr.write(offset, data);
+    }
+
+    fn mirror_vram(&self, addr: u16) -> usize {
+        match self.mirroring {
+            Mirroring::Horizontal => mirror_horizontal(addr),
+            _ => mirror_vertical(addr),
+        }
+    }
+}
+
+/// Mapper 85, VRC7. Eight 1 kB CHR banks, 8 kB PRG RAM and FM audio.
+pub struct Mapper085 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Chr,
+    prg_banks: [u8; 3],
+    chr_banks: [u8; 8],
+    mirroring: Mirroring,
+    prg_ram_enable: bool,
+    irq: VrcIrq,
+    audio: Opll,
+}
+
+impl Mapper085 {
+    const CHR_BANK_SIZE: usize = 1024;
+
+    pub fn new(
+        prg_rom: Vec<u8>,
+        chr_rom: Vec<u8>,
+        chr_ram_size: usize,
+        mirroring: Mirroring,
+    ) -> Self {
+        Self {
+            prg_rom,
+            prg_ram: vec![0; 0x2000],
+            chr: Chr::new(chr_rom, chr_ram_size),
+            prg_banks: [0, 1, 2],
+            chr_banks: [0, 1, 2, 3, 4, 5, 6, 7],
+            mirroring,
+            prg_ram_enable: false,
+            irq: VrcIrq::default(),
+            audio: Opll::new(),
+        }
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        let bank = self.chr_banks[addr as usize / Self::CHR_BANK_SIZE] as usize;
+        (bank * Self::CHR_BANK_SIZE + addr as usize % Self::CHR_BANK_SIZE) % self.chr.len()
+    }
+
+    fn write_control(&mut self, data: u8) {
+        self.mirroring = match data & 0x03 {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleScreenLower,
+            _ => Mirroring::SingleScreenUpper,
+        };
+        self.audio.set_reset(data & 0x40 != 0);
+        self.prg_ram_enable = data & 0x80 != 0;
+    }
+}
+
+impl Snapshot for Mapper085 {
+    state_fields!(
+        prg_ram,
+        chr,
+        prg_banks,
+        chr_banks,
+        mirroring,
+        prg_ram_enable,
+        irq,
+        audio,
+    );
+}
+
+impl Mapper for Mapper085 {
+    fn cpu_read_driven(&self, addr: u16) -> bool {
+        match addr {
+            0x6000..=0x7FFF => self.prg_ram_enable,
+            0x8000.. => true,
+            _ => false,
+        }
+    }
+
+    fn trigger_event(&mut self, event: MapperEvent) {
+        if let MapperEvent::CpuTick = event {
+            self.irq.tick();
+            self.audio.tick();
+        }
+    }
+
+    fn irq_active(&self) -> bool {
+        self.irq.irq
+    }
+
+    fn audio_output(&self) -> Sample {
+        self.audio.output()
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        match addr {
+            0x8000.. => Some(prg_offset(&self.prg_rom, self.prg_banks, addr)),
+            _ => None,
+        }
+    }
+
+    fn prg_rom_len(&self) -> usize {
+        self.prg_rom.len()
+    }
+
+    fn chr_offset(&self, addr: u16) -> Option<usize> {
+        match addr {
+            0..=0x1FFF => Some(self.chr_offset(addr)),
+            _ => None,
+        }
+    }
+
+    fn chr_len(&self) -> usize {
+        self.chr.len()
+    }
+
+    fn chr_writable(&self) -> bool {
+        self.chr.is_ram()
+    }
+
+    fn prg_ram(&self) -> Vec<u8> {
+        self.prg_ram.clone()
+    }
+
+    fn load_prg_ram(&mut self, data: &[u8]) {
+        load_ram(&mut self.prg_ram, data);
+    }
+
+    fn read_cpu(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_enable => self.prg_ram[addr as usize - 0x6000],
+            0x8000.. => self.prg_rom[prg_offset(&self.prg_rom, self.prg_banks, addr)],
+            _ => 0,
+        }
+    }
+
+    fn write_cpu(&mut self, addr: u16, data: u8) {
+        if let 0x6000..=0x7FFF = addr {
+            if self.prg_ram_enable {
+                self.prg_ram[addr as usize - 0x6000] = data;
+            }
+            return;
+        }
+        // Each 4 kB page has two registers, the second at A4 on VRC7a or A3 on VRC7b
+        let second = addr & 0x18 != 0;
+        match (addr & 0xF000, second) {
+            (0x8000, false) => self.prg_banks[0] = data & 0x3F,
+            (0x8000, true) => self.prg_banks[1] = data & 0x3F,
+            (0x9000, false) => self.prg_banks[2] = data & 0x3F,
+            (0x9000, true) if addr & 0x20 == 0 => self.audio.select(data),
+            (0x9000, true) => self.audio.write(data),
+            (page @ 0xA000..=0xDFFF, second) => {
+                self.chr_banks[(page as usize - 0xA000) / 0x800 + second as usize] = data;
+            }
+            (0xE000, false) => self.write_control(data),
+            (0xE000, true) => self.irq.latch = data,
+            (0xF000, false) => self.irq.write_control(data),
+            (0xF000, true) => self.irq.acknowledge(),
+            _ => (),
+        }
+    }
+
+    fn read_ppu(&mut self, addr: u16) -> u8 {
+        self.chr.read(self.chr_offset(addr))
+    }
+
+    fn write_ppu(&mut self, addr: u16, data: u8) {
+        let offset = self.chr_offset(addr);
+        self.chr.write(offset, data);
+    }
+
+    fn mirror_vram(&self, addr: u16) -> usize {
+        match self.mirroring {
+            Mirroring::Vertical | Mirroring::FourScreen => mirror_vertical(addr),
+            Mirroring::Horizontal => mirror_horizontal(addr),
+            Mirroring::SingleScreenLower => mirror_single(addr, false),
+            Mirroring::SingleScreenUpper => mirror_single(addr, true),
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod test {
+    use super::super::banked;
+    use super::*;
+
+    #[test]
+    fn test_vrc1_banking() {
+        let chr = banked(32, 0x1000);
+        let mut mapper = Mapper075::new(banked(16, PRG_BANK_SIZE), chr, 0, Mirroring::Vertical);
+        mapper.write_cpu(0xA000, 5);
+        assert_eq!(mapper.read_cpu(0xA000), 5);
+        assert_eq!(mapper.read_cpu(0xE000), 15);
+
+        mapper.write_cpu(0xF000, 0x03);
+        mapper.write_cpu(0x9000, 0x05);
+        assert_eq!(mapper.read_ppu(0x1000), 0x13);
+        assert_eq!(mapper.mirror_vram(0x2400), mapper.mirror_vram(0x2000));
+    }
+
+    #[test]
+    fn test_vrc3_irq() {
+        let mut mapper = Mapper073::new(
+            banked(4, PRG_BANK_SIZE),
+            vec![],
+            0x2000,
+            Mirroring::Vertical,
+        );
+        for (addr, nibble) in [(0x8000, 0xE), (0x9000, 0xF), (0xA000, 0xF), (0xB000, 0xF)] {
+            mapper.write_cpu(addr, nibble);
+        }
+        mapper.write_cpu(0xC000, 0x02);
+        mapper.trigger_event(MapperEvent::CpuTick);
+        assert!(!mapper.irq_active());
+        mapper.trigger_event(MapperEvent::CpuTick);
+        assert!(mapper.irq_active());
+        assert_eq!(mapper.irq_counter, 0xFFFE);
+        mapper.write_cpu(0xD000, 0);
+        assert!(!mapper.irq_active());
+        assert!(!mapper.irq_enable);
+
+        // 8 bit mode leaves the high byte alone
+        mapper.write_cpu(0xC000, 0x06);
+        mapper.trigger_event(MapperEvent::CpuTick);
+        mapper.trigger_event(MapperEvent::CpuTick);
+        assert_eq!(mapper.irq_counter, 0xFFFE);
+        assert!(mapper.irq_active());
+    }
+
+    #[test]
+    fn test_vrc7_registers_and_scanline_irq() {
+        let mut mapper = Mapper085::new(
+            banked(8, PRG_BANK_SIZE),
+            vec![0; 0x2000],
+            0,
+            Mirroring::Vertical,
+        );
+        mapper.write_cpu(0x8010, 3);
+        mapper.write_cpu(0x9000, 4);
+        assert_eq!(mapper.read_cpu(0xA000), 3);
+        assert_eq!(mapper.read_cpu(0xC000), 4);
+        mapper.write_cpu(0x8008, 5);
+        assert_eq!(mapper.read_cpu(0xA000), 5);
+
+        mapper.write_cpu(0x6000, 0x42);
+        assert_eq!(mapper.read_cpu(0x6000), 0);
+        mapper.write_cpu(0xE000, 0x81);
+        mapper.write_cpu(0x6000, 0x42);
+        assert_eq!(mapper.read_cpu(0x6000), 0x42);
+        assert_eq!(mapper.mirror_vram(0x2400), mapper.mirror_vram(0x2000));
+
+        // Fires on the second scanline, 341 dots being 113.67 CPU cycles
+        mapper.write_cpu(0xE010, 0xFE);
+        mapper.write_cpu(0xF000, 0x02);
+        for _ in 0..227 {
+            mapper.trigger_event(MapperEvent::CpuTick);
+        }
+        assert!(!mapper.irq_active());
+        mapper.trigger_event(MapperEvent::CpuTick);
+        assert!(mapper.irq_active());
+        mapper.write_cpu(0xF010, 0);
+        assert!(!mapper.irq_active());
+    }
+
+    #[test]
+    fn test_vrc7_state_round_trip() {
+        let mut mapper = Mapper085::new(
+            banked(8, PRG_BANK_SIZE),
+            vec![],
+            0x2000,
+            Mirroring::Vertical,
+        );
+        mapper.write_cpu(0x9010, 0x30);
+        mapper.write_cpu(0x9030, 0x20);
+        mapper.write_cpu(0x9010, 0x10);
+        mapper.write_cpu(0x9030, 0xAC);
+        mapper.write_cpu(0x9010, 0x20);
+        mapper.write_cpu(0x9030, 0x18);
+        for _ in 0..1000 {
+            mapper.trigger_event(MapperEvent::CpuTick);
+        }
+        let state = mapper.save_state();
+        let mut restored = Mapper085::new(
+            banked(8, PRG_BANK_SIZE),
+            vec![],
+            0x2000,
+            Mirroring::Vertical,
+        );
+        restored.load_state(&state).unwrap();
+        assert_eq!(restored.save_state(), state);
+    }
+}
@@ -0,0 +1,481 @@
+// VRC7 audio, a cut down Yamaha YM2413 (OPLL): six two-operator FM channels playing
+// one custom or fifteen built-in instruments, without rhythm mode. Runs in floating
+// point at the chip's rate of one sample per 36 CPU cycles, close to the hardware's
+// sound but not bit exact.
+
+use std::f32::consts::TAU;
+
+use eyre::{eyre, Result};
+
+use super::super::{take, StateField};
+use crate::console::apu::mixer::{self, Sample};
+
+const CHANNELS: usize = 6;
+const CLOCK_DIVIDER: u8 = 36;
+const SAMPLE_RATE: f32 = crate::CPU_FREQ as f32 / CLOCK_DIVIDER as f32;
+
+// Built-in instruments 1-15, as dumped from the VRC7
+#[rustfmt::skip]
+const PATCHES: [[u8; 8]; 15] = [
+    [0x03, 0x21, 0x05, 0x06, 0xE8, 0x81, 0x42, 0x27],
+    [0x13, 0x41, 0x14, 0x0D, 0xD8, 0xF6, 0x23, 0x12],
+    [0x11, 0x11, 0x08, 0x08, 0xFA, 0xB2, 0x20, 0x12],
+    [0x31, 0x61, 0x0C, 0x07, 0xA8, 0x64, 0x61, 0x27],
+    [0x32, 0x21, 0x1E, 0x06, 0xE1, 0x76, 0x01, 0x28],
+    [0x02, 0x01, 0x06, 0x00, 0xA3, 0xE2, 0xF4, 0xF4],
+    [0x21, 0x61, 0x1D, 0x07, 0x82, 0x81, 0x11, 0x07],
+    [0x23, 0x21, 0x22, 0x17, 0xA2, 0x72, 0x01, 0x17],
+    [0x35, 0x11, 0x25, 0x00, 0x40, 0x73, 0x72, 0x01],
+    [0xB5, 0x01, 0x0F, 0x0F, 0xA8, 0xA5, 0x51, 0x02],
+    [0x17, 0xC1, 0x24, 0x07, 0xF8, 0xF8, 0x22, 0x12],
+    [0x71, 0x23, 0x11, 0x06, 0x65, 0x74, 0x18, 0x16],
+    [0x01, 0x02, 0xD3, 0x05, 0xC9, 0x95, 0x03, 0x02],
+    [0x61, 0x63, 0x0C, 0x00, 0x94, 0xC0, 0x33, 0xF6],
+    [0x21, 0x72, 0x0D, 0x00, 0xC1, 0xD5, 0x56, 0x06],
+];
+
+// Frequency multiplier of each MULT value, doubled so 0.5 fits
+const MULTIPLIERS: [u8; 16] = [1, 2, 4, 6, 8, 10, 12, 14, 16, 18, 20, 20, 24, 24, 30, 30];
+
+// Key scale attenuation in dB at block 7, by the top four F-number bits
+const KEY_SCALE_DB: [f32; 16] = [
+    0.0, 9.0, 12.0, 13.875, 15.0, 16.125, 16.875, 17.625, 18.0, 18.75, 19.125, 19.5, 19.875, 20.25,
+    20.625, 21.0,
+];
+
+// Envelopes count attenuation in 0.375 dB steps, 128 of them silence the operator
+const ENVELOPE_STEP_DB: f32 = 0.375;
+const ENVELOPE_SILENT: f32 = 128.0;
+
+const VIBRATO_HZ: f32 = 6.4;
+const VIBRATO_CENTS: f32 = 14.0;
+const TREMOLO_HZ: f32 = 3.7;
+const TREMOLO_DB: f32 = 4.8;
+
+// Full scale modulation moves the carrier four cycles, feedback two
+const MODULATION_CYCLES: f32 = 4.0;
+const FEEDBACK_CYCLES: f32 = 2.0;
+
+// One channel at full volume in the APU mixer's scale, a bit below a pulse channel
+const CHANNEL_LEVEL: f32 = 0.06;
+
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+enum Stage {
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+    #[default]
+    Off,
+}
+
+/// Parameters an instrument gives one of the two operators
+#[allow(clippy::struct_excessive_bools)]
+struct OperatorPatch {
+    tremolo: bool,
+    vibrato: bool,
+    /// Holds at the sustain level until key off instead of decaying
+    sustained: bool,
+    key_scale_rate: bool,
+    multiplier: u8,
+    key_scale_level: u8,
+    half_sine: bool,
+    attack: u8,
+    decay: u8,
+    sustain_level: u8,
+    release: u8,
+}
+
+impl OperatorPatch {
+    fn new(patch: [u8; 8], carrier: bool) -> Self {
+        let idx = carrier as usize;
+        Self {
+            tremolo: patch[idx] & 0x80 != 0,
+            vibrato: patch[idx] & 0x40 != 0,
+            sustained: patch[idx] & 0x20 != 0,
+            key_scale_rate: patch[idx] & 0x10 != 0,
+            multiplier: MULTIPLIERS[patch[idx] as usize & 0x0F],
+            key_scale_level: patch[2 + idx] >> 6,
+            half_sine: patch[3] & if carrier { 0x10 } else { 0x08 } != 0,
+            attack: patch[4 + idx] >> 4,
+            decay: patch[4 + idx] & 0x0F,
+            sustain_level: patch[6 + idx] >> 4,
+            release: patch[6 + idx] & 0x0F,
+        }
+    }
+}
+
+/// Pitch and key state of a channel, from registers $10-$25
+#[derive(Clone, Copy)]
+struct Pitch {
+    fnum: u16,
+    block: u8,
+    /// Sustain bit, slows down the release after key off
+    sustain: bool,
+}
+
+impl Pitch {
+    // Attenuation rates are 0-63, rate 4 moves one envelope step per 4096 samples and
+    // every 4 more doubles that
+    fn rate(self, value: u8, key_scale_rate: bool) -> u8 {
+        if value == 0 {
+            return 0;
+        }
+        let scale = (self.block << 1) | (self.fnum >> 8) as u8;
+        let scale = if key_scale_rate { scale } else { scale >> 2 };
+        (4 * value + scale).min(63)
+    }
+
+    fn key_scale_db(self, level: u8) -> f32 {
+        if level == 0 {
+            return 0.0;
+        }
+        let db = KEY_SCALE_DB[self.fnum as usize >> 5] - 6.0 * (7 - self.block) as f32;
+        db.max(0.0) / (1 << (3 - level)) as f32
+    }
+}
+
+fn steps_per_sample(rate: u8) -> f32 {
+    if rate < 4 {
+        return 0.0;
+    }
+    (4 + (rate & 3)) as f32 / 4.0 * ((rate >> 2) as f32 - 13.0).exp2()
+}
+
+fn wave(phase: f32, half_sine: bool) -> f32 {
+    let value = (phase * TAU).sin();
+    if half_sine {
+        value.max(0.0)
+    } else {
+        value
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct Operator {
+    /// Position in the waveform in cycles, 0-1
+    phase: f32,
+    /// Envelope attenuation in steps
+    envelope: f32,
+    stage: Stage,
+    /// Last two outputs, the modulator feeds their average back to itself
+    output: [f32; 2],
+}
+
+impl Operator {
+    fn key_on(&mut self) {
+        self.phase = 0.0;
+        self.stage = Stage::Attack;
+    }
+
+    fn key_off(&mut self) {
+        if self.stage != Stage::Off {
+            self.stage = Stage::Release;
+        }
+    }
+
+    fn tick_envelope(&mut self, params: &OperatorPatch, pitch: Pitch) {
+        let rate = |value| steps_per_sample(pitch.rate(value, params.key_scale_rate));
+        match self.stage {
+            Stage::Attack => {
+                if pitch.rate(params.attack, params.key_scale_rate) >= 60 {
+                    self.envelope = 0.0;
+                } else {
+                    self.envelope -= (self.envelope + 1.0) * rate(params.attack) / 8.0;
+                }
+                if self.envelope <= 0.0 {
+                    self.envelope = 0.0;
+                    self.stage = Stage::Decay;
+                }
+            }
+            Stage::Decay => {
+                self.envelope += rate(params.decay);
+                if self.envelope >= params.sustain_level as f32 * 8.0 {
+                    self.stage = Stage::Sustain;
+                }
+            }
+            Stage::Sustain if !params.sustained => self.envelope += rate(params.release),
+            Stage::Release => {
+                let release = if pitch.sustain {
+                    5
+                } else if params.sustained {
+                    params.release
+                } else {
+                    7
+                };
+                self.envelope += rate(release);
+            }
+            Stage::Sustain | Stage::Off => (),
+        }
+        if self.envelope >= ENVELOPE_SILENT {
+            self.envelope = ENVELOPE_SILENT;
+            self.stage = Stage::Off;
+        }
+    }
+
+    /// Moves the phase by `step` cycles and returns the output for the given phase
+    /// offset and attenuation in dB
+    fn generate(
+        &mut self,
+        step: f32,
+        offset: f32,
+        params: &OperatorPatch,
+        attenuation: f32,
+    ) -> f32 {
+        self.phase = (self.phase + step).fract();
+        let output = if self.stage == Stage::Off {
+            0.0
+        } else {
+            let db = self.envelope * ENVELOPE_STEP_DB + attenuation;
+            wave(self.phase + offset, params.half_sine) * 10f32.powf(db / -20.0)
+        };
+        self.output = [self.output[1], output];
+        output
+    }
+}
+
+pub struct Opll {
+    address: u8,
+    registers: [u8; 0x40],
+    /// Held in reset and silent, set through the mapper's control register
+    reset: bool,
+    divider: u8,
+    /// Modulator and carrier of each channel
+    operators: [[Operator; 2]; CHANNELS],
+    vibrato_phase: f32,
+    tremolo_phase: f32,
+    level: f32,
+    output: Sample,
+}
+
+impl Opll {
+    pub fn new() -> Self {
+        Self {
+            address: 0,
+            registers: [0; 0x40],
+            reset: false,
+            divider: 0,
+            operators: [[Operator {
+                envelope: ENVELOPE_SILENT,
+                ..Operator::default()
+            }; 2]; CHANNELS],
+            vibrato_phase: 0.0,
+            tremolo_phase: 0.0,
+            level: 0.0,
+            output: Sample::default(),
+        }
+    }
+
+    pub fn select(&mut self, address: u8) {
+        self.address = address;
+    }
+
+    pub fn write(&mut self, data: u8) {
+        let Some(register) = self.registers.get_mut(self.address as usize) else {
+            return;
+        };
+        if self.reset {
+            return;
+        }
+        let old = std::mem::replace(register, data);
+        if let 0x20..=0x25 = self.address {
+            let operators = &mut self.operators[self.address as usize - 0x20];
+            match (old & 0x10 != 0, data & 0x10 != 0) {
+                (false, true) => operators.iter_mut().for_each(Operator::key_on),
+                (true, false) => operators.iter_mut().for_each(Operator::key_off),
+                _ => (),
+            }
+        }
+    }
+
+    pub fn set_reset(&mut self, reset: bool) {
+        if reset && !self.reset {
+            *self = Self::new();
+        }
+        self.reset = reset;
+    }
+
+    pub const fn output(&self) -> Sample {
+        self.output
+    }
+
+    pub fn tick(&mut self) {
+        self.divider += 1;
+        if self.divider < CLOCK_DIVIDER {
+            return;
+        }
+        self.divider = 0;
+        if self.reset {
+            return;
+        }
+        self.vibrato_phase = (self.vibrato_phase + VIBRATO_HZ / SAMPLE_RATE).fract();
+        self.tremolo_phase = (self.tremolo_phase + TREMOLO_HZ / SAMPLE_RATE).fract();
+        self.level = (0..CHANNELS).map(|channel| self.channel(channel)).sum();
+        self.output = mixer::from_f32(self.level);
+    }
+
+    fn patch(&self, channel: usize) -> [u8; 8] {
+        match self.registers[0x30 + channel] >> 4 {
+            0 => std::array::from_fn(|i| self.registers[i]),
+            instrument => PATCHES[instrument as usize - 1],
+        }
+    }
+
+    fn channel(&mut self, channel: usize) -> f32 {
+        let instrument = self.patch(channel);
+        let pitch = Pitch {
+            fnum: self.registers[0x10 + channel] as u16
+                | (self.registers[0x20 + channel] as u16 & 1) << 8,
+            block: self.registers[0x20 + channel] >> 1 & 0x07,
+            sustain: self.registers[0x20 + channel] & 0x20 != 0,
+        };
+        let volume_db = (self.registers[0x30 + channel] & 0x0F) as f32 * 3.0;
+        let vibrato = (VIBRATO_CENTS / 1200.0 * (self.vibrato_phase * TAU).sin()).exp2();
+        let tremolo_db = TREMOLO_DB * (1.0 - (self.tremolo_phase * TAU).cos()) / 2.0;
+        // Cycles per sample at a multiplier of 1 are fnum * 2^(block - 1) / 2^18
+        let base_step = (pitch.fnum << pitch.block) as f32 / (1 << 20) as f32;
+
+        let [modulator, carrier] = &mut self.operators[channel];
+        let mut output = 0.0;
+        for (operator, is_carrier) in [(modulator, false), (carrier, true)] {
+            let params = OperatorPatch::new(instrument, is_carrier);
+            operator.tick_envelope(&params, pitch);
+            let mut step = base_step * params.multiplier as f32;
+            if params.vibrato {
+                step *= vibrato;
+            }
+            let mut attenuation = pitch.key_scale_db(params.key_scale_level);
+            if params.tremolo {
+                attenuation += tremolo_db;
+            }
+            let offset = if is_carrier {
+                attenuation += volume_db;
+                output * MODULATION_CYCLES
+            } else {
+                attenuation += (instrument[2] & 0x3F) as f32 * 0.75;
+                let feedback = instrument[3] & 0x07;
+                if feedback == 0 {
+                    0.0
+                } else {
+                    let average = operator.output[0].midpoint(operator.output[1]);
+                    average * FEEDBACK_CYCLES / (1 << (7 - feedback)) as f32
+                }
+            };
+            output = operator.generate(step, offset, &params, attenuation);
+        }
+        output * CHANNEL_LEVEL
+    }
+}
+
+impl StateField for Stage {
+    fn save(&self, out: &mut Vec<u8>) {
+        out.push(*self as u8);
+    }
+    fn load(&mut self, input: &mut &[u8]) -> Result<()> {
+        *self = match take(input, 1)?[0] {
+            0 => Self::Attack,
+            1 => Self::Decay,
+            2 => Self::Sustain,
+            3 => Self::Release,
+            4 => Self::Off,
+            v => return Err(eyre!("Invalid envelope stage {}", v)),
+        };
+        Ok(())
+    }
+    fn describe(&self) -> String {
+        match self {
+            Self::Attack => "attack",
+            Self::Decay => "decay",
+            Self::Sustain => "sustain",
+            Self::Release => "release",
+            Self::Off => "off",
+        }
+        .to_owned()
+    }
+}
+
+impl StateField for Opll {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.address.save(out);
+        self.registers.save(out);
+        self.reset.save(out);
+        self.divider.save(out);
+        for operator in self.operators.iter().flatten() {
+            operator.phase.save(out);
+            operator.envelope.save(out);
+            operator.stage.save(out);
+            operator.output[0].save(out);
+            operator.output[1].save(out);
+        }
+        self.vibrato_phase.save(out);
+        self.tremolo_phase.save(out);
+        self.level.save(out);
+    }
+    fn load(&mut self, input: &mut &[u8]) -> Result<()> {
+        self.address.load(input)?;
+        self.registers.load(input)?;
+        self.reset.load(input)?;
+        self.divider.load(input)?;
+        for operator in self.operators.iter_mut().flatten() {
+            operator.phase.load(input)?;
+            operator.envelope.load(input)?;
+            operator.stage.load(input)?;
+            operator.output[0].load(input)?;
+            operator.output[1].load(input)?;
+        }
+        self.vibrato_phase.load(input)?;
+        self.tremolo_phase.load(input)?;
+        self.level.load(input)?;
+        self.output = mixer::from_f32(self.level);
+        Ok(())
+    }
+    fn describe(&self) -> String {
+        let keys: Vec<String> = (0..CHANNELS)
+            .map(|channel| {
+                let instrument = self.registers[0x30 + channel] >> 4;
+                let stage = self.operators[channel][1].stage.describe();
+                format!("{instrument}:{stage}")
+            })
+            .collect();
+        keys.join(" ")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn write(opll: &mut Opll, address: u8, data: u8) {
+        opll.select(address);
+        opll.write(data);
+    }
+
+    #[test]
+    fn test_note_sounds_and_releases() {
+        let mut opll = Opll::new();
+        // Instrument 3 at full volume, block 4, key on
+        write(&mut opll, 0x30, 0x30);
+        write(&mut opll, 0x10, 0xAC);
+        write(&mut opll, 0x20, 0x18);
+        let mut peak = 0.0f32;
+        for _ in 0..CLOCK_DIVIDER as usize * 2000 {
+            opll.tick();
+            peak = peak.max(opll.level.abs());
+        }
+        assert!(
+            peak > CHANNEL_LEVEL / 4.0 && peak <= CHANNEL_LEVEL,
+            "{peak}"
+        );
+
+        write(&mut opll, 0x20, 0x08);
+        for _ in 0..CLOCK_DIVIDER as usize * 50_000 {
+            opll.tick();
+        }
+        assert!(opll.operators[0].iter().all(|op| op.stage == Stage::Off));
+        assert!(opll.level.abs() < f32::EPSILON);
+
+        opll.set_reset(true);
+        write(&mut opll, 0x20, 0x18);
+        assert_eq!(opll.registers[0x20], 0);
+    }
+}
@@ -0,0 +1,471 @@
+// Namco boards share their banking: three switchable 8 kB PRG banks at $8000, $A000
+// and $C000 with the last 8 kB fixed, and eight 1 kB CHR banks written at $8000-$BFFF.
+// The boards differ in nametable control, PRG RAM, IRQs and expansion audio.
+
+use eyre::Result;
+
+use super::{
+    load_ram, mirror_horizontal, mirror_single, mirror_vertical, Chr, Mapper, MapperEvent,
+    Mirroring, Snapshot, StateField,
+};
+use crate::macros::state_fields;
+
+const PRG_BANK_SIZE: usize = 8 * 1024;
+const CHR_BANK_SIZE: usize = 1024;
+
+/// Banking common to all Namco 129/163/175/340 boards
+struct NamcoBanks {
+    prg_rom: Vec<u8>,
+    chr: Chr,
+    prg_banks: [u8; 3],
+    chr_banks: [u8; 8],
+}
+
+impl NamcoBanks {
+    fn new(prg_rom: Vec<u8>, chr_rom: Vec<u8>, chr_ram_size: usize) -> Self {
+        Self {
+            prg_rom,
+            chr: Chr::new(chr_rom, chr_ram_size),
+            prg_banks: [0, 1, 2],
+            chr_banks: [0, 1, 2, 3, 4, 5, 6, 7],
+        }
+    }
+
+    fn prg_offset(&self, addr: u16) -> usize {
+        let banks = self.prg_rom.len() / PRG_BANK_SIZE;
+        let bank = match addr {
+            0x8000..=0xDFFF => self.prg_banks[(addr as usize - 0x8000) / PRG_BANK_SIZE] as usize,
+            _ => banks - 1,
+        };
+        (bank % banks) * PRG_BANK_SIZE + addr as usize % PRG_BANK_SIZE
+    }
+
+    /// Handles PRG bank registers at $E000, $E800 and $F000, returning the upper bits
+    /// which each board uses for something else
+    fn write_prg_bank(&mut self, addr: u16, data: u8) -> u8 {
+        let idx = (addr as usize - 0xE000) / 0x800;
+        self.prg_banks[idx] = data & 0x3F;
+        data & 0xC0
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        let bank = self.chr_banks[addr as usize / CHR_BANK_SIZE] as usize;
+        (bank * CHR_BANK_SIZE + addr as usize % CHR_BANK_SIZE) % self.chr.len()
+    }
+
+    /// Handles CHR bank registers at $8000-$BFFF, one per 2 kB of CPU address space
+    fn write_chr_bank(&mut self, addr: u16, data: u8) {
+        self.chr_banks[(addr as usize - 0x8000) / 0x800] = data;
+    }
+
+    fn read_chr(&self, addr: u16) -> u8 {
+        self.chr.read(self.chr_offset(addr))
+    }
+
+    fn write_chr(&mut self, addr: u16, data: u8) {
+        let offset = self.chr_offset(addr);
+        self.chr.write(offset, data);
+    }
+}
+
+// Only the registers and CHR RAM change at runtime
+impl StateField for NamcoBanks {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.prg_banks.save(out);
+        self.chr_banks.save(out);
+        self.chr.save(out);
+    }
+    fn load(&mut self, input: &mut &[u8]) -> Result<()> {
+        self.prg_banks.load(input)?;
+        self.chr_banks.load(input)?;
+        self.chr.load(input)
+    }
+    fn describe(&self) -> String {
+        format!("PRG {:02X?} CHR {:02X?}", self.prg_banks, self.chr_banks)
+    }
+}
+
+/// Mapper 19, Namco 129 and 163.
+/// CHR banks pointing at nametable RAM and the expansion audio output are not emulated,
+/// but the sound RAM is, as some games keep data in it.
+pub struct Mapper019 {
+    banks: NamcoBanks,
+    prg_ram: Vec<u8>,
+    nametable_banks: [u8; 4],
+
+    sound_ram: Vec<u8>,
+    sound_addr: u8,
+
+    irq_counter: u16,
+    irq_enable: bool,
+    irq: bool,
+}
+
+impl Mapper019 {
+    pub fn new(prg_rom: Vec<u8>, chr_rom: Vec<u8>, chr_ram_size: usize) -> Self {
+        Self {
+            banks: NamcoBanks::new(prg_rom, chr_rom, chr_ram_size),
+            prg_ram: vec![0; 0x2000],
+            nametable_banks: [0xE0, 0xE1, 0xE0, 0xE1],
+            sound_ram: vec![0; 0x80],
+            sound_addr: 0,
+            irq_counter: 0,
+            irq_enable: false,
+            irq: false,
+        }
+    }
+
+    // Sound port address auto-increments when bit 7 is set
+    fn sound_ram_access(&mut self) -> &mut u8 {
+        let addr = self.sound_addr & 0x7F;
+        if self.sound_addr & 0x80 != 0 {
+            self.sound_addr = 0x80 | ((addr + 1) & 0x7F);
+        }
+        &mut self.sound_ram[addr as usize]
+    }
+}
+
+impl Snapshot for Mapper019 {
+    state_fields!(
+        banks,
+        prg_ram,
+        nametable_banks,
+        sound_ram,
+        sound_addr,
+        irq_counter,
+        irq_enable,
+        irq,
+    );
+}
+
+impl Mapper for Mapper019 {
+    // Sound data and the IRQ counter are readable, unlike the registers above $8000
+    fn cpu_read_driven(&self, addr: u16) -> bool {
+        addr >= 0x4800
+    }
+
+    fn trigger_event(&mut self, event: MapperEvent) {
+        if let MapperEvent::CpuTick = event {
+            if self.irq_enable && self.irq_counter < 0x7FFF {
+                self.irq_counter += 1;
+                self.irq |= self.irq_counter == 0x7FFF;
+            }
+        }
+    }
+
+    fn irq_active(&self) -> bool {
+        self.irq
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        match addr {
+            0x8000.. => Some(self.banks.prg_offset(addr)),
+            _ => None,
+        }
+    }
+
+    fn prg_rom_len(&self) -> usize {
+        self.banks.prg_rom.len()
+    }
+
+    fn chr_offset(&self, addr: u16) -> Option<usize> {
+        match addr {
+            0..=0x1FFF => Some(self.banks.chr_offset(addr)),
+            _ => None,
+        }
+    }
+
+    fn chr_len(&self) -> usize {
+        self.banks.chr.len()
+    }
+
+    fn chr_writable(&self) -> bool {
+        self.banks.chr.is_ram()
+    }
+
+    fn prg_ram(&self) -> Vec<u8> {
+        self.prg_ram.clone()
+    }
+
+    fn load_prg_ram(&mut self, data: &[u8]) {
+        load_ram(&mut self.prg_ram, data);
+    }
+
+    fn read_cpu(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x4800..=0x4FFF => *self.sound_ram_access(),
+            0x5000..=0x57FF => self.irq_counter as u8,
+            0x5800..=0x5FFF => (self.irq_counter >> 8) as u8 | (self.irq_enable as u8) << 7,
+            0x6000..=0x7FFF => self.prg_ram[addr as usize - 0x6000],
+            0x8000.. => self.banks.prg_rom[self.banks.prg_offset(addr)],
+            _ => 0,
+        }
+    }
+
+    fn write_cpu(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4800..=0x4FFF => *self.sound_ram_access() = data,
+            0x5000..=0x57FF => {
+                self.irq_counter = (self.irq_counter & 0x7F00) | data as u16;
+                self.irq = false;
+            }
+            0x5800..=0x5FFF => {
+                self.irq_counter = (self.irq_counter & 0x00FF) | (data as u16 & 0x7F) << 8;
+                self.irq_enable = data & 0x80 != 0;
+                self.irq = false;
+            }
+            0x6000..=0x7FFF => self.prg_ram[addr as usize - 0x6000] = data,
+            0x8000..=0xBFFF => self.banks.write_chr_bank(addr, data),
+            0xC000..=0xDFFF => {
+                self.nametable_banks[(addr as usize - 0xC000) / 0x800] = data;
+            }
+            0xE000..=0xF7FF => {
+                // Upper bits disable sound and CHR RAM mapping, neither is emulated
+                self.banks.write_prg_bank(addr, data);
+            }
+            0xF800.. => self.sound_addr = data,
+            _ => (),
+        }
+    }
+
+    fn read_ppu(&mut self, addr: u16) -> u8 {
+        self.banks.read_chr(addr)
+    }
+
+    fn write_ppu(&mut self, addr: u16, data: u8) {
+        self.banks.write_chr(addr, data);
+    }
+
+    fn mirror_vram(&self, addr: u16) -> usize {
+        // Values below $E0 would map CHR ROM as a nametable, use the low bit as a fallback
+        let bank = self.nametable_banks[(addr as usize / 0x400) % 4];
+        mirror_single(addr, bank & 1 != 0)
+    }
+}
+
+/// Which chip a mapper 210 board uses
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Namco210Chip {
+    /// Hardwired mirroring, 2 kB PRG RAM enabled through $C000
+    N175,
+    /// Mirroring selected by the upper bits of $E000, no PRG RAM
+    N340,
+}
+
+/// Mapper 210, Namco 175 and 340
+pub struct Mapper210 {
+    banks: NamcoBanks,
+    chip: Namco210Chip,
+    prg_ram: Vec<u8>,
+    prg_ram_enable: bool,
+    mirroring: Mirroring,
+}
+
+impl Mapper210 {
+    pub fn new(
+        chip: Namco210Chip,
+        prg_rom: Vec<u8>,
+        chr_rom: Vec<u8>,
+        chr_ram_size: usize,
+        mirroring: Mirroring,
+    ) -> Self {
+        Self {
+            banks: NamcoBanks::new(prg_rom, chr_rom, chr_ram_size),
+            chip,
+            prg_ram: vec![0; 0x800],
+            prg_ram_enable: false,
+            mirroring,
+        }
+    }
+}
+
+impl Snapshot for Mapper210 {
+    state_fields!(banks, prg_ram, prg_ram_enable, mirroring);
+}
+
+impl Mapper for Mapper210 {
+    fn cpu_read_driven(&self, addr: u16) -> bool {
+        match addr {
+            0x6000..=0x7FFF => self.chip == Namco210Chip::N175 && self.prg_ram_enable,
+            0x8000.. => true,
+            _ => false,
+        }
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        match addr {
+            0x8000.. => Some(self.banks.prg_offset(addr)),
+            _ => None,
+        }
+    }
+
+    fn prg_rom_len(&self) -> usize {
+        self.banks.prg_rom.len()
+    }
+
+    fn chr_offset(&self, addr: u16) -> Option<usize> {
+        match addr {
+            0..=0x1FFF => Some(self.banks.chr_offset(addr)),
+            _ => None,
+        }
+    }
+
+    fn chr_len(&self) -> usize {
+        self.banks.chr.len()
+    }
+
+    fn chr_writable(&self) -> bool {
+        self.banks.chr.is_ram()
+    }
+
+    fn prg_ram(&self) -> Vec<u8> {
+        self.prg_ram.clone()
+    }
+
+    fn load_prg_ram(&mut self, data: &[u8]) {
+        load_ram(&mut self.prg_ram, data);
+    }
+
+    fn read_cpu(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF if self.chip == Namco210Chip::N175 && self.prg_ram_enable => {
+                self.prg_ram[addr as usize % 0x800]
+            }
+            0x8000.. => self.banks.prg_rom[self.banks.prg_offset(addr)],
+            _ => 0,
+        }
+    }
+
+    fn write_cpu(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7FFF if self.chip == Namco210Chip::N175 && self.prg_ram_enable => {
+                self.prg_ram[addr as usize % 0x800] = data;
+            }
+            0x8000..=0xBFFF => self.banks.write_chr_bank(addr, data),
+            0xC000..=0xC7FF if self.chip == Namco210Chip::N175 => {
+                self.prg_ram_enable = data & 1 != 0;
+            }
+            0xE000..=0xF7FF => {
+                let upper = self.banks.write_prg_bank(addr, data);
+                if self.chip == Namco210Chip::N340 && addr < 0xE800 {
+                    self.mirroring = match upper >> 6 {
+                        0 => Mirroring::SingleScreenLower,
+                        1 => Mirroring::Vertical,
+                        2 => Mirroring::Horizontal,
+                        _ => Mirroring::SingleScreenUpper,
+                    };
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn read_ppu(&mut self, addr: u16) -> u8 {
+        self.banks.read_chr(addr)
+    }
+
+    fn write_ppu(&mut self, addr: u16, data: u8) {
+        self.banks.write_chr(addr, data);
+    }
+
+    fn mirror_vram(&self, addr: u16) -> usize {
+        match self.mirroring {
+            Mirroring::Vertical | Mirroring::FourScreen => mirror_vertical(addr),
+            Mirroring::Horizontal => mirror_horizontal(addr),
+            Mirroring::SingleScreenLower => mirror_single(addr, false),
+            Mirroring::SingleScreenUpper => mirror_single(addr, true),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::banked;
+    use super::*;
+
+    #[test]
+    fn test_prg_banking_with_fixed_last_bank() {
+        let mut mapper = Mapper019::new(banked(8, PRG_BANK_SIZE), vec![0; 0x2000], 0);
+        assert_eq!(mapper.read_cpu(0x8000), 0);
+        assert_eq!(mapper.read_cpu(0xE000), 7);
+
+        mapper.write_cpu(0xE000, 0x45);
+        mapper.write_cpu(0xF000, 0x03);
+        assert_eq!(mapper.read_cpu(0x8000), 5);
+        assert_eq!(mapper.read_cpu(0xC000), 3);
+        assert_eq!(mapper.prg_rom_offset(0xA001), Some(PRG_BANK_SIZE + 1));
+    }
+
+    #[test]
+    fn test_chr_banks_are_1k() {
+        let chr = banked(16, CHR_BANK_SIZE);
+        let mut mapper = Mapper210::new(
+            Namco210Chip::N175,
+            banked(4, PRG_BANK_SIZE),
+            chr,
+            0,
+            Mirroring::Vertical,
+        );
+        mapper.write_cpu(0x8800, 9);
+        mapper.write_cpu(0xB800, 15);
+        assert_eq!(mapper.read_ppu(0x0400), 9);
+        assert_eq!(mapper.read_ppu(0x1FFF), 15);
+        assert_eq!(mapper.chr_offset(0x0401), Some(9 * CHR_BANK_SIZE + 1));
+    }
+
+    #[test]
+    fn test_namco163_irq_fires_at_7fff() {
+        let mut mapper = Mapper019::new(banked(4, PRG_BANK_SIZE), vec![0; 0x2000], 0);
+        mapper.write_cpu(0x5000, 0xFD);
+        mapper.write_cpu(0x5800, 0xFF);
+        mapper.trigger_event(MapperEvent::CpuTick);
+        assert!(!mapper.irq_active());
+        mapper.trigger_event(MapperEvent::CpuTick);
+        assert!(mapper.irq_active());
+
+        // Counter stops, and any counter write acknowledges
+        mapper.trigger_event(MapperEvent::CpuTick);
+        assert_eq!(mapper.read_cpu(0x5000), 0xFF);
+        mapper.write_cpu(0x5000, 0);
+        assert!(!mapper.irq_active());
+    }
+
+    #[test]
+    fn test_sound_ram_auto_increment() {
+        let mut mapper = Mapper019::new(banked(4, PRG_BANK_SIZE), vec![0; 0x2000], 0);
+        mapper.write_cpu(0xF800, 0xFF);
+        mapper.write_cpu(0x4800, 0x11);
+        mapper.write_cpu(0x4800, 0x22);
+        mapper.write_cpu(0xF800, 0x7F);
+        assert_eq!(mapper.read_cpu(0x4800), 0x11);
+        assert_eq!(mapper.read_cpu(0x4800), 0x11);
+        mapper.write_cpu(0xF800, 0x00);
+        assert_eq!(mapper.read_cpu(0x4800), 0x22);
+    }
+
+    #[test]
+    fn test_namco340_mirroring_and_175_prg_ram() {
+        let mut n340 = Mapper210::new(
+            Namco210Chip::N340,
+            banked(4, PRG_BANK_SIZE),
+            vec![0; 0x2000],
+            0,
+            Mirroring::Vertical,
+        );
+        n340.write_cpu(0xE000, 0x80);
+        assert_eq!(n340.mirror_vram(0x2400), n340.mirror_vram(0x2000));
+
+        let mut n175 = Mapper210::new(
+            Namco210Chip::N175,
+            banked(4, PRG_BANK_SIZE),
+            vec![0; 0x2000],
+            0,
+            Mirroring::Vertical,
+        );
+        n175.write_cpu(0x6000, 0x42);
+        assert_eq!(n175.read_cpu(0x6000), 0);
+        n175.write_cpu(0xC000, 1);
+        n175.write_cpu(0x6000, 0x42);
+        assert_eq!(n175.read_cpu(0x6800), 0x42);
+    }
+}
@@ -0,0 +1,681 @@
+// Nintendo's later MMC boards. The MMC2 and MMC4 switch between two CHR banks per
+// pattern table depending on whether the PPU last fetched tile $FD or $FE from it.
+// The MMC3 switches 8 kB PRG and 1-2 kB CHR banks and counts scanlines by watching
+// PPU address line A12 rise as the PPU moves from background to sprite patterns.
+
+use eyre::Result;
+
+use super::{
+    load_ram, mirror_horizontal, mirror_vertical, Chr, Mapper, MapperEvent, Mirroring, Snapshot,
+    StateField,
+};
+use crate::macros::state_fields;
+
+const PRG_BANK_SIZE: usize = 8 * 1024;
+const CHR_BANK_SIZE: usize = 1024;
+const LATCH_CHR_BANK_SIZE: usize = 4 * 1024;
+
+/// CHR banking and mirroring of the MMC2 and MMC4, written at $B000-$FFFF
+struct ChrLatches {
+    mirroring: Mirroring,
+    /// $B000-$EFFF: the low table with latch $FD and $FE, then the high table
+    banks: [u8; 4],
+    /// Each pattern table's latch holds $FE rather than $FD
+    latched_fe: [bool; 2],
+    /// The MMC2 only latches the low table on the first row of a tile's upper plane
+    exact_low_latch: bool,
+}
+
+impl ChrLatches {
+    fn new(mirroring: Mirroring, exact_low_latch: bool) -> Self {
+        Self {
+            mirroring,
+            banks: [0; 4],
+            latched_fe: [true; 2],
+            exact_low_latch,
+        }
+    }
+
+    fn chr_index(&self, chr: &Chr, addr: u16) -> usize {
+        let table = (addr as usize / LATCH_CHR_BANK_SIZE) & 1;
+        let bank = self.banks[table * 2 + self.latched_fe[table] as usize] as usize;
+        (bank * LATCH_CHR_BANK_SIZE + addr as usize % LATCH_CHR_BANK_SIZE) % chr.len()
+    }
+
+    fn write_register(&mut self, addr: u16, data: u8) {
+        match addr {
+            0xB000..=0xEFFF => self.banks[(addr as usize - 0xB000) / 0x1000] = data & 0x1F,
+            0xF000.. => {
+                self.mirroring = if data & 1 == 0 {
+                    Mirroring::Vertical
+                } else {
+                    Mirroring::Horizontal
+                };
+            }
+            _ => (),
+        }
+    }
+
+    // The latch switches after the fetch, so the tile itself still comes from the old bank
+    fn read(&mut self, chr: &Chr, addr: u16) -> u8 {
+        let data = chr.read(self.chr_index(chr, addr));
+        let table = (addr as usize >> 12) & 1;
+        let row = if table == 0 && self.exact_low_latch {
+            addr & 0x0FFF
+        } else {
+            addr & 0x0FF8
+        };
+        match row {
+            0x0FD8 => self.latched_fe[table] = false,
+            0x0FE8 => self.latched_fe[table] = true,
+            _ => (),
+        }
+        data
+    }
+
+    fn mirror_vram(&self, addr: u16) -> usize {
+        match self.mirroring {
+            Mirroring::Horizontal => mirror_horizontal(addr),
+            _ => mirror_vertical(addr),
+        }
+    }
+}
+
+impl StateField for ChrLatches {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.mirroring.save(out);
+        self.banks.save(out);
+        self.latched_fe.save(out);
+    }
+    fn load(&mut self, input: &mut &[u8]) -> Result<()> {
+        self.mirroring.load(input)?;
+        self.banks.load(input)?;
+        self.latched_fe.load(input)
+    }
+    fn describe(&self) -> String {
+        format!(
+            "CHR {:02X?} latches {:?} {}",
+            self.banks,
+            self.latched_fe.map(|fe| if fe { "FE" } else { "FD" }),
+            self.mirroring.describe()
+        )
+    }
+}
+
+/// Mapper 9, MMC2 as used by Punch-Out!!: an 8 kB PRG bank at $8000 and the last
+/// three fixed after it
+pub struct Mapper009 {
+    prg_rom: Vec<u8>,
+    prg_bank: u8,
+    chr: Chr,
+    latches: ChrLatches,
+}
+
+impl Mapper009 {
+    pub fn new(
+        prg_rom: Vec<u8>,
+        chr_rom: Vec<u8>,
+        chr_ram_size: usize,
+        mirroring: Mirroring,
+    ) -> Self {
+        Self {
+            prg_rom,
+            prg_bank: 0,
+            chr: Chr::new(chr_rom, chr_ram_size),
+            latches: ChrLatches::new(mirroring, true),
+        }
+    }
+
+    fn prg_offset(&self, addr: u16) -> usize {
+        let count = self.prg_rom.len() / PRG_BANK_SIZE;
+        let bank = match addr {
+            0x8000..=0x9FFF => self.prg_bank as usize,
+            // The last three banks, wrapping around on images smaller than that
+            _ => count * 3 + (addr as usize - 0xA000) / PRG_BANK_SIZE - 3,
+        };
+        (bank % count) * PRG_BANK_SIZE + addr as usize % PRG_BANK_SIZE
+    }
+}
+
+impl Snapshot for Mapper009 {
+    state_fields!(prg_bank, chr, latches);
+}
+
+impl Mapper for Mapper009 {
+    fn watches_chr_reads(&self) -> bool {
+        true
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        match addr {
+            0x8000.. => Some(self.prg_offset(addr)),
+            _ => None,
+        }
+    }
+
+    fn prg_rom_len(&self) -> usize {
+        self.prg_rom.len()
+    }
+
+    fn chr_offset(&self, addr: u16) -> Option<usize> {
+        match addr {
+            0..=0x1FFF => Some(self.latches.chr_index(&self.chr, addr)),
+            _ => None,
+        }
+    }
+
+    fn chr_len(&self) -> usize {
+        self.chr.len()
+    }
+
+    fn chr_writable(&self) -> bool {
+        self.chr.is_ram()
+    }
+
+    fn read_cpu(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x8000.. => self.prg_rom[self.prg_offset(addr)],
+            _ => 0,
+        }
+    }
+
+    fn write_cpu(&mut self, addr: u16, data: u8) {
+        match addr {
+            0xA000..=0xAFFF => self.prg_bank = data & 0x0F,
+            0xB000.. => self.latches.write_register(addr, data),
+            _ => (),
+        }
+    }
+
+    fn read_ppu(&mut self, addr: u16) -> u8 {
+        self.latches.read(&self.chr, addr)
+    }
+
+    fn write_ppu(&mut self, addr: u16, data: u8) {
+        let offset = self.latches.chr_index(&self.chr, addr);
+        self.chr.write(offset, data);
+    }
+
+    fn mirror_vram(&self, addr: u16) -> usize {
+        self.latches.mirror_vram(addr)
+    }
+}
+
+/// Mapper 10, MMC4 as used by Fire Emblem: a 16 kB PRG bank at $8000, the last one
+/// fixed at $C000 and 8 kB of PRG RAM
+pub struct Mapper010 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    prg_bank: u8,
+    chr: Chr,
+    latches: ChrLatches,
+}
+
+impl Mapper010 {
+    const PRG_BANK_SIZE: usize = 16 * 1024;
+
+    pub fn new(
+        prg_rom: Vec<u8>,
+        chr_rom: Vec<u8>,
+        chr_ram_size: usize,
+        mirroring: Mirroring,
+    ) -> Self {
+        Self {
+            prg_rom,
+            prg_ram: vec![0; 0x2000],
+            prg_bank: 0,
+            chr: Chr::new(chr_rom, chr_ram_size),
+            latches: ChrLatches::new(mirroring, false),
+        }
+    }
+
+    fn prg_offset(&self, addr: u16) -> usize {
+        let count = self.prg_rom.len() / Self::PRG_BANK_SIZE;
+        let bank = match addr {
+            0x8000..=0xBFFF => self.prg_bank as usize,
+            _ => count - 1,
+        };
+        (bank % count) * Self::PRG_BANK_SIZE + addr as usize % Self::PRG_BANK_SIZE
+    }
+}
+
+impl Snapshot for Mapper010 {
+    state_fields!(prg_ram, prg_bank, chr, latches);
+}
+
+impl Mapper for Mapper010 {
+    fn watches_chr_reads(&self) -> bool {
+        true
+    }
+
+    fn cpu_read_driven(&self, addr: u16) -> bool {
+        addr >= 0x6000
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        match addr {
+            0x8000.. => Some(self.prg_offset(addr)),
+            _ => None,
+        }
+    }
+
+    fn prg_rom_len(&self) -> usize {
+        self.prg_rom.len()
+    }
+
+    fn chr_offset(&self, addr: u16) -> Option<usize> {
+        match addr {
+            0..=0x1FFF => Some(self.latches.chr_index(&self.chr, addr)),
+            _ => None,
+        }
+    }
+
+    fn chr_len(&self) -> usize {
+        self.chr.len()
+    }
+
+    fn chr_writable(&self) -> bool {
+        self.chr.is_ram()
+    }
+
+    fn prg_ram(&self) -> Vec<u8> {
+        self.prg_ram.clone()
+    }
+
+    fn load_prg_ram(&mut self, data: &[u8]) {
+        load_ram(&mut self.prg_ram, data);
+    }
+
+    fn read_cpu(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF => self.prg_ram[addr as usize - 0x6000],
+            0x8000.. => self.prg_rom[self.prg_offset(addr)],
+            _ => 0,
+        }
+    }
+
+    fn write_cpu(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7FFF => self.prg_ram[addr as usize - 0x6000] = data,
+            0xA000..=0xAFFF => self.prg_bank = data & 0x0F,
+            0xB000.. => self.latches.write_register(addr, data),
+            _ => (),
+        }
+    }
+
+    fn read_ppu(&mut self, addr: u16) -> u8 {
+        self.latches.read(&self.chr, addr)
+    }
+
+    fn write_ppu(&mut self, addr: u16, data: u8) {
+        let offset = self.latches.chr_index(&self.chr, addr);
+        self.chr.write(offset, data);
+    }
+
+    fn mirror_vram(&self, addr: u16) -> usize {
+        self.latches.mirror_vram(addr)
+    }
+}
+
+/// Mapper 4, MMC3. Four-screen boards aren't emulated and use vertical mirroring.
+#[allow(clippy::struct_excessive_bools)]
+pub struct Mapper004 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Chr,
+    mirroring: Mirroring,
+
+    /// Register written at $8001, bits 0-2 of the last $8000 write
+    bank_select: u8,
+    /// $C000 is switchable and $8000 fixed instead of the other way around
+    prg_swap: bool,
+    /// The 2 kB CHR banks are at $1000 instead of $0000
+    chr_invert: bool,
+    /// R0-R5 select CHR banks, R6 and R7 PRG banks
+    banks: [u8; 8],
+    prg_ram_enable: bool,
+    prg_ram_write_protect: bool,
+
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enable: bool,
+    irq: bool,
+}
+
+impl Mapper004 {
+    pub fn new(
+        prg_rom: Vec<u8>,
+        chr_rom: Vec<u8>,
+        chr_ram_size: usize,
+        mirroring: Mirroring,
+    ) -> Self {
+        Self {
+            prg_rom,
+            prg_ram: vec![0; 0x2000],
+            chr: Chr::new(chr_rom, chr_ram_size),
+            mirroring,
+            bank_select: 0,
+            prg_swap: false,
+            chr_invert: false,
+            banks: [0, 2, 4, 5, 6, 7, 0, 1],
+            prg_ram_enable: true,
+            prg_ram_write_protect: false,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enable: false,
+            irq: false,
+        }
+    }
+
+    fn prg_offset(&self, addr: u16) -> usize {
+        let count = self.prg_rom.len() / PRG_BANK_SIZE;
+        let bank = match ((addr as usize - 0x8000) / PRG_BANK_SIZE, self.prg_swap) {
+            (0, false) | (2, true) => self.banks[6] as usize,
+            (1, _) => self.banks[7] as usize,
+            (0 | 2, _) => count - 2,
+            _ => count - 1,
+        };
+        (bank % count) * PRG_BANK_SIZE + addr as usize % PRG_BANK_SIZE
+    }
+
+    fn chr_index(&self, addr: u16) -> usize {
+        let addr = if self.chr_invert { addr ^ 0x1000 } else { addr } as usize;
+        let bank = match addr / CHR_BANK_SIZE {
+            // 2 kB banks ignore the lowest bit
+            slot @ 0..=3 => (self.banks[slot / 2] & 0xFE) as usize + slot % 2,
+            slot => self.banks[slot - 2] as usize,
+        };
+        (bank * CHR_BANK_SIZE + addr % CHR_BANK_SIZE) % self.chr.len()
+    }
+
+    // Reloads at zero, or when asked to through $C001, and fires on reaching zero
+    fn clock_irq_counter(&mut self) {
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+        if self.irq_counter == 0 && self.irq_enable {
+            self.irq = true;
+        }
+    }
+}
+
+impl Snapshot for Mapper004 {
+    state_fields!(
+        prg_ram,
+        chr,
+        mirroring,
+        bank_select,
+        prg_swap,
+        chr_invert,
+        banks,
+        prg_ram_enable,
+        prg_ram_write_protect,
+        irq_latch,
+        irq_counter,
+        irq_reload,
+        irq_enable,
+        irq,
+    );
+}
+
+impl Mapper for Mapper004 {
+    fn trigger_event(&mut self, event: MapperEvent) {
+        if let MapperEvent::A12Rise = event {
+            self.clock_irq_counter();
+        }
+    }
+
+    fn watches_a12(&self) -> bool {
+        true
+    }
+
+    fn irq_active(&self) -> bool {
+        self.irq
+    }
+
+    fn cpu_read_driven(&self, addr: u16) -> bool {
+        match addr {
+            0x6000..=0x7FFF => self.prg_ram_enable,
+            0x8000.. => true,
+            _ => false,
+        }
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        match addr {
+            0x8000.. => Some(self.prg_offset(addr)),
+            _ => None,
+        }
+    }
+
+    fn prg_rom_len(&self) -> usize {
+        self.prg_rom.len()
+    }
+
+    fn chr_offset(&self, addr: u16) -> Option<usize> {
+        match addr {
+            0..=0x1FFF => Some(self.chr_index(addr)),
+            _ => None,
+        }
+    }
+
+    fn chr_len(&self) -> usize {
+        self.chr.len()
+    }
+
+    fn chr_writable(&self) -> bool {
+        self.chr.is_ram()
+    }
+
+    fn prg_ram(&self) -> Vec<u8> {
+        self.prg_ram.clone()
+    }
+
+    fn load_prg_ram(&mut self, data: &[u8]) {
+        load_ram(&mut self.prg_ram, data);
+    }
+
+    fn read_cpu(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF => self.prg_ram[addr as usize - 0x6000],
+            0x8000.. => self.prg_rom[self.prg_offset(addr)],
+            _ => 0,
+        }
+    }
+
+    fn write_cpu(&mut self, addr: u16, data: u8) {
+        match (addr, addr & 1 == 0) {
+            (0x6000..=0x7FFF, _) if self.prg_ram_enable && !self.prg_ram_write_protect => {
+                self.prg_ram[addr as usize - 0x6000] = data;
+            }
+            (0x8000..=0x9FFF, true) => {
+                self.bank_select = data & 0x07;
+                self.prg_swap = data & 0x40 != 0;
+                self.chr_invert = data & 0x80 != 0;
+            }
+            (0x8000..=0x9FFF, false) => self.banks[self.bank_select as usize] = data,
+            (0xA000..=0xBFFF, true) if !matches!(self.mirroring, Mirroring::FourScreen) => {
+                self.mirroring = if data & 1 == 0 {
+                    Mirroring::Vertical
+                } else {
+                    Mirroring::Horizontal
+                };
+            }
+            (0xA000..=0xBFFF, false) => {
+                self.prg_ram_enable = data & 0x80 != 0;
+                self.prg_ram_write_protect = data & 0x40 != 0;
+            }
+            (0xC000..=0xDFFF, true) => self.irq_latch = data,
+            (0xC000..=0xDFFF, false) => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            (0xE000.., true) => {
+                self.irq_enable = false;
+                self.irq = false;
+            }
+            (0xE000.., false) => self.irq_enable = true,
+            _ => (),
+        }
+    }
+
+    fn read_ppu(&mut self, addr: u16) -> u8 {
+        self.chr.read(self.chr_index(addr))
+    }
+
+    fn write_ppu(&mut self, addr: u16, data: u8) {
+        let offset = self.chr_index(addr);
+        self.chr.write(offset, data);
+    }
+
+    fn mirror_vram(&self, addr: u16) -> usize {
+        match self.mirroring {
+            Mirroring::Horizontal => mirror_horizontal(addr),
+            _ => mirror_vertical(addr),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::banked;
+    use super::*;
+
+    fn mmc3() -> Mapper004 {
+        Mapper004::new(
+            banked(16, PRG_BANK_SIZE),
+            banked(64, CHR_BANK_SIZE),
+            0,
+            Mirroring::Vertical,
+        )
+    }
+
+    #[test]
+    fn test_mmc2_latches() {
+        let mut mapper = Mapper009::new(
+            banked(16, PRG_BANK_SIZE),
+            banked(32, LATCH_CHR_BANK_SIZE),
+            0,
+            Mirroring::Vertical,
+        );
+        for (addr, bank) in [(0xB000, 1), (0xC000, 2), (0xD000, 3), (0xE000, 4)] {
+            mapper.write_cpu(addr, bank);
+        }
+        assert_eq!([0x0000, 0x1000].map(|addr| mapper.read_ppu(addr)), [2, 4]);
+
+        // The fetch that sets the latch still reads from the old bank
+        assert_eq!(mapper.read_ppu(0x0FD8), 2);
+        assert_eq!(mapper.read_ppu(0x0000), 1);
+        assert_eq!(mapper.read_ppu(0x1FDD), 4);
+        assert_eq!(mapper.read_ppu(0x1000), 3);
+        // Only the first row of the low table's tiles latches on the MMC2
+        mapper.read_ppu(0x0FEA);
+        assert_eq!(mapper.read_ppu(0x0000), 1);
+        mapper.read_ppu(0x0FE8);
+        assert_eq!(mapper.read_ppu(0x0000), 2);
+        assert_eq!(mapper.chr_offset(0x0001), Some(2 * LATCH_CHR_BANK_SIZE + 1));
+
+        mapper.write_cpu(0xA000, 5);
+        assert_eq!(
+            [0x8000, 0xA000, 0xC000, 0xE000].map(|addr| mapper.read_cpu(addr)),
+            [5, 13, 14, 15]
+        );
+    }
+
+    #[test]
+    fn test_mmc4_latches() {
+        let mut mapper = Mapper010::new(
+            banked(8, Mapper010::PRG_BANK_SIZE),
+            banked(32, LATCH_CHR_BANK_SIZE),
+            0,
+            Mirroring::Vertical,
+        );
+        mapper.write_cpu(0xB000, 6);
+        mapper.write_cpu(0xC000, 7);
+        mapper.read_ppu(0x0FDF);
+        assert_eq!(mapper.read_ppu(0x0000), 6);
+        mapper.write_cpu(0xA000, 3);
+        assert_eq!([0x8000, 0xC000].map(|addr| mapper.read_cpu(addr)), [3, 7]);
+        mapper.write_cpu(0x6000, 0x42);
+        assert_eq!(mapper.prg_ram()[0], 0x42);
+    }
+
+    #[test]
+    fn test_prg_banking() {
+        let mut mapper = mmc3();
+        mapper.write_cpu(0x8000, 6);
+        mapper.write_cpu(0x8001, 3);
+        mapper.write_cpu(0x8000, 7);
+        mapper.write_cpu(0x8001, 9);
+        assert_eq!(
+            [0x8000, 0xA000, 0xC000, 0xE000].map(|addr| mapper.read_cpu(addr)),
+            [3, 9, 14, 15]
+        );
+        // Swapped mode fixes the second to last bank at $8000 instead
+        mapper.write_cpu(0x8000, 0x46);
+        assert_eq!(
+            [0x8000, 0xA000, 0xC000, 0xE000].map(|addr| mapper.read_cpu(addr)),
+            [14, 9, 3, 15]
+        );
+    }
+
+    #[test]
+    fn test_chr_banking() {
+        let mut mapper = mmc3();
+        for (reg, bank) in [(0, 9), (1, 20), (2, 30), (5, 33)] {
+            mapper.write_cpu(0x8000, reg);
+            mapper.write_cpu(0x8001, bank);
+        }
+        // 2 kB banks ignore the lowest bit of the bank number
+        assert_eq!(
+            [0x0000, 0x0400, 0x0800, 0x1000, 0x1C00].map(|addr| mapper.read_ppu(addr)),
+            [8, 9, 20, 30, 33]
+        );
+        mapper.write_cpu(0x8000, 0x80);
+        assert_eq!(
+            [0x0000, 0x0C00, 0x1000, 0x1400].map(|addr| mapper.read_ppu(addr)),
+            [30, 33, 8, 9]
+        );
+        assert_eq!(mapper.chr_offset(0x1401), Some(9 * CHR_BANK_SIZE + 1));
+    }
+
+    #[test]
+    fn test_prg_ram_protect() {
+        let mut mapper = mmc3();
+        mapper.write_cpu(0x6000, 0x42);
+        mapper.write_cpu(0xA001, 0xC0);
+        mapper.write_cpu(0x6000, 0x17);
+        assert_eq!(mapper.read_cpu(0x6000), 0x42);
+        mapper.write_cpu(0xA001, 0x00);
+        assert!(!mapper.cpu_read_driven(0x6000));
+    }
+
+    #[test]
+    fn test_scanline_irq() {
+        let mut mapper = mmc3();
+        mapper.write_cpu(0xC000, 2);
+        mapper.write_cpu(0xC001, 0);
+        mapper.write_cpu(0xE001, 0);
+        // Reload, then two more lines count down to zero
+        for _ in 0..2 {
+            mapper.trigger_event(MapperEvent::A12Rise);
+            assert!(!mapper.irq_active());
+        }
+        mapper.trigger_event(MapperEvent::A12Rise);
+        assert!(mapper.irq_active());
+
+        // Acknowledged and disabled through $E000, the counter keeps going
+        mapper.write_cpu(0xE000, 0);
+        assert!(!mapper.irq_active());
+        for _ in 0..3 {
+            mapper.trigger_event(MapperEvent::A12Rise);
+        }
+        assert!(!mapper.irq_active());
+        assert_eq!(mapper.irq_counter, 0);
+    }
+}
@@ -1,4 +1,8 @@
-#[derive(Clone, Copy)]
+use eyre::Result;
+
+use super::state::StateField;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum Button {
     A = 0,
     B,
@@ -10,10 +14,81 @@ pub enum Button {
     Right,
 }
 
+impl Button {
+    /// In the order the game reads them
+    pub const ALL: [Self; 8] = [
+        Self::A,
+        Self::B,
+        Self::Select,
+        Self::Start,
+        Self::Up,
+        Self::Down,
+        Self::Left,
+        Self::Right,
+    ];
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::A => "A",
+            Self::B => "B",
+            Self::Select => "Select",
+            Self::Start => "Start",
+            Self::Up => "Up",
+            Self::Down => "Down",
+            Self::Left => "Left",
+            Self::Right => "Right",
+        }
+    }
+}
+
+/// What the latch does with Left+Right or Up+Down held together, which a real pad
+/// can't do but a keyboard can
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum OpposingDirections {
+    /// Passed to the game as is, some games glitch on it
+    Allow,
+    /// The direction pressed last is kept
+    LastPressed,
+    /// Both are released
+    Neither,
+}
+
+impl OpposingDirections {
+    pub const ALL: [Self; 3] = [Self::Allow, Self::LastPressed, Self::Neither];
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|mode| mode.name() == name)
+    }
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Allow => "allow",
+            Self::LastPressed => "last_pressed",
+            Self::Neither => "neither",
+        }
+    }
+}
+
+const AXES: [(Button, Button); 2] = [(Button::Up, Button::Down), (Button::Left, Button::Right)];
+
+fn pack(buttons: [bool; 8]) -> u8 {
+    buttons
+        .iter()
+        .enumerate()
+        .fold(0, |acc, (i, &b)| acc | (b as u8) << i)
+}
+
 pub struct Controller {
+    // Live host state, and the state captured when the game last strobed the controller
     buttons: [bool; 8],
+    latched: [bool; 8],
     strobe: bool,
     read_ptr: usize,
+    // Number of times the game has latched the buttons
+    latches: usize,
+    opposing: OpposingDirections,
+    // Direction of each axis in `AXES` that was pressed last
+    newest: [Button; 2],
 
     reset: bool,
 }
@@ -22,8 +97,12 @@ impl Controller {
     pub const fn new() -> Self {
         Self {
             buttons: [false; 8],
+            latched: [false; 8],
             strobe: false,
             read_ptr: 0,
+            latches: 0,
+            opposing: OpposingDirections::Allow,
+            newest: [Button::Up, Button::Left],
             reset: true,
         }
     }
@@ -31,14 +110,70 @@ impl Controller {
     // False positive from clippy?
     #[allow(clippy::only_used_in_recursion)]
     pub fn set_button_state(&mut self, button: Button, state: bool) {
+        if state && !self.buttons[button as usize] {
+            self.pressed(button);
+        }
         self.buttons[button as usize] = state;
     }
 
+    fn pressed(&mut self, button: Button) {
+        for (newest, (first, second)) in self.newest.iter_mut().zip(AXES) {
+            if button == first || button == second {
+                *newest = button;
+            }
+        }
+    }
+
+    /// Live button state packed one bit per button, bit 0 being A
+    pub fn buttons(&self) -> u8 {
+        pack(self.buttons)
+    }
+
+    /// Live button state as the game would latch it, with opposing directions
+    /// filtered
+    pub fn effective_buttons(&self) -> u8 {
+        pack(self.effective())
+    }
+
+    pub fn set_buttons(&mut self, bits: u8) {
+        let newly_pressed = bits & !self.buttons();
+        for button in Button::ALL {
+            if newly_pressed >> button as usize & 1 != 0 {
+                self.pressed(button);
+            }
+        }
+        for (i, b) in self.buttons.iter_mut().enumerate() {
+            *b = bits >> i & 1 != 0;
+        }
+    }
+
+    pub fn set_opposing_directions(&mut self, opposing: OpposingDirections) {
+        self.opposing = opposing;
+    }
+
+    fn effective(&self) -> [bool; 8] {
+        let mut buttons = self.buttons;
+        if self.opposing == OpposingDirections::Allow {
+            return buttons;
+        }
+        for (newest, (first, second)) in self.newest.into_iter().zip(AXES) {
+            if buttons[first as usize] && buttons[second as usize] {
+                let keep_newest = self.opposing == OpposingDirections::LastPressed;
+                buttons[first as usize] = keep_newest && newest == first;
+                buttons[second as usize] = keep_newest && newest == second;
+            }
+        }
+        buttons
+    }
+
     pub fn write(&mut self, data: u8) {
         if data & 0x1 != 0 {
             self.strobe = true;
+            self.latched = self.effective();
+            self.latches += 1;
         } else if self.strobe {
             self.strobe = false;
+            self.latched = self.effective();
             self.read_ptr = 0;
         }
     }
@@ -47,7 +182,7 @@ impl Controller {
         if self.strobe {
             self.buttons[0] as u8
         } else if self.read_ptr < 8 {
-            let val = self.buttons[self.read_ptr] as u8;
+            let val = self.latched[self.read_ptr] as u8;
             self.read_ptr += 1;
             val
         } else {
@@ -55,10 +190,19 @@ impl Controller {
         }
     }
 
+    pub const fn latch_count(&self) -> usize {
+        self.latches
+    }
+
     pub fn reset(&mut self) {
         self.reset = true;
     }
 
+    /// Whether a reset was requested and not yet picked up by the CPU
+    pub const fn reset_pending(&self) -> bool {
+        self.reset
+    }
+
     // Gets current reset state and clears it if active
     pub fn reset_triggered(&mut self) -> bool {
         let state = self.reset;
@@ -66,3 +210,69 @@ impl Controller {
         state
     }
 }
+
+// The shift register as the game sees it, held buttons come from the host
+impl StateField for Controller {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.latched.save(out);
+        self.strobe.save(out);
+        self.read_ptr.save(out);
+    }
+    fn load(&mut self, input: &mut &[u8]) -> Result<()> {
+        self.latched.load(input)?;
+        self.strobe.load(input)?;
+        self.read_ptr.load(input)
+    }
+    fn describe(&self) -> String {
+        format!("{:#04X}, bit {}", pack(self.latched), self.read_ptr)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_reads_return_state_latched_at_strobe() {
+        let mut controller = Controller::new();
+        controller.set_button_state(Button::A, true);
+        controller.write(1);
+        controller.write(0);
+        controller.set_button_state(Button::A, false);
+        controller.set_button_state(Button::Start, true);
+
+        let bits: Vec<u8> = (0..8).map(|_| controller.read()).collect();
+        assert_eq!(bits, vec![1, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(controller.read(), 1);
+    }
+
+    #[test]
+    fn test_opposing_directions() {
+        let mut controller = Controller::new();
+        controller.set_button_state(Button::Right, true);
+        controller.set_button_state(Button::Left, true);
+        controller.set_button_state(Button::Up, true);
+        assert_eq!(controller.effective_buttons(), 0b1101_0000);
+        controller.set_opposing_directions(OpposingDirections::LastPressed);
+        assert_eq!(controller.effective_buttons(), 0b0101_0000);
+        controller.set_opposing_directions(OpposingDirections::Neither);
+        assert_eq!(controller.effective_buttons(), 0b0001_0000);
+
+        controller.set_opposing_directions(OpposingDirections::LastPressed);
+        controller.set_buttons(0b0110_0000);
+        controller.set_buttons(0b1110_0000);
+        controller.write(1);
+        controller.write(0);
+        let bits: Vec<u8> = (0..8).map(|_| controller.read()).collect();
+        assert_eq!(bits, vec![0, 0, 0, 0, 0, 1, 0, 1]);
+    }
+
+    #[test]
+    fn test_strobe_high_reads_live_a_button() {
+        let mut controller = Controller::new();
+        controller.write(1);
+        assert_eq!(controller.read(), 0);
+        controller.set_button_state(Button::A, true);
+        assert_eq!(controller.read(), 1);
+    }
+}
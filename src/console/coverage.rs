@@ -0,0 +1,89 @@
+use std::fmt::Write as _;
+
+use eyre::Result;
+
+/// Access counters for a single PRG ROM byte
+#[derive(Clone, Copy, Default)]
+pub struct ByteCoverage {
+    pub executed: u32,
+    pub read: u32,
+    pub written: u32,
+}
+
+/// Per-byte access counters for the whole PRG ROM, keyed by ROM offset
+pub struct Coverage {
+    pub bytes: Vec<ByteCoverage>,
+}
+
+impl Coverage {
+    // Bits used in FCEUX-compatible code/data logs
+    const CDL_CODE: u8 = 0x01;
+    const CDL_DATA: u8 = 0x02;
+
+    pub fn new(prg_rom_len: usize) -> Self {
+        Self {
+            bytes: vec![ByteCoverage::default(); prg_rom_len],
+        }
+    }
+
+    pub fn mark_executed(&mut self, offset: usize) {
+        if let Some(byte) = self.bytes.get_mut(offset) {
+            byte.executed = byte.executed.saturating_add(1);
+        }
+    }
+
+    pub fn mark_read(&mut self, offset: usize) {
+        if let Some(byte) = self.bytes.get_mut(offset) {
+            byte.read = byte.read.saturating_add(1);
+        }
+    }
+
+    pub fn mark_written(&mut self, offset: usize) {
+        if let Some(byte) = self.bytes.get_mut(offset) {
+            byte.written = byte.written.saturating_add(1);
+        }
+    }
+
+    /// Code/data log with one byte per PRG ROM byte, as used by FCEUX and disassemblers.
+    /// Bytes that were executed are marked as code, bytes only read as data.
+    pub fn to_cdl(&self) -> Vec<u8> {
+        self.bytes
+            .iter()
+            .map(|byte| {
+                if byte.executed > 0 {
+                    Self::CDL_CODE
+                } else if byte.read > 0 {
+                    Self::CDL_DATA
+                } else {
+                    0
+                }
+            })
+            .collect()
+    }
+
+    /// Human readable summary of the coverage
+    pub fn summary(&self) -> String {
+        let count = |f: fn(&ByteCoverage) -> bool| self.bytes.iter().filter(|b| f(b)).count();
+        let code = count(|b| b.executed > 0);
+        let data = count(|b| b.executed == 0 && b.read > 0);
+        let written = count(|b| b.written > 0);
+        let total = self.bytes.len().max(1);
+
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "PRG ROM coverage: {} code bytes, {} data bytes of {} ({:.1}%)",
+            code,
+            data,
+            self.bytes.len(),
+            (code + data) as f64 * 100.0 / total as f64
+        );
+        let _ = write!(out, "{written} bytes were written to (mapper registers)");
+        out
+    }
+
+    pub fn export_cdl(&self, file: &str) -> Result<()> {
+        std::fs::write(file, self.to_cdl())?;
+        Ok(())
+    }
+}
@@ -1,15 +1,27 @@
 #![allow(clippy::range_plus_one)]
 #![allow(clippy::use_self)]
 
+mod idle;
 mod instr;
+mod trace_ring;
 
-use eyre::Result;
+use eyre::{eyre, Result};
 
 use super::bus::Bus;
+use super::debug::{CpuRegs, InterruptCause};
+use super::events::{ExecutedInstruction, InstructionHook};
+use super::state::{self, SaveState, Snapshot, StateField, StateRequest};
 use crate::macros::bit_bool;
 use crate::macros::bool_u8;
+use crate::macros::state_fields;
+use idle::IdleLoop;
 use instr::AddressingMode;
+use trace_ring::TraceRing;
 
+// Written when the trace ring is dumped
+const TRACE_DUMP_FILE: &str = "trace_dump.txt";
+
+#[allow(clippy::struct_excessive_bools)]
 pub struct Cpu<'a> {
     pub register_a: u8,
     pub register_x: u8,
@@ -18,10 +30,35 @@ pub struct Cpu<'a> {
     pub stack_pointer: u8,
     pub status: StatusReg,
     pub bus: Bus<'a>,
-    pub mnemonic: String,
+    pub mnemonic: &'static str,
     pub cycles: u8,
     nmi_seen: bool,
+    /// NMI polled too late in the last instruction, taken after the next one
+    nmi_deferred: bool,
     quit_on_brk: bool,
+    jammed: bool,
+    pub jam_behavior: JamBehavior,
+    trace_ring: TraceRing,
+    idle: IdleLoop,
+    instruction_hook: Option<InstructionHook<'a>>,
+}
+
+/// What the CPU does when it executes a jam (KIL) opcode
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum JamBehavior {
+    /// Hang until reset like real hardware, while the PPU and APU keep running
+    Hang,
+    /// Stop emulation with an error, to inspect the state that led to the jam
+    Break,
+}
+
+/// Sources of the interrupt sequence
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Interrupt {
+    Brk,
+    Irq,
+    Nmi,
+    Reset,
 }
 
 #[allow(clippy::struct_excessive_bools)]
@@ -62,8 +99,40 @@ impl From<StatusReg> for u8 {
     }
 }
 
+impl StateField for StatusReg {
+    fn save(&self, out: &mut Vec<u8>) {
+        u8::from(*self).save(out);
+    }
+    fn load(&mut self, input: &mut &[u8]) -> Result<()> {
+        let mut data = 0u8;
+        data.load(input)?;
+        *self = data.into();
+        Ok(())
+    }
+    fn describe(&self) -> String {
+        u8::from(*self).describe()
+    }
+}
+
+// Between instructions the rest is either constant or set up by the next one
+impl Snapshot for Cpu<'_> {
+    state_fields!(
+        register_a,
+        register_x,
+        register_y,
+        program_counter,
+        stack_pointer,
+        status,
+        nmi_seen,
+        nmi_deferred,
+        jammed,
+    );
+}
+
 const SIGN_MASK: u8 = 0x1 << 7;
+const NMI_ADDR: u16 = 0xFFFA;
 const RESET_ADDR: u16 = 0xFFFC;
+const IRQ_ADDR: u16 = 0xFFFE;
 const STACK_PAGE: u16 = 0x0100;
 const IRQ_DIS: u8 = 1 << 2;
 const UNUSED: u8 = 1 << 5;
@@ -78,13 +147,39 @@ impl<'a> Cpu<'a> {
             stack_pointer: 0,
             status: (IRQ_DIS | UNUSED).into(),
             bus,
-            mnemonic: "".to_owned(),
+            mnemonic: "",
             cycles: 0,
             nmi_seen: false,
+            nmi_deferred: false,
             quit_on_brk: false,
+            jammed: false,
+            jam_behavior: JamBehavior::Hang,
+            trace_ring: TraceRing::new(),
+            idle: IdleLoop::default(),
+            instruction_hook: None,
         }
     }
 
+    pub fn regs(&self) -> CpuRegs {
+        CpuRegs {
+            a: self.register_a,
+            x: self.register_x,
+            y: self.register_y,
+            p: self.status.into(),
+            sp: self.stack_pointer,
+            pc: self.program_counter,
+        }
+    }
+
+    pub fn set_regs(&mut self, regs: CpuRegs) {
+        self.register_a = regs.a;
+        self.register_x = regs.x;
+        self.register_y = regs.y;
+        self.status = regs.p.into();
+        self.stack_pointer = regs.sp;
+        self.program_counter = regs.pc;
+    }
+
     fn update_zero_neg(&mut self, val: u8) {
         self.status.zero = val == 0;
         self.status.negative = val >= 128;
@@ -96,23 +191,75 @@ impl<'a> Cpu<'a> {
         self.stack_pointer = self.stack_pointer.wrapping_sub(1);
     }
 
-    /// Pushes a 16-bit value onto the stack, decrementing stack pointer
-    fn push_stack_u16(&mut self, data: u16) {
-        self.push_stack((data >> 8) as u8);
-        self.push_stack((data & 0x00FF) as u8);
-    }
-
     /// Pulls a 8-bit value from the stack, incrementing stack pointer
     fn pull_stack(&mut self) -> u8 {
         self.stack_pointer = self.stack_pointer.wrapping_add(1);
         self.read(STACK_PAGE | self.stack_pointer as u16)
     }
 
-    /// Pulls a 16-bit value from the stack, incrementing stack pointer
-    fn pull_stack_u16(&mut self) -> u16 {
-        let lo = self.pull_stack() as u16;
-        let hi = self.pull_stack() as u16;
-        (hi << 8) | lo
+    /// Makes BRK return from `run_with_callback` instead of interrupting
+    pub fn set_quit_on_brk(&mut self, quit: bool) {
+        self.quit_on_brk = quit;
+    }
+
+    /// Runs to the end of the frame without pixel or audio output
+    pub fn run_frame_silent(&mut self) -> Result<()> {
+        self.bus.start_silent_frame();
+        let result = self.run_with_callback(|_| {});
+        self.bus.end_silent_frame();
+        result
+    }
+
+    /// Save state of the whole console, taken between instructions
+    pub fn snapshot(&mut self) -> SaveState {
+        let mut state = SaveState::new();
+        state.put(&state::CPU, self.save_state());
+        self.bus.save_sections(&mut state);
+        state
+    }
+
+    /// Loads a save state, going back to the state before if any section fails
+    pub fn restore(&mut self, state: &SaveState) -> Result<()> {
+        let before = self.snapshot();
+        let result = self.load_sections(state);
+        if result.is_err() {
+            self.load_sections(&before)?;
+        } else {
+            // The power-on reset of a fresh console would undo the load
+            self.bus.reset_triggered();
+        }
+        result
+    }
+
+    fn load_sections(&mut self, state: &SaveState) -> Result<()> {
+        self.cancel_idle_loop();
+        self.load_state(&state.require(&state::CPU)?)
+            .map_err(|e| eyre!("Failed to load the CPU section: {e}"))?;
+        self.bus.cpu_regs = self.regs();
+        self.bus.load_sections(state)
+    }
+
+    // Save states are taken and loaded at instruction boundaries
+    fn handle_state_request(&mut self) {
+        match self.bus.state_request.take() {
+            Some(StateRequest::Save) => {
+                let state = self.snapshot().encode();
+                self.bus.state_saved(state);
+            }
+            Some(StateRequest::Load(data)) => {
+                match SaveState::decode(&data).and_then(|state| self.restore(&state)) {
+                    Ok(()) => self.bus.state_loaded(),
+                    Err(e) => self.bus.state_load_failed(&e),
+                }
+            }
+            None => (),
+        }
+    }
+
+    /// Replays loops waiting for NMI instead of executing them, see `idle`
+    pub fn set_idle_skip(&mut self, enabled: bool) {
+        self.idle.enabled = enabled;
+        self.cancel_idle_loop();
     }
 
     // Used for testing
@@ -260,35 +407,99 @@ impl<'a> Cpu<'a> {
         }
     }
 
-    fn nmi(&mut self) -> Result<()> {
-        // println!("In NMI");
-        self.push_stack_u16(self.program_counter);
-        self.push_stack(self.status.into());
-        self.status.irq_disable = true;
+    fn nmi_pending(&mut self) -> bool {
+        self.bus.nmi_active() && !self.nmi_seen
+    }
 
-        self.bus.tick(7)?;
-        let target = self.read_u16(0xFFFA);
-        self.program_counter = target;
+    /// The seven cycle sequence shared by BRK, IRQ, NMI and reset, ticking the bus after
+    /// each access. Only BRK pushes the status with the B flag set. An NMI arriving
+    /// before the status push hijacks BRK and IRQ, which then jump through the NMI vector.
+    fn interrupt(&mut self, kind: Interrupt) -> Result<()> {
+        self.cancel_idle_loop();
+        let irq_cause = self.bus.irq_cause();
+        // Opcode fetch and the discarded read of the next byte, BRK skips it as padding
+        self.bus.tick(2)?;
+        let return_addr = match kind {
+            Interrupt::Brk => self.program_counter.wrapping_add(1),
+            Interrupt::Irq | Interrupt::Nmi | Interrupt::Reset => self.program_counter,
+        };
+        self.interrupt_push(kind, (return_addr >> 8) as u8);
+        self.bus.tick(1)?;
+        self.interrupt_push(kind, return_addr as u8);
+        self.bus.tick(1)?;
+
+        let vector = match kind {
+            Interrupt::Reset => RESET_ADDR,
+            Interrupt::Nmi => NMI_ADDR,
+            Interrupt::Brk | Interrupt::Irq if self.nmi_pending() => NMI_ADDR,
+            Interrupt::Brk | Interrupt::Irq => IRQ_ADDR,
+        };
+        if vector == NMI_ADDR {
+            self.nmi_seen = true;
+            self.bus.log_interrupt(InterruptCause::Nmi);
+        } else if kind == Interrupt::Irq {
+            self.bus.log_interrupt(irq_cause);
+        }
+        let mut status = self.status;
+        status.break_cmd = kind == Interrupt::Brk;
+        self.interrupt_push(kind, status.into());
+        self.status.irq_disable = true;
+        self.bus.tick(1)?;
+
+        let lo = self.read(vector) as u16;
+        self.bus.tick(1)?;
+        let hi = self.read(vector + 1) as u16;
+        self.bus.tick(1)?;
+        self.program_counter = hi << 8 | lo;
+        // The handler's first instruction always runs before interrupts are polled
+        self.bus.take_nmi_late();
         Ok(())
     }
 
-    fn irq(&mut self) -> Result<()> {
-        // println!("In IRQ");
-        self.push_stack_u16(self.program_counter);
-        self.push_stack(self.status.into());
-        self.status.irq_disable = true;
+    // Reset holds the CPU's write line high, so its pushes become stack reads
+    fn interrupt_push(&mut self, kind: Interrupt, data: u8) {
+        if kind == Interrupt::Reset {
+            self.read(STACK_PAGE | self.stack_pointer as u16);
+            self.stack_pointer = self.stack_pointer.wrapping_sub(1);
+        } else {
+            self.push_stack(data);
+        }
+    }
 
-        self.bus.tick(7)?;
-        let target = self.read_u16(0xFFFE);
-        self.program_counter = target;
-        Ok(())
+    /// Runs the interrupt sequence through the reset vector while the PPU and APU keep
+    /// going. Registers survive, except the stack pointer which drops by three.
+    fn reset(&mut self) -> Result<()> {
+        self.jammed = false;
+        self.nmi_deferred = false;
+        self.interrupt(Interrupt::Reset)
     }
 
-    fn reset(&mut self) {
-        self.stack_pointer = 0xfd;
-        self.status.irq_disable = true;
+    /// Every instruction is reported to the hook, so idle loops are no longer replayed
+    pub fn set_instruction_hook(&mut self, hook: Option<InstructionHook<'a>>) {
+        self.cancel_idle_loop();
+        self.instruction_hook = hook;
+    }
 
-        self.program_counter = self.read_u16(RESET_ADDR);
+    // Operands that can't be peeked, e.g. executed from open bus, are reported as 0
+    fn report_instruction(&mut self, opcode: u8, bytes: u8) {
+        let pc = self.program_counter;
+        let mut operands = [0; 2];
+        let len = (bytes as usize).saturating_sub(1).min(2);
+        for (i, operand) in operands[..len].iter_mut().enumerate() {
+            *operand = self.bus.peek(pc.wrapping_add(i as u16 + 1)).unwrap_or(0);
+        }
+        let event =
+            ExecutedInstruction::new(pc, opcode, &operands[..len], self.bus.time().cpu_cycles);
+        if let Some(hook) = self.instruction_hook.as_mut() {
+            hook(&event);
+        }
+    }
+
+    /// Writes the last executed instructions to a file, if built with the `trace-ring` feature
+    pub fn dump_trace(&self, reason: &str) {
+        if let Err(e) = self.trace_ring.dump(TRACE_DUMP_FILE, reason) {
+            println!("{e}");
+        }
     }
 
     #[allow(clippy::too_many_lines)]
@@ -300,20 +511,62 @@ impl<'a> Cpu<'a> {
         instructions.sort_unstable_by_key(|k| k.opcode);
 
         loop {
+            if self.bus.quit_requested() || self.bus.silent_frame_done() {
+                return Ok(());
+            }
+
+            if let Some(regs) = self.bus.pending_cpu_regs.take() {
+                self.cancel_idle_loop();
+                self.set_regs(regs);
+            }
+
+            if self.bus.state_request.is_some() {
+                self.handle_state_request();
+            }
+
             if self.bus.reset_triggered() {
                 self.bus.reset();
-                self.reset();
+                self.reset()?;
+            }
+
+            if self.idle.replaying() {
+                self.replay_idle_step()?;
+                continue;
+            }
+
+            // A jammed CPU stops fetching and ignores interrupts, only reset gets it going
+            if self.jammed {
+                self.bus.tick(1)?;
+                continue;
             }
 
             let op = self.read(self.program_counter);
 
             let instruction = instructions[op as usize];
+            if self.idle.enabled
+                && self.instruction_hook.is_none()
+                && self.record_idle_step(&instruction)
+            {
+                continue;
+            }
 
-            self.mnemonic = instruction.mnemonic.to_owned();
+            self.mnemonic = instruction.mnemonic;
             self.cycles = instruction.duration;
+            self.bus
+                .mark_executed(self.program_counter, instruction.bytes);
+            if self.instruction_hook.is_some() {
+                self.report_instruction(op, instruction.bytes);
+            }
 
             callback(self);
 
+            self.bus.cpu_regs = self.regs();
+            self.trace_ring
+                .push(self.bus.cpu_regs, op, instruction.mnemonic);
+            if std::mem::take(&mut self.bus.trace_dump_requested) {
+                self.dump_trace("Trace dump requested");
+            }
+
             self.program_counter += 1;
 
             match instruction.mnemonic {
@@ -332,7 +585,10 @@ impl<'a> Cpu<'a> {
                     if self.quit_on_brk {
                         return Ok(());
                     }
-                    self.brk();
+                    // Ticks on its own, and the first handler instruction runs before
+                    // interrupts are polled again
+                    self.interrupt(Interrupt::Brk)?;
+                    continue;
                 }
                 "BVC" => self.bvc(),
                 "BVS" => self.bvs(),
@@ -347,12 +603,24 @@ impl<'a> Cpu<'a> {
                 "DEX" => self.dex(),
                 "DEY" => self.dey(),
                 "EOR" => self.eor(instruction.addressing_mode),
-                "HLT" => return Ok(()),
+                "HLT" => {
+                    self.program_counter -= 1;
+                    self.dump_trace("CPU jammed");
+                    match self.jam_behavior {
+                        JamBehavior::Hang => {
+                            self.jammed = true;
+                            self.bus.cpu_jammed(self.program_counter);
+                        }
+                        JamBehavior::Break => {
+                            return Err(eyre!("CPU jammed at ${:04X}", self.program_counter))
+                        }
+                    }
+                }
                 "INC" => self.inc(instruction.addressing_mode),
                 "INX" => self.inx(),
                 "INY" => self.iny(),
-                "JMP" => self.jmp(instruction.addressing_mode),
-                "JSR" => self.jsr(),
+                "JMP" => self.jmp(instruction.addressing_mode)?,
+                "JSR" => self.jsr()?,
                 "LDA" => self.lda(instruction.addressing_mode),
                 "LDX" => self.ldx(instruction.addressing_mode),
                 "LDY" => self.ldy(instruction.addressing_mode),
@@ -372,8 +640,8 @@ impl<'a> Cpu<'a> {
                 "PLP" => self.status = (self.pull_stack() & 0xEF | 0x20).into(),
                 "ROL" => self.rol(instruction.addressing_mode),
                 "ROR" => self.ror(instruction.addressing_mode),
-                "RTI" => self.rti(),
-                "RTS" => self.rts(),
+                "RTI" => self.rti()?,
+                "RTS" => self.rts()?,
                 "SBC" => self.adc(instruction.addressing_mode, true),
                 "SEC" => self.status.carry = true,
                 "SED" => self.status.decimal = true,
@@ -411,7 +679,9 @@ impl<'a> Cpu<'a> {
                 _ => panic!("Uncrecognized mnemonic {}", instruction.mnemonic),
             }
 
-            self.bus.tick(instruction.duration)?;
+            if !instruction.ticks_per_access() {
+                self.bus.tick(instruction.duration)?;
+            }
 
             // Don't increment program counter for some instructions
             match instruction.mnemonic {
@@ -419,17 +689,28 @@ impl<'a> Cpu<'a> {
                 _ => self.program_counter += (instruction.bytes - 1) as u16,
             }
 
-            if self.bus.nmi_active() && !self.nmi_seen {
-                self.nmi_seen = true;
-                self.nmi()?;
-            } else {
-                self.nmi_seen = self.bus.nmi_active();
-            }
+            self.poll_interrupts()?;
+        }
+    }
 
-            if !self.status.irq_disable && self.bus.irq_active() {
-                self.irq()?;
-            }
+    /// Takes a pending NMI or IRQ at the end of an instruction
+    fn poll_interrupts(&mut self) -> Result<()> {
+        let nmi_late = self.bus.take_nmi_late();
+        if self.nmi_deferred || (self.nmi_pending() && !nmi_late) {
+            self.nmi_deferred = false;
+            self.nmi_seen = true;
+            self.interrupt(Interrupt::Nmi)?;
+        } else if self.nmi_pending() {
+            self.nmi_seen = true;
+            self.nmi_deferred = true;
+        } else {
+            self.nmi_seen = self.bus.nmi_active();
+        }
+
+        if !self.status.irq_disable && self.bus.irq_active() {
+            self.interrupt(Interrupt::Irq)?;
         }
+        Ok(())
     }
 }
 
@@ -558,13 +839,6 @@ impl<'a> Cpu<'a> {
         self.status.negative = operand & 0x1 << 7 != 0; // and bit 7
     }
 
-    fn brk(&mut self) {
-        self.push_stack_u16(self.program_counter.wrapping_add(1));
-        self.push_stack(u8::from(self.status) | 0x10);
-        let target = self.read_u16(0xFFFE);
-        self.program_counter = target;
-    }
-
     fn compare(&mut self, source: u8, mode: AddressingMode) {
         let addr = self.get_operand_addr(mode);
         let operand = self.read(addr);
@@ -614,30 +888,52 @@ impl<'a> Cpu<'a> {
         self.update_zero_neg(self.register_y);
     }
 
-    fn jmp(&mut self, mode: AddressingMode) {
-        self.program_counter = match mode {
-            AddressingMode::Absolute => self.read_u16(self.program_counter),
-            AddressingMode::None => {
-                // 6502 reads MSB of indirect operand from the wrong address.
-                // If operand is 0x30ff, address is read from 0x30ff and 0x3000
-                // instead of 0x30ff and 0x3100
-                let operand_addr = self.read_u16(self.program_counter);
-                let correct_operand = self.read_u16(operand_addr);
-
-                if operand_addr & 0x00FF == 0x00FF {
-                    let wrong_msb = (self.read(operand_addr & 0xFF00) as u16) << 8;
-                    wrong_msb | correct_operand & 0x00FF
-                } else {
-                    correct_operand
-                }
+    fn jmp(&mut self, mode: AddressingMode) -> Result<()> {
+        match mode {
+            AddressingMode::Absolute => {
+                self.program_counter = self.read_u16(self.program_counter);
+                Ok(())
             }
+            AddressingMode::None => self.jmp_indirect(),
             _ => panic!("Unsupported addressing mode for JMP!"),
-        };
+        }
     }
 
-    fn jsr(&mut self) {
-        self.push_stack_u16(self.program_counter + 1);
-        self.program_counter = self.read_u16(self.program_counter);
+    // 6502 reads MSB of indirect operand from the wrong address.
+    // If operand is 0x30ff, address is read from 0x30ff and 0x3000
+    // instead of 0x30ff and 0x3100
+    fn jmp_indirect(&mut self) -> Result<()> {
+        self.bus.tick(1)?;
+        let operand_addr = self.read_u16(self.program_counter);
+        self.bus.tick(2)?;
+        let lo = self.read(operand_addr);
+        self.bus.tick(1)?;
+        self.bus.take_nmi_late();
+        let hi = self.read(operand_addr & 0xFF00 | operand_addr.wrapping_add(1) & 0x00FF);
+        self.bus.tick(1)?;
+        self.program_counter = u16::from_le_bytes([lo, hi]);
+        Ok(())
+    }
+
+    // The target's MSB is only read after the return address is pushed, so a JSR
+    // whose operand is on the stack jumps through the pushed byte
+    fn jsr(&mut self) -> Result<()> {
+        self.bus.tick(1)?;
+        let lo = self.read(self.program_counter);
+        self.bus.tick(1)?;
+        // Internal cycle with a discarded stack read
+        self.read(STACK_PAGE | self.stack_pointer as u16);
+        self.bus.tick(1)?;
+        let return_addr = self.program_counter.wrapping_add(1);
+        self.push_stack((return_addr >> 8) as u8);
+        self.bus.tick(1)?;
+        self.push_stack(return_addr as u8);
+        self.bus.tick(1)?;
+        self.bus.take_nmi_late();
+        let hi = self.read(return_addr);
+        self.bus.tick(1)?;
+        self.program_counter = u16::from_le_bytes([lo, hi]);
+        Ok(())
     }
 
     fn lda(&mut self, mode: AddressingMode) {
@@ -723,14 +1019,37 @@ impl<'a> Cpu<'a> {
         }
     }
 
-    fn rti(&mut self) {
+    fn rti(&mut self) -> Result<()> {
+        // Opcode fetch and the discarded read of the next byte
+        self.bus.tick(2)?;
+        // Discarded stack read before the pointer is incremented
+        self.read(STACK_PAGE | self.stack_pointer as u16);
+        self.bus.tick(1)?;
         self.status = (self.pull_stack() & 0xEF | 0x20).into();
-        self.program_counter = self.pull_stack_u16();
-        // println!("Returning from exception");
+        self.bus.tick(1)?;
+        let lo = self.pull_stack();
+        self.bus.tick(1)?;
+        self.bus.take_nmi_late();
+        let hi = self.pull_stack();
+        self.bus.tick(1)?;
+        self.program_counter = u16::from_le_bytes([lo, hi]);
+        Ok(())
     }
 
-    fn rts(&mut self) {
-        self.program_counter = self.pull_stack_u16().wrapping_add(1);
+    fn rts(&mut self) -> Result<()> {
+        // Opcode fetch and the discarded read of the next byte
+        self.bus.tick(2)?;
+        self.read(STACK_PAGE | self.stack_pointer as u16);
+        self.bus.tick(1)?;
+        let lo = self.pull_stack();
+        self.bus.tick(1)?;
+        let hi = self.pull_stack();
+        self.bus.tick(1)?;
+        // The last cycle increments the return address past the JSR
+        self.bus.take_nmi_late();
+        self.bus.tick(1)?;
+        self.program_counter = u16::from_le_bytes([lo, hi]).wrapping_add(1);
+        Ok(())
     }
 
     fn tax(&mut self) {
@@ -813,12 +1132,24 @@ impl<'a> Cpu<'a> {
 mod test {
     use super::*;
     use crate::console::cartridge::mappers::{get_mapper, Mirroring};
-    use crate::console::cartridge::Cartridge;
+    use crate::console::cartridge::{Cartridge, Region};
+    use crate::console::{apu::Apu, controller::Controller, video::Frame, Frontend};
 
     fn _dummy_cart() -> Cartridge {
         Cartridge {
-            mapper: get_mapper(0, vec![0; 0x4000], vec![0; 0x2000], 0, Mirroring::Vertical)
-                .unwrap(),
+            mapper: get_mapper(
+                0,
+                vec![0; 0x4000],
+                vec![0; 0x2000],
+                0,
+                0x2000,
+                Mirroring::Vertical,
+            )
+            .unwrap(),
+            region: Region::Ntsc,
+            battery: false,
+            chr_rom_write: None,
+            fallback_from: None,
         }
     }
 
@@ -827,6 +1158,282 @@ mod test {
         todo!()
     }
 
+    struct NullFrontend;
+
+    impl Frontend for NullFrontend {
+        fn handle_io(&mut self, _frame: &Frame, _apu: &Apu, _controller: &mut Controller) {}
+
+        fn handle_audio(&mut self, _apu: &Apu) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    // Quits after the given number of frames and counts audio chunks
+    struct FrameLimit {
+        frames: usize,
+        chunks: usize,
+    }
+
+    impl Frontend for FrameLimit {
+        fn handle_io(&mut self, _frame: &Frame, _apu: &Apu, _controller: &mut Controller) {
+            self.frames -= 1;
+        }
+
+        fn handle_audio(&mut self, _apu: &Apu) -> Result<()> {
+            self.chunks += 1;
+            Ok(())
+        }
+
+        fn quit_requested(&self) -> bool {
+            self.frames == 0
+        }
+    }
+
+    // NMI handler at $9000 and IRQ/BRK handler at $A000
+    fn interrupt_cpu(frontend: &mut NullFrontend) -> Cpu<'_> {
+        program_cpu(frontend, &[])
+    }
+
+    // Like `interrupt_cpu`, with the given code placed in PRG ROM
+    fn program_cpu<'a>(frontend: &'a mut dyn Frontend, code: &[(u16, &[u8])]) -> Cpu<'a> {
+        let mut prg = vec![0; 0x4000];
+        for &(addr, bytes) in code {
+            let start = addr as usize - 0x8000;
+            prg[start..start + bytes.len()].copy_from_slice(bytes);
+        }
+        prg[0x3FFA..0x3FFC].copy_from_slice(&[0x00, 0x90]);
+        prg[0x3FFC..0x3FFE].copy_from_slice(&[0x00, 0xB0]);
+        prg[0x3FFE..].copy_from_slice(&[0x00, 0xA0]);
+        let cartridge = Cartridge {
+            mapper: get_mapper(0, prg, vec![0; 0x2000], 0, 0x2000, Mirroring::Vertical).unwrap(),
+            region: Region::Ntsc,
+            battery: false,
+            chr_rom_write: None,
+            fallback_from: None,
+        };
+        let mut cpu = Cpu::new(Bus::new(cartridge, frontend));
+        cpu.program_counter = 0x8001;
+        cpu.stack_pointer = 0xFF;
+        cpu.status = 0x00.into();
+        cpu
+    }
+
+    #[test]
+    fn test_interrupt_sequences() {
+        for (kind, return_addr, status, target) in [
+            (Interrupt::Brk, 0x8002, 0x30, 0xA000),
+            (Interrupt::Irq, 0x8001, 0x20, 0xA000),
+            (Interrupt::Nmi, 0x8001, 0x20, 0x9000),
+        ] {
+            let mut frontend = NullFrontend;
+            let mut cpu = interrupt_cpu(&mut frontend);
+            let start = cpu.bus.time().cpu_cycles;
+            cpu.interrupt(kind).unwrap();
+            assert_eq!(cpu.bus.time().cpu_cycles - start, 7);
+            assert_eq!(cpu.program_counter, target);
+            assert_eq!(cpu.stack_pointer, 0xFC);
+            assert_eq!(cpu.bus.read_u16(0x1FE), return_addr);
+            assert_eq!(cpu.bus.read(0x1FD), status, "{kind:?}");
+            assert!(cpu.status.irq_disable);
+        }
+    }
+
+    #[test]
+    fn test_reset_sequence() {
+        let mut frontend = NullFrontend;
+        let mut cpu = interrupt_cpu(&mut frontend);
+        cpu.register_a = 0x12;
+        let start = cpu.bus.time().cpu_cycles;
+        cpu.reset().unwrap();
+        assert_eq!(cpu.bus.time().cpu_cycles - start, 7);
+        assert_eq!(cpu.program_counter, 0xB000);
+        assert_eq!(cpu.stack_pointer, 0xFC);
+        assert_eq!(cpu.register_a, 0x12);
+        assert!(cpu.status.irq_disable);
+        // Nothing was pushed
+        assert_eq!(cpu.bus.read_u16(0x1FE), 0);
+        assert_eq!(cpu.bus.read(0x1FD), 0);
+    }
+
+    #[test]
+    fn test_subroutine_access_order() {
+        let mut frontend = NullFrontend;
+        let mut cpu = interrupt_cpu(&mut frontend);
+        // JSR $B000 at $01FD, the push overwrites its last byte before it is read
+        for (addr, data) in [(0x1FD, 0x20), (0x1FE, 0x00), (0x1FF, 0xB0)] {
+            cpu.bus.write(addr, data);
+        }
+        cpu.stack_pointer = 0xFF;
+        cpu.program_counter = 0x1FE;
+        let start = cpu.bus.time().cpu_cycles;
+        cpu.jsr().unwrap();
+        assert_eq!(cpu.program_counter, 0x0100);
+        assert_eq!(cpu.bus.time().cpu_cycles - start, 6);
+        cpu.rts().unwrap();
+        assert_eq!(cpu.program_counter, 0x0200);
+        assert_eq!(cpu.bus.time().cpu_cycles - start, 12);
+
+        // JMP ($02FF) takes the MSB from $0200
+        for (addr, data) in [(0x401, 0xFF), (0x402, 0x02), (0x2FF, 0x34), (0x200, 0x12)] {
+            cpu.bus.write(addr, data);
+        }
+        cpu.bus.write(0x300, 0x56);
+        cpu.program_counter = 0x401;
+        cpu.jmp(AddressingMode::None).unwrap();
+        assert_eq!(cpu.program_counter, 0x1234);
+        assert_eq!(cpu.bus.time().cpu_cycles - start, 17);
+    }
+
+    #[test]
+    fn test_nmi_hijacks_brk() {
+        let mut frontend = NullFrontend;
+        let mut cpu = interrupt_cpu(&mut frontend);
+        cpu.bus.write(0x2000, 0x80);
+        while !cpu.bus.nmi_active() {
+            cpu.bus.tick(1).unwrap();
+        }
+        cpu.interrupt(Interrupt::Brk).unwrap();
+        assert_eq!(cpu.program_counter, 0x9000);
+        // Still pushed as BRK, and the NMI isn't taken a second time
+        assert_eq!(cpu.bus.read(0x1FD), 0x30);
+        assert!(!cpu.nmi_pending());
+    }
+
+    #[test]
+    fn test_idle_skip_keeps_timing() {
+        // Turns NMI on after reset and waits for the handler to set $10
+        let main: &[u8] = &[0xA9, 0x80, 0x8D, 0x00, 0x20, 0xA5, 0x10, 0xF0, 0xFC, 0x00];
+        let nmi: &[u8] = &[0xE6, 0x10, 0x40];
+        let run = |skip: bool| {
+            let mut frontend = NullFrontend;
+            let mut cpu = program_cpu(&mut frontend, &[(0xB000, main), (0x9000, nmi)]);
+            cpu.quit_on_brk = true;
+            cpu.set_idle_skip(skip);
+            let mut executed = 0;
+            cpu.run_with_callback(|_| executed += 1).unwrap();
+            (cpu.bus.time().cpu_cycles, cpu.regs(), executed)
+        };
+        let (cycles, regs, executed) = run(false);
+        let (skip_cycles, skip_regs, skip_executed) = run(true);
+        assert_eq!(skip_cycles, cycles);
+        assert_eq!(skip_regs, regs);
+        assert!(skip_executed < 20 && executed > 1000);
+    }
+
+    #[test]
+    fn test_instruction_hook() {
+        let main: &[u8] = &[0xA9, 0x80, 0x8D, 0x00, 0x20, 0xA5, 0x10, 0xF0, 0xFC, 0x00];
+        let nmi: &[u8] = &[0xE6, 0x10, 0x40];
+        let seen = std::cell::RefCell::new(Vec::new());
+        let mut frontend = NullFrontend;
+        let mut cpu = program_cpu(&mut frontend, &[(0xB000, main), (0x9000, nmi)]);
+        cpu.quit_on_brk = true;
+        cpu.set_idle_skip(true);
+        cpu.set_instruction_hook(Some(Box::new(|instr| seen.borrow_mut().push(*instr))));
+        let mut executed = 0;
+        cpu.run_with_callback(|_| executed += 1).unwrap();
+        drop(cpu);
+
+        // Idle loops aren't replayed while hooked
+        let seen = seen.into_inner();
+        assert_eq!(seen.len(), executed);
+        let first: Vec<_> = seen[..3]
+            .iter()
+            .map(|i| (i.pc, i.opcode, i.operands().to_vec()))
+            .collect();
+        assert_eq!(
+            first,
+            [
+                (0xB000, 0xA9, vec![0x80]),
+                (0xB002, 0x8D, vec![0x00, 0x20]),
+                (0xB005, 0xA5, vec![0x10])
+            ]
+        );
+        assert_eq!(seen[1].cycle - seen[0].cycle, 2);
+        assert_eq!(seen[2].cycle - seen[1].cycle, 4);
+        assert!(seen.iter().any(|i| i.pc == 0x9000));
+    }
+
+    // Turns on rendering and pulse 1, then counts loop iterations in $00
+    const COUNTING_LOOP: &[u8] = &[
+        0xA9, 0x1E, 0x8D, 0x01, 0x20, 0xA9, 0x01, 0x8D, 0x15, 0x40, 0xA9, 0xBF, 0x8D, 0x00, 0x40,
+        0xA9, 0x08, 0x8D, 0x03, 0x40, 0xE6, 0x00, 0x4C, 0x14, 0xB0,
+    ];
+
+    // What the game could find out about the console
+    fn observable_state(cpu: &mut Cpu) -> impl PartialEq + std::fmt::Debug {
+        let ram: Vec<_> = (0..0x800).filter_map(|addr| cpu.bus.peek(addr)).collect();
+        let status = cpu.bus.read(0x4015);
+        (cpu.regs(), cpu.bus.time(), ram, status, cpu.bus.ppu_regs())
+    }
+
+    #[test]
+    fn test_silent_frames_match_normal_ones() {
+        let main = COUNTING_LOOP;
+        let state = observable_state;
+
+        let mut normal = FrameLimit {
+            frames: 3,
+            chunks: 0,
+        };
+        let mut cpu = program_cpu(&mut normal, &[(0xB000, main)]);
+        cpu.run_with_callback(|_| {}).unwrap();
+        let expected = state(&mut cpu);
+        drop(cpu);
+        assert!(normal.chunks > 0);
+
+        let mut silent = FrameLimit {
+            frames: usize::MAX,
+            chunks: 0,
+        };
+        let mut cpu = program_cpu(&mut silent, &[(0xB000, main)]);
+        for _ in 0..3 {
+            cpu.run_frame_silent().unwrap();
+        }
+        assert_eq!(state(&mut cpu), expected);
+        drop(cpu);
+        assert_eq!((silent.frames, silent.chunks), (usize::MAX, 0));
+    }
+
+    #[test]
+    fn test_save_state_round_trip() {
+        let mut frontend = NullFrontend;
+        let mut cpu = program_cpu(&mut frontend, &[(0xB000, COUNTING_LOOP)]);
+        for _ in 0..2 {
+            cpu.run_frame_silent().unwrap();
+        }
+        let saved = cpu.snapshot().encode();
+        for _ in 0..2 {
+            cpu.run_frame_silent().unwrap();
+        }
+        let expected = observable_state(&mut cpu);
+
+        cpu.restore(&SaveState::decode(&saved).unwrap()).unwrap();
+        for _ in 0..2 {
+            cpu.run_frame_silent().unwrap();
+        }
+        assert_eq!(observable_state(&mut cpu), expected);
+
+        // A freshly powered on console with the same ROM picks up from the same point
+        let mut frontend = NullFrontend;
+        let mut other = program_cpu(&mut frontend, &[(0xB000, COUNTING_LOOP)]);
+        other.restore(&SaveState::decode(&saved).unwrap()).unwrap();
+        for _ in 0..2 {
+            other.run_frame_silent().unwrap();
+        }
+        assert_eq!(observable_state(&mut other), expected);
+
+        // A state that fails to load halfway leaves the console as it was
+        let before = other.snapshot().encode();
+        let mut broken = SaveState::decode(&saved).unwrap();
+        broken.put(&state::APU, vec![0; 3]);
+        assert_eq!(
+            other.restore(&broken).err().unwrap().to_string(),
+            "Failed to load the APU section: Failed to load field cycle: State ended unexpectedly"
+        );
+        assert_eq!(other.snapshot().encode(), before);
+    }
+
     #[test]
     fn test_lda_immediate() {
         let bus = dummy_bus();
@@ -0,0 +1,127 @@
+// Games waiting for the NMI often spin in a loop that only reads RAM, such as
+// `lda flag / beq loop`. Once an iteration ends with the registers it started with,
+// every further one reads the same values and ticks the bus the same way until an
+// interrupt arrives. Those iterations are replayed from a recording of the bus ticks
+// instead of decoding instructions, so cycle counts and interrupt timing don't change.
+// Only done with NMI on and rendering off, like on title and menu screens.
+
+use eyre::Result;
+
+use super::instr::{AddressingMode, Instruction};
+use super::Cpu;
+use crate::console::debug::CpuRegs;
+
+// Longest loop recognized, in instructions
+const MAX_STEPS: usize = 4;
+// Highest address of internal RAM and its mirrors
+const RAM_END: u16 = 0x1FFF;
+
+struct Step {
+    regs: CpuRegs,
+    /// Cycles and open bus value of each `Bus::tick` during the instruction
+    ticks: Vec<(u8, u8)>,
+}
+
+#[derive(Default)]
+pub struct IdleLoop {
+    pub enabled: bool,
+    steps: Vec<Step>,
+    /// Next step to replay, None while still looking for a loop
+    replaying: Option<usize>,
+}
+
+impl IdleLoop {
+    pub const fn replaying(&self) -> bool {
+        self.replaying.is_some()
+    }
+}
+
+impl Cpu<'_> {
+    /// Forgets the recorded instructions, after anything the loop can't account for
+    pub(super) fn cancel_idle_loop(&mut self) {
+        self.idle.steps.clear();
+        self.idle.replaying = None;
+        self.bus.tick_log = None;
+    }
+
+    /// Records the instruction about to run as part of a possible idle loop. Returns
+    /// true once it closes the loop, it is then replayed instead of executed.
+    pub(super) fn record_idle_step(&mut self, instruction: &Instruction) -> bool {
+        if let (Some(ticks), Some(last)) = (self.bus.tick_log.take(), self.idle.steps.last_mut()) {
+            last.ticks = ticks;
+        }
+        let regs = self.regs();
+        if let Some(start) = self.idle.steps.iter().position(|step| step.regs == regs) {
+            if self.bus.idle_skip_allowed() {
+                self.idle.steps.drain(..start);
+                self.idle.replaying = Some(0);
+                return true;
+            }
+            self.idle.steps.clear();
+        }
+        if !self.idle_safe(instruction) {
+            self.cancel_idle_loop();
+            return false;
+        }
+        if self.idle.steps.len() == MAX_STEPS {
+            self.idle.steps.remove(0);
+        }
+        self.idle.steps.push(Step {
+            regs,
+            ticks: Vec::new(),
+        });
+        self.bus.tick_log = Some(Vec::new());
+        false
+    }
+
+    /// Ticks the bus like the next instruction of the loop would and polls interrupts
+    pub(super) fn replay_idle_step(&mut self) -> Result<()> {
+        let Some(next) = self.idle.replaying else {
+            return Ok(());
+        };
+        let frames = self.bus.time().frames;
+        let step = &self.idle.steps[next];
+        self.bus.cpu_regs = step.regs;
+        for &(cycles, open_bus) in &step.ticks {
+            self.bus.replay_tick(cycles, open_bus)?;
+        }
+        let next = (next + 1) % self.idle.steps.len();
+        self.set_regs(self.idle.steps[next].regs);
+        self.idle.replaying = Some(next);
+        // The frontend may have changed memory at the end of the frame
+        if self.bus.time().frames != frames {
+            self.cancel_idle_loop();
+        }
+        self.poll_interrupts()
+    }
+
+    // Instructions that only read RAM, so they can't tell one iteration from the next
+    fn idle_safe(&mut self, instruction: &Instruction) -> bool {
+        let operand = self.program_counter.wrapping_add(1);
+        match instruction.mnemonic {
+            "BCC" | "BCS" | "BEQ" | "BMI" | "BNE" | "BPL" | "BVC" | "BVS" | "NOP" | "CLC"
+            | "SEC" | "CLV" | "TAX" | "TAY" | "TXA" | "TYA" => true,
+            "JMP" => matches!(instruction.addressing_mode, AddressingMode::Absolute),
+            "LDA" | "LDX" | "LDY" | "BIT" | "CMP" | "CPX" | "CPY" | "AND" | "ORA" | "EOR" => {
+                let index = match instruction.addressing_mode {
+                    AddressingMode::Immediate
+                    | AddressingMode::ZeroPage
+                    | AddressingMode::ZeroPageX
+                    | AddressingMode::ZeroPageY => return true,
+                    AddressingMode::Absolute => 0,
+                    AddressingMode::AbsoluteX => self.register_x,
+                    AddressingMode::AbsoluteY => self.register_y,
+                    _ => return false,
+                };
+                let (Some(lo), Some(hi)) = (
+                    self.bus.peek(operand),
+                    self.bus.peek(operand.wrapping_add(1)),
+                ) else {
+                    return false;
+                };
+                u16::from_le_bytes([lo, hi]).wrapping_add(index as u16) <= RAM_END
+            }
+            _ => false,
+        }
+    }
+}
@@ -40,6 +40,15 @@ impl Instruction {
             duration,
         }
     }
+
+    /// Whether the instruction ticks the bus after each of its accesses, like the
+    /// hardware orders them, instead of all its cycles at the end
+    pub fn ticks_per_access(&self) -> bool {
+        matches!(
+            (self.mnemonic, self.addressing_mode),
+            ("JSR" | "RTS" | "RTI", _) | ("JMP", AddressingMode::None)
+        )
+    }
 }
 
 lazy_static::lazy_static! {
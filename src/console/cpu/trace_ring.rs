@@ -0,0 +1,109 @@
+use std::fmt::Write;
+
+use eyre::{Result, WrapErr};
+
+use crate::console::debug::CpuRegs;
+
+// Number of instructions kept when the trace-ring feature is enabled
+const TRACE_LEN: usize = 10_000;
+
+#[derive(Clone, Copy)]
+struct Entry {
+    regs: CpuRegs,
+    opcode: u8,
+    mnemonic: &'static str,
+}
+
+/// The last executed instructions, for finding out how the game got stuck.
+/// Recording costs a little time on every instruction, so it is only done
+/// when built with the `trace-ring` feature.
+pub struct TraceRing {
+    entries: Vec<Entry>,
+    capacity: usize,
+    next: usize,
+}
+
+impl TraceRing {
+    pub fn new() -> Self {
+        let capacity = if cfg!(feature = "trace-ring") {
+            TRACE_LEN
+        } else {
+            0
+        };
+        Self {
+            entries: Vec::with_capacity(capacity),
+            capacity,
+            next: 0,
+        }
+    }
+
+    pub fn push(&mut self, regs: CpuRegs, opcode: u8, mnemonic: &'static str) {
+        if self.capacity == 0 {
+            return;
+        }
+        let entry = Entry {
+            regs,
+            opcode,
+            mnemonic,
+        };
+        if self.entries.len() < self.capacity {
+            self.entries.push(entry);
+        } else {
+            self.entries[self.next] = entry;
+        }
+        self.next = (self.next + 1) % self.capacity;
+    }
+
+    /// One line per instruction, oldest first
+    fn to_text(&self) -> String {
+        let (newer, older) = self.entries.split_at(self.next.min(self.entries.len()));
+        let mut out = String::new();
+        for entry in older.iter().chain(newer) {
+            let r = entry.regs;
+            let _ = writeln!(
+                out,
+                "{:04X}  {:02X}  {:3}  A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}",
+                r.pc, entry.opcode, entry.mnemonic, r.a, r.x, r.y, r.p, r.sp
+            );
+        }
+        out
+    }
+
+    /// Writes the trace to a file, does nothing if tracing is not built in
+    pub fn dump(&self, file: &str, reason: &str) -> Result<()> {
+        if self.entries.is_empty() {
+            return Ok(());
+        }
+        std::fs::write(file, self.to_text())
+            .wrap_err_with(|| format!("Failed to write trace dump {file}"))?;
+        println!(
+            "{reason}, wrote the last {} instructions to {file}",
+            self.entries.len()
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_wraps_oldest_first() {
+        let mut ring = TraceRing {
+            entries: Vec::new(),
+            capacity: 2,
+            next: 0,
+        };
+        for pc in 1..=3 {
+            let regs = CpuRegs {
+                pc,
+                ..CpuRegs::default()
+            };
+            ring.push(regs, 0xEA, "NOP");
+        }
+        let text = ring.to_text();
+        let pcs: Vec<&str> = text.lines().map(|l| &l[..4]).collect();
+        assert_eq!(pcs, ["0002", "0003"]);
+    }
+}
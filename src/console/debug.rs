@@ -0,0 +1,638 @@
+// State captured for the debugger panels once per frame
+
+use std::collections::VecDeque;
+
+use super::apu::Apu;
+
+/// CPU registers at the start of the last executed instruction
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct CpuRegs {
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub p: u8,
+    pub sp: u8,
+    pub pc: u16,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ReturnKind {
+    Subroutine,
+    Interrupt,
+}
+
+/// A return address found on the stack
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ReturnAddr {
+    /// Offset in the stack page of the first byte belonging to the frame
+    pub offset: u8,
+    /// Number of bytes the frame takes, 2 for JSR and 3 for interrupts
+    pub len: u8,
+    /// Where execution continues once the frame is popped
+    pub target: u16,
+    pub kind: ReturnKind,
+}
+
+/// PPU registers as the CPU last wrote them
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct PpuRegs {
+    pub ctrl: u8,
+    pub mask: u8,
+    pub scroll_x: u8,
+    pub scroll_y: u8,
+}
+
+/// A change to console state requested by the debugger or a script
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DebugWrite {
+    /// Replaces the CPU registers before the next instruction
+    CpuRegs(CpuRegs),
+    /// Sets PPUCTRL without the $2000 write toggling anything else
+    PpuCtrl(u8),
+    /// Sets the scroll position without touching the $2005/$2006 write latch
+    PpuScroll { x: u8, y: u8 },
+    /// A CPU bus write with all its side effects, e.g. to RAM or an APU register
+    Memory { addr: u16, data: u8 },
+}
+
+/// Background scroll a visible line was drawn with, taken when its first tile is fetched
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct LineOrigin {
+    /// VRAM address of the first tile, in the $2006 layout
+    pub vaddr: u16,
+    pub fine_x: u8,
+    /// Background pattern table, 0 or 1
+    pub bg_half: u16,
+}
+
+/// Background tile under a screen pixel
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct TileInfo {
+    pub tile: u8,
+    pub pattern_addr: u16,
+    /// Background palette 0-3 from the attribute table
+    pub palette: u8,
+    /// Logical nametable 0-3, at $2000 + $400 * nametable
+    pub nametable: u8,
+    /// Tile column and row in the nametable
+    pub column: u8,
+    pub row: u8,
+}
+
+/// Enough of the last frame to map screen pixels back to background tiles
+#[derive(Clone, Default)]
+pub struct TileMap {
+    /// One per visible line, `None` where the background was off
+    pub lines: Vec<Option<LineOrigin>>,
+    /// $2000-$2FFF as the PPU sees it through the cartridge's mirroring
+    pub nametables: Vec<u8>,
+}
+
+impl TileMap {
+    pub fn tile_at(&self, x: usize, y: usize) -> Option<TileInfo> {
+        let origin = (*self.lines.get(y)?)?;
+        let v = origin.vaddr;
+        // Coarse X wraps into the horizontally adjacent nametable
+        let column = (v & 0x1F) as usize + (x + origin.fine_x as usize) / 8;
+        let nametable = ((v >> 10) & 0x3) as usize ^ (column / 32);
+        let column = column % 32;
+        let row = ((v >> 5) & 0x1F) as usize;
+        let fine_y = (v >> 12) & 0x7;
+
+        let base = 0x400 * nametable;
+        let tile = *self.nametables.get(base + 32 * row + column)?;
+        let attribute = *self
+            .nametables
+            .get(base + 0x3C0 + 8 * (row / 4) + column / 4)?;
+        let shift = (column & 0x2) + 2 * (row & 0x2);
+        Some(TileInfo {
+            tile,
+            pattern_addr: 0x1000 * origin.bg_half + 16 * tile as u16 + fine_y,
+            palette: (attribute >> shift) & 0x3,
+            nametable: nametable as u8,
+            column: column as u8,
+            row: row as u8,
+        })
+    }
+}
+
+/// The mapper raising or dropping its IRQ line
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct IrqEdge {
+    pub asserted: bool,
+    pub frame: u64,
+    pub scanline: isize,
+    pub dot: usize,
+    pub cpu_cycle: u64,
+}
+
+/// The last few mapper IRQ line changes, for tracking down split screen glitches
+#[derive(Clone, Default)]
+pub struct IrqHistory {
+    line: bool,
+    edges: VecDeque<IrqEdge>,
+}
+
+impl IrqHistory {
+    const LEN: usize = 32;
+
+    /// Records an edge if the line differs from the last sample
+    pub fn sample(&mut self, line: bool, at: impl FnOnce(bool) -> IrqEdge) {
+        if line == self.line {
+            return;
+        }
+        self.line = line;
+        if self.edges.len() == Self::LEN {
+            self.edges.pop_front();
+        }
+        self.edges.push_back(at(line));
+    }
+
+    /// Level of the last sample
+    pub const fn line(&self) -> bool {
+        self.line
+    }
+
+    /// Oldest first
+    pub fn edges(&self) -> Vec<IrqEdge> {
+        self.edges.iter().copied().collect()
+    }
+}
+
+/// Why the CPU took an interrupt
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum InterruptCause {
+    /// Vblank NMI from the PPU
+    Nmi,
+    /// IRQ, with the sources holding the line when the CPU polled it
+    Irq {
+        apu_frame: bool,
+        dmc: bool,
+        mapper: bool,
+    },
+}
+
+impl InterruptCause {
+    pub fn describe(self) -> String {
+        match self {
+            Self::Nmi => "NMI (PPU)".to_owned(),
+            Self::Irq {
+                apu_frame,
+                dmc,
+                mapper,
+            } => {
+                let sources: Vec<&str> =
+                    [(apu_frame, "APU frame"), (dmc, "DMC"), (mapper, "mapper")]
+                        .iter()
+                        .filter(|(active, _)| *active)
+                        .map(|(_, name)| *name)
+                        .collect();
+                format!("IRQ ({})", sources.join(", "))
+            }
+        }
+    }
+}
+
+/// An NMI or IRQ the CPU jumped to the handler of
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct TakenInterrupt {
+    pub cause: InterruptCause,
+    pub frame: u64,
+    pub scanline: isize,
+    pub dot: usize,
+    pub cpu_cycle: u64,
+}
+
+/// The last interrupts taken, enough to cover a few frames of a busy IRQ game
+#[derive(Clone, Default)]
+pub struct InterruptLog {
+    taken: VecDeque<TakenInterrupt>,
+}
+
+impl InterruptLog {
+    const LEN: usize = 256;
+
+    pub fn push(&mut self, interrupt: TakenInterrupt) {
+        if self.taken.len() == Self::LEN {
+            self.taken.pop_front();
+        }
+        self.taken.push_back(interrupt);
+    }
+
+    /// Oldest first
+    pub fn taken(&self) -> Vec<TakenInterrupt> {
+        self.taken.iter().copied().collect()
+    }
+}
+
+/// PPU settings that decide whether anything gets drawn
+#[derive(Clone, Copy, Default)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct PpuState {
+    pub nmi_enabled: bool,
+    pub show_bg: bool,
+    pub show_sprites: bool,
+    /// Every pixel of the last frame had the same palette index
+    pub uniform_frame: bool,
+}
+
+/// What the CPU did during the last frame
+#[derive(Clone, Copy)]
+pub struct FrameStats {
+    pub status_reads: usize,
+    pub pc_min: u16,
+    pub pc_max: u16,
+}
+
+impl Default for FrameStats {
+    fn default() -> Self {
+        Self {
+            status_reads: 0,
+            pc_min: u16::MAX,
+            pc_max: 0,
+        }
+    }
+}
+
+impl FrameStats {
+    pub fn executed(&mut self, pc: u16) {
+        self.pc_min = self.pc_min.min(pc);
+        self.pc_max = self.pc_max.max(pc);
+    }
+}
+
+/// 64-bit FNV-1a, stable across builds and platforms unlike std's hasher
+#[derive(Clone, Copy)]
+pub struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Self(0xCBF2_9CE4_8422_2325)
+    }
+}
+
+impl Fnv1a {
+    pub fn write(&mut self, data: &[u8]) {
+        for &b in data {
+            self.0 = (self.0 ^ b as u64).wrapping_mul(0x0100_0000_01B3);
+        }
+    }
+
+    pub fn write_u16s(&mut self, data: &[u16]) {
+        for value in data {
+            self.write(&value.to_le_bytes());
+        }
+    }
+
+    pub const fn finish(self) -> u64 {
+        self.0
+    }
+
+    pub fn hash(data: &[u8]) -> u64 {
+        let mut hasher = Self::default();
+        hasher.write(data);
+        hasher.finish()
+    }
+}
+
+/// Hashes of each subsystem's state, for finding where two runs diverge
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct StateHashes {
+    pub cpu: u64,
+    pub ram: u64,
+    pub ppu: u64,
+    pub apu: u64,
+}
+
+impl StateHashes {
+    pub const NAMES: [&'static str; 4] = ["CPU registers", "RAM", "PPU", "APU"];
+
+    /// In the order of `NAMES`
+    pub const fn values(&self) -> [u64; 4] {
+        [self.cpu, self.ram, self.ppu, self.apu]
+    }
+
+    pub const fn from_values(values: [u64; 4]) -> Self {
+        let [cpu, ram, ppu, apu] = values;
+        Self { cpu, ram, ppu, apu }
+    }
+
+    /// Names of the subsystems whose hashes differ
+    pub fn differences(&self, other: &Self) -> Vec<&'static str> {
+        Self::NAMES
+            .iter()
+            .zip(self.values().iter().zip(other.values()))
+            .filter(|(_, (a, b))| *a != b)
+            .map(|(name, _)| *name)
+            .collect()
+    }
+}
+
+#[derive(Clone)]
+pub struct DebugSnapshot {
+    pub regs: CpuRegs,
+    pub stack: [u8; 256],
+    pub return_addrs: Vec<ReturnAddr>,
+    pub ppu: PpuState,
+    pub ppu_regs: PpuRegs,
+    /// Last values written to $4000-$4017
+    pub apu_regs: [u8; Apu::REGISTERS],
+    pub frame_stats: FrameStats,
+    /// Recent mapper IRQ edges, oldest first
+    pub irq_edges: Vec<IrqEdge>,
+    /// Recent NMIs and IRQs taken, oldest first
+    pub interrupts: Vec<TakenInterrupt>,
+    /// Start of each 8 kB PRG window at $8000-$FFFF that contains a single repeated byte
+    pub empty_prg_windows: Vec<u16>,
+    pub hashes: StateHashes,
+    pub tile_map: TileMap,
+    /// Internal RAM and $6000-$7FFF, for watching values
+    pub ram: [u8; 0x800],
+    pub prg_ram: Vec<u8>,
+}
+
+impl DebugSnapshot {
+    /// Reads captured RAM the way the CPU sees it, other addresses return `None`
+    pub fn peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x0000..=0x1FFF => Some(self.ram[addr as usize & 0x7FF]),
+            0x6000..=0x7FFF => self.prg_ram.get(addr as usize - 0x6000).copied(),
+            _ => None,
+        }
+    }
+}
+
+/// Common reasons for a blank screen, judged from the state at the end of a frame
+pub fn black_screen_causes(snapshot: &DebugSnapshot) -> Vec<String> {
+    // A frame spent within this many bytes of code is taken as a stuck loop
+    const LOOP_SPAN: u16 = 16;
+    // A vblank wait loop reads $2002 thousands of times per frame
+    const POLL_READS: usize = 1000;
+
+    let ppu = snapshot.ppu;
+    let stats = snapshot.frame_stats;
+    let mut causes = Vec::new();
+
+    if !ppu.show_bg && !ppu.show_sprites {
+        causes.push("Rendering is disabled in $2001 (PPUMASK)".to_owned());
+    }
+    if !ppu.nmi_enabled {
+        causes
+            .push("NMI is disabled in $2000 (PPUCTRL), the game gets no vblank signal".to_owned());
+    }
+    if stats.pc_min <= stats.pc_max && stats.pc_max - stats.pc_min < LOOP_SPAN {
+        let range = format!("${:04X}-${:04X}", stats.pc_min, stats.pc_max);
+        if stats.status_reads >= POLL_READS {
+            causes.push(format!(
+                "CPU spent the whole frame in a loop at {range} polling $2002"
+            ));
+        } else {
+            causes.push(format!("CPU spent the whole frame in a loop at {range}"));
+        }
+    }
+    for window in &snapshot.empty_prg_windows {
+        causes.push(format!(
+            "PRG at ${window:04X}-${:04X} holds a single repeated byte, a bank may point at empty ROM",
+            window + 0x1FFF
+        ));
+    }
+    if causes.is_empty() && ppu.uniform_frame {
+        causes.push(
+            "Frame is a single color but rendering looks normal, check palette and CHR".to_owned(),
+        );
+    }
+    causes
+}
+
+/// Guesses which bytes above the stack pointer are return addresses.
+/// A word is taken as a JSR return if the byte it points just before is a JSR opcode,
+/// and as an interrupt frame if it follows a status byte and points into PRG ROM.
+/// `peek` reads memory without side effects, returning `None` for registers.
+pub fn find_return_addrs(
+    stack: &[u8; 256],
+    sp: u8,
+    mut peek: impl FnMut(u16) -> Option<u8>,
+) -> Vec<ReturnAddr> {
+    const JSR: u8 = 0x20;
+    const UNUSED_FLAG: u8 = 0x20;
+
+    let word = |idx: usize| u16::from_le_bytes([stack[idx], stack[idx + 1]]);
+    let mut found = Vec::new();
+    let mut idx = sp as usize + 1;
+    while idx < 0xFF {
+        let pushed = word(idx);
+        if peek(pushed.wrapping_sub(2)) == Some(JSR) {
+            found.push(ReturnAddr {
+                offset: idx as u8,
+                len: 2,
+                target: pushed.wrapping_add(1),
+                kind: ReturnKind::Subroutine,
+            });
+            idx += 2;
+            continue;
+        }
+        if idx + 2 <= 0xFF && stack[idx] & UNUSED_FLAG != 0 && word(idx + 1) >= 0x8000 {
+            found.push(ReturnAddr {
+                offset: idx as u8,
+                len: 3,
+                target: word(idx + 1),
+                kind: ReturnKind::Interrupt,
+            });
+            idx += 3;
+            continue;
+        }
+        idx += 1;
+    }
+    found
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_finds_jsr_and_interrupt_frames() {
+        let mut stack = [0; 256];
+        // NMI frame: status, then return address $C010
+        stack[0xF9] = 0x24;
+        stack[0xFA] = 0x10;
+        stack[0xFB] = 0xC0;
+        // JSR at $8000 pushes $8002
+        stack[0xFC] = 0x02;
+        stack[0xFD] = 0x80;
+        // Unrelated data
+        stack[0xFE] = 0x01;
+        stack[0xFF] = 0x02;
+
+        let peek = |addr| Some(if addr == 0x8000 { 0x20 } else { 0xEA });
+        let found = find_return_addrs(&stack, 0xF8, peek);
+        assert_eq!(
+            found,
+            vec![
+                ReturnAddr {
+                    offset: 0xF9,
+                    len: 3,
+                    target: 0xC010,
+                    kind: ReturnKind::Interrupt
+                },
+                ReturnAddr {
+                    offset: 0xFC,
+                    len: 2,
+                    target: 0x8003,
+                    kind: ReturnKind::Subroutine
+                },
+            ]
+        );
+    }
+
+    fn snapshot() -> DebugSnapshot {
+        DebugSnapshot {
+            regs: CpuRegs::default(),
+            stack: [0; 256],
+            return_addrs: Vec::new(),
+            ppu: PpuState {
+                nmi_enabled: true,
+                show_bg: true,
+                show_sprites: true,
+                uniform_frame: false,
+            },
+            ppu_regs: PpuRegs::default(),
+            apu_regs: [0; Apu::REGISTERS],
+            frame_stats: FrameStats {
+                status_reads: 0,
+                pc_min: 0x8000,
+                pc_max: 0x9000,
+            },
+            irq_edges: Vec::new(),
+            interrupts: Vec::new(),
+            empty_prg_windows: Vec::new(),
+            hashes: StateHashes::default(),
+            tile_map: TileMap::default(),
+            ram: [0; 0x800],
+            prg_ram: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_irq_history_keeps_last_edges() {
+        let mut history = IrqHistory::default();
+        let edge = |frame| {
+            move |asserted| IrqEdge {
+                asserted,
+                frame,
+                scanline: 0,
+                dot: 0,
+                cpu_cycle: 0,
+            }
+        };
+        history.sample(false, edge(0));
+        assert!(history.edges().is_empty());
+        for frame in 0..100 {
+            history.sample(true, edge(frame));
+            history.sample(true, edge(frame));
+            history.sample(false, edge(frame));
+        }
+        let edges = history.edges();
+        assert_eq!(edges.len(), IrqHistory::LEN);
+        assert_eq!((edges[0].frame, edges[0].asserted), (84, true));
+        assert_eq!((edges[31].frame, edges[31].asserted), (99, false));
+    }
+
+    #[test]
+    fn test_interrupt_log() {
+        let mut log = InterruptLog::default();
+        let cause = InterruptCause::Irq {
+            apu_frame: true,
+            dmc: false,
+            mapper: true,
+        };
+        for frame in 0..300 {
+            log.push(TakenInterrupt {
+                cause,
+                frame,
+                scanline: 0,
+                dot: 0,
+                cpu_cycle: 0,
+            });
+        }
+        let taken = log.taken();
+        assert_eq!(taken.len(), InterruptLog::LEN);
+        assert_eq!(taken[0].frame, 44);
+        assert_eq!(cause.describe(), "IRQ (APU frame, mapper)");
+        assert_eq!(InterruptCause::Nmi.describe(), "NMI (PPU)");
+    }
+
+    #[test]
+    fn test_fnv1a() {
+        assert_eq!(Fnv1a::hash(&[]), 0xCBF2_9CE4_8422_2325);
+        assert_eq!(Fnv1a::hash(b"a"), 0xAF63_DC4C_8601_EC8C);
+    }
+
+    #[test]
+    fn test_state_differences() {
+        let a = StateHashes::from_values([1, 2, 3, 4]);
+        let b = StateHashes { ppu: 0, ..a };
+        assert!(a.differences(&a).is_empty());
+        assert_eq!(a.differences(&b), vec!["PPU"]);
+    }
+
+    #[test]
+    fn test_no_causes_for_healthy_frame() {
+        assert!(black_screen_causes(&snapshot()).is_empty());
+    }
+
+    #[test]
+    fn test_black_screen_causes() {
+        let mut snapshot = snapshot();
+        snapshot.ppu.show_bg = false;
+        snapshot.ppu.show_sprites = false;
+        snapshot.ppu.nmi_enabled = false;
+        snapshot.frame_stats = FrameStats {
+            status_reads: 4000,
+            pc_min: 0xC010,
+            pc_max: 0xC015,
+        };
+        snapshot.empty_prg_windows = vec![0xA000];
+
+        let causes = black_screen_causes(&snapshot);
+        assert_eq!(causes.len(), 4);
+        assert!(causes[2].contains("$C010-$C015 polling $2002"));
+        assert!(causes[3].contains("$A000-$BFFF"));
+    }
+
+    #[test]
+    fn test_empty_stack() {
+        let stack = [0x20; 256];
+        assert!(find_return_addrs(&stack, 0xFF, |_| Some(0x20)).is_empty());
+    }
+
+    #[test]
+    fn test_tile_at() {
+        let mut nametables = vec![0; 0x1000];
+        nametables[0x400 + 32 * 3 + 1] = 0x42;
+        nametables[0x400 + 0x3C0] = 0b1110_0100;
+        let origin = LineOrigin {
+            vaddr: (2 << 12) | (3 << 5) | 0x1E,
+            fine_x: 5,
+            bg_half: 1,
+        };
+        let map = TileMap {
+            lines: vec![Some(origin), None],
+            nametables,
+        };
+        // Past the right edge of nametable 0
+        assert_eq!(
+            map.tile_at(20, 0),
+            Some(TileInfo {
+                tile: 0x42,
+                pattern_addr: 0x1422,
+                palette: 2,
+                nametable: 1,
+                column: 1,
+                row: 3,
+            })
+        );
+        assert_eq!(map.tile_at(0, 1), None);
+        assert_eq!(map.tile_at(0, 240), None);
+    }
+}
@@ -0,0 +1,107 @@
+use super::time::EmulatedTime;
+
+/// Things happening in the console that features outside the emulation core react to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConsoleEvent {
+    /// The PPU entered vblank, before the frame is handed to the frontend
+    VblankStarted,
+    /// The frontend is done with the frame, carries the emulated time since power-on
+    FrameCompleted(EmulatedTime),
+    /// The reset button was pressed
+    Reset,
+    /// Console state was restored from a save state
+    StateLoaded,
+    /// A new cartridge was hot-swapped in without resetting
+    RomSwapped,
+    /// The game tried to write CHR ROM at the given address, which usually means the
+    /// header or the mapper is wrong. Only the first attempt is reported.
+    ChrRomWrite(u16),
+    /// The cartridge's mapper isn't supported and it runs as NROM instead
+    MapperFallback(u8),
+}
+
+/// An instruction about to be executed, see `Console::set_instruction_hook`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ExecutedInstruction {
+    pub pc: u16,
+    pub opcode: u8,
+    operand_bytes: [u8; 2],
+    operand_len: u8,
+    /// CPU cycles since power-on at the opcode fetch. The difference to the next
+    /// instruction includes interrupts and DMA in between.
+    pub cycle: u64,
+}
+
+impl ExecutedInstruction {
+    pub(crate) fn new(pc: u16, opcode: u8, operands: &[u8], cycle: u64) -> Self {
+        let mut operand_bytes = [0; 2];
+        let operand_len = operands.len().min(2);
+        operand_bytes[..operand_len].copy_from_slice(&operands[..operand_len]);
+        Self {
+            pc,
+            opcode,
+            operand_bytes,
+            operand_len: operand_len as u8,
+            cycle,
+        }
+    }
+
+    /// Bytes after the opcode, 0 to 2 depending on the addressing mode
+    pub fn operands(&self) -> &[u8] {
+        &self.operand_bytes[..self.operand_len as usize]
+    }
+}
+
+/// Called for every executed instruction, see `Console::set_instruction_hook`
+pub type InstructionHook<'a> = Box<dyn FnMut(&ExecutedInstruction) + 'a>;
+
+/// Subscriber to console events, see `Console::subscribe`
+pub trait EventListener {
+    fn on_event(&mut self, event: ConsoleEvent);
+}
+
+/// Delivers console events to listeners in the order they subscribed
+#[derive(Default)]
+pub struct EventBus<'a> {
+    listeners: Vec<Box<dyn EventListener + 'a>>,
+}
+
+impl<'a> EventBus<'a> {
+    pub fn subscribe(&mut self, listener: Box<dyn EventListener + 'a>) {
+        self.listeners.push(listener);
+    }
+
+    pub fn publish(&mut self, event: ConsoleEvent) {
+        for listener in &mut self.listeners {
+            listener.on_event(event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct Log<'a>(&'a str, &'a std::cell::RefCell<Vec<String>>);
+
+    impl EventListener for Log<'_> {
+        fn on_event(&mut self, event: ConsoleEvent) {
+            self.1.borrow_mut().push(format!("{} {event:?}", self.0));
+        }
+    }
+
+    #[test]
+    fn test_publish_in_subscription_order() {
+        let log = std::cell::RefCell::new(Vec::new());
+        let mut bus = EventBus::default();
+        bus.subscribe(Box::new(Log("a", &log)));
+        bus.subscribe(Box::new(Log("b", &log)));
+        bus.publish(ConsoleEvent::Reset);
+        bus.publish(ConsoleEvent::RomSwapped);
+        drop(bus);
+        assert_eq!(
+            log.into_inner(),
+            ["a Reset", "b Reset", "a RomSwapped", "b RomSwapped"]
+        );
+    }
+}
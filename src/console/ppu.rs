@@ -1,8 +1,13 @@
 mod regs;
 
+use eyre::Result;
 use regs::{ControllerReg, MaskReg, StatusReg};
 
+use super::cartridge::mappers::MapperEvent;
 use super::cartridge::Cartridge;
+use super::debug::{Fnv1a, LineOrigin, PpuRegs, PpuState, TileMap};
+use super::state::{Snapshot, StateField};
+use crate::macros::state_fields;
 
 use self::regs::ScrollReg;
 
@@ -16,6 +21,66 @@ struct Sprite {
     pattern: u16,
 }
 
+impl StateField for Sprite {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.sprite_idx.save(out);
+        self.x_pos.save(out);
+        self.y_pos.save(out);
+        self.tile_idx.save(out);
+        self.attributes.save(out);
+        self.pattern.save(out);
+    }
+    fn load(&mut self, input: &mut &[u8]) -> Result<()> {
+        self.sprite_idx.load(input)?;
+        self.x_pos.load(input)?;
+        self.y_pos.load(input)?;
+        self.tile_idx.load(input)?;
+        self.attributes.load(input)?;
+        self.pattern.load(input)
+    }
+    fn describe(&self) -> String {
+        format!("#{} at {},{}", self.sprite_idx, self.x_pos, self.y_pos)
+    }
+}
+
+impl<const N: usize> StateField for [Sprite; N] {
+    fn save(&self, out: &mut Vec<u8>) {
+        for sprite in self {
+            sprite.save(out);
+        }
+    }
+    fn load(&mut self, input: &mut &[u8]) -> Result<()> {
+        for sprite in self {
+            sprite.load(input)?;
+        }
+        Ok(())
+    }
+    fn describe(&self) -> String {
+        format!("[{N} sprites]")
+    }
+}
+
+/// Output of the sprite priority multiplexer for one pixel
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct SpritePixel {
+    pixel: u8,
+    attribute: u8,
+    behind_bg: bool,
+    sprite_zero: bool,
+}
+
+/// Which layer a pixel came from, for the priority debug view
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PixelSource {
+    Backdrop,
+    Background,
+    /// Sprite drawn in front of the background
+    SpriteFront,
+    /// Sprite with the behind-background priority, visible through a transparent pixel
+    SpriteBehind,
+}
+
+#[allow(clippy::struct_excessive_bools)]
 pub struct Ppu {
     vram: [u8; 2048],
     palette: [u8; 32],
@@ -36,8 +101,21 @@ pub struct Ppu {
     x: usize,
 
     pub nmi_up: bool,
+    suppress_vblank: bool,
+    /// Dots left until writes to the ctrl, mask, scroll and address registers work
+    warmup_dots: u32,
+    /// Register writes are ignored from a reset until the pre-render line
+    reset_held: bool,
+    scanline_start: Option<(i16, bool)>,
+    /// Background scroll of each visible line, for mapping pixels back to tiles
+    line_origins: [Option<LineOrigin>; 240],
 
-    pub frame: [u8; 256 * 240],
+    pub frame: [u16; 256 * 240],
+    /// Write pixels to `frame`. When off everything else, e.g. sprite zero hits, still
+    /// happens, for frames nobody will see.
+    pub output: bool,
+    /// Source of each pixel in `frame`, only kept while the debug view wants it
+    pixel_sources: Option<Vec<PixelSource>>,
 
     bg_pattern_shift: u32,
     bg_attr_shift: u32,
@@ -51,6 +129,18 @@ pub struct Ppu {
     sprite_data: u8,
     attribute: u8,
     cycle: usize,
+
+    /// PPU address line A12 was high on the last CHR or nametable access
+    a12_high: bool,
+    /// `cycle` at which A12 last went low
+    a12_fell: usize,
+    /// The cartridge counts A12 rises, see `quiet_dots`
+    pub a12_watched: bool,
+    /// Fetching a pattern row can switch CHR banks, so it mustn't come from the cache
+    pub chr_reads_watched: bool,
+
+    // Interleaved pattern rows, indexed by CHR offset of the row with the plane bit removed
+    tile_cache: Vec<Option<u16>>,
 }
 
 const REG_CONTROLLER: u16 = 0x2000;
@@ -64,12 +154,66 @@ const REG_DATA: u16 = 0x2007;
 
 const PPU_BUS_MIRROR_MASK: u16 = 0x2007;
 
+// 29658 CPU cycles after power-on before the PPU accepts most register writes
+const WARMUP_DOTS: u32 = 29658 * 3;
+
+// A12 rises are only passed on after it was low this long, about three CPU cycles.
+// MMC3 boards filter like this so the sprite fetches count once per line.
+const A12_LOW_DOTS: usize = 9;
+
+// Palette RAM contents after power-on, as observed on real hardware
+#[rustfmt::skip]
+const POWER_ON_PALETTE: [u8; 32] = [
+    0x09, 0x01, 0x00, 0x01, 0x00, 0x02, 0x02, 0x0D, 0x08, 0x10, 0x08, 0x24, 0x00, 0x00, 0x04, 0x2C,
+    0x09, 0x01, 0x34, 0x03, 0x00, 0x04, 0x00, 0x14, 0x08, 0x3A, 0x00, 0x02, 0x00, 0x20, 0x2C, 0x08,
+];
+
+// Line origins and pixel sources are left out, they only feed the debugger
+impl Snapshot for Ppu {
+    state_fields!(
+        vram,
+        palette,
+        oam,
+        render_oam,
+        prefetch_oam,
+        ctrl,
+        mask,
+        status,
+        scroll,
+        vaddr,
+        oam_addr,
+        read_buf,
+        scanline,
+        x,
+        nmi_up,
+        suppress_vblank,
+        warmup_dots,
+        reset_held,
+        scanline_start,
+        frame,
+        bg_pattern_shift,
+        bg_attr_shift,
+        read_addr,
+        sp_in_idx,
+        sp_out_idx,
+        sp_render_idx,
+        pattern_addr,
+        pattern,
+        sprite_data,
+        attribute,
+        cycle,
+        a12_high,
+        a12_fell,
+    );
+}
+
 impl Ppu {
     const CYCLES_PER_LINE: usize = 341;
 
     const LAST_LINE: isize = 261;
     const RENDER_LINES: isize = 240;
     const VBLANK_START_LINE: isize = 241;
+    const PREFETCH_START: usize = 320;
 
     pub fn new() -> Self {
         let empty_sprite = Sprite {
@@ -82,7 +226,7 @@ impl Ppu {
         };
         Self {
             vram: [0; 2048],
-            palette: [0; 32],
+            palette: POWER_ON_PALETTE,
             oam: [0; 4 * 64],
             prefetch_oam: [empty_sprite; 8],
             render_oam: [empty_sprite; 8],
@@ -96,7 +240,14 @@ impl Ppu {
             scanline: 0,
             x: 0,
             nmi_up: false,
+            suppress_vblank: false,
+            warmup_dots: 0,
+            reset_held: false,
+            scanline_start: None,
+            line_origins: [None; 240],
             frame: [0; 256 * 240],
+            output: true,
+            pixel_sources: None,
             bg_pattern_shift: 0,
             bg_attr_shift: 0,
             read_addr: 0,
@@ -108,10 +259,65 @@ impl Ppu {
             attribute: 0,
             sprite_data: 0,
             cycle: 0,
+            a12_high: false,
+            a12_fell: 0,
+            a12_watched: false,
+            chr_reads_watched: false,
+            tile_cache: Vec::new(),
         }
     }
 
-    pub fn reset(&mut self) {
+    /// Ignores most register writes for a while after power-on, like real hardware
+    pub fn set_warmup(&mut self, enabled: bool) {
+        self.warmup_dots = if enabled { WARMUP_DOTS } else { 0 };
+    }
+
+    /// Starts or stops recording where each pixel came from
+    pub fn set_pixel_sources(&mut self, enabled: bool) {
+        if enabled != self.pixel_sources.is_some() {
+            self.pixel_sources = enabled.then(|| vec![PixelSource::Backdrop; 256 * 240]);
+        }
+    }
+
+    pub fn pixel_sources(&self) -> Option<&[PixelSource]> {
+        self.pixel_sources.as_deref()
+    }
+
+    /// Level the NMI line takes on the next dot
+    pub const fn nmi_output(&self) -> bool {
+        self.status.vblank && self.ctrl.generate_nmi
+    }
+
+    /// NMI is on and rendering off, so the CPU has nothing to do but wait for the NMI
+    pub const fn waiting_for_nmi(&self) -> bool {
+        self.ctrl.generate_nmi && !(self.mask.show_bg || self.mask.show_sprites)
+    }
+
+    /// Dots the PPU can run without starting a scanline or moving the NMI line, so
+    /// nothing the CPU sees changes unless it accesses a register
+    pub const fn quiet_dots(&self) -> u32 {
+        if self.nmi_up != self.nmi_output() {
+            return 0;
+        }
+        let line = (Self::CYCLES_PER_LINE - 1 - self.x) as u32;
+        // Any pattern fetch may raise A12
+        if self.a12_watched
+            && (self.mask.show_bg || self.mask.show_sprites)
+            && self.scanline < Self::RENDER_LINES
+        {
+            let fetch = (7 - self.x % 8) as u32;
+            if fetch < line {
+                return fetch;
+            }
+        }
+        line
+    }
+
+    /// The reset line clears some registers without stopping the picture. With
+    /// `hold_writes` the same registers as during warm-up ignore writes until the
+    /// pre-render line.
+    pub fn reset(&mut self, hold_writes: bool) {
+        self.reset_held = hold_writes;
         self.ctrl = ControllerReg::default();
         self.mask = MaskReg::default();
         self.scroll.reset_latch();
@@ -120,12 +326,32 @@ impl Ppu {
         self.cycle = 0;
     }
 
+    /// Drops any pattern data fetched from the old CHR memory, e.g. after a cartridge swap
+    pub fn invalidate_chr(&mut self) {
+        self.read_buf = 0;
+        self.pattern = 0;
+        for sprite in &mut self.render_oam {
+            sprite.pattern = 0;
+        }
+        self.tile_cache.clear();
+    }
+
     // Progress by one PPU clock cycle
     pub fn tick(&mut self, cartridge: &mut Cartridge) -> bool {
         self.cycle += 1;
-        self.nmi_up = self.status.vblank && self.ctrl.generate_nmi;
+        self.warmup_dots = self.warmup_dots.saturating_sub(1);
+        self.nmi_up = self.nmi_output();
 
         if self.scanline < Self::RENDER_LINES {
+            // The first tile of the next line is fetched from here on
+            if self.x == Self::PREFETCH_START && self.scanline < Self::RENDER_LINES - 1 {
+                self.line_origins[(self.scanline + 1) as usize] =
+                    self.mask.show_bg.then_some(LineOrigin {
+                        vaddr: self.vaddr.addr(),
+                        fine_x: self.scroll.x_fine(),
+                        bg_half: self.ctrl.bg_half,
+                    });
+            }
             if self.mask.show_bg | self.mask.show_sprites {
                 self.render_tick(cartridge);
             }
@@ -141,24 +367,138 @@ impl Ppu {
             match self.scanline {
                 Self::LAST_LINE => {
                     self.scanline = -1;
+                    self.reset_held = false;
                     self.status.vblank = false;
                     self.status.sprite0_hit = false;
                     self.status.sprite_overflow = false;
                     // println!("Vblank cleared");
-                    self.frame = [0; 256 * 240];
+                    if self.output {
+                        self.frame = [0; 256 * 240];
+                    }
+                    self.start_scanline();
                 }
                 Self::VBLANK_START_LINE => {
-                    self.status.vblank = true;
+                    self.start_scanline();
+                    // Flag stays clear if $2002 was read just before it would be set
+                    self.status.vblank = !self.suppress_vblank;
+                    self.suppress_vblank = false;
                     // println!("frame done after {} cycles", self.cycle);
                     self.cycle = 0;
                     return true;
                 }
-                _ => (),
+                _ => self.start_scanline(),
             }
         }
         false
     }
 
+    /// Runs up to `max` dots at once while rendering is off and the NMI line is
+    /// settled, stopping before the dot that ends the line. Has the same effect as
+    /// ticking them one by one. Returns the dots run, 0 if the next one needs `tick`.
+    pub fn skip_idle_dots(&mut self, max: u32) -> u32 {
+        if self.mask.show_bg | self.mask.show_sprites || self.nmi_up != self.nmi_output() {
+            return 0;
+        }
+        let dots = (max as usize).min(Self::CYCLES_PER_LINE - 1 - self.x);
+        if dots == 0 {
+            return 0;
+        }
+        let end = self.x + dots;
+        if (0..Self::RENDER_LINES - 1).contains(&self.scanline)
+            && (self.x..end).contains(&Self::PREFETCH_START)
+        {
+            self.line_origins[(self.scanline + 1) as usize] = None;
+        }
+        if self.output && (0..Self::RENDER_LINES).contains(&self.scanline) && self.x < 256 {
+            // Without rendering every pixel is the backdrop color
+            let greyscale_mask = if self.mask.greyscale { 0x30 } else { 0x3F };
+            let pixel = (self.palette[self.backdrop_idx()] & greyscale_mask) as u16
+                | (self.mask.emphasis as u16) << 6;
+            let line = self.scanline as usize * 256;
+            let pixels = line + self.x..line + end.min(256);
+            self.frame[pixels.clone()].fill(pixel);
+            if let Some(sources) = self.pixel_sources.as_mut() {
+                sources[pixels].fill(PixelSource::Backdrop);
+            }
+        }
+        self.cycle += dots;
+        self.warmup_dots = self.warmup_dots.saturating_sub(dots as u32);
+        self.x = end;
+        dots as u32
+    }
+
+    pub fn regs(&self) -> PpuRegs {
+        PpuRegs {
+            ctrl: self.ctrl.into(),
+            mask: self.mask.into(),
+            scroll_x: self.scroll.x(),
+            scroll_y: self.scroll.y(),
+        }
+    }
+
+    pub fn set_ctrl(&mut self, data: u8) {
+        self.ctrl = data.into();
+        self.scroll.set_base_nametable(self.ctrl.nametable);
+    }
+
+    /// Takes effect from the next frame, or the next line for X while rendering
+    pub fn set_scroll(&mut self, x: u8, y: u8) {
+        self.scroll.set_x(x);
+        self.scroll.set_y(y);
+    }
+
+    pub fn debug_state(&self) -> PpuState {
+        let first = self.frame[0];
+        PpuState {
+            nmi_enabled: self.ctrl.generate_nmi,
+            show_bg: self.mask.show_bg,
+            show_sprites: self.mask.show_sprites,
+            uniform_frame: self.frame.iter().all(|&p| p == first),
+        }
+    }
+
+    /// Hash of memory, the picture and the registers the CPU can see
+    pub fn state_hash(&self) -> u64 {
+        let mut hasher = Fnv1a::default();
+        hasher.write(&self.vram);
+        hasher.write(&self.palette);
+        hasher.write(&self.oam);
+        hasher.write_u16s(&self.frame);
+        hasher.write(&self.vaddr.addr().to_le_bytes());
+        hasher.write(&self.scroll.addr().to_le_bytes());
+        hasher.write(&[self.oam_addr, self.read_buf]);
+        hasher.write(&(self.scanline as i16).to_le_bytes());
+        hasher.write(&(self.x as u16).to_le_bytes());
+        hasher.finish()
+    }
+
+    /// Nametables as seen through the cartridge and the scroll of each line of the
+    /// frame so far
+    pub fn tile_map(&self, cartridge: &mut Cartridge) -> TileMap {
+        TileMap {
+            lines: self.line_origins.to_vec(),
+            nametables: (0x2000..0x3000)
+                .map(|addr| self.vram[cartridge.mirror_vram_addr(addr)])
+                .collect(),
+        }
+    }
+
+    /// Current scanline and dot
+    pub const fn position(&self) -> (isize, usize) {
+        (self.scanline, self.x)
+    }
+
+    fn start_scanline(&mut self) {
+        let rendering = self.mask.show_bg | self.mask.show_sprites;
+        self.scanline_start = Some((self.scanline as i16, rendering));
+    }
+
+    /// Scanline that started on the last tick and whether rendering is enabled on it.
+    /// Pre-render line is -1.
+    pub fn take_scanline_start(&mut self) -> Option<(i16, bool)> {
+        self.scanline_start.take()
+    }
+
     #[allow(clippy::too_many_lines)]
     fn render_tick(&mut self, cartridge: &mut Cartridge) {
         let tile_fetch = matches!(self.x, 0..=255 | 320..=335);
@@ -202,11 +542,7 @@ impl Ppu {
                 self.attribute = (tile_attribute >> offset_in_byte) & 0x3;
 
                 // Increment X coordinate to prepare for next tile
-                // Go to next nametable if coordinate wraps
-                if self.vaddr.inc_x_coarse() {
-                    self.vaddr
-                        .set_base_nametable_h(1 - self.vaddr.base_nametable_h());
-                }
+                self.increment_x();
             }
             // Prepare sprite for rendering
             (3, false) if self.sp_render_idx < self.sp_out_idx => {
@@ -217,25 +553,15 @@ impl Ppu {
                 if sprite.attributes & 0x80 != 0 {
                     sprite_line = self.ctrl.sprite_size as u16 - 1 - sprite_line;
                 };
-                // Set tile base address based on sprite size & tile index
-                self.pattern_addr = if self.ctrl.sprite_size == 16 {
-                    0x1000 * (sprite.tile_idx as u16 & 0x01)
-                        + 0x10 * ((sprite.tile_idx as u16) & 0xFE)
-                } else {
-                    0x1000 * self.ctrl.sprite_half + 0x10 * (sprite.tile_idx as u16)
-                };
-                // Go to correct line in tile
-                self.pattern_addr += (sprite_line & 0x7) + (sprite_line & 0x8) * 2;
+                self.pattern_addr = self.sprite_pattern_addr(sprite.tile_idx, sprite_line);
+            }
+            // Empty slots, and all of them on the pre-render line, still fetch tile $FF.
+            // Mappers watching the PPU bus see these reads.
+            (3, false) if self.x < 320 => {
+                self.pattern_addr = self.sprite_pattern_addr(0xFF, 0);
             }
-            (5, _) => self.pattern = self.internal_read(self.pattern_addr, cartridge) as u16,
             (7, _) => {
-                // Interleave two bytes of pattern data
-                let p = self.pattern
-                    | ((self.internal_read(self.pattern_addr + 8, cartridge) as u16) << 8);
-                let p = (p & 0xF00F) | ((p & 0x0F00) >> 4) | ((p & 0x00F0) << 4);
-                let p = (p & 0xC3C3) | ((p & 0x3030) >> 2) | ((p & 0x0C0C) << 2);
-                let p = (p & 0x9999) | ((p & 0x4444) >> 1) | ((p & 0x2222) << 1);
-                self.pattern = p;
+                self.pattern = self.fetch_pattern(self.pattern_addr, cartridge);
                 if !tile_fetch && self.sp_render_idx < self.sp_out_idx {
                     self.render_oam[self.sp_render_idx].pattern = self.pattern;
                     self.sp_render_idx += 1;
@@ -248,9 +574,13 @@ impl Ppu {
         if self.x == 0 {
             self.sp_in_idx = 0;
             self.sp_out_idx = 0;
-            if self.mask.show_sprites {
-                self.oam_addr = 0;
-            }
+        }
+        // OAMADDR is held at zero while sprite patterns are fetched
+        if (256..320).contains(&self.x) {
+            self.oam_addr = 0;
+        }
+        if self.x == 256 {
+            self.sp_render_idx = 0;
         }
 
         if self.mask.show_bg {
@@ -263,21 +593,18 @@ impl Ppu {
                 self.vaddr.set_x_coarse(self.scroll.x_coarse());
                 self.vaddr
                     .set_base_nametable_h(self.scroll.base_nametable_h());
-                self.sp_render_idx = 0;
             }
         }
 
         // Increment Y coordinate at the end of scanline
-        // Go to next nametable if coordinate wraps
-        if self.x == 251 && !self.vaddr.inc_y() && self.vaddr.y_coarse() == 30 {
-            self.vaddr.set_y_coarse(0);
-            self.vaddr
-                .set_base_nametable_v(1 - self.vaddr.base_nametable_v());
+        if self.x == 251 {
+            self.increment_y();
         }
 
-        // Evaluate sprites visible on next scanline
+        // Evaluate sprites visible on next scanline, which the pre-render line doesn't do
         // Every other cycle is just read from current OAM address, see else branch
-        let sprite_store_cycle = self.x >= 64 && self.x < 256 && self.x % 2 != 0;
+        let sprite_store_cycle =
+            self.scanline >= 0 && self.x >= 64 && self.x < 256 && self.x % 2 != 0;
         if sprite_store_cycle {
             let oam_addr = self.oam_addr & 0x3;
             self.oam_addr = self.oam_addr.wrapping_add(1);
@@ -333,32 +660,106 @@ impl Ppu {
         }
     }
 
+    // Pattern row of a sprite tile, 8x16 sprites pick the table with bit 0 of the index
+    fn sprite_pattern_addr(&self, tile_idx: u8, line: u16) -> u16 {
+        let tile_addr = if self.ctrl.sprite_size == 16 {
+            0x1000 * (tile_idx as u16 & 0x01) + 0x10 * (tile_idx as u16 & 0xFE)
+        } else {
+            0x1000 * self.ctrl.sprite_half + 0x10 * tile_idx as u16
+        };
+        tile_addr + (line & 0x7) + (line & 0x8) * 2
+    }
+
+    /// Fetches both bit planes of a pattern row, interleaved into 2-bit pixels.
+    /// Decoded rows are cached by CHR offset so bank switches don't need invalidation,
+    /// only writes to CHR memory do.
+    fn fetch_pattern(&mut self, addr: u16, cartridge: &mut Cartridge) -> u16 {
+        let key = cartridge
+            .chr_offset(addr)
+            .filter(|_| !self.chr_reads_watched)
+            .map(Self::tile_cache_key);
+
+        if let Some(key) = key {
+            if self.tile_cache.is_empty() {
+                self.tile_cache = vec![None; cartridge.chr_len() / 2];
+            }
+            if let Some(&Some(pattern)) = self.tile_cache.get(key) {
+                // The fetch still happens on the PPU bus
+                self.watch_a12(addr, cartridge);
+                return pattern;
+            }
+        }
+
+        let lo = self.internal_read(addr, cartridge);
+        let hi = self.internal_read(addr + 8, cartridge);
+        let pattern = Self::interleave(lo, hi);
+
+        if let Some(entry) = key.and_then(|key| self.tile_cache.get_mut(key)) {
+            *entry = Some(pattern);
+        }
+        pattern
+    }
+
+    // Pattern rows are 16 bytes apart per tile, with the high plane 8 bytes after the low one
+    const fn tile_cache_key(chr_offset: usize) -> usize {
+        (chr_offset >> 4) << 3 | chr_offset & 0x7
+    }
+
+    // Interleave two bytes of pattern data
+    const fn interleave(lo: u8, hi: u8) -> u16 {
+        let p = lo as u16 | (hi as u16) << 8;
+        let p = (p & 0xF00F) | ((p & 0x0F00) >> 4) | ((p & 0x00F0) << 4);
+        let p = (p & 0xC3C3) | ((p & 0x3030) >> 2) | ((p & 0x0C0C) << 2);
+        (p & 0x9999) | ((p & 0x4444) >> 1) | ((p & 0x2222) << 1)
+    }
+
     fn draw_pixel(&mut self) {
-        let draw_bg = self.mask.show_bg && (self.mask.show_left_bg || self.x > 8);
-        let draw_sp = self.mask.show_sprites && (self.mask.show_left_sp || self.x > 8);
+        // The left clipping masks hide the first 8 pixels
+        let draw_bg = self.mask.show_bg && (self.mask.show_left_bg || self.x >= 8);
+        let draw_sp = self.mask.show_sprites && (self.mask.show_left_sp || self.x >= 8);
 
         let (mut pixel, mut attribute) = (0, 0);
 
         if draw_bg {
             (pixel, attribute) = self.bg_pixel();
         }
-        // else if (self.vaddr.addr() & 0x3F00) == 0x3F00 && !draw_bg && !draw_sp {
-        //     pixel = self.vaddr.addr() as u8;
-        // }
 
-        if draw_sp {
-            if let Some(sprite_pixel) = self.sprite_pixel(pixel) {
-                if !sprite_pixel.0 || pixel == 0 {
-                    pixel = sprite_pixel.1;
-                    attribute = sprite_pixel.2;
-                }
+        let mut source = if pixel == 0 {
+            PixelSource::Backdrop
+        } else {
+            PixelSource::Background
+        };
+        if let Some(sprite) = self.sprite_pixel().filter(|_| draw_sp) {
+            // Sprite zero hit needs both layers opaque, and never happens on the last pixel
+            if sprite.sprite_zero && pixel != 0 && self.x != 255 {
+                self.status.sprite0_hit = true;
+            }
+            if !sprite.behind_bg || pixel == 0 {
+                pixel = sprite.pixel;
+                attribute = sprite.attribute;
+                source = if sprite.behind_bg {
+                    PixelSource::SpriteBehind
+                } else {
+                    PixelSource::SpriteFront
+                };
             }
         }
 
-        let palette_idx = (attribute * 4 + pixel) as usize;
+        if !self.output {
+            return;
+        }
+        let palette_idx = if pixel == 0 {
+            self.backdrop_idx()
+        } else {
+            (attribute * 4 + pixel) as usize
+        };
         let greyscale_mask = if self.mask.greyscale { 0x30 } else { 0x3F };
         let pixel = self.palette[palette_idx] & greyscale_mask;
-        self.frame[self.scanline as usize * 256 + self.x] = pixel;
+        let idx = self.scanline as usize * 256 + self.x;
+        self.frame[idx] = pixel as u16 | (self.mask.emphasis as u16) << 6;
+        if let Some(sources) = self.pixel_sources.as_mut() {
+            sources[idx] = source;
+        }
     }
 
     fn bg_pixel(&self) -> (u8, u8) {
@@ -376,39 +777,46 @@ impl Ppu {
         (pixel, attribute)
     }
 
-    fn sprite_pixel(&mut self, pixel: u8) -> Option<(bool, u8, u8)> {
-        for sprite in self.render_oam.iter().take(self.sp_render_idx) {
-            // Check current X position against sprite position
-            let mut offset = (self.x as u16).wrapping_sub(sprite.x_pos as u16);
-            if offset >= 8 {
-                continue;
-            }
-            // Vertical flip
-            if sprite.attributes & 0x40 == 0 {
-                offset = 7 - offset;
-            }
-            let sp_pixel = (sprite.pattern >> (offset * 2)) & 0x3;
-            // Skip transparent pixels
-            if sp_pixel == 0 {
-                continue;
-            }
-            if pixel > 0 && sprite.sprite_idx == 0 {
-                // println!("Sprite zero hit");
-                self.status.sprite0_hit = true;
-            }
-            return Some((
-                sprite.attributes & 0x20 != 0,
-                sp_pixel as u8,
-                (sprite.attributes & 3) + 4,
-            ));
-        }
-        None
+    /// The first opaque sprite in OAM order wins, and its priority bit alone decides
+    /// against the background. A sprite behind the background therefore also hides
+    /// later sprites in front of it wherever the background is opaque.
+    fn sprite_pixel(&self) -> Option<SpritePixel> {
+        self.render_oam
+            .iter()
+            .take(self.sp_render_idx)
+            .find_map(|sprite| {
+                // Check current X position against sprite position
+                let mut offset = (self.x as u16).wrapping_sub(sprite.x_pos as u16);
+                if offset >= 8 {
+                    return None;
+                }
+                // Horizontal flip
+                if sprite.attributes & 0x40 == 0 {
+                    offset = 7 - offset;
+                }
+                // Transparent pixels fall through to the next sprite
+                let pixel = ((sprite.pattern >> (offset * 2)) & 0x3) as u8;
+                (pixel != 0).then_some(SpritePixel {
+                    pixel,
+                    attribute: (sprite.attributes & 3) + 4,
+                    behind_bg: sprite.attributes & 0x20 != 0,
+                    sprite_zero: sprite.sprite_idx == 0,
+                })
+            })
     }
 
     pub fn read(&mut self, addr: u16, cartridge: &mut Cartridge) -> u8 {
         let addr = addr & PPU_BUS_MIRROR_MASK;
         match addr {
             REG_STATUS => {
+                // Reading one clock before vblank starts returns the flag clear and
+                // keeps it from being set at all, which also suppresses that frame's NMI.
+                // Reading on the clock it gets set clears it before NMI can be raised.
+                if self.scanline == Self::VBLANK_START_LINE - 1
+                    && self.x == Self::CYCLES_PER_LINE - 1
+                {
+                    self.suppress_vblank = true;
+                }
                 self.scroll.reset_latch();
                 let old_status = self.status.into();
                 self.status.vblank = false;
@@ -422,11 +830,10 @@ impl Ppu {
 
     pub fn write(&mut self, addr: u16, data: u8, cartridge: &mut Cartridge) {
         let addr = addr & PPU_BUS_MIRROR_MASK;
+        let warming_up = self.warmup_dots > 0 || self.reset_held;
         match addr {
-            REG_CONTROLLER => {
-                self.ctrl = data.into();
-                self.scroll.set_base_nametable(self.ctrl.nametable);
-            }
+            REG_CONTROLLER | REG_MASK | REG_SCROLL | REG_ADDR if warming_up => (),
+            REG_CONTROLLER => self.set_ctrl(data),
             REG_MASK => self.mask = data.into(),
             REG_OAM_ADDR => self.oam_addr = data,
             REG_OAM_DATA => self.oam_write(data),
@@ -443,11 +850,47 @@ impl Ppu {
         }
     }
 
+    // Go to next nametable if coordinate wraps
+    fn increment_x(&mut self) {
+        if self.vaddr.inc_x_coarse() {
+            self.vaddr
+                .set_base_nametable_h(1 - self.vaddr.base_nametable_h());
+        }
+    }
+
+    // Only rows 0-29 are on screen, wrapping from 29 goes to the next nametable
+    // while wrapping from 31 (attribute data) stays on the same one
+    fn increment_y(&mut self) {
+        if !self.vaddr.inc_y() && self.vaddr.y_coarse() == 30 {
+            self.vaddr.set_y_coarse(0);
+            self.vaddr
+                .set_base_nametable_v(1 - self.vaddr.base_nametable_v());
+        }
+    }
+
+    // Visible and pre-render lines with rendering enabled
+    fn rendering(&self) -> bool {
+        (self.mask.show_bg || self.mask.show_sprites) && self.scanline < Self::RENDER_LINES
+    }
+
+    /// Advances the VRAM address after a $2007 access. While rendering, the access
+    /// collides with the fetch logic, which bumps both coarse X and Y instead.
+    fn increment_data_addr(&mut self) {
+        if self.rendering() {
+            self.increment_x();
+            self.increment_y();
+        } else {
+            self.vaddr.increment(self.ctrl.increment);
+        }
+    }
+
     fn data_read(&mut self, cartridge: &mut Cartridge) -> u8 {
-        let addr = self.vaddr.addr();
-        self.vaddr.increment(self.ctrl.increment);
+        // Fine Y is bits 12-14 of v, but the PPU address bus is only 14 bits wide
+        let addr = self.vaddr.addr() & 0x3FFF;
+        self.increment_data_addr();
 
         let old_buf = self.read_buf;
+        self.watch_a12(addr, cartridge);
         match addr {
             0..=0x1FFF => {
                 self.read_buf = cartridge.read_ppu(addr);
@@ -465,8 +908,21 @@ impl Ppu {
         }
     }
 
+    /// Tells the cartridge when the PPU address line A12 rises, after filtering
+    /// like an MMC3 does
+    fn watch_a12(&mut self, addr: u16, cartridge: &mut Cartridge) {
+        let high = addr & 0x1000 != 0;
+        if high && !self.a12_high && self.cycle.wrapping_sub(self.a12_fell) >= A12_LOW_DOTS {
+            cartridge.trigger_event(MapperEvent::A12Rise);
+        } else if !high && self.a12_high {
+            self.a12_fell = self.cycle;
+        }
+        self.a12_high = high;
+    }
+
     fn internal_read(&mut self, addr: u16, cartridge: &mut Cartridge) -> u8 {
         let addr = addr & 0x3FFF;
+        self.watch_a12(addr, cartridge);
         match addr {
             0..=0x1FFF => cartridge.read_ppu(addr),
             0x3F00.. => panic!("Internal read to palette"),
@@ -475,11 +931,18 @@ impl Ppu {
     }
 
     fn data_write(&mut self, data: u8, cartridge: &mut Cartridge) {
-        let addr = self.vaddr.addr();
-        self.vaddr.increment(self.ctrl.increment);
+        let addr = self.vaddr.addr() & 0x3FFF;
+        self.increment_data_addr();
+        self.watch_a12(addr, cartridge);
 
         match addr {
-            0..=0x1FFF => cartridge.write_ppu(addr, data),
+            0..=0x1FFF => {
+                let key = cartridge.chr_offset(addr).map(Self::tile_cache_key);
+                if let Some(entry) = key.and_then(|key| self.tile_cache.get_mut(key)) {
+                    *entry = None;
+                }
+                cartridge.write_ppu(addr, data);
+            }
             0x2000..=0x3EFF => self.vram[cartridge.mirror_vram_addr(addr)] = data,
             0x3F00..=0x3FFF => self.palette[Self::palette_idx(addr)] = data,
             _ => panic!("Data write to unsupported PPU address at 0x{:x}", addr),
@@ -495,15 +958,461 @@ impl Ppu {
     }
 
     fn oam_write(&mut self, data: u8) {
+        if self.rendering() {
+            // Sprite evaluation owns OAM, the write is dropped and only the
+            // sprite number part of the address is bumped
+            self.oam_addr = self.oam_addr.wrapping_add(4);
+            return;
+        }
         self.oam[self.oam_addr as usize] = data;
         self.oam_addr = self.oam_addr.wrapping_add(1);
     }
 
+    // Entries 0, 4, 8 and C of the sprite palettes are shared with the background ones
     const fn palette_idx(addr: u16) -> usize {
-        if addr >= 0x3f10 && addr % 4 == 0 {
-            0
+        let idx = (addr & 0x1F) as usize;
+        if idx >= 0x10 && idx % 4 == 0 {
+            idx - 0x10
         } else {
-            (addr & 0x001f) as usize
+            idx
+        }
+    }
+
+    /// Palette entry drawn where no layer is opaque. With rendering off the PPU shows
+    /// the entry the VRAM address points at instead, if it points into the palette,
+    /// which some programs use to show more colors than the backdrop.
+    fn backdrop_idx(&self) -> usize {
+        let addr = self.vaddr.addr() & 0x3FFF;
+        if !(self.mask.show_bg || self.mask.show_sprites) && addr >= 0x3F00 {
+            Self::palette_idx(addr)
+        } else {
+            0
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod test {
+    use super::*;
+    use crate::console::cartridge::ines_image;
+
+    fn run_until(ppu: &mut Ppu, cart: &mut Cartridge, scanline: isize, x: usize) {
+        while ppu.scanline != scanline || ppu.x != x {
+            ppu.tick(cart);
+        }
+    }
+
+    #[test]
+    fn test_warmup_ignores_writes() {
+        let mut cart = Cartridge::blank(0).unwrap();
+        let mut ppu = Ppu::new();
+        ppu.set_warmup(true);
+        ppu.write(REG_CONTROLLER, 0x80, &mut cart);
+        ppu.write(REG_OAM_ADDR, 0x10, &mut cart);
+        assert!(!ppu.ctrl.generate_nmi);
+        assert_eq!(ppu.oam_addr, 0x10);
+
+        for _ in 0..WARMUP_DOTS {
+            ppu.tick(&mut cart);
+        }
+        ppu.write(REG_CONTROLLER, 0x80, &mut cart);
+        assert!(ppu.ctrl.generate_nmi);
+    }
+
+    #[test]
+    fn test_tile_cache_invalidated_on_chr_write() {
+        let mut cart = Cartridge::new(&ines_image(0, &[0; 0x4000], &[])).unwrap();
+        let mut ppu = Ppu::new();
+        let write_chr = |ppu: &mut Ppu, cart: &mut Cartridge, addr: u16, data: u8| {
+            ppu.write(REG_ADDR, (addr >> 8) as u8, cart);
+            ppu.write(REG_ADDR, addr as u8, cart);
+            ppu.write(REG_DATA, data, cart);
+        };
+
+        write_chr(&mut ppu, &mut cart, 0x0012, 0xF0);
+        write_chr(&mut ppu, &mut cart, 0x001A, 0x0F);
+        assert_eq!(
+            ppu.fetch_pattern(0x0012, &mut cart),
+            Ppu::interleave(0xF0, 0x0F)
+        );
+
+        write_chr(&mut ppu, &mut cart, 0x001A, 0xFF);
+        assert_eq!(
+            ppu.fetch_pattern(0x0012, &mut cart),
+            Ppu::interleave(0xF0, 0xFF)
+        );
+    }
+
+    #[test]
+    fn test_a12_rises_once_per_line() {
+        let mut cart = Cartridge::blank(4).unwrap();
+        let mut ppu = Ppu::new();
+        run_until(&mut ppu, &mut cart, -1, 0);
+        // IRQ after 10 counted lines, the pre-render line loading the counter
+        cart.write_cpu(0xC000, 10);
+        cart.write_cpu(0xC001, 0);
+        cart.write_cpu(0xE001, 0);
+        ppu.set_ctrl(0x08);
+        ppu.mask = 0x18.into();
+        while !cart.irq_active() {
+            ppu.tick(&mut cart);
+        }
+        // On the first sprite fetch of line 9
+        assert_eq!(ppu.position(), (9, 264));
+    }
+
+    #[test]
+    fn test_scanline_start_reported_once_per_line() {
+        let mut cart = Cartridge::blank(0).unwrap();
+        let mut ppu = Ppu::new();
+        run_until(&mut ppu, &mut cart, 10, 0);
+        assert_eq!(ppu.take_scanline_start(), Some((10, false)));
+        assert_eq!(ppu.take_scanline_start(), None);
+
+        ppu.write(REG_MASK, 0x08, &mut cart);
+        run_until(&mut ppu, &mut cart, -1, 0);
+        assert_eq!(ppu.take_scanline_start(), Some((-1, true)));
+        ppu.tick(&mut cart);
+        assert_eq!(ppu.take_scanline_start(), None);
+    }
+
+    #[test]
+    fn test_line_origins() {
+        let mut cart = Cartridge::blank(0).unwrap();
+        let mut ppu = Ppu::new();
+        ppu.write(REG_CONTROLLER, 0x10, &mut cart);
+        ppu.write(REG_SCROLL, 0x1D, &mut cart);
+        ppu.write(REG_SCROLL, 0x10, &mut cart);
+        ppu.write(REG_MASK, 0x08, &mut cart);
+        run_until(&mut ppu, &mut cart, -1, 0);
+        run_until(&mut ppu, &mut cart, 1, 0);
+
+        let lines = ppu.tile_map(&mut cart).lines;
+        let origin = |vaddr| {
+            Some(LineOrigin {
+                vaddr,
+                fine_x: 5,
+                bg_half: 1,
+            })
+        };
+        assert_eq!(lines[0], origin(0x0043));
+        assert_eq!(lines[1], origin(0x1043));
+    }
+
+    #[test]
+    fn test_data_access_increment_while_rendering() {
+        let mut cart = Cartridge::blank(0).unwrap();
+        let mut ppu = Ppu::new();
+        ppu.write(REG_CONTROLLER, 0x04, &mut cart);
+        ppu.write(REG_DATA, 0, &mut cart);
+        assert_eq!(ppu.vaddr.addr(), 32);
+
+        ppu.write(REG_MASK, 0x08, &mut cart);
+        run_until(&mut ppu, &mut cart, 10, 100);
+        ppu.vaddr.set_x_coarse(31);
+        ppu.vaddr.set_y_coarse(29);
+        ppu.vaddr.set_y_fine(7);
+        ppu.data_read(&mut cart);
+        assert_eq!(ppu.vaddr.x_coarse(), 0);
+        assert_eq!(ppu.vaddr.y_coarse(), 0);
+        assert_eq!(ppu.vaddr.base_nametable(), 3);
+    }
+
+    // PPU with both layers shown everywhere and palette entry N holding N
+    fn sprite_ppu(sprites: &[Sprite]) -> Ppu {
+        let mut cart = Cartridge::blank(0).unwrap();
+        let mut ppu = Ppu::new();
+        ppu.write(REG_MASK, 0x1E, &mut cart);
+        for (idx, entry) in ppu.palette.iter_mut().enumerate() {
+            *entry = idx as u8;
+        }
+        ppu.render_oam[..sprites.len()].copy_from_slice(sprites);
+        ppu.sp_render_idx = sprites.len();
+        ppu
+    }
+
+    // Sprite at x = 16 with all eight pixels set to `pixel`
+    const fn sprite(sprite_idx: u8, attributes: u8, pixel: u8) -> Sprite {
+        Sprite {
+            sprite_idx,
+            x_pos: 16,
+            y_pos: 0,
+            tile_idx: 0,
+            attributes,
+            pattern: 0x5555 * pixel as u16,
+        }
+    }
+
+    fn draw_at(ppu: &mut Ppu, x: usize, bg_opaque: bool) -> u16 {
+        ppu.bg_pattern_shift = if bg_opaque { u32::MAX } else { 0 };
+        ppu.scanline = 0;
+        ppu.x = x;
+        ppu.draw_pixel();
+        ppu.frame[x]
+    }
+
+    #[test]
+    fn test_sprite_behind_bg_hides_later_sprites() {
+        let mut ppu = sprite_ppu(&[sprite(0, 0x20, 1), sprite(1, 0x01, 2)]);
+        assert_eq!(draw_at(&mut ppu, 20, true), 3);
+        assert!(ppu.status.sprite0_hit);
+        // With a transparent background the first sprite shows, not the front one
+        assert_eq!(draw_at(&mut ppu, 20, false), 16 + 1);
+    }
+
+    #[test]
+    fn test_transparent_sprite_pixels_fall_through() {
+        let mut ppu = sprite_ppu(&[sprite(0, 0x20, 0), sprite(1, 0x01, 2)]);
+        assert_eq!(draw_at(&mut ppu, 20, true), 16 + 4 + 2);
+        assert!(!ppu.status.sprite0_hit);
+    }
+
+    #[test]
+    fn test_sprite_zero_hit_edges() {
+        let mut ppu = sprite_ppu(&[Sprite {
+            x_pos: 250,
+            ..sprite(0, 0, 3)
+        }]);
+        draw_at(&mut ppu, 255, true);
+        assert!(!ppu.status.sprite0_hit);
+
+        // Left clipping covers pixels 0-7 only
+        let mut ppu = sprite_ppu(&[Sprite {
+            x_pos: 4,
+            ..sprite(0, 0, 3)
+        }]);
+        ppu.write(REG_MASK, 0x18, &mut Cartridge::blank(0).unwrap());
+        assert_eq!(draw_at(&mut ppu, 7, true), 0);
+        assert!(!ppu.status.sprite0_hit);
+        assert_eq!(draw_at(&mut ppu, 8, true), 16 + 3);
+        assert!(ppu.status.sprite0_hit);
+    }
+
+    #[test]
+    fn test_oam_write_while_rendering_bumps_address() {
+        let mut cart = Cartridge::blank(0).unwrap();
+        let mut ppu = Ppu::new();
+        ppu.write(REG_OAM_ADDR, 1, &mut cart);
+        ppu.write(REG_OAM_DATA, 0xAB, &mut cart);
+        assert_eq!((ppu.oam[1], ppu.oam_addr), (0xAB, 2));
+
+        ppu.write(REG_MASK, 0x10, &mut cart);
+        run_until(&mut ppu, &mut cart, 10, 300);
+        ppu.write(REG_OAM_ADDR, 1, &mut cart);
+        ppu.write(REG_OAM_DATA, 0xCD, &mut cart);
+        assert_eq!((ppu.oam[1], ppu.oam_addr), (0xAB, 5));
+    }
+
+    #[test]
+    fn test_oam_addr_reset_during_sprite_fetches() {
+        let mut cart = Cartridge::blank(0).unwrap();
+        let mut ppu = Ppu::new();
+        ppu.write(REG_MASK, 0x08, &mut cart);
+        run_until(&mut ppu, &mut cart, 250, 0);
+        ppu.write(REG_OAM_ADDR, 0x20, &mut cart);
+        run_until(&mut ppu, &mut cart, -1, 256);
+        assert_eq!(ppu.oam_addr, 0x20);
+        ppu.tick(&mut cart);
+        assert_eq!(ppu.oam_addr, 0);
+    }
+
+    #[test]
+    fn test_sprites_without_background() {
+        let mut cart = Cartridge::new(&ines_image(0, &[0; 0x4000], &[])).unwrap();
+        let mut ppu = Ppu::new();
+        // Tile 1 fully opaque, sprite 0 at y = 10 and sprite 1 at y = 20
+        ppu.write(REG_ADDR, 0x00, &mut cart);
+        ppu.write(REG_ADDR, 0x10, &mut cart);
+        for _ in 0..8 {
+            ppu.write(REG_DATA, 0xFF, &mut cart);
+        }
+        for data in [10, 1, 0, 0, 20, 1, 0, 0] {
+            ppu.write(REG_OAM_DATA, data, &mut cart);
+        }
+        ppu.write(REG_MASK, 0x14, &mut cart);
+
+        run_until(&mut ppu, &mut cart, -1, 0);
+        run_until(&mut ppu, &mut cart, 239, 0);
+        let sprite_pixel = |ppu: &Ppu, line: usize| ppu.frame[256 * line] != ppu.frame[0];
+        // Sprites show from the line after their Y position
+        assert!(!sprite_pixel(&ppu, 10));
+        assert!((11..19).all(|line| sprite_pixel(&ppu, line)));
+        assert!(!sprite_pixel(&ppu, 19));
+        assert!((21..29).all(|line| sprite_pixel(&ppu, line)));
+    }
+
+    #[test]
+    fn test_interleave() {
+        assert_eq!(Ppu::interleave(0x80, 0x00), 0x4000);
+        assert_eq!(Ppu::interleave(0x00, 0x80), 0x8000);
+        assert_eq!(Ppu::interleave(0x01, 0x01), 0x0003);
+    }
+
+    #[test]
+    fn test_power_on_palette() {
+        let ppu = Ppu::new();
+        assert_eq!(ppu.palette, POWER_ON_PALETTE);
+    }
+
+    #[test]
+    fn test_vblank_set_without_read() {
+        let mut cart = Cartridge::blank(0).unwrap();
+        let mut ppu = Ppu::new();
+        ppu.write(REG_CONTROLLER, 0x80, &mut cart);
+        run_until(&mut ppu, &mut cart, 241, 2);
+        assert!(ppu.nmi_up);
+        assert_eq!(ppu.read(REG_STATUS, &mut cart) & 0x80, 0x80);
+    }
+
+    #[test]
+    fn test_status_read_before_vblank_suppresses_flag_and_nmi() {
+        let mut cart = Cartridge::blank(0).unwrap();
+        let mut ppu = Ppu::new();
+        ppu.write(REG_CONTROLLER, 0x80, &mut cart);
+        run_until(&mut ppu, &mut cart, 240, 340);
+        assert_eq!(ppu.read(REG_STATUS, &mut cart) & 0x80, 0);
+        for _ in 0..10 {
+            ppu.tick(&mut cart);
+            assert!(!ppu.nmi_up);
         }
+        assert_eq!(ppu.read(REG_STATUS, &mut cart) & 0x80, 0);
+    }
+
+    #[test]
+    fn test_status_read_on_vblank_clock_suppresses_nmi() {
+        let mut cart = Cartridge::blank(0).unwrap();
+        let mut ppu = Ppu::new();
+        ppu.write(REG_CONTROLLER, 0x80, &mut cart);
+        run_until(&mut ppu, &mut cart, 241, 0);
+        assert_eq!(ppu.read(REG_STATUS, &mut cart) & 0x80, 0x80);
+        for _ in 0..10 {
+            ppu.tick(&mut cart);
+            assert!(!ppu.nmi_up);
+        }
+    }
+
+    #[test]
+    fn test_palette_mirrors() {
+        let mut cart = Cartridge::blank(0).unwrap();
+        let mut ppu = Ppu::new();
+        for (addr, data) in [(0x3F14, 0x14), (0x3F30, 0x30), (0x3F1D, 0x1D)] {
+            ppu.write(REG_ADDR, (addr >> 8) as u8, &mut cart);
+            ppu.write(REG_ADDR, addr as u8, &mut cart);
+            ppu.write(REG_DATA, data, &mut cart);
+        }
+        assert_eq!(ppu.palette[0x04], 0x14);
+        assert_eq!(ppu.palette[0x00], 0x30);
+        assert_eq!(ppu.palette[0x1D], 0x1D);
+        assert_eq!(ppu.palette[0x10], POWER_ON_PALETTE[0x10]);
+    }
+
+    #[test]
+    fn test_forced_blank_shows_palette_at_vram_address() {
+        let mut cart = Cartridge::blank(0).unwrap();
+        let mut ppu = Ppu::new();
+        ppu.palette[0x00] = 0x0F;
+        ppu.palette[0x05] = 0x16;
+        ppu.palette[0x08] = 0x2A;
+        // Point at $3F05 and then at $3F18, a mirror of $3F08
+        for (line, addr) in [(10, 0x3F05_u16), (20, 0x3F18), (30, 0x2000)] {
+            run_until(&mut ppu, &mut cart, line, 0);
+            ppu.write(REG_ADDR, (addr >> 8) as u8, &mut cart);
+            ppu.write(REG_ADDR, addr as u8, &mut cart);
+        }
+        run_until(&mut ppu, &mut cart, 40, 0);
+        assert_eq!(ppu.frame[256 * 5], 0x0F);
+        assert_eq!(ppu.frame[256 * 15 + 100], 0x16);
+        assert_eq!(ppu.frame[256 * 25 + 255], 0x2A);
+        assert_eq!(ppu.frame[256 * 35], 0x0F);
+
+        // Lines run in bulk get the same colors
+        let mut skipped = Ppu::new();
+        skipped.palette = ppu.palette;
+        skipped.write(REG_ADDR, 0x3F, &mut cart);
+        skipped.write(REG_ADDR, 0x05, &mut cart);
+        while skipped.scanline < 2 {
+            if skipped.skip_idle_dots(1000) == 0 {
+                skipped.tick(&mut cart);
+            }
+        }
+        assert_eq!(skipped.frame[256 + 17], 0x16);
+
+        // Rendering shows the backdrop whatever the address
+        ppu.write(REG_MASK, 0x08, &mut cart);
+        ppu.write(REG_ADDR, 0x3F, &mut cart);
+        ppu.write(REG_ADDR, 0x05, &mut cart);
+        assert_eq!(ppu.backdrop_idx(), 0);
+    }
+
+    #[test]
+    fn test_skip_idle_dots_matches_ticks() {
+        let mut cart = Cartridge::blank(0).unwrap();
+        let mut ticked = Ppu::new();
+        let mut skipped = Ppu::new();
+        for ppu in [&mut ticked, &mut skipped] {
+            ppu.write(REG_CONTROLLER, 0x80, &mut cart);
+            ppu.palette[0] = 0x21;
+        }
+        // Dots where the NMI line rises
+        let mut nmi_dots = (vec![], vec![]);
+        for dot in 0..100_000 {
+            let was_up = ticked.nmi_up;
+            ticked.tick(&mut cart);
+            if ticked.nmi_up && !was_up {
+                nmi_dots.0.push(dot);
+            }
+        }
+        let mut dot = 0;
+        while dot < 100_000 {
+            match skipped.skip_idle_dots(100_000 - dot) {
+                0 => {
+                    let was_up = skipped.nmi_up;
+                    skipped.tick(&mut cart);
+                    if skipped.nmi_up && !was_up {
+                        nmi_dots.1.push(dot);
+                    }
+                    dot += 1;
+                }
+                n => dot += n,
+            }
+        }
+        assert_eq!(ticked.state_hash(), skipped.state_hash());
+        assert_eq!(skipped.frame[256 * 10], 0x21);
+        assert_eq!(nmi_dots.0.len(), 1);
+        assert_eq!(nmi_dots.0, nmi_dots.1);
+    }
+
+    #[test]
+    fn test_pixel_sources() {
+        // Tile 0 is solid color 1, every sprite sits at the top left corner
+        let mut chr = vec![0; 0x2000];
+        chr[..8].fill(0xFF);
+        let mut cart = Cartridge::new(&ines_image(0, &[0; 0x4000], &chr)).unwrap();
+        let mut ppu = Ppu::new();
+        ppu.set_pixel_sources(true);
+        let source = |ppu: &Ppu, x: usize, y: usize| ppu.pixel_sources().unwrap()[y * 256 + x];
+
+        run_until(&mut ppu, &mut cart, 241, 0);
+        assert_eq!(source(&ppu, 100, 100), PixelSource::Backdrop);
+
+        ppu.write(REG_MASK, 0x1E, &mut cart);
+        ppu.tick(&mut cart);
+        run_until(&mut ppu, &mut cart, 241, 0);
+        assert_eq!(source(&ppu, 100, 100), PixelSource::Background);
+        assert_eq!(source(&ppu, 3, 4), PixelSource::SpriteFront);
+
+        // Behind the background the sprite only shows where the background is clear
+        ppu.write(REG_MASK, 0x14, &mut cart);
+        for sprite in ppu.oam.chunks_exact_mut(4) {
+            sprite[2] = 0x20;
+        }
+        ppu.tick(&mut cart);
+        run_until(&mut ppu, &mut cart, 241, 0);
+        assert_eq!(source(&ppu, 3, 4), PixelSource::SpriteBehind);
+        assert_eq!(source(&ppu, 100, 100), PixelSource::Backdrop);
+
+        ppu.set_pixel_sources(false);
+        assert!(ppu.pixel_sources().is_none());
     }
 }
@@ -2,11 +2,13 @@
 #![allow(clippy::use_self)]
 
 use bitbash::bitfield;
+use eyre::Result;
 
+use crate::console::state::StateField;
 use crate::macros::bit_bool;
 use crate::macros::bool_u8;
 
-#[derive(Default)]
+#[derive(Clone, Copy)]
 pub struct ControllerReg {
     pub nametable: u16,
     pub increment: u16,
@@ -17,6 +19,13 @@ pub struct ControllerReg {
     pub generate_nmi: bool,
 }
 
+// Power-on value is all bits clear, which means 8x8 sprites
+impl Default for ControllerReg {
+    fn default() -> Self {
+        0.into()
+    }
+}
+
 impl From<u8> for ControllerReg {
     fn from(data: u8) -> Self {
         Self {
@@ -31,17 +40,28 @@ impl From<u8> for ControllerReg {
     }
 }
 
+impl From<ControllerReg> for u8 {
+    fn from(v: ControllerReg) -> Self {
+        v.nametable as u8
+            | bool_u8!(v.increment == 32, 2)
+            | (v.sprite_half as u8) << 3
+            | (v.bg_half as u8) << 4
+            | bool_u8!(v.sprite_size == 16, 5)
+            | bool_u8!(v.ppu_master, 6)
+            | bool_u8!(v.generate_nmi, 7)
+    }
+}
+
 #[allow(clippy::struct_excessive_bools)]
-#[derive(Default)]
+#[derive(Default, Clone, Copy)]
 pub struct MaskReg {
     pub greyscale: bool,
     pub show_left_bg: bool,
     pub show_left_sp: bool,
     pub show_bg: bool,
     pub show_sprites: bool,
-    pub emphasize_red: bool,
-    pub emphasize_green: bool,
-    pub emphasize_blue: bool,
+    /// Red, green and blue emphasis in bits 0-2
+    pub emphasis: u8,
 }
 
 impl From<u8> for MaskReg {
@@ -52,13 +72,22 @@ impl From<u8> for MaskReg {
             show_left_sp: bit_bool!(data, 2),
             show_bg: bit_bool!(data, 3),
             show_sprites: bit_bool!(data, 4),
-            emphasize_red: bit_bool!(data, 5),
-            emphasize_green: bit_bool!(data, 6),
-            emphasize_blue: bit_bool!(data, 7),
+            emphasis: data >> 5,
         }
     }
 }
 
+impl From<MaskReg> for u8 {
+    fn from(v: MaskReg) -> Self {
+        bool_u8!(v.greyscale, 0)
+            | bool_u8!(v.show_left_bg, 1)
+            | bool_u8!(v.show_left_sp, 2)
+            | bool_u8!(v.show_bg, 3)
+            | bool_u8!(v.show_sprites, 4)
+            | v.emphasis << 5
+    }
+}
+
 #[derive(Default, Clone, Copy)]
 pub struct StatusReg {
     pub sprite_overflow: bool, // = [5];
@@ -72,6 +101,38 @@ impl From<StatusReg> for u8 {
     }
 }
 
+impl From<u8> for StatusReg {
+    fn from(data: u8) -> Self {
+        Self {
+            sprite_overflow: bit_bool!(data, 5),
+            sprite0_hit: bit_bool!(data, 6),
+            vblank: bit_bool!(data, 7),
+        }
+    }
+}
+
+// The register structs are kept as the byte written to or read from the register
+macro_rules! reg_state_field {
+    ($($reg:ty),*) => {$(
+        impl StateField for $reg {
+            fn save(&self, out: &mut Vec<u8>) {
+                u8::from(*self).save(out);
+            }
+            fn load(&mut self, input: &mut &[u8]) -> Result<()> {
+                let mut data = 0u8;
+                data.load(input)?;
+                *self = data.into();
+                Ok(())
+            }
+            fn describe(&self) -> String {
+                u8::from(*self).describe()
+            }
+        }
+    )*};
+}
+
+reg_state_field!(ControllerReg, MaskReg, StatusReg);
+
 bitfield! {
     #[derive(Copy, Clone)]
     pub struct ScrollReg {
@@ -138,3 +199,31 @@ impl ScrollReg {
         self.offset = !self.offset;
     }
 }
+
+impl StateField for ScrollReg {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.data.save(out);
+        self.offset.save(out);
+    }
+    fn load(&mut self, input: &mut &[u8]) -> Result<()> {
+        self.data.load(input)?;
+        self.offset.load(input)
+    }
+    fn describe(&self) -> String {
+        format!("{:#06X}", self.addr())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ctrl_and_mask_round_trip() {
+        for data in 0..=255u8 {
+            assert_eq!(u8::from(ControllerReg::from(data)), data);
+            assert_eq!(u8::from(MaskReg::from(data)), data);
+            assert_eq!(u8::from(StatusReg::from(data)), data & 0xE0);
+        }
+    }
+}
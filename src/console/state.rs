@@ -0,0 +1,350 @@
+// Layout of save state files. A file is a list of sections, one per subsystem,
+// each versioned on its own so subsystems can change independently.
+//
+// Compatibility policy:
+// - Changing what a section holds bumps its version and adds a migration from the
+//   previous version. Older files are migrated one version at a time on load, so
+//   any state written by a released build stays loadable.
+// - Sections newer than the running build are refused, guessing at their layout
+//   would make a game desync silently.
+// - Unknown sections are skipped. New subsystems add a section and must cope with
+//   it missing from older files.
+// - The file version only changes if the header or section framing does.
+
+mod field;
+
+use eyre::{eyre, Result};
+
+pub(crate) use field::take;
+pub use field::StateField;
+
+/// Turns a section's data from one version into the next
+pub type Migration = fn(Vec<u8>) -> Result<Vec<u8>>;
+
+/// Current layout of one section and how to get there from older versions
+pub struct SectionFormat {
+    pub tag: [u8; 4],
+    pub version: u16,
+    /// `migrations[n]` turns version `n + 1` into version `n + 2`
+    pub migrations: &'static [Migration],
+}
+
+/// Registers and memory of the mapper, see `Mapper::save_state`
+pub const MAPPER: SectionFormat = SectionFormat {
+    tag: *b"MAPR",
+    version: 1,
+    migrations: &[],
+};
+
+/// CPU registers and pending interrupts
+pub const CPU: SectionFormat = SectionFormat {
+    tag: *b"CPU ",
+    version: 1,
+    migrations: &[],
+};
+
+/// RAM, the controller port and the time since power-on
+pub const BUS: SectionFormat = SectionFormat {
+    tag: *b"BUS ",
+    version: 1,
+    migrations: &[],
+};
+
+/// VRAM, OAM, palette, registers, the rendering pipeline and the picture so far
+pub const PPU: SectionFormat = SectionFormat {
+    tag: *b"PPU ",
+    version: 2,
+    migrations: &[
+        // A12 filter, low since power-on
+        |mut data| {
+            false.save(&mut data);
+            0usize.save(&mut data);
+            Ok(data)
+        },
+    ],
+};
+
+/// Channels, frame counter and IRQs. Samples not yet handed to the frontend are lost.
+pub const APU: SectionFormat = SectionFormat {
+    tag: *b"APU ",
+    version: 1,
+    migrations: &[],
+};
+
+/// Part of the console kept in a save state section, listing its fields with
+/// `state_fields!`
+pub trait Snapshot {
+    fn state_fields(&mut self) -> Vec<(&'static str, &mut dyn StateField)>;
+
+    fn save_state(&mut self) -> Vec<u8> {
+        let mut out = Vec::new();
+        for (_, field) in self.state_fields() {
+            field.save(&mut out);
+        }
+        out
+    }
+
+    fn load_state(&mut self, mut data: &[u8]) -> Result<()> {
+        for (name, field) in self.state_fields() {
+            field
+                .load(&mut data)
+                .map_err(|e| eyre!("Failed to load field {}: {}", name, e))?;
+        }
+        if !data.is_empty() {
+            return Err(eyre!("{} bytes of unused state", data.len()));
+        }
+        Ok(())
+    }
+}
+
+/// What the frontend wants done with save states, see `Frontend::take_state_request`
+pub enum StateRequest {
+    Save,
+    /// Contents of a file written from `Frontend::state_saved`
+    Load(Vec<u8>),
+}
+
+struct Section {
+    tag: [u8; 4],
+    version: u16,
+    data: Vec<u8>,
+}
+
+/// Sections of a save state, in the order they were added
+#[derive(Default)]
+pub struct SaveState {
+    sections: Vec<Section>,
+}
+
+impl SaveState {
+    const MAGIC: [u8; 8] = *b"RNESSAVE";
+    pub const VERSION: u16 = 1;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stores data in the current layout of the section, replacing earlier data
+    pub fn put(&mut self, format: &SectionFormat, data: Vec<u8>) {
+        self.sections.retain(|section| section.tag != format.tag);
+        self.sections.push(Section {
+            tag: format.tag,
+            version: format.version,
+            data,
+        });
+    }
+
+    /// Data of the section migrated to its current layout, None if the file has none
+    pub fn get(&self, format: &SectionFormat) -> Result<Option<Vec<u8>>> {
+        let Some(section) = self.sections.iter().find(|s| s.tag == format.tag) else {
+            return Ok(None);
+        };
+        let name = String::from_utf8_lossy(&format.tag);
+        if section.version == 0 || section.version > format.version {
+            return Err(eyre!(
+                "Save state has version {} of the {} section, this build reads up to version {}",
+                section.version,
+                name,
+                format.version
+            ));
+        }
+        let pending = (section.version - 1) as usize..(format.version - 1) as usize;
+        let Some(migrations) = format.migrations.get(pending) else {
+            return Err(eyre!(
+                "No migration of the {} section from version {}",
+                name,
+                section.version
+            ));
+        };
+        let mut data = section.data.clone();
+        for (version, migrate) in (section.version..).zip(migrations) {
+            data = migrate(data).map_err(|e| {
+                eyre!("Failed to migrate the {name} section from version {version}: {e}")
+            })?;
+        }
+        Ok(Some(data))
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Self::MAGIC.to_vec();
+        Self::VERSION.save(&mut out);
+        for section in &self.sections {
+            out.extend_from_slice(&section.tag);
+            section.version.save(&mut out);
+            (section.data.len() as u32).save(&mut out);
+            out.extend_from_slice(&section.data);
+        }
+        out
+    }
+
+    /// Data of a section every save state has
+    pub fn require(&self, format: &SectionFormat) -> Result<Vec<u8>> {
+        self.get(format)?.ok_or_else(|| {
+            eyre!(
+                "Save state has no {} section",
+                String::from_utf8_lossy(&format.tag).trim_end()
+            )
+        })
+    }
+
+    pub fn decode(mut input: &[u8]) -> Result<Self> {
+        let Some(rest) = input.strip_prefix(&Self::MAGIC) else {
+            return Err(eyre!("Not an rnes save state"));
+        };
+        input = rest;
+        let mut version = 0u16;
+        version.load(&mut input)?;
+        if version != Self::VERSION {
+            return Err(eyre!("Unsupported save state file version {}", version));
+        }
+
+        let mut state = Self::new();
+        while !input.is_empty() {
+            let mut tag = [0; 4];
+            let mut version = 0u16;
+            let mut len = 0u32;
+            tag.load(&mut input)?;
+            version.load(&mut input)?;
+            len.load(&mut input)?;
+            if input.len() < len as usize {
+                return Err(eyre!(
+                    "Save state ends inside the {} section",
+                    String::from_utf8_lossy(&tag)
+                ));
+            }
+            let (data, rest) = input.split_at(len as usize);
+            input = rest;
+            state.sections.push(Section {
+                tag,
+                version,
+                data: data.to_vec(),
+            });
+        }
+        Ok(state)
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod test {
+    use super::super::cartridge::mappers::{get_mapper, Mapper, Mirroring};
+    use super::*;
+
+    // Version 1 held a single byte, version 2 added a second one and version 3
+    // widened the first to 16 bits
+    const TEST: SectionFormat = SectionFormat {
+        tag: *b"TEST",
+        version: 3,
+        migrations: &[
+            |mut data| {
+                data.push(0xFF);
+                Ok(data)
+            },
+            |data| match data[..] {
+                [a, b] => Ok(vec![a, 0, b]),
+                _ => Err(eyre!("expected 2 bytes")),
+            },
+        ],
+    };
+
+    fn mmc1() -> Box<dyn Mapper> {
+        get_mapper(
+            1,
+            vec![0; 0x8000],
+            vec![],
+            0x2000,
+            0x2000,
+            Mirroring::Vertical,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_mapper_round_trip() {
+        let mut mapper = mmc1();
+        for bit in 0..5 {
+            mapper.write_cpu(0x8000, (0b01011 >> bit) & 1);
+        }
+        mapper.write_cpu(0x6123, 0x42);
+        let mut state = SaveState::new();
+        state.put(&MAPPER, mapper.save_state());
+        let file = state.encode();
+
+        let mut restored = mmc1();
+        let data = SaveState::decode(&file).unwrap().get(&MAPPER).unwrap();
+        restored.load_state(&data.unwrap()).unwrap();
+        assert_eq!(restored.read_cpu(0x6123), 0x42);
+
+        // Saving the restored console gives back the same file
+        let mut state = SaveState::new();
+        state.put(&MAPPER, restored.save_state());
+        assert_eq!(state.encode(), file);
+    }
+
+    // Files written by earlier releases, these must keep loading
+    #[test]
+    fn test_version_1_file() {
+        #[rustfmt::skip]
+        let file = [
+            b'R', b'N', b'E', b'S', b'S', b'A', b'V', b'E', 1, 0,
+            b'T', b'E', b'S', b'T', 1, 0, 1, 0, 0, 0, 0x12,
+            b'M', b'A', b'P', b'R', 1, 0, 2, 0, 0, 0, 0xAB, 0xCD,
+        ];
+        let state = SaveState::decode(&file).unwrap();
+        assert_eq!(state.get(&TEST).unwrap(), Some(vec![0x12, 0, 0xFF]));
+        assert_eq!(state.get(&MAPPER).unwrap(), Some(vec![0xAB, 0xCD]));
+    }
+
+    #[test]
+    fn test_migrations() {
+        let old = |version, data: &[u8]| SaveState {
+            sections: vec![Section {
+                tag: TEST.tag,
+                version,
+                data: data.to_vec(),
+            }],
+        };
+        assert_eq!(old(2, &[1, 2]).get(&TEST).unwrap(), Some(vec![1, 0, 2]));
+        assert_eq!(old(3, &[1, 0, 2]).get(&TEST).unwrap(), Some(vec![1, 0, 2]));
+        assert_eq!(
+            old(2, &[1]).get(&TEST).err().unwrap().to_string(),
+            "Failed to migrate the TEST section from version 2: expected 2 bytes"
+        );
+        assert_eq!(
+            old(4, &[]).get(&TEST).err().unwrap().to_string(),
+            "Save state has version 4 of the TEST section, this build reads up to version 3"
+        );
+    }
+
+    #[test]
+    fn test_unknown_and_missing_sections() {
+        let mut state = SaveState::new();
+        state.put(
+            &SectionFormat {
+                tag: *b"NEW!",
+                version: 7,
+                migrations: &[],
+            },
+            vec![1, 2, 3],
+        );
+        let state = SaveState::decode(&state.encode()).unwrap();
+        assert_eq!(state.get(&MAPPER).unwrap(), None);
+    }
+
+    #[test]
+    fn test_malformed_files() {
+        let mut state = SaveState::new();
+        state.put(&MAPPER, vec![0; 16]);
+        let file = state.encode();
+        for len in 0..file.len() {
+            assert!(SaveState::decode(&file[..len]).is_err() || len == 10);
+        }
+        let mut newer = file;
+        newer[8] = 2;
+        assert_eq!(
+            SaveState::decode(&newer).err().unwrap().to_string(),
+            "Unsupported save state file version 2"
+        );
+        assert!(SaveState::decode(b"NES\x1A").is_err());
+    }
+}
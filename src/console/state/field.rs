@@ -0,0 +1,298 @@
+// Encoding of the values making up console state, integers are little endian
+
+use eyre::{eyre, Result};
+
+/// A piece of console state that can be saved, restored and shown in an inspector
+pub trait StateField {
+    fn save(&self, out: &mut Vec<u8>);
+    fn load(&mut self, input: &mut &[u8]) -> Result<()>;
+    fn describe(&self) -> String;
+}
+
+pub fn take<'a>(input: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    if input.len() < len {
+        return Err(eyre!("State ended unexpectedly"));
+    }
+    let (data, rest) = input.split_at(len);
+    *input = rest;
+    Ok(data)
+}
+
+impl StateField for u8 {
+    fn save(&self, out: &mut Vec<u8>) {
+        out.push(*self);
+    }
+    fn load(&mut self, input: &mut &[u8]) -> Result<()> {
+        *self = take(input, 1)?[0];
+        Ok(())
+    }
+    fn describe(&self) -> String {
+        format!("{self:#04X}")
+    }
+}
+
+impl StateField for u16 {
+    fn save(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+    fn load(&mut self, input: &mut &[u8]) -> Result<()> {
+        let data = take(input, 2)?;
+        *self = u16::from_le_bytes([data[0], data[1]]);
+        Ok(())
+    }
+    fn describe(&self) -> String {
+        format!("{self:#06X}")
+    }
+}
+
+impl StateField for usize {
+    fn save(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&(*self as u64).to_le_bytes());
+    }
+    fn load(&mut self, input: &mut &[u8]) -> Result<()> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(take(input, 8)?);
+        *self = u64::from_le_bytes(bytes) as Self;
+        Ok(())
+    }
+    fn describe(&self) -> String {
+        format!("{self}")
+    }
+}
+
+impl StateField for bool {
+    fn save(&self, out: &mut Vec<u8>) {
+        out.push(*self as u8);
+    }
+    fn load(&mut self, input: &mut &[u8]) -> Result<()> {
+        *self = take(input, 1)?[0] != 0;
+        Ok(())
+    }
+    fn describe(&self) -> String {
+        format!("{self}")
+    }
+}
+
+// Memory blocks keep their size, only the contents are restored
+impl StateField for Vec<u8> {
+    fn save(&self, out: &mut Vec<u8>) {
+        (self.len() as u32).save(out);
+        out.extend_from_slice(self);
+    }
+    fn load(&mut self, input: &mut &[u8]) -> Result<()> {
+        let mut len = 0u32;
+        len.load(input)?;
+        if len as usize != self.len() {
+            return Err(eyre!(
+                "Memory size mismatch, expected {} got {}",
+                self.len(),
+                len
+            ));
+        }
+        self.copy_from_slice(take(input, len as usize)?);
+        Ok(())
+    }
+    fn describe(&self) -> String {
+        format!("[{} bytes]", self.len())
+    }
+}
+
+impl StateField for Vec<Vec<u8>> {
+    fn save(&self, out: &mut Vec<u8>) {
+        (self.len() as u32).save(out);
+        for bank in self {
+            bank.save(out);
+        }
+    }
+    fn load(&mut self, input: &mut &[u8]) -> Result<()> {
+        let mut len = 0u32;
+        len.load(input)?;
+        if len as usize != self.len() {
+            return Err(eyre!(
+                "Bank count mismatch, expected {} got {}",
+                self.len(),
+                len
+            ));
+        }
+        for bank in self.iter_mut() {
+            bank.load(input)?;
+        }
+        Ok(())
+    }
+    fn describe(&self) -> String {
+        format!("[{} banks]", self.len())
+    }
+}
+
+impl<const N: usize> StateField for [u8; N] {
+    fn save(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(self);
+    }
+    fn load(&mut self, input: &mut &[u8]) -> Result<()> {
+        self.copy_from_slice(take(input, N)?);
+        Ok(())
+    }
+    fn describe(&self) -> String {
+        format!("{self:02X?}")
+    }
+}
+
+impl StateField for u32 {
+    fn save(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+    fn load(&mut self, input: &mut &[u8]) -> Result<()> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(take(input, 4)?);
+        *self = u32::from_le_bytes(bytes);
+        Ok(())
+    }
+    fn describe(&self) -> String {
+        format!("{self:#010X}")
+    }
+}
+
+impl StateField for f32 {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.to_bits().save(out);
+    }
+    fn load(&mut self, input: &mut &[u8]) -> Result<()> {
+        let mut bits = 0u32;
+        bits.load(input)?;
+        *self = Self::from_bits(bits);
+        Ok(())
+    }
+    fn describe(&self) -> String {
+        format!("{self}")
+    }
+}
+
+impl StateField for u64 {
+    fn save(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+    fn load(&mut self, input: &mut &[u8]) -> Result<()> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(take(input, 8)?);
+        *self = Self::from_le_bytes(bytes);
+        Ok(())
+    }
+    fn describe(&self) -> String {
+        format!("{self}")
+    }
+}
+
+impl StateField for i8 {
+    fn save(&self, out: &mut Vec<u8>) {
+        (*self as u8).save(out);
+    }
+    fn load(&mut self, input: &mut &[u8]) -> Result<()> {
+        *self = take(input, 1)?[0] as Self;
+        Ok(())
+    }
+    fn describe(&self) -> String {
+        format!("{self}")
+    }
+}
+
+impl StateField for i16 {
+    fn save(&self, out: &mut Vec<u8>) {
+        (*self as u16).save(out);
+    }
+    fn load(&mut self, input: &mut &[u8]) -> Result<()> {
+        let mut value = 0u16;
+        value.load(input)?;
+        *self = value as Self;
+        Ok(())
+    }
+    fn describe(&self) -> String {
+        format!("{self}")
+    }
+}
+
+impl StateField for isize {
+    fn save(&self, out: &mut Vec<u8>) {
+        (*self as u64).save(out);
+    }
+    fn load(&mut self, input: &mut &[u8]) -> Result<()> {
+        let mut value = 0u64;
+        value.load(input)?;
+        *self = value as Self;
+        Ok(())
+    }
+    fn describe(&self) -> String {
+        format!("{self}")
+    }
+}
+
+impl<const N: usize> StateField for [u16; N] {
+    fn save(&self, out: &mut Vec<u8>) {
+        for value in self {
+            value.save(out);
+        }
+    }
+    fn load(&mut self, input: &mut &[u8]) -> Result<()> {
+        for (value, bytes) in self.iter_mut().zip(take(input, 2 * N)?.chunks_exact(2)) {
+            *value = u16::from_le_bytes([bytes[0], bytes[1]]);
+        }
+        Ok(())
+    }
+    fn describe(&self) -> String {
+        format!("[{N} words]")
+    }
+}
+
+impl<const N: usize> StateField for [bool; N] {
+    fn save(&self, out: &mut Vec<u8>) {
+        for value in self {
+            value.save(out);
+        }
+    }
+    fn load(&mut self, input: &mut &[u8]) -> Result<()> {
+        for value in self {
+            value.load(input)?;
+        }
+        Ok(())
+    }
+    fn describe(&self) -> String {
+        format!("{self:?}")
+    }
+}
+
+impl<T: StateField + Default> StateField for Option<T> {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.is_some().save(out);
+        if let Some(value) = self {
+            value.save(out);
+        }
+    }
+    fn load(&mut self, input: &mut &[u8]) -> Result<()> {
+        let mut some = false;
+        some.load(input)?;
+        *self = if some {
+            let mut value = T::default();
+            value.load(input)?;
+            Some(value)
+        } else {
+            None
+        };
+        Ok(())
+    }
+    fn describe(&self) -> String {
+        self.as_ref().map_or_else(|| "none".to_owned(), T::describe)
+    }
+}
+
+impl<A: StateField, B: StateField> StateField for (A, B) {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.0.save(out);
+        self.1.save(out);
+    }
+    fn load(&mut self, input: &mut &[u8]) -> Result<()> {
+        self.0.load(input)?;
+        self.1.load(input)
+    }
+    fn describe(&self) -> String {
+        format!("({}, {})", self.0.describe(), self.1.describe())
+    }
+}
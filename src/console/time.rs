@@ -0,0 +1,63 @@
+use std::fmt;
+
+use eyre::Result;
+
+use super::state::StateField;
+
+/// Time as seen by the emulated console since power-on, independent of how fast
+/// the host actually ran it. Not reset by a console reset or cartridge swap.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct EmulatedTime {
+    pub frames: u64,
+    pub cpu_cycles: u64,
+}
+
+impl EmulatedTime {
+    pub fn seconds(self) -> f64 {
+        self.cpu_cycles as f64 / crate::CPU_FREQ as f64
+    }
+}
+
+impl StateField for EmulatedTime {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.frames.save(out);
+        self.cpu_cycles.save(out);
+    }
+    fn load(&mut self, input: &mut &[u8]) -> Result<()> {
+        self.frames.load(input)?;
+        self.cpu_cycles.load(input)
+    }
+    fn describe(&self) -> String {
+        self.to_string()
+    }
+}
+
+/// Formats as `h:mm:ss.cc`
+impl fmt::Display for EmulatedTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let centis = (self.seconds() * 100.0) as u64;
+        let secs = centis / 100;
+        write!(
+            f,
+            "{}:{:02}:{:02}.{:02}",
+            secs / 3600,
+            secs / 60 % 60,
+            secs % 60,
+            centis % 100
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let time = EmulatedTime {
+            frames: 0,
+            cpu_cycles: crate::CPU_FREQ as u64 * 3725 + crate::CPU_FREQ as u64 / 2,
+        };
+        assert_eq!(time.to_string(), "1:02:05.50");
+    }
+}
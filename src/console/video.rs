@@ -0,0 +1,80 @@
+pub mod diff;
+pub mod palette;
+
+use palette::{Palette, PALETTE_LEN};
+
+use super::ppu::PixelSource;
+
+use super::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+/// A completed frame, both as PPU palette indices and as RGBA pixels
+pub struct Frame<'a> {
+    /// One palette index per pixel, the color in bits 0-5 and emphasis in bits 6-8
+    pub indices: &'a [u16],
+    /// Four bytes per pixel, alpha always 255
+    pub rgba: &'a [u8],
+    /// Layer of each pixel, if the frontend asked for them
+    pub sources: Option<&'a [PixelSource]>,
+}
+
+/// Converts PPU output to RGBA once per frame so frontends don't each do it
+pub struct Video {
+    palette: Palette,
+    rgba: Vec<u8>,
+}
+
+impl Video {
+    pub fn new() -> Self {
+        Self {
+            palette: Palette::default(),
+            rgba: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT * 4],
+        }
+    }
+
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    pub fn convert<'a>(&'a mut self, indices: &'a [u16]) -> Frame<'a> {
+        for (pixel, rgba) in indices.iter().zip(self.rgba.chunks_exact_mut(4)) {
+            let (r, g, b) = self.palette.palette[*pixel as usize % PALETTE_LEN];
+            rgba.copy_from_slice(&[r, g, b, 255]);
+        }
+        Frame {
+            indices,
+            rgba: &self.rgba,
+            sources: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_convert_keeps_indices_and_maps_rgb() {
+        let mut video = Video::new();
+        let mut indices = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT];
+        indices[1] = 0x21;
+
+        let frame = video.convert(&indices);
+        assert_eq!(frame.indices[1], 0x21);
+        assert_eq!(
+            &frame.rgba[..8],
+            &[0x80, 0x80, 0x80, 255, 0x0F, 0xD7, 0xFF, 255]
+        );
+    }
+
+    #[test]
+    fn test_convert_applies_emphasis() {
+        let mut video = Video::new();
+        let mut indices = vec![0x30; SCREEN_WIDTH * SCREEN_HEIGHT];
+        indices[0] |= 7 << 6;
+
+        let (r, g, b) = video.palette.palette[7 << 6 | 0x30];
+        let frame = video.convert(&indices);
+        assert_eq!(&frame.rgba[..4], &[r, g, b, 255]);
+        assert_ne!(frame.rgba[..4], frame.rgba[4..8]);
+    }
+}
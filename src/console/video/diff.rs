@@ -0,0 +1,152 @@
+use std::fmt;
+
+use crate::console::SCREEN_WIDTH;
+
+// Differing pixels are drawn in this color over a dimmed picture
+pub const DIFF_COLOR: [u8; 4] = [0xFF, 0x00, 0xFF, 0xFF];
+
+/// Smallest rectangle holding every differing pixel
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct DiffRect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl fmt::Display for DiffRect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}x{} at ({}, {})",
+            self.width, self.height, self.x, self.y
+        )
+    }
+}
+
+/// Which pixels of two frames differ
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct FrameDiff {
+    /// One entry per pixel, true where the frames differ
+    pub mask: Vec<bool>,
+    pub count: usize,
+    pub bounds: Option<DiffRect>,
+}
+
+impl FrameDiff {
+    fn from_mask(mask: Vec<bool>) -> Self {
+        let mut count = 0;
+        let (mut min_x, mut min_y, mut max_x, mut max_y) = (usize::MAX, usize::MAX, 0, 0);
+        for (idx, _) in mask.iter().enumerate().filter(|(_, &differs)| differs) {
+            let (x, y) = (idx % SCREEN_WIDTH, idx / SCREEN_WIDTH);
+            count += 1;
+            min_x = min_x.min(x);
+            min_y = min_y.min(y);
+            max_x = max_x.max(x);
+            max_y = max_y.max(y);
+        }
+        let bounds = (count > 0).then(|| DiffRect {
+            x: min_x,
+            y: min_y,
+            width: max_x - min_x + 1,
+            height: max_y - min_y + 1,
+        });
+        Self {
+            mask,
+            count,
+            bounds,
+        }
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Dims matching pixels of an RGBA frame and paints differing ones in `DIFF_COLOR`
+    pub fn highlight(&self, rgba: &mut [u8]) {
+        for (&differs, pixel) in self.mask.iter().zip(rgba.chunks_exact_mut(4)) {
+            if differs {
+                pixel.copy_from_slice(&DIFF_COLOR);
+            } else {
+                for c in &mut pixel[..3] {
+                    *c /= 2;
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for FrameDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} pixels differ", self.count)?;
+        if let Some(bounds) = self.bounds {
+            write!(f, " in {bounds}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Compares frames of PPU palette indices
+pub fn compare_indices(a: &[u16], b: &[u16]) -> FrameDiff {
+    FrameDiff::from_mask(a.iter().zip(b).map(|(a, b)| a != b).collect())
+}
+
+/// Compares RGBA frames, ignoring alpha and channel differences up to `tolerance`,
+/// so output of different palettes or lossy captures can be checked
+pub fn compare_rgb(a: &[u8], b: &[u8], tolerance: u8) -> FrameDiff {
+    FrameDiff::from_mask(
+        a.chunks_exact(4)
+            .zip(b.chunks_exact(4))
+            .map(|(a, b)| {
+                a[..3]
+                    .iter()
+                    .zip(&b[..3])
+                    .any(|(a, b)| a.abs_diff(*b) > tolerance)
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_compare_indices_bounds() {
+        let a = vec![0; SCREEN_WIDTH * 4];
+        let mut b = a.clone();
+        b[SCREEN_WIDTH + 10] = 1;
+        b[3 * SCREEN_WIDTH + 5] = 1;
+
+        let diff = compare_indices(&a, &b);
+        assert_eq!(diff.count, 2);
+        assert_eq!(
+            diff.bounds,
+            Some(DiffRect {
+                x: 5,
+                y: 1,
+                width: 6,
+                height: 3
+            })
+        );
+        assert_eq!(diff.to_string(), "2 pixels differ in 6x3 at (5, 1)");
+        assert!(compare_indices(&a, &a).is_empty());
+    }
+
+    #[test]
+    fn test_compare_rgb_tolerance() {
+        let a = [10, 20, 30, 255, 10, 20, 30, 255];
+        let b = [12, 20, 30, 0, 10, 20, 40, 255];
+        assert_eq!(compare_rgb(&a, &b, 2).mask, vec![false, true]);
+        assert_eq!(compare_rgb(&a, &b, 10).count, 0);
+    }
+
+    #[test]
+    fn test_highlight() {
+        let diff = compare_indices(&[1, 2, 3], &[1, 5, 3]);
+        let mut rgba = vec![100; 12];
+        diff.highlight(&mut rgba);
+        assert_eq!(&rgba[..4], &[50, 50, 50, 100]);
+        assert_eq!(&rgba[4..8], &DIFF_COLOR);
+    }
+}
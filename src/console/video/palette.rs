@@ -0,0 +1,191 @@
+use eyre::{eyre, Result, WrapErr};
+
+#[rustfmt::skip]
+pub static DEFAULT_PALETTE: [(u8,u8,u8); 64] = [
+   (0x80, 0x80, 0x80), (0x00, 0x3D, 0xA6), (0x00, 0x12, 0xB0), (0x44, 0x00, 0x96), (0xA1, 0x00, 0x5E),
+   (0xC7, 0x00, 0x28), (0xBA, 0x06, 0x00), (0x8C, 0x17, 0x00), (0x5C, 0x2F, 0x00), (0x10, 0x45, 0x00),
+   (0x05, 0x4A, 0x00), (0x00, 0x47, 0x2E), (0x00, 0x41, 0x66), (0x00, 0x00, 0x00), (0x05, 0x05, 0x05),
+   (0x05, 0x05, 0x05), (0xC7, 0xC7, 0xC7), (0x00, 0x77, 0xFF), (0x21, 0x55, 0xFF), (0x82, 0x37, 0xFA),
+   (0xEB, 0x2F, 0xB5), (0xFF, 0x29, 0x50), (0xFF, 0x22, 0x00), (0xD6, 0x32, 0x00), (0xC4, 0x62, 0x00),
+   (0x35, 0x80, 0x00), (0x05, 0x8F, 0x00), (0x00, 0x8A, 0x55), (0x00, 0x99, 0xCC), (0x21, 0x21, 0x21),
+   (0x09, 0x09, 0x09), (0x09, 0x09, 0x09), (0xFF, 0xFF, 0xFF), (0x0F, 0xD7, 0xFF), (0x69, 0xA2, 0xFF),
+   (0xD4, 0x80, 0xFF), (0xFF, 0x45, 0xF3), (0xFF, 0x61, 0x8B), (0xFF, 0x88, 0x33), (0xFF, 0x9C, 0x12),
+   (0xFA, 0xBC, 0x20), (0x9F, 0xE3, 0x0E), (0x2B, 0xF0, 0x35), (0x0C, 0xF0, 0xA4), (0x05, 0xFB, 0xFF),
+   (0x5E, 0x5E, 0x5E), (0x0D, 0x0D, 0x0D), (0x0D, 0x0D, 0x0D), (0xFF, 0xFF, 0xFF), (0xA6, 0xFC, 0xFF),
+   (0xB3, 0xEC, 0xFF), (0xDA, 0xAB, 0xEB), (0xFF, 0xA8, 0xF9), (0xFF, 0xAB, 0xB3), (0xFF, 0xD2, 0xB0),
+   (0xFF, 0xEF, 0xA6), (0xFF, 0xF7, 0x9C), (0xD7, 0xE8, 0x95), (0xA6, 0xED, 0xAF), (0xA2, 0xF2, 0xDA),
+   (0x99, 0xFF, 0xFC), (0xDD, 0xDD, 0xDD), (0x11, 0x11, 0x11), (0x11, 0x11, 0x11)
+];
+
+/// 64 colors under each of the 8 combinations of the PPUMASK emphasis bits
+pub const PALETTE_LEN: usize = 64 * 8;
+
+/// RGB colors indexed by the PPU's 9-bit output, emphasis bits above the color
+#[derive(Clone)]
+pub struct Palette {
+    pub palette: [(u8, u8, u8); PALETTE_LEN],
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self::from_base(&DEFAULT_PALETTE)
+    }
+}
+
+impl Palette {
+    /// Loads a .pal file of 64 RGB triplets, or 512 with the emphasis variations
+    pub fn new(file: &str) -> Result<Self> {
+        let palette: Vec<u8> =
+            std::fs::read(file).wrap_err_with(|| format!("Failed to open palette file {file}"))?;
+        let colors: Vec<(u8, u8, u8)> = palette
+            .chunks_exact(3)
+            .map(|rgb| (rgb[0], rgb[1], rgb[2]))
+            .collect();
+
+        if colors.len() >= PALETTE_LEN {
+            let mut inst = Self {
+                palette: [(0, 0, 0); PALETTE_LEN],
+            };
+            inst.palette.copy_from_slice(&colors[..PALETTE_LEN]);
+            Ok(inst)
+        } else if colors.len() >= 64 {
+            let mut base = [(0, 0, 0); 64];
+            base.copy_from_slice(&colors[..64]);
+            Ok(Self::from_base(&base))
+        } else {
+            Err(eyre!("Palette file {file} is too short"))
+        }
+    }
+
+    /// Generates every color from a model of the PPU's composite video signal
+    pub fn ntsc() -> Self {
+        let mut inst = Self {
+            palette: [(0, 0, 0); PALETTE_LEN],
+        };
+        for (idx, color) in inst.palette.iter_mut().enumerate() {
+            let (r, g, b) = ntsc::decode(idx);
+            *color = (ntsc::to_u8(r), ntsc::to_u8(g), ntsc::to_u8(b));
+        }
+        inst
+    }
+
+    /// Extends a 64 color palette with emphasis, dimming each channel as much as
+    /// emphasis dims it on average in the signal model
+    fn from_base(base: &[(u8, u8, u8); 64]) -> Self {
+        let mut inst = Self {
+            palette: [(0, 0, 0); PALETTE_LEN],
+        };
+        for emphasis in 0..8 {
+            let factors = ntsc::emphasis_factors(emphasis);
+            for (color, &(r, g, b)) in base.iter().enumerate() {
+                let scale = |c: u8, factor: f32| (c as f32 * factor).round() as u8;
+                inst.palette[emphasis << 6 | color] = (
+                    scale(r, factors[0]),
+                    scale(g, factors[1]),
+                    scale(b, factors[2]),
+                );
+            }
+        }
+        inst
+    }
+}
+
+mod ntsc {
+    // Composite signal voltages of the four luma levels, low and high half of the
+    // color cycle, relative to sync, and the black and white levels
+    const LOW: [f32; 4] = [0.350, 0.518, 0.962, 1.550];
+    const HIGH: [f32; 4] = [1.094, 1.506, 1.962, 1.962];
+    const BLACK: f32 = 0.518;
+    const WHITE: f32 = 1.962;
+    // Emphasis attenuates the signal while the emphasized color's phase is active
+    const ATTENUATION: f32 = 0.746;
+    // Color phases of red, green and blue, the emphasis bits in PPUMASK order
+    const EMPHASIS_PHASES: [usize; 3] = [0, 4, 8];
+    // Phase of the TV's color decoder relative to the PPU, puts hue 6 at red
+    const DECODER_PHASE: usize = 4;
+
+    /// RGB levels of a 9-bit PPU output value, by sampling one color cycle of the
+    /// square wave the PPU generates in 12 phases and decoding it as YIQ
+    pub fn decode(idx: usize) -> (f32, f32, f32) {
+        let hue = idx & 0xF;
+        let emphasis = idx >> 6;
+        // Columns $E and $F are black
+        let level = if hue < 0xE { (idx >> 4) & 3 } else { 1 };
+        let low = if hue == 0 { HIGH[level] } else { LOW[level] };
+        let high = if hue < 0xD { HIGH[level] } else { LOW[level] };
+
+        let in_phase = |color: usize, phase: usize| (color + phase) % 12 < 6;
+        let (mut y, mut i, mut q) = (0.0, 0.0, 0.0);
+        for phase in 0..12 {
+            let mut signal = if in_phase(hue, phase) { high } else { low };
+            let attenuated = EMPHASIS_PHASES
+                .iter()
+                .enumerate()
+                .any(|(bit, &color)| emphasis & (1 << bit) != 0 && in_phase(color, phase));
+            if attenuated && hue < 0xE {
+                signal *= ATTENUATION;
+            }
+            let signal = (signal - BLACK) / (WHITE - BLACK) / 12.0;
+            let angle = std::f32::consts::PI / 6.0 * (phase + DECODER_PHASE) as f32;
+            y += signal;
+            i += signal * angle.cos();
+            q += signal * angle.sin();
+        }
+        (
+            y + 0.956 * i + 0.621 * q,
+            y - 0.272 * i - 0.647 * q,
+            y - 1.106 * i + 1.703 * q,
+        )
+    }
+
+    pub fn to_u8(level: f32) -> u8 {
+        // The signal is gamma encoded for a CRT, which is a little darker than sRGB
+        (level.clamp(0.0, 1.0).powf(1.1) * 255.0).round() as u8
+    }
+
+    /// How much the emphasis bits dim red, green and blue over all colors
+    pub fn emphasis_factors(emphasis: usize) -> [f32; 3] {
+        let sum = |emphasis: usize| {
+            (0..64).fold([0.0; 3], |acc, color| {
+                let (r, g, b) = decode(emphasis << 6 | color);
+                [
+                    acc[0] + r.max(0.0),
+                    acc[1] + g.max(0.0),
+                    acc[2] + b.max(0.0),
+                ]
+            })
+        };
+        let (plain, emphasized) = (sum(0), sum(emphasis));
+        [0, 1, 2].map(|c| (emphasized[c] / plain[c]).min(1.0))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_base_palette_emphasis() {
+        let palette = Palette::default();
+        assert_eq!(palette.palette[0x16], DEFAULT_PALETTE[0x16]);
+
+        // Red emphasis dims the other channels more, all three dim everything
+        let (r, g, b) = palette.palette[1 << 6 | 0x30];
+        assert!(r > g && r > b);
+        let (r, g, b) = palette.palette[7 << 6 | 0x30];
+        assert!(r < 0xF0 && g < 0xF0 && b < 0xF0);
+    }
+
+    #[test]
+    fn test_ntsc_hues() {
+        let palette = Palette::ntsc();
+        let (r, g, b) = palette.palette[0x16];
+        assert!(r > g && r > b, "$16 should be red");
+        let (r, g, b) = palette.palette[0x12];
+        assert!(b > r && b > g, "$12 should be blue");
+        let (r, g, b) = palette.palette[0x1A];
+        assert!(g > r && g > b, "$1A should be green");
+        assert_eq!(palette.palette[0x0F], (0, 0, 0));
+        assert_eq!(palette.palette[7 << 6 | 0x0F], (0, 0, 0));
+    }
+}
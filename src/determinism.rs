@@ -0,0 +1,222 @@
+use std::fmt::Write;
+
+use eyre::{eyre, Result, WrapErr};
+
+use crate::console::{
+    apu::Apu,
+    controller::Controller,
+    debug::{DebugSnapshot, StateHashes},
+    video::Frame,
+    Console, Frontend,
+};
+use crate::headless::Headless;
+use crate::movie::Movie;
+
+const LOG_HEADER: &str = "rhash 1";
+
+/// State hashes taken at the end of a frame
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Checkpoint {
+    pub frame: usize,
+    pub hashes: StateHashes,
+}
+
+/// Where and how to compare runs
+pub struct CheckOptions<'a> {
+    /// Frames between checkpoints, 1 finds the exact frame of a divergence
+    pub interval: usize,
+    /// Write the checkpoints of this build here
+    pub log_file: Option<&'a str>,
+    /// Compare against a log written by another build instead of running twice
+    pub against: Option<&'a str>,
+}
+
+/// Headless runner that records state hashes every `interval` frames
+struct Recorder {
+    headless: Headless,
+    interval: usize,
+    checkpoints: Vec<Checkpoint>,
+}
+
+impl Frontend for Recorder {
+    fn handle_io(&mut self, frame: &Frame, apu: &Apu, controller: &mut Controller) {
+        self.headless.handle_io(frame, apu, controller);
+    }
+
+    fn handle_audio(&mut self, apu: &Apu) -> Result<()> {
+        self.headless.handle_audio(apu)
+    }
+
+    fn wants_debug(&self) -> bool {
+        (self.headless.frames_done() + 1).is_multiple_of(self.interval)
+    }
+
+    fn debug_snapshot(&mut self, snapshot: &DebugSnapshot) {
+        self.checkpoints.push(Checkpoint {
+            frame: self.headless.frames_done() + 1,
+            hashes: snapshot.hashes,
+        });
+    }
+
+    fn quit_requested(&self) -> bool {
+        self.headless.quit_requested()
+    }
+}
+
+/// Runs the ROM with the same input twice, or once against a hash log from another
+/// build, and fails with the first checkpoint and subsystems where the state differs
+pub fn run(
+    rom: &[u8],
+    movie: Option<&Movie>,
+    frames: usize,
+    options: &CheckOptions,
+    configure: impl Fn(&mut Console),
+) -> Result<()> {
+    let first = record(rom, movie, frames, options.interval, &configure)?;
+    if let Some(file) = options.log_file {
+        std::fs::write(file, to_text(&first))
+            .wrap_err_with(|| format!("Failed to write hash log {file}"))?;
+    }
+
+    let (second, other) = match options.against {
+        Some(file) => {
+            let text = std::fs::read_to_string(file)
+                .wrap_err_with(|| format!("Failed to open hash log {file}"))?;
+            let log = parse(&text).wrap_err_with(|| format!("Invalid hash log {file}"))?;
+            (log, file)
+        }
+        None => (
+            record(rom, movie, frames, options.interval, &configure)?,
+            "second run",
+        ),
+    };
+
+    if first.len() != second.len() {
+        println!(
+            "Warning: {} checkpoints against {} in {other}",
+            first.len(),
+            second.len()
+        );
+    }
+    let Some((last_match, ours, theirs)) = first_divergence(&first, &second) else {
+        println!(
+            "{} checkpoints match {other}",
+            first.len().min(second.len())
+        );
+        return Ok(());
+    };
+    if ours.frame != theirs.frame {
+        return Err(eyre!(
+            "Checkpoints are at different frames ({} and {}), use the same interval",
+            ours.frame,
+            theirs.frame
+        ));
+    }
+    let since = last_match.map_or_else(|| "power-on".to_owned(), |c| format!("frame {}", c.frame));
+    Err(eyre!(
+        "State differs from {other} at frame {} in {}, last match at {since}",
+        ours.frame,
+        ours.hashes.differences(&theirs.hashes).join(", ")
+    ))
+}
+
+fn record(
+    rom: &[u8],
+    movie: Option<&Movie>,
+    frames: usize,
+    interval: usize,
+    configure: &impl Fn(&mut Console),
+) -> Result<Vec<Checkpoint>> {
+    let mut recorder = Recorder {
+        headless: Headless::new(movie.cloned(), frames),
+        interval: interval.max(1),
+        checkpoints: Vec::new(),
+    };
+    let mut console = Console::new(rom, &mut recorder)?;
+    configure(&mut console);
+    console.run_with_callback(|_| {})?;
+    drop(console);
+    Ok(recorder.checkpoints)
+}
+
+/// The last matching checkpoint, if any, and the first pair that differs
+fn first_divergence<'a>(
+    first: &'a [Checkpoint],
+    second: &'a [Checkpoint],
+) -> Option<(Option<&'a Checkpoint>, &'a Checkpoint, &'a Checkpoint)> {
+    let idx = first.iter().zip(second).position(|(a, b)| a != b)?;
+    Some((
+        idx.checked_sub(1).map(|i| &first[i]),
+        &first[idx],
+        &second[idx],
+    ))
+}
+
+// A header line, then one line per checkpoint: the frame and the hashes in hex
+fn to_text(checkpoints: &[Checkpoint]) -> String {
+    let mut out = format!("{LOG_HEADER}\n");
+    for checkpoint in checkpoints {
+        let _ = write!(out, "{}", checkpoint.frame);
+        for hash in checkpoint.hashes.values() {
+            let _ = write!(out, " {hash:016X}");
+        }
+        out.push('\n');
+    }
+    out
+}
+
+fn parse(text: &str) -> Result<Vec<Checkpoint>> {
+    let mut lines = text.lines();
+    if lines.next() != Some(LOG_HEADER) {
+        return Err(eyre!("Missing {LOG_HEADER} header"));
+    }
+    lines
+        .enumerate()
+        .map(|(idx, line)| {
+            let mut fields = line.split_whitespace();
+            let frame = fields.next().and_then(|f| f.parse().ok());
+            let hashes: Vec<u64> = fields
+                .filter_map(|h| u64::from_str_radix(h, 16).ok())
+                .collect();
+            match (frame, <[u64; 4]>::try_from(hashes)) {
+                (Some(frame), Ok(values)) => Ok(Checkpoint {
+                    frame,
+                    hashes: StateHashes::from_values(values),
+                }),
+                _ => Err(eyre!("Invalid checkpoint on line {}", idx + 2)),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod test {
+    use super::*;
+
+    fn checkpoint(frame: usize, ppu: u64) -> Checkpoint {
+        Checkpoint {
+            frame,
+            hashes: StateHashes::from_values([1, 2, ppu, 4]),
+        }
+    }
+
+    #[test]
+    fn test_log_round_trip() {
+        let log = vec![checkpoint(10, 3), checkpoint(20, u64::MAX)];
+        assert_eq!(parse(&to_text(&log)).unwrap(), log);
+        assert!(parse("rhash 1\n10 1 2 3\n").is_err());
+    }
+
+    #[test]
+    fn test_first_divergence() {
+        let a = [checkpoint(10, 3), checkpoint(20, 3), checkpoint(30, 3)];
+        let b = [checkpoint(10, 3), checkpoint(20, 5), checkpoint(30, 5)];
+        assert_eq!(first_divergence(&a, &a), None);
+        assert_eq!(first_divergence(&a, &b), Some((Some(&a[0]), &a[1], &b[1])));
+        assert_eq!(
+            first_divergence(&b[1..], &a[1..]),
+            Some((None, &b[1], &a[1]))
+        );
+    }
+}
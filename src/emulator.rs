@@ -1,6 +1,28 @@
-mod renderer;
+mod audio_info;
+mod autosave;
+mod bindings;
+mod capture;
+mod debugger;
+mod file_watch;
+mod gamepad;
+mod latency;
+mod layout;
+mod play_stats;
+#[cfg(feature = "presence")]
+pub mod presence;
+mod ram_watch;
+mod report;
+mod rumble;
+mod scaler;
+mod symbols;
+mod time_stretch;
 mod ui;
 
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::time::{Duration, Instant, SystemTime};
+
 use biquad::{Biquad, Coefficients, DirectForm2Transposed, ToHertz, Q_BUTTERWORTH_F32};
 
 use eyre::eyre;
@@ -14,37 +36,325 @@ use sdl2::{
     Sdl,
 };
 
+use crate::anchor::Anchor;
+use crate::checksum::crc32;
+use crate::compare::{self, CompareInput, Comparison};
+use crate::console::video::palette::Palette;
+use crate::console::{
+    debug::{DebugSnapshot, DebugWrite, StateHashes},
+    events::ConsoleEvent,
+    state::StateRequest,
+    Frontend, Region,
+};
 use crate::macros::fw_error;
-use crate::{console::apu::Apu, console::controller::Controller, console::ppu::Ppu};
-use renderer::Renderer;
+use crate::movie::Movie;
+use crate::romdb::{self, RomDb, RomInfo};
+use crate::run_info::{RunInfo, SyncSettings};
+use crate::update_check::{self, Release};
+use crate::{console::apu::Apu, console::controller::Controller, console::video::Frame};
+use audio_info::{AudioInfo, OutputSpec};
+use autosave::Autosave;
+use capture::{Capture, CaptureTags};
+use debugger::Debugger;
+use file_watch::FileWatch;
+use play_stats::PlayStats;
+use report::{RecentLog, Report};
+use time_stretch::TimeStretch;
+pub use ui::Renderer;
 use ui::Ui;
 
+// Play time and other statistics of every game played
+const PLAY_STATS_FILE: &str = "playstats.cfg";
+
+// Written by the Debug menu's audio info button
+const AUDIO_INFO_FILE: &str = "audio_info.txt";
+
+// How often window events are checked while paused in the background
+const PAUSE_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Information about the currently loaded game, shown in the window title
+pub struct GameInfo {
+    pub name: String,
+    pub region: Region,
+}
+
+impl Default for GameInfo {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            region: Region::Ntsc,
+        }
+    }
+}
+
 pub struct Emulator {
-    renderer: Renderer,
     audio_handler: AudioHandler,
     audio_device: AudioQueue<f32>,
+    /// APU samples handed over at a time
+    audio_chunk_len: usize,
     ui: Ui,
+    rom_path: Option<PathBuf>,
+    recording: Option<(Movie, PathBuf)>,
+    /// Anchor being replayed to and the frames replayed so far
+    replay: Option<(Anchor, usize)>,
+    /// Name and state of the anchor marked this frame, saved with the recording
+    marked_anchor: Option<(String, StateHashes)>,
+    rom_crc32: u32,
+    /// Settings the console was configured with, kept with movies and save states
+    sync: SyncSettings,
+    rom_db: RomDb,
+    compare: Option<Comparison>,
+    palette_watch: Option<FileWatch>,
+    autosave: Option<Autosave>,
+    play_stats: PlayStats,
+    log: RecentLog,
+    update_check: Option<Receiver<Release>>,
+    #[cfg(feature = "presence")]
+    presence: Option<Box<dyn presence::PresenceHook>>,
 }
 
 impl Emulator {
-    pub fn new(fullscreen: bool) -> Result<Self> {
+    /// Audio is taken from the console `audio_chunk` at a time, shorter chunks
+    /// lower the latency but wake the audio pipeline more often
+    pub fn new(fullscreen: bool, renderer: Renderer, audio_chunk: Duration) -> Result<Self> {
         let sdl = fw_error!(sdl2::init());
 
-        let renderer = Renderer::new()?;
         let audio_device = Self::init_audio(&sdl)?;
 
-        let audio_handler = AudioHandler::new(48000, crate::APU_FREQ / 120)?;
+        let spec = audio_device.spec();
+        let output_spec = OutputSpec {
+            freq: spec.freq,
+            channels: spec.channels,
+            samples: spec.samples,
+            format: format!("{:?}", spec.format),
+        };
+        let audio_chunk_len = (crate::APU_FREQ as f64 * audio_chunk.as_secs_f64()).round() as usize;
+        let audio_handler = AudioHandler::new(48000, audio_chunk_len.max(1), output_spec)?;
 
-        let ui = Ui::new(&sdl, fullscreen)?;
+        let ui = Ui::new(&sdl, fullscreen, renderer)?;
 
         Ok(Self {
-            renderer,
             audio_handler,
             audio_device,
+            audio_chunk_len: audio_chunk_len.max(1),
             ui,
+            rom_path: None,
+            recording: None,
+            replay: None,
+            marked_anchor: None,
+            rom_crc32: 0,
+            sync: SyncSettings::default(),
+            rom_db: RomDb::default(),
+            compare: None,
+            palette_watch: None,
+            autosave: None,
+            play_stats: PlayStats::load(Path::new(PLAY_STATS_FILE)),
+            log: RecentLog::default(),
+            update_check: None,
+            #[cfg(feature = "presence")]
+            presence: None,
         })
     }
 
+    /// Sets the file the running ROM was loaded from, used for the game name and reloading
+    pub fn set_rom_path(&mut self, file: &str) {
+        let path = PathBuf::from(file);
+        self.ui.debugger.ram_watch.load_symbols_for_rom(&path);
+        self.rom_path = Some(path.clone());
+        self.set_game_name(
+            path.file_stem()
+                .map_or_else(|| file.to_owned(), |s| s.to_string_lossy().into_owned()),
+        );
+    }
+
+    /// Forgets the ROM file and its battery saves, for a ROM that didn't come from a file
+    pub fn clear_rom_path(&mut self) {
+        self.rom_path = None;
+        self.autosave = None;
+    }
+
+    /// Names of the ROMs that can be switched between, the first one runs first
+    pub fn set_rom_list(&mut self, names: Vec<String>) {
+        self.ui.rom_list = names;
+        self.ui.current_rom = 0;
+    }
+
+    /// ROM list index the user switched to, once the console has stopped for it
+    pub fn take_rom_switch(&mut self) -> Option<usize> {
+        let next = self
+            .ui
+            .switch_to
+            .take()
+            .filter(|_| !self.ui.quit_requested)?;
+        self.ui.current_rom = next;
+        Some(next)
+    }
+
+    /// Sets the name shown for a ROM that didn't come from a file
+    pub fn set_game_name(&mut self, name: String) {
+        self.ui.game_info.name = name;
+        self.report_presence();
+    }
+
+    /// Keeps battery backed RAM in a .sav file next to the ROM, saving every `interval`
+    /// and on exit. Returns the RAM saved by an earlier session, if any.
+    pub fn enable_battery_saves(&mut self, interval: Option<Duration>) -> Result<Option<Vec<u8>>> {
+        let path = self
+            .rom_path
+            .as_ref()
+            .ok_or_else(|| eyre!("No ROM file loaded"))?;
+        let mut autosave = Autosave::new(path, interval);
+        let ram = autosave.load()?;
+        self.autosave = Some(autosave);
+        Ok(ram)
+    }
+
+    /// Reloads the palette whenever the file changes on disk
+    pub fn watch_palette(&mut self, file: &str) {
+        self.palette_watch = Some(FileWatch::new(file));
+    }
+
+    pub fn set_rom_db(&mut self, db: RomDb) {
+        self.rom_db = db;
+    }
+
+    /// Checksums the loaded ROM and reports any problems with the dump. A ROM found in
+    /// the database is named after its entry rather than the file.
+    pub fn identify_rom(&mut self, rom: &[u8]) {
+        let info = RomInfo::new(rom, &self.rom_db);
+        for warning in &info.warnings {
+            self.log.push(format!("ROM warning: {warning}"));
+        }
+        if let Some(name) = &info.db_name {
+            self.set_game_name(name.clone());
+        }
+        self.rom_crc32 = crc32(rom);
+        self.play_stats.select(info.crc32);
+        self.ui.game_stats = self.play_stats.current();
+        self.ui.set_rom_info(info);
+        self.ui.fallback_mapper = None;
+    }
+
+    // Screenshots go where the capture settings say, by default next to the ROM or in
+    // the working directory without one
+    fn save_capture(&mut self, capture: &Capture) {
+        let rom_dir = self
+            .rom_path
+            .as_deref()
+            .and_then(Path::parent)
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        let tags = CaptureTags {
+            game: &self.ui.game_info.name,
+            frame: self.ui.emulated_time.frames,
+            time: SystemTime::now(),
+        };
+        let path =
+            capture::screenshot_path(self.ui.capture_naming(), &rom_dir, &tags, capture.stage);
+        let saved = path
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .map_err(eyre::Report::from)
+            .and_then(|()| capture.save(&path));
+        match saved {
+            Ok(()) => self
+                .log
+                .push(format!("Saved screenshot to {}", path.display())),
+            Err(e) => self.log.push(format!("Failed to save screenshot: {e}")),
+        }
+    }
+
+    // Saves an issue report next to the ROM and copies a link to a pre-filled issue
+    fn save_report(&mut self, frame: &Frame) {
+        let base = self
+            .rom_path
+            .clone()
+            .unwrap_or_else(|| PathBuf::from("rnes"));
+        let mut screenshot = None;
+        if self.ui.report_screenshot {
+            let path = base.with_extension("report.ppm");
+            match Capture::game(frame.rgba).save(&path) {
+                Ok(()) => screenshot = Some(path),
+                Err(e) => self.log.push(format!("Failed to save screenshot: {e}")),
+            }
+        }
+        let report = Report {
+            game: &self.ui.game_info.name,
+            region: self.ui.game_info.region,
+            rom: self.ui.rom_info(),
+            settings: self.ui.settings_text(),
+            log: &self.log,
+            screenshot: screenshot.as_deref(),
+        };
+        let path = base.with_extension("report.md");
+        let saved = std::fs::write(&path, report.text());
+        let copied = self.ui.copy_to_clipboard(&report.issue_url());
+        match saved {
+            Ok(()) => self
+                .log
+                .push(format!("Saved issue report to {}", path.display())),
+            Err(e) => self.log.push(format!("Failed to save issue report: {e}")),
+        }
+        match copied {
+            Ok(()) => self
+                .log
+                .push("Copied a link to a pre-filled issue to the clipboard".to_owned()),
+            Err(e) => self.log.push(format!("Failed to copy the issue link: {e}")),
+        }
+    }
+
+    // Writes a copy of the ROM with overdumped data removed next to the original
+    // Save states are kept next to the ROM like battery saves
+    fn state_path(&self) -> Option<PathBuf> {
+        Some(self.rom_path.as_ref()?.with_extension("savestate"))
+    }
+
+    fn save_trimmed_rom(&mut self) -> Result<()> {
+        let path = self
+            .rom_path
+            .as_ref()
+            .ok_or_else(|| eyre!("No ROM file loaded"))?;
+        let trimmed = romdb::trim(&std::fs::read(path)?)?;
+        let out = path.with_extension("trimmed.nes");
+        std::fs::write(&out, trimmed)?;
+        self.log
+            .push(format!("Saved trimmed ROM to {}", out.display()));
+        Ok(())
+    }
+
+    #[cfg(feature = "presence")]
+    pub fn set_presence_hook(&mut self, hook: Box<dyn presence::PresenceHook>) {
+        self.presence = Some(hook);
+        self.report_presence();
+    }
+
+    #[cfg(feature = "presence")]
+    fn report_presence(&mut self) {
+        if let Some(hook) = self.presence.as_mut() {
+            hook.game_changed(&self.ui.game_info);
+        }
+    }
+
+    #[cfg(not(feature = "presence"))]
+    #[allow(clippy::unused_self)]
+    const fn report_presence(&self) {}
+
+    /// Audio output format, rates and buffer depth history
+    pub fn audio_report(&self) -> String {
+        self.audio_handler.info.report()
+    }
+
+    fn save_audio_info(&mut self) {
+        let report = self.audio_report();
+        println!("{report}");
+        match std::fs::write(AUDIO_INFO_FILE, &report) {
+            Ok(()) => self
+                .log
+                .push(format!("Audio info saved to {AUDIO_INFO_FILE}")),
+            Err(e) => self.log.push(format!("Failed to save audio info: {e}")),
+        }
+    }
+
     fn init_audio(sdl: &Sdl) -> Result<AudioQueue<f32>> {
         let audio_spec = AudioSpecDesired {
             freq: Some(48000),
@@ -58,15 +368,417 @@ impl Emulator {
         Ok(device)
     }
 
-    pub fn handle_io(&mut self, ppu: &Ppu, controller: &mut Controller) {
-        let game_texture = self.renderer.render_texture(ppu);
-        self.ui.update(game_texture, controller);
+    /// Parks audio and only handles window events until the window is brought back
+    fn wait_in_background(&mut self, controller: &mut Controller) {
+        self.audio_device.pause();
+        self.audio_device.clear();
+        // Key releases go to whichever window has focus now
+        controller.set_buttons(0);
+        while self.ui.paused() && !self.ui.quit_requested {
+            std::thread::sleep(PAUSE_POLL_INTERVAL);
+            self.ui.handle_input(controller);
+        }
+        self.audio_handler.restart();
+        self.audio_device.resume();
+        self.ui.resume_pacing();
+    }
+
+    pub fn set_sync_settings(&mut self, sync: SyncSettings) {
+        self.sync = sync;
+    }
+
+    fn run_info(&self) -> RunInfo {
+        RunInfo::new(self.rom_crc32, self.ui.game_info.region, self.sync)
+    }
+
+    /// Records the controller state of every frame, written out by `finish_recording`
+    pub fn start_recording(&mut self, file: &str) {
+        self.recording = Some((Movie::default(), PathBuf::from(file)));
+    }
+
+    /// Runs the anchor's input from power-on before handing control to the player
+    pub fn start_from(&mut self, anchor: Anchor) {
+        self.replay = Some((anchor, 0));
+    }
+
+    pub fn anchor(&self) -> Option<&Anchor> {
+        self.replay.as_ref().map(|(anchor, _)| anchor)
+    }
+
+    // Plays the next frame of the anchor's input, returns false once it's done
+    fn replay_frame(&mut self, controller: &mut Controller) -> bool {
+        let Some((anchor, replayed)) = self.replay.as_mut() else {
+            return false;
+        };
+        let Some(buttons) = anchor.movie().frame(*replayed) else {
+            self.replay = None;
+            return false;
+        };
+        controller.set_buttons(buttons);
+        *replayed += 1;
+        true
+    }
+
+    // Anchors hold the input from power-on, so they're cut from the recording
+    fn save_marked_anchor(&mut self) {
+        let Some((name, hashes)) = self.marked_anchor.take() else {
+            return;
+        };
+        let Some((movie, _)) = self.recording.as_ref() else {
+            self.log
+                .push("Anchors need the input from power-on, start with --record".to_owned());
+            return;
+        };
+        let anchor = Anchor::new(&name, self.rom_crc32, hashes, movie.clone());
+        let path = self
+            .rom_path
+            .as_ref()
+            .and_then(|rom| rom.parent())
+            .unwrap_or_else(|| Path::new(""))
+            .join(format!("{name}.state"));
+        match anchor.save(&path) {
+            Ok(()) => self
+                .log
+                .push(format!("Saved anchor {name} to {}", path.display())),
+            Err(e) => self.log.push(e.to_string()),
+        }
+    }
+
+    /// Looks for a newer release in the background, shown in the window if found
+    pub fn check_for_updates(&mut self) {
+        self.update_check = Some(update_check::start());
+    }
+
+    fn poll_update_check(&mut self) {
+        let Some(receiver) = self.update_check.as_ref() else {
+            return;
+        };
+        match receiver.try_recv() {
+            Ok(release) => {
+                self.log.push(format!(
+                    "rnes {} is available at {}",
+                    release.version, release.url
+                ));
+                self.ui.set_update(release);
+                self.update_check = None;
+            }
+            Err(TryRecvError::Empty) => (),
+            Err(TryRecvError::Disconnected) => self.update_check = None,
+        }
+    }
+
+    /// Shows a second console next to the main one, both running on the same input
+    pub fn start_comparison(&mut self, comparison: Comparison) {
+        self.compare = Some(comparison);
+    }
+
+    // Fetches the second console's frame and marks where it differs from the main one
+    fn update_comparison(&mut self, frame: &Frame) {
+        let Some(comparison) = self.compare.as_ref() else {
+            return;
+        };
+        match comparison.next_frame() {
+            Ok(mut other) => {
+                let diff = compare::highlight_diff(frame.indices, &mut other);
+                self.ui.set_compare_frame(other.rgba, diff.to_string());
+            }
+            Err(e) => {
+                self.log.push(e.to_string());
+                self.compare = None;
+            }
+        }
+    }
+
+    /// Saves the movie being recorded, if any
+    pub fn finish_recording(&mut self) -> Result<()> {
+        if let Some((movie, path)) = self.recording.take() {
+            movie.save(&path)?;
+            println!(
+                "Recorded {} frames ({} emulated) to {}",
+                movie.len(),
+                self.ui.emulated_time,
+                path.display()
+            );
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Emulator {
+    fn drop(&mut self) {
+        if let Err(e) = self.play_stats.save() {
+            println!("Failed to save play statistics: {e}");
+        }
+    }
+}
+
+impl Frontend for Emulator {
+    fn handle_io(&mut self, frame: &Frame, apu: &Apu, controller: &mut Controller) {
+        // The replay runs as fast as it can, without drawing or player input
+        if self.replay_frame(controller) {
+            if let Some((movie, _)) = self.recording.as_mut() {
+                movie.push(controller.effective_buttons());
+            }
+            return;
+        }
+        self.update_comparison(frame);
+        self.poll_update_check();
+        let game_texture = frame
+            .sources
+            .map_or_else(|| frame.rgba.to_vec(), Debugger::pixel_source_rgba);
+        self.ui.update(game_texture, apu, controller);
+        for capture in std::mem::take(&mut self.ui.captures) {
+            self.save_capture(&capture);
+        }
         self.ui.handle_input(controller);
+        if self.ui.paused() {
+            self.wait_in_background(controller);
+        }
+        if let Some(comparison) = self.compare.as_ref() {
+            let input = CompareInput {
+                buttons: controller.effective_buttons(),
+                reset: controller.reset_pending(),
+            };
+            if let Err(e) = comparison.send_input(input) {
+                self.log.push(e.to_string());
+                self.compare = None;
+            }
+        }
+        if std::mem::take(&mut self.ui.audio_info_requested) {
+            self.save_audio_info();
+        }
+        if std::mem::take(&mut self.ui.report_requested) {
+            self.save_report(frame);
+        }
+        if std::mem::take(&mut self.ui.trim_requested) {
+            if let Err(e) = self.save_trimmed_rom() {
+                self.log.push(format!("Failed to trim ROM: {e}"));
+            }
+        }
+        // Filtered input is recorded, so replays don't depend on the setting
+        if let Some((movie, _)) = self.recording.as_mut() {
+            movie.push(controller.effective_buttons());
+        }
+        self.save_marked_anchor();
+    }
+
+    /// Updates controller state from pending host input events
+    fn poll_input(&mut self, controller: &mut Controller) {
+        // Movies and the comparison console only take input once per frame,
+        // so mid-frame changes would make them diverge
+        if self.recording.is_none() && self.compare.is_none() && self.replay.is_none() {
+            self.ui.handle_input(controller);
+        }
     }
 
-    pub fn handle_audio(&mut self, apu: &Apu) -> Result<()> {
+    fn handle_audio(&mut self, apu: &Apu) -> Result<()> {
+        // Silence keeps the queue filled, so unmuting doesn't start with a gap
+        let samples = if self.ui.muted {
+            Cow::Owned(vec![0.0; apu.output.len()])
+        } else {
+            apu.output_f32()
+        };
         self.audio_handler
-            .process(&apu.output, &mut self.audio_device)
+            .process(&samples, self.ui.speed(), &mut self.audio_device)
+    }
+
+    fn audio_chunk_len(&self) -> usize {
+        self.audio_chunk_len
+    }
+
+    fn wants_audio(&self) -> bool {
+        self.replay.is_none() && self.ui.wants_audio()
+    }
+
+    /// Returns the ROM re-read from disk if the user asked for a reload
+    fn take_reloaded_rom(&mut self) -> Option<Vec<u8>> {
+        if !std::mem::take(&mut self.ui.reload_requested) {
+            return None;
+        }
+        let path = self.rom_path.as_ref()?;
+        match std::fs::read(path) {
+            Ok(rom) => {
+                self.identify_rom(&rom);
+                Some(rom)
+            }
+            Err(e) => {
+                self.log
+                    .push(format!("Failed to reload ROM {}: {}", path.display(), e));
+                None
+            }
+        }
+    }
+
+    fn reload_failed(&mut self, error: &eyre::Report) {
+        self.log.push(format!("Failed to reload ROM: {error}"));
+    }
+
+    fn take_state_request(&mut self) -> Option<StateRequest> {
+        let save = std::mem::take(&mut self.ui.save_state_requested);
+        let load = std::mem::take(&mut self.ui.load_state_requested);
+        if !save && !load {
+            return None;
+        }
+        let Some(path) = self.state_path() else {
+            self.log
+                .push("No ROM file to keep the save state next to".to_owned());
+            return None;
+        };
+        if save {
+            return Some(StateRequest::Save);
+        }
+        match std::fs::read(&path) {
+            Ok(state) => {
+                // Broken files are reported by the console when it loads them
+                if let Ok(Some(info)) = RunInfo::from_state(&state) {
+                    for warning in info.differences(&self.run_info()) {
+                        self.log.push(format!("Save state warning: {warning}"));
+                    }
+                }
+                Some(StateRequest::Load(state))
+            }
+            Err(e) => {
+                self.log.push(format!(
+                    "Failed to read save state {}: {}",
+                    path.display(),
+                    e
+                ));
+                None
+            }
+        }
+    }
+
+    fn state_saved(&mut self, state: Vec<u8>) {
+        let Some(path) = self.state_path() else {
+            return;
+        };
+        let state = match self.run_info().add_to_state(&state) {
+            Ok(state) => state,
+            Err(e) => {
+                self.log.push(format!("Failed to save state: {e}"));
+                return;
+            }
+        };
+        match autosave::write_atomic(&path, &state) {
+            Ok(()) => self.log.push(format!("Saved state to {}", path.display())),
+            Err(e) => self
+                .log
+                .push(format!("Failed to save state to {}: {}", path.display(), e)),
+        }
+    }
+
+    fn state_load_failed(&mut self, error: &eyre::Report) {
+        self.log.push(format!("Failed to load save state: {error}"));
+    }
+
+    fn take_debug_writes(&mut self) -> Vec<DebugWrite> {
+        self.ui.debugger.take_writes()
+    }
+
+    fn take_trace_dump_request(&mut self) -> bool {
+        std::mem::take(&mut self.ui.trace_dump_requested)
+    }
+
+    fn take_palette(&mut self) -> Option<Palette> {
+        let watch = self.palette_watch.as_mut()?;
+        if !watch.changed() {
+            return None;
+        }
+        match Palette::new(watch.path()) {
+            Ok(palette) => {
+                self.log.push(format!("Reloaded palette {}", watch.path()));
+                Some(palette)
+            }
+            Err(e) => {
+                self.log.push(format!("Failed to reload palette: {e}"));
+                None
+            }
+        }
+    }
+
+    fn battery_save_due(&mut self) -> bool {
+        self.autosave.as_ref().is_some_and(Autosave::due)
+    }
+
+    fn save_battery_ram(&mut self, ram: &[u8]) {
+        if let Some(autosave) = self.autosave.as_mut() {
+            if let Err(e) = autosave.save(ram) {
+                self.log.push(format!("Failed to save battery RAM: {e}"));
+            }
+        }
+    }
+
+    fn set_region(&mut self, region: Region) {
+        self.ui.game_info.region = region;
+        // The console reports its region on power-on, once the ROM is identified
+        let info = self.run_info();
+        if let Some((movie, _)) = self.recording.as_mut() {
+            movie.info.get_or_insert(info);
+        }
+        self.report_presence();
+    }
+
+    fn console_event(&mut self, event: ConsoleEvent) {
+        match event {
+            ConsoleEvent::FrameCompleted(time) => self.ui.emulated_time = time,
+            ConsoleEvent::ChrRomWrite(addr) => {
+                let warning = format!(
+                    "Game wrote to CHR ROM at ${addr:04X}, the header or mapper may be wrong"
+                );
+                self.log.push(format!("ROM warning: {warning}"));
+                self.ui.add_rom_warning(warning);
+            }
+            ConsoleEvent::MapperFallback(mapper) => {
+                let warning = format!("Mapper {mapper} isn't supported, running the game as NROM");
+                self.log.push(format!("ROM warning: {warning}"));
+                self.ui.add_rom_warning(warning);
+                self.ui.fallback_mapper = Some(mapper);
+            }
+            ConsoleEvent::StateLoaded => self.log.push("Loaded save state".to_owned()),
+            _ => (),
+        }
+        self.play_stats.console_event(event);
+        self.ui.game_stats = self.play_stats.current();
+    }
+
+    fn wants_debug(&self) -> bool {
+        let at_anchor = self
+            .replay
+            .as_ref()
+            .is_some_and(|(anchor, replayed)| *replayed + 1 == anchor.frames());
+        self.ui.debugger_active() || self.ui.anchor_requested.is_some() || at_anchor
+    }
+
+    fn wants_pixel_sources(&self) -> bool {
+        self.ui.show_pixel_sources
+    }
+
+    fn debug_snapshot(&mut self, snapshot: &DebugSnapshot) {
+        if let Some((anchor, replayed)) = self.replay.as_ref() {
+            // The anchor's last input is played after this snapshot
+            if *replayed + 1 == anchor.frames() {
+                match anchor.verify(&snapshot.hashes) {
+                    Ok(()) => self.log.push(format!("Reached anchor {}", anchor.name)),
+                    Err(e) => {
+                        self.log.push(e.to_string());
+                        self.ui.quit_requested = true;
+                    }
+                }
+            }
+        }
+        if let Some(name) = self.ui.anchor_requested.take() {
+            self.marked_anchor = Some((name, snapshot.hashes));
+        }
+        self.ui.debugger.set_snapshot(snapshot);
+    }
+
+    fn cpu_jammed(&mut self, addr: u16) {
+        self.ui.jammed_at = Some(addr);
+    }
+
+    /// Also stops the console to switch ROMs, see `take_rom_switch`
+    fn quit_requested(&self) -> bool {
+        self.ui.quit_requested || self.ui.switch_to.is_some()
     }
 }
 
@@ -78,8 +790,11 @@ struct AudioHandler {
     lp_14khz: DirectForm2Transposed<f32>,
     hp_90hz: DirectForm2Transposed<f32>,
     hp_440hz: DirectForm2Transposed<f32>,
+    time_stretch: TimeStretch,
     average_buff: usize,
     pub average_history: Vec<f32>,
+    started: Instant,
+    info: AudioInfo,
 }
 
 impl AudioHandler {
@@ -92,7 +807,7 @@ impl AudioHandler {
     const RATIO_EMPTY: f64 = 1.0 / Self::RATIO_FILL;
     const RATIO_NORMAL: f64 = 1.0;
 
-    fn new(out_freq: usize, input_len: usize) -> Result<Self> {
+    fn new(out_freq: usize, input_len: usize, spec: OutputSpec) -> Result<Self> {
         let params = InterpolationParameters {
             sinc_len: 256,
             f_cutoff: 0.95,
@@ -150,12 +865,21 @@ impl AudioHandler {
             lp_14khz,
             hp_90hz,
             hp_440hz,
+            time_stretch: TimeStretch::new(),
             average_buff: 0,
             average_history: vec![0.0; 100],
+            started: Instant::now(),
+            info: AudioInfo::new(spec, crate::APU_FREQ as u32, Self::TARGET_BUFFER_LEN),
         })
     }
 
-    fn process(&mut self, input: &[f32], queue: &mut AudioQueue<f32>) -> Result<()> {
+    /// Queues silence again before the next samples, after the queue was cleared
+    fn restart(&mut self) {
+        self.samples_received = 0;
+    }
+
+    /// Resamples and queues a buffer of APU output, produced at `speed` times real time
+    fn process(&mut self, input: &[f32], speed: f32, queue: &mut AudioQueue<f32>) -> Result<()> {
         if self.samples_received == 0 {
             match queue.queue_audio(&[0.0; 1200]) {
                 Ok(_) => (),
@@ -174,17 +898,19 @@ impl AudioHandler {
         self.average_history.push(queue_size as f32);
         // println!("Average buffer length is {}", self.average_buff);
 
-        match self.average_buff {
-            0..=Self::BUFFER_LOW_LIMIT => self
-                .resampler
-                .set_resample_ratio_relative(Self::RATIO_FILL)?,
-            Self::BUFFER_HIGH_LIMIT.. => self
-                .resampler
-                .set_resample_ratio_relative(Self::RATIO_EMPTY)?,
-            _ => self
-                .resampler
-                .set_resample_ratio_relative(Self::RATIO_NORMAL)?,
-        }
+        let ratio = match self.average_buff {
+            0..=Self::BUFFER_LOW_LIMIT => Self::RATIO_FILL,
+            Self::BUFFER_HIGH_LIMIT.. => Self::RATIO_EMPTY,
+            _ => Self::RATIO_NORMAL,
+        };
+        self.resampler.set_resample_ratio_relative(ratio)?;
+        self.info.record(
+            self.started.elapsed(),
+            input.len(),
+            ratio,
+            speed,
+            queue_size as usize,
+        );
 
         // println!("next samples is {}", self.resampler.output_frames_next());
 
@@ -198,6 +924,8 @@ impl AudioHandler {
             // .map(|x| self.hp_90hz.run(x))
             // .map(|x| self.hp_440hz.run(x))
             .collect();
+        // Keeps the pitch when running slower or faster than normal
+        let output = self.time_stretch.process(&output, speed);
 
         match queue.queue_audio(&output) {
             Ok(_) => (),
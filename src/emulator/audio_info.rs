@@ -0,0 +1,169 @@
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::time::Duration;
+
+// Length of one history entry, and how many are kept
+const WINDOW: Duration = Duration::from_secs(1);
+const HISTORY_LEN: usize = 120;
+
+/// Output format SDL gave the audio queue, which may differ from the one asked for
+pub struct OutputSpec {
+    pub freq: i32,
+    pub channels: u8,
+    pub samples: u16,
+    pub format: String,
+}
+
+/// Averages over one second of audio processing
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct Window {
+    /// End of the window, from the first processed buffer
+    at: Duration,
+    /// APU samples received per second
+    input_rate: f64,
+    /// Average of the relative ratio the resampler was set to
+    ratio: f64,
+    speed: f32,
+    queue_min: usize,
+    queue_max: usize,
+}
+
+/// Measures the audio pipeline as it runs, for a report that can go in bug reports
+pub struct AudioInfo {
+    spec: OutputSpec,
+    nominal_rate: u32,
+    target_queue: usize,
+    window_start: Option<Duration>,
+    buffers: usize,
+    input_samples: usize,
+    ratio_sum: f64,
+    queue_range: Option<(usize, usize)>,
+    history: VecDeque<Window>,
+}
+
+impl AudioInfo {
+    pub const fn new(spec: OutputSpec, nominal_rate: u32, target_queue: usize) -> Self {
+        Self {
+            spec,
+            nominal_rate,
+            target_queue,
+            window_start: None,
+            buffers: 0,
+            input_samples: 0,
+            ratio_sum: 0.0,
+            queue_range: None,
+            history: VecDeque::new(),
+        }
+    }
+
+    /// Records one processed buffer of `input` APU samples at `now`, any fixed
+    /// starting point will do
+    pub fn record(&mut self, now: Duration, input: usize, ratio: f64, speed: f32, queue: usize) {
+        let start = *self.window_start.get_or_insert(now);
+        self.buffers += 1;
+        self.input_samples += input;
+        self.ratio_sum += ratio;
+        let (min, max) = self.queue_range.get_or_insert((queue, queue));
+        *min = (*min).min(queue);
+        *max = (*max).max(queue);
+
+        let elapsed = now.saturating_sub(start);
+        if elapsed < WINDOW {
+            return;
+        }
+        let (queue_min, queue_max) = self.queue_range.take().unwrap_or_default();
+        if self.history.len() == HISTORY_LEN {
+            self.history.pop_front();
+        }
+        self.history.push_back(Window {
+            at: now,
+            input_rate: self.input_samples as f64 / elapsed.as_secs_f64(),
+            ratio: self.ratio_sum / self.buffers as f64,
+            speed,
+            queue_min,
+            queue_max,
+        });
+        self.window_start = Some(now);
+        self.buffers = 0;
+        self.input_samples = 0;
+        self.ratio_sum = 0.0;
+    }
+
+    pub fn report(&self) -> String {
+        let spec = &self.spec;
+        let mut report = format!(
+            "Audio output: {} Hz, {} channel(s), {} sample buffer, {}\n\
+             Nominal APU rate: {} Hz, target queue depth: {} samples\n",
+            spec.freq,
+            spec.channels,
+            spec.samples,
+            spec.format,
+            self.nominal_rate,
+            self.target_queue
+        );
+        let Some(last) = self.history.back() else {
+            report.push_str("No audio processed for a full second yet\n");
+            return report;
+        };
+        let _ = writeln!(
+            report,
+            "Measured emulation rate: {:.0} Hz, {:.2}% of nominal at {:.0}% speed",
+            last.input_rate,
+            100.0 * last.input_rate / self.nominal_rate as f64,
+            100.0 * last.speed
+        );
+        let _ = writeln!(report, "\n  time    input Hz  speed  ratio     queue");
+        for window in &self.history {
+            let _ = writeln!(
+                report,
+                "{:6.1}s {:10.0} {:5.0}% {:.5} {:5}-{}",
+                window.at.as_secs_f64(),
+                window.input_rate,
+                100.0 * window.speed,
+                window.ratio,
+                window.queue_min,
+                window.queue_max
+            );
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn info() -> AudioInfo {
+        let spec = OutputSpec {
+            freq: 48000,
+            channels: 1,
+            samples: 1024,
+            format: "F32LSB".to_owned(),
+        };
+        AudioInfo::new(spec, 1000, 1200)
+    }
+
+    #[test]
+    fn test_windows() {
+        let mut info = info();
+        assert!(info.report().contains("No audio processed"));
+        for ms in (0..=2000).step_by(100) {
+            let ratio = if ms < 1000 { 1.0 } else { 1.003 };
+            info.record(
+                Duration::from_millis(ms),
+                50,
+                ratio,
+                1.0,
+                1000 + ms as usize,
+            );
+        }
+        assert_eq!(info.history.len(), 2);
+        let first = info.history[0];
+        assert!((first.input_rate - 550.0).abs() < 1e-9);
+        assert_eq!((first.queue_min, first.queue_max), (1000, 2000));
+        assert!(info.history[1].ratio > 1.0);
+        assert!(info
+            .report()
+            .contains("Measured emulation rate: 500 Hz, 50.00% of nominal"));
+    }
+}
@@ -0,0 +1,103 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use eyre::{Result, WrapErr};
+
+/// Keeps a game's battery backed RAM in a save file next to the ROM
+pub struct Autosave {
+    path: PathBuf,
+    /// Time between saves while running, `None` only saves on exit
+    interval: Option<Duration>,
+    last_save: Instant,
+    /// What the file holds, so unchanged RAM isn't rewritten
+    saved: Vec<u8>,
+}
+
+impl Autosave {
+    pub fn new(rom_path: &Path, interval: Option<Duration>) -> Self {
+        Self {
+            path: rom_path.with_extension("sav"),
+            interval,
+            last_save: Instant::now(),
+            saved: Vec::new(),
+        }
+    }
+
+    /// Contents of the save file, if there is one
+    pub fn load(&mut self) -> Result<Option<Vec<u8>>> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+        self.saved = std::fs::read(&self.path)
+            .wrap_err_with(|| format!("Failed to read save file {}", self.path.display()))?;
+        println!("Loaded battery RAM from {}", self.path.display());
+        Ok(Some(self.saved.clone()))
+    }
+
+    pub fn due(&self) -> bool {
+        self.interval
+            .is_some_and(|interval| self.last_save.elapsed() >= interval)
+    }
+
+    pub fn save(&mut self, ram: &[u8]) -> Result<()> {
+        self.last_save = Instant::now();
+        if ram == self.saved {
+            return Ok(());
+        }
+        write_atomic(&self.path, ram)?;
+        self.saved = ram.to_vec();
+        Ok(())
+    }
+}
+
+/// Writes to a temporary file and renames it over `path`, so a crash or power loss
+/// leaves either the old or the new contents but never a partial file
+pub fn write_atomic(path: &Path, data: &[u8]) -> Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    let write = || -> std::io::Result<()> {
+        let mut file = File::create(&tmp)?;
+        file.write_all(data)?;
+        file.sync_all()?;
+        std::fs::rename(&tmp, path)
+    };
+    write().wrap_err_with(|| format!("Failed to write {}", path.display()))
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_save_and_load() {
+        let rom = std::env::temp_dir().join("rnes_autosave_test.nes");
+        let mut autosave = Autosave::new(&rom, None);
+        let _ = std::fs::remove_file(&autosave.path);
+        assert_eq!(autosave.load().unwrap(), None);
+        assert!(!autosave.due());
+
+        autosave.save(&[1, 2, 3]).unwrap();
+        let mut reloaded = Autosave::new(&rom, Some(Duration::ZERO));
+        assert_eq!(reloaded.load().unwrap(), Some(vec![1, 2, 3]));
+        assert!(reloaded.due());
+
+        // Unchanged RAM leaves the file alone
+        std::fs::remove_file(&autosave.path).unwrap();
+        autosave.save(&[1, 2, 3]).unwrap();
+        assert!(!autosave.path.exists());
+    }
+
+    #[test]
+    fn test_write_atomic_replaces_file() {
+        let path = std::env::temp_dir().join("rnes_atomic_test.bin");
+        write_atomic(&path, b"old").unwrap();
+        write_atomic(&path, b"new").unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"new");
+        assert!(!path.with_extension("bin.tmp").exists());
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,278 @@
+use std::fmt::Write;
+
+use egui_sdl2_gl::egui::{self, CtxRef};
+use eyre::{eyre, Result};
+use sdl2::controller::Button as PadButton;
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+
+use crate::console::controller::Button;
+
+// Settings file keys are these followed by the NES button name, e.g. key.A=S
+const KEY_PREFIX: &str = "key.";
+const PAD_PREFIX: &str = "pad.";
+
+// Hotkeys handled before controller input, binding them would do nothing
+const RESERVED_KEYS: [Keycode; 14] = [
+    Keycode::Escape,
+    Keycode::R,
+    Keycode::F2,
+    Keycode::F3,
+    Keycode::F5,
+    Keycode::F6,
+    Keycode::F9,
+    Keycode::F12,
+    Keycode::Tab,
+    Keycode::Num1,
+    Keycode::Num2,
+    Keycode::Num3,
+    Keycode::Num4,
+    Keycode::Num5,
+];
+
+/// A key or controller button to bind to an NES button
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HostInput {
+    Key(Keycode),
+    Pad(PadButton),
+}
+
+/// Keyboard and controller buttons for each NES button, indexed like `Button::ALL`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Bindings {
+    keys: [Keycode; 8],
+    pads: [PadButton; 8],
+}
+
+impl Default for Bindings {
+    /// Controller buttons are positional, so the pad's right face button is A
+    fn default() -> Self {
+        Self {
+            keys: [
+                Keycode::S,
+                Keycode::A,
+                Keycode::Q,
+                Keycode::W,
+                Keycode::Up,
+                Keycode::Down,
+                Keycode::Left,
+                Keycode::Right,
+            ],
+            pads: [
+                PadButton::B,
+                PadButton::A,
+                PadButton::Back,
+                PadButton::Start,
+                PadButton::DPadUp,
+                PadButton::DPadDown,
+                PadButton::DPadLeft,
+                PadButton::DPadRight,
+            ],
+        }
+    }
+}
+
+impl Bindings {
+    /// Reads a `key.<button>` or `pad.<button>` settings key, returns false if it
+    /// isn't one
+    pub fn parse_line(&mut self, key: &str, value: &str) -> bool {
+        let (prefix, name) = match (key.strip_prefix(KEY_PREFIX), key.strip_prefix(PAD_PREFIX)) {
+            (Some(name), _) => (KEY_PREFIX, name),
+            (_, Some(name)) => (PAD_PREFIX, name),
+            _ => return false,
+        };
+        let Some(idx) = Button::ALL.iter().position(|b| b.name() == name) else {
+            return true;
+        };
+        if prefix == KEY_PREFIX {
+            if let Some(keycode) = Keycode::from_name(value) {
+                self.keys[idx] = keycode;
+            }
+        } else if let Some(pad) = PadButton::from_string(value) {
+            self.pads[idx] = pad;
+        }
+        true
+    }
+
+    /// Settings lines for the bindings that differ from the defaults
+    pub fn lines(&self) -> String {
+        let defaults = Self::default();
+        let mut out = String::new();
+        for (idx, button) in Button::ALL.iter().enumerate() {
+            if self.keys[idx] != defaults.keys[idx] {
+                let _ = writeln!(
+                    out,
+                    "{KEY_PREFIX}{}={}",
+                    button.name(),
+                    self.keys[idx].name()
+                );
+            }
+            if self.pads[idx] != defaults.pads[idx] {
+                let _ = writeln!(
+                    out,
+                    "{PAD_PREFIX}{}={}",
+                    button.name(),
+                    self.pads[idx].string()
+                );
+            }
+        }
+        out
+    }
+
+    pub fn key_button(&self, keycode: Keycode) -> Option<Button> {
+        let idx = self.keys.iter().position(|&k| k == keycode)?;
+        Some(Button::ALL[idx])
+    }
+
+    pub fn pad_button(&self, pad: PadButton) -> Option<Button> {
+        let idx = self.pads.iter().position(|&p| p == pad)?;
+        Some(Button::ALL[idx])
+    }
+
+    pub fn key_name(&self, button: Button) -> String {
+        self.keys[button as usize].name()
+    }
+
+    pub fn pad_name(&self, button: Button) -> String {
+        self.pads[button as usize].string()
+    }
+
+    /// Binds a key or controller button. If another NES button had it, the two swap
+    /// and that button is returned.
+    pub fn bind(&mut self, button: Button, input: HostInput) -> Result<Option<Button>> {
+        let idx = button as usize;
+        let other = match input {
+            HostInput::Key(keycode) => {
+                if RESERVED_KEYS.contains(&keycode) {
+                    return Err(eyre!("Hotkeys can't be bound"));
+                }
+                let other = self.keys.iter().position(|&k| k == keycode);
+                if let Some(other) = other {
+                    self.keys[other] = self.keys[idx];
+                }
+                self.keys[idx] = keycode;
+                other
+            }
+            HostInput::Pad(pad) => {
+                let other = self.pads.iter().position(|&p| p == pad);
+                if let Some(other) = other {
+                    self.pads[other] = self.pads[idx];
+                }
+                self.pads[idx] = pad;
+                other
+            }
+        };
+        Ok(other
+            .filter(|&other| other != idx)
+            .map(|other| Button::ALL[other]))
+    }
+}
+
+/// Controls window, where Set binds the next key or controller button pressed
+#[derive(Default)]
+pub struct BindingsWindow {
+    pub open: bool,
+    /// NES button waiting for a press
+    capturing: Option<Button>,
+    /// Result of the last binding, e.g. a conflict that was resolved
+    note: Option<String>,
+}
+
+impl BindingsWindow {
+    /// Binds the press while waiting for one, Escape cancels. Returns true if the
+    /// event was used.
+    pub fn capture(&mut self, bindings: &mut Bindings, event: &Event) -> bool {
+        let Some(button) = self.capturing else {
+            return false;
+        };
+        let input = match *event {
+            Event::KeyDown {
+                keycode: Some(Keycode::Escape),
+                ..
+            } => None,
+            Event::KeyDown {
+                keycode: Some(keycode),
+                ..
+            } => Some(HostInput::Key(keycode)),
+            Event::ControllerButtonDown { button, .. } => Some(HostInput::Pad(button)),
+            _ => return false,
+        };
+        self.capturing = None;
+        self.note = match input.map(|input| bindings.bind(button, input)) {
+            Some(Ok(Some(other))) => Some(format!("Swapped with {}", other.name())),
+            Some(Err(e)) => {
+                self.capturing = Some(button);
+                Some(format!("{e}, press another"))
+            }
+            Some(Ok(None)) | None => None,
+        };
+        true
+    }
+
+    pub fn draw(&mut self, ctx: &CtxRef, bindings: &mut Bindings) {
+        let capturing = self.capturing;
+        let mut capture = None;
+        let mut reset = false;
+        egui::Window::new("Controls")
+            .open(&mut self.open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                egui::Grid::new("bindings").show(ui, |ui| {
+                    for button in Button::ALL {
+                        ui.label(button.name());
+                        if capturing == Some(button) {
+                            ui.label("Press a key or button...");
+                            ui.label("");
+                        } else {
+                            ui.label(bindings.key_name(button));
+                            ui.label(bindings.pad_name(button));
+                        }
+                        if ui.button("Set").clicked() {
+                            capture = Some(button);
+                        }
+                        ui.end_row();
+                    }
+                });
+                if let Some(note) = &self.note {
+                    ui.label(note);
+                }
+                reset = ui.button("Reset to defaults").clicked();
+            });
+        if capture.is_some() || reset || !self.open {
+            self.capturing = capture;
+            self.note = None;
+        }
+        if reset {
+            *bindings = Bindings::default();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_defaults() {
+        let bindings = Bindings::default();
+        assert_eq!(bindings.key_button(Keycode::S), Some(Button::A));
+        assert_eq!(bindings.pad_button(PadButton::B), Some(Button::A));
+        assert_eq!(bindings.pad_button(PadButton::A), Some(Button::B));
+        assert_eq!(bindings.pad_button(PadButton::Guide), None);
+        assert_eq!(bindings.lines(), "");
+    }
+
+    #[test]
+    fn test_bind_swaps_conflicts() {
+        let mut bindings = Bindings::default();
+        let swapped = bindings.bind(Button::A, HostInput::Key(Keycode::A)).ok();
+        assert_eq!(swapped, Some(Some(Button::B)));
+        assert_eq!(bindings.key_button(Keycode::A), Some(Button::A));
+        assert_eq!(bindings.key_button(Keycode::S), Some(Button::B));
+        let swapped = bindings.bind(Button::Start, HostInput::Pad(PadButton::Start));
+        assert_eq!(swapped.ok(), Some(None));
+        assert!(bindings
+            .bind(Button::A, HostInput::Key(Keycode::F5))
+            .is_err());
+    }
+}
@@ -0,0 +1,272 @@
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use eyre::Result;
+
+use crate::console::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::golden;
+
+/// Where in the render pipeline an image is read back
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CaptureStage {
+    /// The emulated picture alone, full 256x240 frame before scaling or cropping
+    Game,
+    /// What the window shows, with the menu, panels and other overlays on top
+    Composited,
+}
+
+/// An RGBA image read back from the renderer, top row first
+pub struct Capture {
+    pub stage: CaptureStage,
+    pub width: usize,
+    pub height: usize,
+    pub rgba: Vec<u8>,
+}
+
+impl Capture {
+    pub fn game(rgba: &[u8]) -> Self {
+        Self {
+            stage: CaptureStage::Game,
+            width: SCREEN_WIDTH,
+            height: SCREEN_HEIGHT,
+            rgba: rgba.to_vec(),
+        }
+    }
+
+    /// Reads the back buffer before it's swapped, so it has everything drawn this frame
+    pub fn read_back_buffer(width: u32, height: u32) -> Self {
+        let (width, height) = (width as usize, height as usize);
+        let mut rgba = vec![0; width * height * 4];
+        unsafe {
+            gl::ReadBuffer(gl::BACK);
+            gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+            gl::ReadPixels(
+                0,
+                0,
+                width as i32,
+                height as i32,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                rgba.as_mut_ptr().cast(),
+            );
+        }
+        Self {
+            stage: CaptureStage::Composited,
+            width,
+            height,
+            rgba: flip_rows(&rgba, width * 4),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        golden::write_ppm(path, self.width, self.height, &self.rgba)
+    }
+}
+
+// OpenGL reads start from the bottom row
+fn flip_rows(data: &[u8], stride: usize) -> Vec<u8> {
+    data.chunks_exact(stride).rev().flatten().copied().collect()
+}
+
+/// Where captures are saved and what they're called, the same for every kind of capture
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct CaptureNaming {
+    /// Directory for captures, next to the ROM if None
+    pub dir: Option<PathBuf>,
+    /// File name without the extension, see `path`
+    pub template: String,
+}
+
+impl Default for CaptureNaming {
+    fn default() -> Self {
+        Self {
+            dir: None,
+            template: Self::DEFAULT_TEMPLATE.to_owned(),
+        }
+    }
+}
+
+/// What a capture's file name can be made of
+pub struct CaptureTags<'a> {
+    pub game: &'a str,
+    pub frame: u64,
+    pub time: SystemTime,
+}
+
+impl CaptureNaming {
+    pub const DEFAULT_TEMPLATE: &'static str = "{game}-{n}";
+
+    /// First unused path for a capture ending in `extension`. In the template `{game}`,
+    /// `{frame}` and `{timestamp}` become the game name, the frame number and the UTC
+    /// time as YYYYMMDD-HHMMSS, and `{n}` the lowest number giving a new file. Without
+    /// `{n}` a number is only added if the file exists. `rom_dir` is used without a
+    /// directory setting.
+    pub fn path(&self, rom_dir: &Path, tags: &CaptureTags, extension: &str) -> PathBuf {
+        let dir = self.dir.as_deref().unwrap_or(rom_dir);
+        let name = self
+            .template
+            .replace("{game}", &file_name_safe(tags.game))
+            .replace("{frame}", &tags.frame.to_string())
+            .replace("{timestamp}", &timestamp(tags.time));
+        if !name.contains("{n}") {
+            let path = dir.join(format!("{name}{extension}"));
+            if !path.exists() {
+                return path;
+            }
+        }
+        let numbered = if name.contains("{n}") {
+            name
+        } else {
+            format!("{name}-{{n}}")
+        };
+        let mut n = 1;
+        loop {
+            let path = dir.join(format!(
+                "{}{extension}",
+                numbered.replace("{n}", &n.to_string())
+            ));
+            if !path.exists() {
+                return path;
+            }
+            n += 1;
+        }
+    }
+}
+
+/// Path for a screenshot, with `.overlay` before the extension for composited captures
+pub fn screenshot_path(
+    naming: &CaptureNaming,
+    rom_dir: &Path,
+    tags: &CaptureTags,
+    stage: CaptureStage,
+) -> PathBuf {
+    let extension = match stage {
+        CaptureStage::Game => ".ppm",
+        CaptureStage::Composited => ".overlay.ppm",
+    };
+    naming.path(rom_dir, tags, extension)
+}
+
+// Game names come from file names and databases, keep them to one path component
+fn file_name_safe(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| {
+            if c.is_control() || "/\\:*?\"<>|".contains(c) {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+    match name.trim() {
+        "" | "." | ".." => "capture".to_owned(),
+        name => name.to_owned(),
+    }
+}
+
+// UTC date and time as YYYYMMDD-HHMMSS
+fn timestamp(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (days, secs) = (secs / 86400, secs % 86400);
+    // Civil date from days since 1970-01-01, counting in 400 year eras from March
+    let days = days as i64 + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}{month:02}{day:02}-{:02}{:02}{:02}",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_flip_rows() {
+        assert_eq!(flip_rows(&[1, 2, 3, 4, 5, 6], 2), [5, 6, 3, 4, 1, 2]);
+    }
+
+    fn tags(game: &str) -> CaptureTags<'_> {
+        CaptureTags {
+            game,
+            frame: 1234,
+            // 2023-03-01 13:14:15 UTC
+            time: UNIX_EPOCH + std::time::Duration::from_secs(1_677_676_455),
+        }
+    }
+
+    #[test]
+    fn test_screenshot_path() {
+        let naming = CaptureNaming::default();
+        let dir = std::env::temp_dir();
+        let path = screenshot_path(
+            &naming,
+            &dir,
+            &tags("rnes_capture_test"),
+            CaptureStage::Composited,
+        );
+        assert_eq!(
+            path.file_name().and_then(|name| name.to_str()),
+            Some("rnes_capture_test-1.overlay.ppm")
+        );
+        assert_eq!(
+            screenshot_path(&naming, Path::new(""), &tags("smb"), CaptureStage::Game),
+            Path::new("smb-1.ppm")
+        );
+    }
+
+    #[test]
+    fn test_templates() {
+        let naming = CaptureNaming {
+            dir: Some(PathBuf::from("shots")),
+            template: "{game}/{timestamp}_f{frame}".to_owned(),
+        };
+        assert_eq!(
+            naming.path(Path::new("roms"), &tags("Zelda: A?"), ".ppm"),
+            Path::new("shots/Zelda_ A_/20230301-131415_f1234.ppm")
+        );
+
+        // A number is added to names that are taken
+        let dir = std::env::temp_dir().join("rnes_capture_template_test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let naming = CaptureNaming {
+            dir: Some(dir.clone()),
+            template: "{game}".to_owned(),
+        };
+        let first = naming.path(Path::new(""), &tags("game"), ".ppm");
+        assert_eq!(first, dir.join("game.ppm"));
+        std::fs::write(&first, []).unwrap();
+        assert_eq!(
+            naming.path(Path::new(""), &tags("game"), ".ppm"),
+            dir.join("game-1.ppm")
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_timestamp() {
+        assert_eq!(timestamp(UNIX_EPOCH), "19700101-000000");
+        assert_eq!(timestamp(tags("").time), "20230301-131415");
+        let leap_day = UNIX_EPOCH + std::time::Duration::from_secs(951_782_400 + 86399);
+        assert_eq!(timestamp(leap_day), "20000229-235959");
+    }
+}
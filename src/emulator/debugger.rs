@@ -0,0 +1,473 @@
+use egui_sdl2_gl::egui::{self, Color32, CtxRef, DragValue, Pos2, Rect, RichText, Sense, Vec2};
+
+use super::layout::PanelLayout;
+use super::ram_watch::{RamWatch, RAM_WATCH_TITLE};
+use crate::console::apu::Apu;
+use crate::console::debug::{
+    black_screen_causes, DebugSnapshot, DebugWrite, InterruptCause, ReturnKind, TileInfo,
+};
+use crate::console::ppu::PixelSource;
+use crate::console::SCREEN_WIDTH;
+
+const SP_COLOR: Color32 = Color32::from_rgb(0xE0, 0x40, 0x40);
+const RETURN_COLOR: Color32 = Color32::from_rgb(0x40, 0xA0, 0xE0);
+const IRQ_RAISED_COLOR: Color32 = Color32::from_rgb(0xE0, 0xA0, 0x40);
+const NMI_COLOR: Color32 = Color32::from_rgb(0x40, 0xA0, 0xE0);
+
+const STACK_TITLE: &str = "Stack";
+const BLACK_SCREEN_TITLE: &str = "Black screen diagnostics";
+const REGISTERS_TITLE: &str = "Registers";
+const IRQ_TITLE: &str = "Mapper IRQ";
+const TILES_TITLE: &str = "Tile inspector";
+const INTERRUPTS_TITLE: &str = "Interrupt timeline";
+const PANELS: [&str; 7] = [
+    STACK_TITLE,
+    BLACK_SCREEN_TITLE,
+    REGISTERS_TITLE,
+    IRQ_TITLE,
+    TILES_TITLE,
+    RAM_WATCH_TITLE,
+    INTERRUPTS_TITLE,
+];
+
+// Colors of the pixel layer view
+const PIXEL_SOURCES: [(PixelSource, &str, Color32); 4] = [
+    (
+        PixelSource::Backdrop,
+        "Backdrop",
+        Color32::from_rgb(0x20, 0x20, 0x20),
+    ),
+    (
+        PixelSource::Background,
+        "Background",
+        Color32::from_rgb(0x40, 0x60, 0xC0),
+    ),
+    (
+        PixelSource::SpriteFront,
+        "Sprite in front",
+        Color32::from_rgb(0xE0, 0x50, 0x40),
+    ),
+    (
+        PixelSource::SpriteBehind,
+        "Sprite behind background",
+        Color32::from_rgb(0xE0, 0xC0, 0x40),
+    ),
+];
+
+// Frames shown as rows of the interrupt timeline, and interrupts listed below it
+const TIMELINE_FRAMES: u64 = 8;
+const TIMELINE_ROW_HEIGHT: f32 = 10.0;
+const INTERRUPT_LIST_LEN: usize = 32;
+// Dots per line, and lines per NTSC frame including the pre-render line
+const LINE_DOTS: f32 = 341.0;
+const MIN_FRAME_LINES: isize = 262;
+
+// Status flags and their bits, in the order they are usually written
+const FLAGS: [(&str, u8); 6] = [("N", 7), ("V", 6), ("D", 3), ("I", 2), ("Z", 1), ("C", 0)];
+
+/// Debugger panels, drawn from the state captured at the end of each frame
+#[derive(Default)]
+pub struct Debugger {
+    snapshot: Option<DebugSnapshot>,
+    /// Edits made in the panels, applied by the console at the end of the frame
+    writes: Vec<DebugWrite>,
+    /// Picture pixel under the cursor
+    hovered_pixel: Option<(usize, usize)>,
+    pub ram_watch: RamWatch,
+}
+
+impl Debugger {
+    /// True if any panel is open and needs console state
+    pub fn active(panels: &PanelLayout) -> bool {
+        PANELS.iter().any(|title| panels.is_open(title))
+    }
+
+    pub fn set_snapshot(&mut self, snapshot: &DebugSnapshot) {
+        self.snapshot = Some(snapshot.clone());
+    }
+
+    pub fn take_writes(&mut self) -> Vec<DebugWrite> {
+        std::mem::take(&mut self.writes)
+    }
+
+    pub fn menu(ui: &mut egui::Ui, panels: &mut PanelLayout) {
+        for title in PANELS {
+            panels.checkbox(ui, title);
+        }
+    }
+
+    /// Picture colored by the layer of each pixel instead of its palette color
+    pub fn pixel_source_rgba(sources: &[PixelSource]) -> Vec<u8> {
+        sources
+            .iter()
+            .flat_map(|source| {
+                let (_, _, color) = PIXEL_SOURCES[*source as usize];
+                [color.r(), color.g(), color.b(), 255]
+            })
+            .collect()
+    }
+
+    pub fn pixel_source_legend(ui: &mut egui::Ui) {
+        for (_, name, color) in PIXEL_SOURCES {
+            ui.label(RichText::new(name).color(color));
+        }
+    }
+
+    /// Shows the background tile under the cursor in a tooltip while the tile
+    /// inspector is open. The picture shows `lines` lines starting from `first_line`.
+    pub fn inspect_picture(
+        &mut self,
+        response: egui::Response,
+        panels: &PanelLayout,
+        first_line: usize,
+        lines: usize,
+    ) {
+        self.hovered_pixel = None;
+        if !panels.is_open(TILES_TITLE) {
+            return;
+        }
+        let (Some(pos), Some(snapshot)) = (response.hover_pos(), self.snapshot.as_ref()) else {
+            return;
+        };
+        let rect = response.rect;
+        let x = ((pos.x - rect.min.x) / rect.width() * SCREEN_WIDTH as f32) as usize;
+        let y = first_line + ((pos.y - rect.min.y) / rect.height() * lines as f32) as usize;
+        let pixel = (x.min(SCREEN_WIDTH - 1), y.min(first_line + lines - 1));
+        self.hovered_pixel = Some(pixel);
+        response.on_hover_text(Self::tile_text(snapshot, pixel));
+    }
+
+    fn tile_text(snapshot: &DebugSnapshot, (x, y): (usize, usize)) -> String {
+        let Some(tile) = snapshot.tile_map.tile_at(x, y) else {
+            return format!("Pixel {x}, {y}\nBackground off on this line");
+        };
+        let TileInfo {
+            tile,
+            pattern_addr,
+            palette,
+            nametable,
+            column,
+            row,
+        } = tile;
+        format!(
+            "Pixel {x}, {y}\nTile ${tile:02X}, pattern ${pattern_addr:04X}\n\
+             Palette {palette}\nNametable {nametable} (${:04X}), column {column}, row {row}",
+            0x2000 + 0x400 * nametable as u16
+        )
+    }
+
+    pub fn draw(&mut self, ctx: &CtxRef, panels: &mut PanelLayout) {
+        let Some(snapshot) = self.snapshot.as_ref() else {
+            return;
+        };
+        Self::draw_stack(ctx, snapshot, panels);
+        Self::draw_black_screen(ctx, snapshot, panels);
+        Self::draw_registers(ctx, snapshot, panels, &mut self.writes);
+        Self::draw_irq(ctx, snapshot, panels);
+        Self::draw_interrupts(ctx, snapshot, panels);
+        Self::draw_tiles(ctx, snapshot, panels, self.hovered_pixel);
+        self.ram_watch.draw(ctx, snapshot, panels);
+    }
+
+    // Background tile under the cursor, the same as its tooltip
+    fn draw_tiles(
+        ctx: &CtxRef,
+        snapshot: &DebugSnapshot,
+        panels: &mut PanelLayout,
+        hovered_pixel: Option<(usize, usize)>,
+    ) {
+        panels.show(
+            ctx,
+            TILES_TITLE,
+            |window| window.resizable(false),
+            |ui| match hovered_pixel {
+                Some(pixel) => {
+                    ui.monospace(Self::tile_text(snapshot, pixel));
+                }
+                None => {
+                    ui.label("Hover over the picture to inspect its background tiles");
+                }
+            },
+        );
+    }
+
+    // Recent mapper IRQ line changes, newest first
+    fn draw_irq(ctx: &CtxRef, snapshot: &DebugSnapshot, panels: &mut PanelLayout) {
+        panels.show(
+            ctx,
+            IRQ_TITLE,
+            |window| window.resizable(false),
+            |ui| {
+                if snapshot.irq_edges.is_empty() {
+                    ui.label("The mapper hasn't raised an IRQ");
+                    return;
+                }
+                ui.monospace("Frame   Line  Dot  CPU cycle");
+                for edge in snapshot.irq_edges.iter().rev() {
+                    let text = format!(
+                        "{:>5} {:>6} {:>4} {:>10}  {}",
+                        edge.frame,
+                        edge.scanline,
+                        edge.dot,
+                        edge.cpu_cycle,
+                        if edge.asserted { "raised" } else { "cleared" }
+                    );
+                    let mut text = RichText::new(text).monospace();
+                    if edge.asserted {
+                        text = text.color(IRQ_RAISED_COLOR);
+                    }
+                    ui.label(text);
+                }
+            },
+        );
+    }
+
+    // NMIs and IRQs taken in the last frames, one row per frame with time going right
+    fn draw_interrupts(ctx: &CtxRef, snapshot: &DebugSnapshot, panels: &mut PanelLayout) {
+        panels.show(
+            ctx,
+            INTERRUPTS_TITLE,
+            |window| window.resizable(false),
+            |ui| {
+                let Some(last) = snapshot.interrupts.last() else {
+                    ui.label("No interrupts taken yet");
+                    return;
+                };
+                let first_frame = last.frame.saturating_sub(TIMELINE_FRAMES - 1);
+                // PAL frames are longer, the pre-render line counts as -1
+                let lines = snapshot
+                    .interrupts
+                    .iter()
+                    .map(|taken| taken.scanline + 2)
+                    .max()
+                    .unwrap_or_default()
+                    .max(MIN_FRAME_LINES);
+                let frame_dots = lines as f32 * LINE_DOTS;
+
+                let size = Vec2::new(
+                    lines as f32 * 2.0,
+                    TIMELINE_FRAMES as f32 * TIMELINE_ROW_HEIGHT,
+                );
+                let (response, painter) = ui.allocate_painter(size, Sense::hover());
+                let rect = response.rect;
+                painter.rect_filled(rect, 0.0, Color32::from_gray(0x20));
+                for taken in snapshot
+                    .interrupts
+                    .iter()
+                    .filter(|t| t.frame >= first_frame)
+                {
+                    let dots = (taken.scanline + 1) as f32 * LINE_DOTS + taken.dot as f32;
+                    let x = rect.min.x + dots / frame_dots * rect.width();
+                    let y = rect.min.y + (taken.frame - first_frame) as f32 * TIMELINE_ROW_HEIGHT;
+                    let color = match taken.cause {
+                        InterruptCause::Nmi => NMI_COLOR,
+                        InterruptCause::Irq { .. } => IRQ_RAISED_COLOR,
+                    };
+                    let mark = Rect::from_min_size(
+                        Pos2::new(x, y + 1.0),
+                        Vec2::new(1.0, TIMELINE_ROW_HEIGHT - 2.0),
+                    );
+                    painter.rect_filled(mark, 0.0, color);
+                }
+                ui.horizontal(|ui| {
+                    ui.label(RichText::new("NMI").color(NMI_COLOR));
+                    ui.label(RichText::new("IRQ").color(IRQ_RAISED_COLOR));
+                    ui.label(format!(
+                        "frames {first_frame}-{}, newest at the bottom",
+                        last.frame
+                    ));
+                });
+
+                ui.separator();
+                let this_frame = snapshot.interrupts.iter().filter(|t| t.frame == last.frame);
+                let (nmis, irqs) =
+                    this_frame.fold((0, 0), |(nmis, irqs), taken| match taken.cause {
+                        InterruptCause::Nmi => (nmis + 1, irqs),
+                        InterruptCause::Irq { .. } => (nmis, irqs + 1),
+                    });
+                ui.label(format!("Frame {}: {nmis} NMI, {irqs} IRQ", last.frame));
+                ui.monospace("Frame   Line  Dot  CPU cycle");
+                for taken in snapshot.interrupts.iter().rev().take(INTERRUPT_LIST_LEN) {
+                    let text = format!(
+                        "{:>5} {:>6} {:>4} {:>10}  {}",
+                        taken.frame,
+                        taken.scanline,
+                        taken.dot,
+                        taken.cpu_cycle,
+                        taken.cause.describe()
+                    );
+                    ui.monospace(text);
+                }
+            },
+        );
+    }
+
+    // Editable CPU, PPU and APU registers
+    fn draw_registers(
+        ctx: &CtxRef,
+        snapshot: &DebugSnapshot,
+        panels: &mut PanelLayout,
+        writes: &mut Vec<DebugWrite>,
+    ) {
+        panels.show(
+            ctx,
+            REGISTERS_TITLE,
+            |window| window.resizable(false),
+            |ui| {
+                let mut regs = snapshot.regs;
+                ui.horizontal(|ui| {
+                    ui.add(DragValue::new(&mut regs.a).prefix("A "));
+                    ui.add(DragValue::new(&mut regs.x).prefix("X "));
+                    ui.add(DragValue::new(&mut regs.y).prefix("Y "));
+                    ui.add(DragValue::new(&mut regs.sp).prefix("SP "));
+                    ui.add(DragValue::new(&mut regs.pc).prefix("PC "));
+                });
+                ui.horizontal(|ui| {
+                    for (name, bit) in FLAGS {
+                        let mut set = regs.p & (1 << bit) != 0;
+                        if ui.checkbox(&mut set, name).changed() {
+                            regs.p ^= 1 << bit;
+                        }
+                    }
+                });
+                if regs != snapshot.regs {
+                    writes.push(DebugWrite::CpuRegs(regs));
+                }
+
+                ui.separator();
+                let ppu = snapshot.ppu_regs;
+                let (mut ctrl, mut mask) = (ppu.ctrl, ppu.mask);
+                let (mut x, mut y) = (ppu.scroll_x, ppu.scroll_y);
+                ui.horizontal(|ui| {
+                    ui.add(DragValue::new(&mut ctrl).prefix("PPUCTRL "));
+                    ui.add(DragValue::new(&mut mask).prefix("PPUMASK "));
+                    ui.add(DragValue::new(&mut x).prefix("Scroll X "));
+                    ui.add(DragValue::new(&mut y).prefix("Y "));
+                });
+                if ctrl != ppu.ctrl {
+                    writes.push(DebugWrite::PpuCtrl(ctrl));
+                }
+                if mask != ppu.mask {
+                    writes.push(DebugWrite::Memory {
+                        addr: 0x2001,
+                        data: mask,
+                    });
+                }
+                if (x, y) != (ppu.scroll_x, ppu.scroll_y) {
+                    writes.push(DebugWrite::PpuScroll { x, y });
+                }
+
+                ui.separator();
+                // Writes restart envelopes and reload length counters like the game's would
+                egui::Grid::new("apu registers").show(ui, |ui| {
+                    for (channel, name) in Apu::CHANNEL_NAMES.iter().enumerate() {
+                        ui.label(*name);
+                        for reg in 0..4 {
+                            let addr = 0x4000 + (channel * 4 + reg) as u16;
+                            Self::apu_register(ui, snapshot, addr, writes);
+                        }
+                        ui.end_row();
+                    }
+                    ui.label("Status, frame counter");
+                    Self::apu_register(ui, snapshot, 0x4015, writes);
+                    Self::apu_register(ui, snapshot, 0x4017, writes);
+                    ui.end_row();
+                });
+            },
+        );
+    }
+
+    fn apu_register(
+        ui: &mut egui::Ui,
+        snapshot: &DebugSnapshot,
+        addr: u16,
+        writes: &mut Vec<DebugWrite>,
+    ) {
+        let mut data = snapshot.apu_regs[(addr - 0x4000) as usize];
+        let response = ui.add(DragValue::new(&mut data).prefix(format!("${addr:04X} ")));
+        if response.changed() {
+            writes.push(DebugWrite::Memory { addr, data });
+        }
+    }
+
+    // Likely reasons for a blank picture, re-evaluated every frame
+    fn draw_black_screen(ctx: &CtxRef, snapshot: &DebugSnapshot, panels: &mut PanelLayout) {
+        panels.show(
+            ctx,
+            BLACK_SCREEN_TITLE,
+            |window| window.resizable(false),
+            |ui| {
+                let causes = black_screen_causes(snapshot);
+                if causes.is_empty() {
+                    ui.label("No issues found");
+                }
+                for cause in causes {
+                    ui.label(cause);
+                }
+            },
+        );
+    }
+
+    // Stack page as a 16x16 grid with the stack pointer and return addresses highlighted
+    fn draw_stack(ctx: &CtxRef, snapshot: &DebugSnapshot, panels: &mut PanelLayout) {
+        let sp = snapshot.regs.sp;
+        let in_return_addr = |offset: u8| {
+            snapshot
+                .return_addrs
+                .iter()
+                .any(|r| (r.offset..r.offset.saturating_add(r.len)).contains(&offset))
+        };
+
+        panels.show(
+            ctx,
+            STACK_TITLE,
+            |window| window.resizable(false),
+            |ui| {
+                let regs = snapshot.regs;
+                ui.monospace(format!(
+                    "A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{sp:02X} PC:{:04X}",
+                    regs.a, regs.x, regs.y, regs.p, regs.pc
+                ));
+                egui::Grid::new("stack grid")
+                    .spacing([4.0, 2.0])
+                    .show(ui, |ui| {
+                        for row in 0..16u8 {
+                            ui.monospace(format!("$01{row:X}0"));
+                            for col in 0..16u8 {
+                                let offset = row * 16 + col;
+                                let mut text = RichText::new(format!(
+                                    "{:02X}",
+                                    snapshot.stack[offset as usize]
+                                ))
+                                .monospace();
+                                if offset == sp {
+                                    text = text.background_color(SP_COLOR);
+                                } else if offset > sp && in_return_addr(offset) {
+                                    text = text.color(RETURN_COLOR);
+                                } else if offset < sp {
+                                    text = text.weak();
+                                }
+                                ui.label(text);
+                            }
+                            ui.end_row();
+                        }
+                    });
+
+                ui.separator();
+                if snapshot.return_addrs.is_empty() {
+                    ui.label("No return addresses found above SP");
+                }
+                for ret in &snapshot.return_addrs {
+                    let kind = match ret.kind {
+                        ReturnKind::Subroutine => "JSR",
+                        ReturnKind::Interrupt => "Interrupt",
+                    };
+                    ui.monospace(format!(
+                        "$01{:02X}: {kind} return to ${:04X}",
+                        ret.offset, ret.target
+                    ));
+                }
+            },
+        );
+    }
+}
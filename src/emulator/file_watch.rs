@@ -0,0 +1,46 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+// Checking the file system every frame would be wasteful
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Notices when a file is modified by polling its modification time
+pub struct FileWatch {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    last_poll: Instant,
+}
+
+impl FileWatch {
+    pub fn new(path: &str) -> Self {
+        let path = PathBuf::from(path);
+        Self {
+            modified: Self::modified_time(&path),
+            path,
+            last_poll: Instant::now(),
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        self.path.to_str().unwrap_or_default()
+    }
+
+    /// True once after each change, polled at most once a second
+    pub fn changed(&mut self) -> bool {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return false;
+        }
+        self.last_poll = Instant::now();
+        let modified = Self::modified_time(&self.path);
+        if modified == self.modified {
+            return false;
+        }
+        self.modified = modified;
+        // A file being rewritten may briefly vanish, wait until it is back
+        modified.is_some()
+    }
+
+    fn modified_time(path: &Path) -> Option<SystemTime> {
+        std::fs::metadata(path).and_then(|m| m.modified()).ok()
+    }
+}
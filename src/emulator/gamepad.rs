@@ -0,0 +1,254 @@
+use std::time::{Duration, Instant};
+
+use egui_sdl2_gl::egui::{self, CtxRef, Frame};
+use sdl2::controller::{Button as PadButton, GameController};
+use sdl2::GameControllerSubsystem;
+
+/// SDL mappings for NES style USB pads that SDL doesn't know out of the box. The pad's
+/// right face button is mapped to `b` and its left one to `a`, like on other pads.
+/// More can be added with `controller_mapping=` lines in the settings file.
+const BUILTIN_MAPPINGS: [&str; 6] = [
+    "03000000830500006020000010010000,Buffalo Classic USB Gamepad,a:b1,b:b0,back:b6,start:b7,dpup:-a1,dpdown:+a1,dpleft:-a0,dpright:+a0,platform:Linux,",
+    "03000000830500006020000000000000,Buffalo Classic USB Gamepad,a:b1,b:b0,back:b6,start:b7,dpup:-a1,dpdown:+a1,dpleft:-a0,dpright:+a0,platform:Windows,",
+    "03000000c82d00002038000000010000,8BitDo NES30,a:b1,b:b0,back:b10,start:b11,dpup:-a1,dpdown:+a1,dpleft:-a0,dpright:+a0,platform:Linux,",
+    "03000000c82d00002038000000000000,8BitDo NES30,a:b1,b:b0,back:b10,start:b11,dpup:-a1,dpdown:+a1,dpleft:-a0,dpright:+a0,platform:Windows,",
+    "03000000c82d00000190000011010000,8BitDo NES30 Pro,a:b1,b:b0,back:b10,start:b11,dpup:h0.1,dpdown:h0.4,dpleft:h0.8,dpright:h0.2,platform:Linux,",
+    "03000000c82d00000190000000000000,8BitDo NES30 Pro,a:b1,b:b0,back:b10,start:b11,dpup:h0.1,dpdown:h0.4,dpleft:h0.8,dpright:h0.2,platform:Windows,",
+];
+
+// How long a controller being connected or disconnected is shown
+const NOTICE_TIME: Duration = Duration::from_secs(4);
+
+/// Console controller port a game controller plays on
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Port {
+    One,
+    /// Connected but its buttons are ignored
+    Unused,
+}
+
+impl Port {
+    // Only controller 1 is emulated
+    const ALL: [Self; 2] = [Self::One, Self::Unused];
+
+    const fn name(self) -> &'static str {
+        match self {
+            Self::One => "Controller 1",
+            Self::Unused => "Not used",
+        }
+    }
+}
+
+struct Pad {
+    /// SDL instance id, changes each time the pad connects
+    id: u32,
+    guid: String,
+    name: String,
+    port: Port,
+    /// Buttons held down, released if the pad goes away mid-press
+    held: Vec<PadButton>,
+}
+
+/// Connected game controllers and the port each plays on. A pad that disconnects
+/// and comes back, e.g. a wireless one waking up, gets its old port back.
+pub struct Gamepads {
+    subsystem: Option<GameControllerSubsystem>,
+    open: Vec<GameController>,
+    pads: Vec<Pad>,
+    /// GUIDs and ports of disconnected pads, oldest first
+    gone: Vec<(String, Port)>,
+    notice: Option<(String, Instant)>,
+    pub window_open: bool,
+}
+
+impl Gamepads {
+    /// Registers the built-in mappings and then `extra_mappings`, which replace
+    /// built-in ones for the same GUID
+    pub fn new(subsystem: Option<GameControllerSubsystem>, extra_mappings: &[String]) -> Self {
+        if let Some(subsystem) = &subsystem {
+            let extra = extra_mappings.iter().map(String::as_str);
+            for mapping in BUILTIN_MAPPINGS.into_iter().chain(extra) {
+                if let Err(e) = subsystem.add_mapping(mapping) {
+                    println!("Ignoring controller mapping {mapping}: {e}");
+                }
+            }
+        }
+        Self {
+            subsystem,
+            open: Vec::new(),
+            pads: Vec::new(),
+            gone: Vec::new(),
+            notice: None,
+            window_open: false,
+        }
+    }
+
+    /// Opens a newly connected controller, `index` is its joystick index
+    pub fn added(&mut self, index: u32) {
+        let Some(subsystem) = &self.subsystem else {
+            return;
+        };
+        match subsystem.open(index) {
+            Ok(controller) => {
+                let id = controller.instance_id();
+                if !self.open.iter().any(|c| c.instance_id() == id) {
+                    // Mappings start with the GUID
+                    let mapping = controller.mapping();
+                    let guid = mapping.split(',').next().unwrap_or_default();
+                    self.connect(id, guid, &controller.name());
+                    self.open.push(controller);
+                }
+            }
+            Err(e) => println!("Failed to open controller: {e}"),
+        }
+    }
+
+    /// Closes a disconnected controller, `id` is its instance id. Returns the
+    /// buttons it was holding on its port, which need releasing.
+    pub fn removed(&mut self, id: u32) -> Vec<PadButton> {
+        self.open.retain(|c| c.instance_id() != id);
+        self.disconnect(id)
+    }
+
+    fn connect(&mut self, id: u32, guid: &str, name: &str) {
+        let port = if let Some(idx) = self.gone.iter().position(|(gone, _)| gone == guid) {
+            let (_, port) = self.gone.remove(idx);
+            self.notify(format!("{name} reconnected on {}", port.name()));
+            port
+        } else {
+            self.notify(format!("{name} connected on {}", Port::One.name()));
+            Port::One
+        };
+        self.pads.push(Pad {
+            id,
+            guid: guid.to_owned(),
+            name: name.to_owned(),
+            port,
+            held: Vec::new(),
+        });
+    }
+
+    fn disconnect(&mut self, id: u32) -> Vec<PadButton> {
+        let Some(idx) = self.pads.iter().position(|pad| pad.id == id) else {
+            return Vec::new();
+        };
+        let pad = self.pads.remove(idx);
+        self.notify(format!("{} disconnected", pad.name));
+        self.gone.push((pad.guid, pad.port));
+        pad.held
+    }
+
+    /// Tracks a button of controller `id`, returns true if it goes to the console
+    pub fn button(&mut self, id: u32, button: PadButton, pressed: bool) -> bool {
+        let Some(pad) = self.pads.iter_mut().find(|pad| pad.id == id) else {
+            return false;
+        };
+        pad.held.retain(|&held| held != button);
+        if pad.port == Port::Unused {
+            return false;
+        }
+        if pressed {
+            pad.held.push(button);
+        }
+        true
+    }
+
+    fn notify(&mut self, text: String) {
+        println!("{text}");
+        self.notice = Some((text, Instant::now()));
+    }
+
+    /// Draws the last connection change and the window for choosing ports. Returns
+    /// buttons of pads taken off a port while holding them, which need releasing.
+    pub fn draw(&mut self, ctx: &CtxRef) -> Vec<PadButton> {
+        if let Some((text, since)) = self.notice.as_ref() {
+            if since.elapsed() < NOTICE_TIME {
+                egui::Area::new("gamepad notice")
+                    .anchor(egui::Align2::CENTER_TOP, [0.0, 32.0])
+                    .show(ctx, |ui| {
+                        Frame::popup(ui.style()).show(ui, |ui| {
+                            ui.horizontal(|ui| {
+                                ui.label(text.as_str());
+                                if ui.button("Change").clicked() {
+                                    self.window_open = true;
+                                }
+                            });
+                        });
+                    });
+            }
+        }
+
+        let mut released = Vec::new();
+        egui::Window::new("Gamepads")
+            .open(&mut self.window_open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                if self.pads.is_empty() {
+                    ui.label("No game controllers connected");
+                }
+                egui::Grid::new("gamepad ports").show(ui, |ui| {
+                    for pad in &mut self.pads {
+                        ui.label(pad.name.as_str());
+                        for port in Port::ALL {
+                            if ui.radio_value(&mut pad.port, port, port.name()).changed()
+                                && port == Port::Unused
+                            {
+                                released.append(&mut pad.held);
+                            }
+                        }
+                        ui.end_row();
+                    }
+                });
+            });
+        released
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_reconnected_pad_keeps_port() {
+        let mut pads = Gamepads::new(None, &[]);
+        pads.connect(0, "guid-a", "Pad A");
+        pads.connect(1, "guid-b", "Pad B");
+        pads.pads[1].port = Port::Unused;
+        assert!(pads.button(0, PadButton::A, true));
+        assert!(!pads.button(1, PadButton::A, true));
+        assert!(!pads.button(7, PadButton::A, true));
+
+        // Going to sleep mid-press releases the button
+        assert_eq!(pads.disconnect(0), [PadButton::A]);
+        assert_eq!(pads.disconnect(1), []);
+        assert!(!pads.button(0, PadButton::A, false));
+
+        // Waking up gives a new instance id but the same GUID
+        pads.connect(2, "guid-b", "Pad B");
+        pads.connect(3, "guid-a", "Pad A");
+        assert!(!pads.button(2, PadButton::B, true));
+        assert!(pads.button(3, PadButton::B, true));
+        assert!(pads.gone.is_empty());
+        assert_eq!(pads.notice.unwrap().0, "Pad A reconnected on Controller 1");
+    }
+
+    #[test]
+    fn test_builtin_mappings_are_complete() {
+        for mapping in BUILTIN_MAPPINGS {
+            let mut fields = mapping.split(',');
+            let guid = fields.next().unwrap_or_default();
+            assert_eq!(guid.len(), 32, "{mapping}");
+            assert!(guid.chars().all(|c| c.is_ascii_hexdigit()), "{mapping}");
+            let keys: Vec<_> = fields
+                .filter_map(|f| f.split_once(':'))
+                .map(|(k, _)| k)
+                .collect();
+            for key in [
+                "a", "b", "back", "start", "dpup", "dpdown", "dpleft", "dpright",
+            ] {
+                assert!(keys.contains(&key), "{key} missing from {mapping}");
+            }
+        }
+    }
+}
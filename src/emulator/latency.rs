@@ -0,0 +1,115 @@
+/// Measures how long it takes from a host key press until the game reads it.
+/// The press counts as observed once the game latches the controller after it.
+#[derive(Default)]
+pub struct LatencyMeter {
+    pending: Option<Press>,
+    last: Option<Sample>,
+    total_frames: u64,
+    total_ms: u64,
+    count: u64,
+    flash: bool,
+}
+
+struct Press {
+    event_ms: u32,
+    latch_count: usize,
+    frame: u64,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Sample {
+    pub frames: u64,
+    pub ms: u32,
+}
+
+impl LatencyMeter {
+    /// Records a button press, unless an earlier one is still waiting to be observed
+    pub fn button_pressed(&mut self, event_ms: u32, latch_count: usize, frame: u64) {
+        if self.pending.is_none() {
+            self.pending = Some(Press {
+                event_ms,
+                latch_count,
+                frame,
+            });
+        }
+    }
+
+    /// Called after each emulated frame, completes the measurement if the game
+    /// latched the controller since the press
+    pub fn frame_done(&mut self, latch_count: usize, frame: u64, now_ms: u32) {
+        self.flash = false;
+        let Some(press) = self.pending.as_ref() else {
+            return;
+        };
+        if latch_count == press.latch_count {
+            return;
+        }
+
+        let sample = Sample {
+            frames: frame - press.frame,
+            ms: now_ms.saturating_sub(press.event_ms),
+        };
+        self.total_frames += sample.frames;
+        self.total_ms += sample.ms as u64;
+        self.count += 1;
+        self.last = Some(sample);
+        self.pending = None;
+        self.flash = true;
+    }
+
+    /// True for the frame in which the last press was observed
+    pub const fn flash(&self) -> bool {
+        self.flash
+    }
+
+    pub fn summary(&self) -> String {
+        match self.last {
+            None => "Press a button to measure".to_owned(),
+            Some(last) => format!(
+                "Last: {} frames, {} ms\nAverage over {}: {:.1} frames, {:.1} ms",
+                last.frames,
+                last.ms,
+                self.count,
+                self.total_frames as f64 / self.count as f64,
+                self.total_ms as f64 / self.count as f64
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_press_observed_at_next_latch() {
+        let mut meter = LatencyMeter::default();
+        meter.button_pressed(1000, 5, 10);
+        // A second press before the first is seen doesn't restart the measurement
+        meter.button_pressed(1010, 5, 10);
+
+        meter.frame_done(5, 11, 1017);
+        assert!(!meter.flash());
+        assert_eq!(meter.last, None);
+
+        meter.frame_done(6, 12, 1034);
+        assert!(meter.flash());
+        assert_eq!(meter.last, Some(Sample { frames: 2, ms: 34 }));
+
+        meter.frame_done(7, 13, 1050);
+        assert!(!meter.flash());
+    }
+
+    #[test]
+    fn test_summary_averages() {
+        let mut meter = LatencyMeter::default();
+        meter.button_pressed(0, 0, 0);
+        meter.frame_done(1, 1, 20);
+        meter.button_pressed(100, 1, 5);
+        meter.frame_done(2, 8, 140);
+        assert_eq!(
+            meter.summary(),
+            "Last: 3 frames, 40 ms\nAverage over 2: 2.0 frames, 30.0 ms"
+        );
+    }
+}
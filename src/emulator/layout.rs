@@ -0,0 +1,139 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use egui_sdl2_gl::egui::{self, CtxRef, Pos2, Rect, Vec2};
+
+// Settings file keys of panels start with this, followed by the window title
+const KEY_PREFIX: &str = "panel.";
+
+/// Whether a panel is open and where it was last drawn
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+struct PanelState {
+    open: bool,
+    /// Window position and content size
+    rect: Option<Rect>,
+}
+
+impl PanelState {
+    // "open" or "closed", optionally followed by ",x,y,width,height"
+    fn parse(value: &str) -> Option<Self> {
+        let mut fields = value.split(',').map(str::trim);
+        let open = match fields.next()? {
+            "open" => true,
+            "closed" => false,
+            _ => return None,
+        };
+        let numbers: Vec<f32> = fields.map(str::parse).collect::<Result<_, _>>().ok()?;
+        let rect = match numbers[..] {
+            [] => None,
+            [x, y, w, h] => Some(Rect::from_min_size(Pos2::new(x, y), Vec2::new(w, h))),
+            _ => return None,
+        };
+        Some(Self { open, rect })
+    }
+}
+
+/// Open state, position and size of the debug windows, kept between runs
+#[derive(Clone, Default, Debug, PartialEq)]
+pub struct PanelLayout {
+    panels: BTreeMap<String, PanelState>,
+}
+
+impl PanelLayout {
+    /// Reads a `panel.<title>` settings key, returns false if it isn't one
+    pub fn parse_line(&mut self, key: &str, value: &str) -> bool {
+        let Some(title) = key.strip_prefix(KEY_PREFIX) else {
+            return false;
+        };
+        if let Some(state) = PanelState::parse(value) {
+            self.panels.insert(title.to_owned(), state);
+        }
+        true
+    }
+
+    /// Settings lines for every panel that has been opened
+    pub fn lines(&self) -> String {
+        let mut out = String::new();
+        for (title, state) in &self.panels {
+            let open = if state.open { "open" } else { "closed" };
+            let _ = write!(out, "{KEY_PREFIX}{title}={open}");
+            if let Some(rect) = state.rect {
+                let _ = write!(
+                    out,
+                    ",{},{},{},{}",
+                    rect.min.x,
+                    rect.min.y,
+                    rect.width(),
+                    rect.height()
+                );
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    pub fn is_open(&self, title: &str) -> bool {
+        self.panels.get(title).is_some_and(|state| state.open)
+    }
+
+    /// Menu checkbox that opens and closes a panel
+    pub fn checkbox(&mut self, ui: &mut egui::Ui, title: &str) {
+        let state = self.panels.entry(title.to_owned()).or_default();
+        ui.checkbox(&mut state.open, title);
+    }
+
+    /// Shows the panel if it's open, where it was last time. `configure` can set
+    /// other window options.
+    pub fn show(
+        &mut self,
+        ctx: &CtxRef,
+        title: &str,
+        configure: impl FnOnce(egui::Window) -> egui::Window,
+        add_contents: impl FnOnce(&mut egui::Ui),
+    ) {
+        let Some(state) = self.panels.get_mut(title).filter(|state| state.open) else {
+            return;
+        };
+        let mut window = egui::Window::new(title);
+        if let Some(rect) = state.rect {
+            window = window.default_pos(rect.min).default_size(rect.size());
+        }
+        let mut content_size = None;
+        let response = configure(window).open(&mut state.open).show(ctx, |ui| {
+            add_contents(ui);
+            content_size = Some(ui.max_rect().size());
+        });
+        if let (Some(response), Some(size)) = (response, content_size) {
+            state.rect = Some(Rect::from_min_size(response.response.rect.min, size));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_layout_round_trip() {
+        let mut layout = PanelLayout::default();
+        assert!(layout.parse_line("panel.Stack", "open,10,20.5,300,400"));
+        assert!(layout.parse_line("panel.Channel scopes", "closed"));
+        assert!(layout.parse_line("panel.Bogus", "open,1,2"));
+        assert!(!layout.parse_line("width", "512"));
+        assert!(layout.is_open("Stack"));
+        assert!(!layout.is_open("Channel scopes"));
+        assert!(!layout.is_open("Bogus"));
+
+        let text = layout.lines();
+        assert_eq!(
+            text,
+            "panel.Channel scopes=closed\npanel.Stack=open,10,20.5,300,400\n"
+        );
+        let mut parsed = PanelLayout::default();
+        for line in text.lines() {
+            let (key, value) = line.split_once('=').unwrap_or_default();
+            parsed.parse_line(key, value);
+        }
+        assert_eq!(parsed, layout);
+    }
+}
@@ -0,0 +1,166 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use eyre::Result;
+
+use super::autosave::write_atomic;
+use crate::console::{events::ConsoleEvent, time::EmulatedTime};
+
+/// What has been done with one game over all sessions
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct GameStats {
+    /// Emulated time, so fast forward counts for more and pausing for nothing
+    pub play_time: Duration,
+    pub resets: u64,
+    pub states_loaded: u64,
+}
+
+/// Per game statistics keyed by the ROM's CRC32, kept in a text file with one
+/// `<crc32> <play time ms> <resets> <states loaded>` line per game
+pub struct PlayStats {
+    path: PathBuf,
+    games: HashMap<u32, GameStats>,
+    /// Game being played
+    current: Option<u32>,
+    /// Console time when the last frame completed
+    last_time: Option<EmulatedTime>,
+}
+
+impl PlayStats {
+    /// Starts from scratch if the file is missing or unreadable
+    pub fn load(file: &Path) -> Self {
+        let games = match std::fs::read_to_string(file) {
+            Ok(text) => Self::parse(&text),
+            Err(_) => HashMap::new(),
+        };
+        Self {
+            path: file.to_owned(),
+            games,
+            current: None,
+            last_time: None,
+        }
+    }
+
+    // Malformed lines are skipped
+    fn parse(text: &str) -> HashMap<u32, GameStats> {
+        text.lines()
+            .filter_map(|line| {
+                let mut fields = line.split_whitespace();
+                let crc32 = u32::from_str_radix(fields.next()?, 16).ok()?;
+                let mut next = || fields.next()?.parse::<u64>().ok();
+                let stats = GameStats {
+                    play_time: Duration::from_millis(next()?),
+                    resets: next()?,
+                    states_loaded: next()?,
+                };
+                Some((crc32, stats))
+            })
+            .collect()
+    }
+
+    fn lines(&self) -> String {
+        let mut crcs: Vec<_> = self.games.keys().collect();
+        crcs.sort();
+        crcs.into_iter().fold(String::new(), |mut text, crc32| {
+            let stats = self.games[crc32];
+            let _ = writeln!(
+                text,
+                "{crc32:08X} {} {} {}",
+                stats.play_time.as_millis(),
+                stats.resets,
+                stats.states_loaded
+            );
+            text
+        })
+    }
+
+    pub fn save(&self) -> Result<()> {
+        write_atomic(&self.path, self.lines().as_bytes())
+    }
+
+    /// Counts what happens from now on towards the game with this ROM CRC32
+    pub fn select(&mut self, crc32: u32) {
+        self.current = Some(crc32);
+        self.last_time = None;
+    }
+
+    pub fn current(&self) -> Option<GameStats> {
+        self.current
+            .map(|crc32| self.games.get(&crc32).copied().unwrap_or_default())
+    }
+
+    pub fn console_event(&mut self, event: ConsoleEvent) {
+        let Some(crc32) = self.current else {
+            return;
+        };
+        let stats = self.games.entry(crc32).or_default();
+        match event {
+            ConsoleEvent::FrameCompleted(time) => {
+                if let Some(last) = self.last_time {
+                    let cycles = time.cpu_cycles.saturating_sub(last.cpu_cycles);
+                    stats.play_time +=
+                        Duration::from_nanos(cycles * 1_000_000_000 / rnes::CPU_FREQ as u64);
+                }
+                self.last_time = Some(time);
+            }
+            ConsoleEvent::Reset => stats.resets += 1,
+            ConsoleEvent::StateLoaded => stats.states_loaded += 1,
+            ConsoleEvent::VblankStarted
+            | ConsoleEvent::RomSwapped
+            | ConsoleEvent::ChrRomWrite(_)
+            | ConsoleEvent::MapperFallback(_) => (),
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod test {
+    use super::*;
+
+    fn frame_at(seconds: u64) -> ConsoleEvent {
+        ConsoleEvent::FrameCompleted(EmulatedTime {
+            frames: 0,
+            cpu_cycles: seconds * rnes::CPU_FREQ as u64,
+        })
+    }
+
+    #[test]
+    fn test_events_and_round_trip() {
+        let path = std::env::temp_dir().join("rnes_play_stats_test.cfg");
+        let _ = std::fs::remove_file(&path);
+        let mut stats = PlayStats::load(&path);
+        stats.console_event(ConsoleEvent::Reset);
+        assert_eq!(stats.current(), None);
+
+        stats.select(0x1234_ABCD);
+        for seconds in [10, 12, 15] {
+            stats.console_event(frame_at(seconds));
+        }
+        stats.console_event(ConsoleEvent::Reset);
+        stats.console_event(ConsoleEvent::StateLoaded);
+        let expected = GameStats {
+            play_time: Duration::from_secs(5),
+            resets: 1,
+            states_loaded: 1,
+        };
+        assert_eq!(stats.current(), Some(expected));
+
+        stats.save().unwrap();
+        let mut reloaded = PlayStats::load(&path);
+        reloaded.select(0x1234_ABCD);
+        assert_eq!(reloaded.current(), Some(expected));
+        reloaded.select(0x5555_5555);
+        assert_eq!(reloaded.current(), Some(GameStats::default()));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_parse_skips_bad_lines() {
+        let games = PlayStats::parse("0000000A 1500 2 3\nnot a line\n0000000B 1\n");
+        assert_eq!(games.len(), 1);
+        assert_eq!(games[&0xA].play_time, Duration::from_millis(1500));
+    }
+}
@@ -0,0 +1,15 @@
+use super::GameInfo;
+
+/// Receives updates about the game currently running, e.g. for Discord-style rich presence
+pub trait PresenceHook {
+    fn game_changed(&mut self, game: &GameInfo);
+}
+
+/// Default hook that just writes presence updates to the log
+pub struct LogPresence;
+
+impl PresenceHook for LogPresence {
+    fn game_changed(&mut self, game: &GameInfo) {
+        log::info!("Now playing {} ({})", game.name, game.region);
+    }
+}
@@ -0,0 +1,207 @@
+use std::path::Path;
+
+use egui_sdl2_gl::egui::{self, CtxRef};
+
+use super::layout::PanelLayout;
+use super::symbols::Symbols;
+use crate::console::debug::DebugSnapshot;
+
+pub const RAM_WATCH_TITLE: &str = "RAM watch";
+
+/// How a watched value is shown
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WatchFormat {
+    U8,
+    /// Little endian, the byte at the address and the one after it
+    U16,
+    Signed,
+    Bcd,
+    Binary,
+}
+
+impl WatchFormat {
+    const ALL: [Self; 5] = [Self::U8, Self::U16, Self::Signed, Self::Bcd, Self::Binary];
+
+    const fn name(self) -> &'static str {
+        match self {
+            Self::U8 => "u8",
+            Self::U16 => "u16",
+            Self::Signed => "signed",
+            Self::Bcd => "BCD",
+            Self::Binary => "binary",
+        }
+    }
+
+    /// Formats the value at `addr`, `None` if it can't be read
+    fn format(self, addr: u16, mut peek: impl FnMut(u16) -> Option<u8>) -> Option<String> {
+        let value = peek(addr)?;
+        let text = match self {
+            Self::U8 => format!("{value} (${value:02X})"),
+            Self::U16 => {
+                let value = u16::from_le_bytes([value, peek(addr.wrapping_add(1))?]);
+                format!("{value} (${value:04X})")
+            }
+            Self::Signed => (value as i8).to_string(),
+            // Nibbles above 9 aren't valid BCD, they show up as hex digits
+            Self::Bcd => format!("{value:02X}"),
+            Self::Binary => format!("%{value:08b}"),
+        };
+        Some(text)
+    }
+}
+
+struct Watch {
+    addr: u16,
+    format: WatchFormat,
+}
+
+/// Addresses to show every frame, with labels from symbol files
+pub struct RamWatch {
+    watches: Vec<Watch>,
+    symbols: Symbols,
+    /// Address or label being typed in
+    new_addr: String,
+    new_format: WatchFormat,
+    symbol_file: String,
+    /// Result of the last symbol load or failed add
+    message: String,
+}
+
+impl Default for RamWatch {
+    fn default() -> Self {
+        Self {
+            watches: Vec::new(),
+            symbols: Symbols::default(),
+            new_addr: String::new(),
+            new_format: WatchFormat::U8,
+            symbol_file: String::new(),
+            message: String::new(),
+        }
+    }
+}
+
+impl RamWatch {
+    /// Loads the symbol files found next to a ROM, replacing the previous symbols
+    pub fn load_symbols_for_rom(&mut self, rom: &Path) {
+        self.symbols = Symbols::default();
+        self.message.clear();
+        for file in Symbols::files_for_rom(rom) {
+            self.load_symbols(&file);
+        }
+    }
+
+    fn load_symbols(&mut self, file: &Path) {
+        match Symbols::load(file) {
+            Ok(symbols) => {
+                self.message = format!("{} symbols from {}", symbols.len(), file.display());
+                self.symbols.merge(symbols);
+            }
+            Err(e) => self.message = e.to_string(),
+        }
+    }
+
+    /// Takes a hex address, with or without `$`, or a symbol name
+    fn parse_addr(&self, text: &str) -> Option<u16> {
+        let text = text.trim();
+        self.symbols
+            .find(text)
+            .or_else(|| u16::from_str_radix(text.trim_start_matches('$'), 16).ok())
+    }
+
+    pub fn draw(&mut self, ctx: &CtxRef, snapshot: &DebugSnapshot, panels: &mut PanelLayout) {
+        panels.show(
+            ctx,
+            RAM_WATCH_TITLE,
+            |window| window.resizable(false),
+            |ui| {
+                let mut removed = None;
+                egui::Grid::new("ram watch grid").show(ui, |ui| {
+                    for (idx, watch) in self.watches.iter_mut().enumerate() {
+                        ui.monospace(format!("${:04X}", watch.addr));
+                        ui.label(self.symbols.label(watch.addr).unwrap_or_default());
+                        let value = watch.format.format(watch.addr, |addr| snapshot.peek(addr));
+                        ui.monospace(value.as_deref().unwrap_or("--"));
+                        Self::format_combo(ui, ("watch format", idx), &mut watch.format);
+                        if ui.small_button("x").clicked() {
+                            removed = Some(idx);
+                        }
+                        ui.end_row();
+                    }
+                });
+                if let Some(idx) = removed {
+                    self.watches.remove(idx);
+                }
+
+                ui.separator();
+                ui.horizontal(|ui| {
+                    ui.add(
+                        egui::TextEdit::singleline(&mut self.new_addr)
+                            .hint_text("$0300 or label")
+                            .desired_width(100.0),
+                    );
+                    Self::format_combo(ui, "new watch format", &mut self.new_format);
+                    if ui.button("Add").clicked() {
+                        match self.parse_addr(&self.new_addr) {
+                            Some(addr) => {
+                                self.watches.push(Watch {
+                                    addr,
+                                    format: self.new_format,
+                                });
+                                self.new_addr.clear();
+                            }
+                            None => self.message = format!("Unknown address {}", self.new_addr),
+                        }
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.add(
+                        egui::TextEdit::singleline(&mut self.symbol_file)
+                            .hint_text(".nl or .dbg file")
+                            .desired_width(160.0),
+                    );
+                    if ui.button("Load symbols").clicked() {
+                        let file = self.symbol_file.clone();
+                        self.load_symbols(Path::new(&file));
+                    }
+                });
+                if !self.message.is_empty() {
+                    ui.label(&self.message);
+                }
+            },
+        );
+    }
+
+    fn format_combo(ui: &mut egui::Ui, id: impl std::hash::Hash, format: &mut WatchFormat) {
+        egui::ComboBox::from_id_source(id)
+            .selected_text(format.name())
+            .width(70.0)
+            .show_ui(ui, |ui| {
+                for option in WatchFormat::ALL {
+                    ui.selectable_value(format, option, option.name());
+                }
+            });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_formats() {
+        let memory = [0x42, 0x93, 0x01];
+        let peek = |addr: u16| memory.get(addr as usize).copied();
+        assert_eq!(WatchFormat::U8.format(0, peek).as_deref(), Some("66 ($42)"));
+        assert_eq!(
+            WatchFormat::U16.format(1, peek).as_deref(),
+            Some("403 ($0193)")
+        );
+        assert_eq!(WatchFormat::U16.format(2, peek), None);
+        assert_eq!(WatchFormat::Signed.format(1, peek).as_deref(), Some("-109"));
+        assert_eq!(WatchFormat::Bcd.format(1, peek).as_deref(), Some("93"));
+        assert_eq!(
+            WatchFormat::Binary.format(0, peek).as_deref(),
+            Some("%01000010")
+        );
+    }
+}
@@ -0,0 +1,181 @@
+// Report of a problem with the running game, with what's needed to reproduce it and
+// nothing identifying the user: home directories are replaced with ~ and files are
+// named without their directory.
+
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::path::Path;
+
+use crate::console::Region;
+use crate::romdb::RomInfo;
+
+const NEW_ISSUE_URL: &str = "https://github.com/kolmone/rnes/issues/new";
+// Longer links get cut off by browsers or refused by GitHub
+const MAX_URL_LEN: usize = 8000;
+// Log lines kept for reports
+const LOG_LINES: usize = 50;
+
+/// Messages the emulator printed lately
+#[derive(Default)]
+pub struct RecentLog {
+    lines: VecDeque<String>,
+}
+
+impl RecentLog {
+    /// Prints a line and keeps it for reports
+    pub fn push(&mut self, line: String) {
+        println!("{line}");
+        if self.lines.len() == LOG_LINES {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(line);
+    }
+}
+
+pub struct Report<'a> {
+    pub game: &'a str,
+    pub region: Region,
+    pub rom: Option<&'a RomInfo>,
+    /// Contents of the settings file
+    pub settings: String,
+    pub log: &'a RecentLog,
+    /// Screenshot saved with the report, to attach to the issue by hand
+    pub screenshot: Option<&'a Path>,
+}
+
+impl Report<'_> {
+    pub fn text(&self) -> String {
+        let home = std::env::var("HOME")
+            .or_else(|_| std::env::var("USERPROFILE"))
+            .ok();
+        let mut text = format!(
+            "**Game:** {}\n**Region:** {}\n**rnes version:** {}\n**OS:** {}\n",
+            self.game,
+            self.region,
+            env!("CARGO_PKG_VERSION"),
+            std::env::consts::OS
+        );
+        if let Some(rom) = self.rom {
+            let header: Vec<String> = rom.header.iter().map(|b| format!("{b:02X}")).collect();
+            let _ = write!(
+                text,
+                "**ROM:** CRC32 {:08X}, SHA-1 {}, {} bytes, {} kB PRG, {} kB CHR\n\
+                 **Database name:** {}\n**Header:** `{}`\n",
+                rom.crc32,
+                rom.sha1,
+                rom.file_len,
+                rom.prg_len / 1024,
+                rom.chr_len / 1024,
+                rom.db_name.as_deref().unwrap_or("not in the database"),
+                header.join(" ")
+            );
+            for warning in &rom.warnings {
+                let _ = writeln!(text, "**ROM warning:** {warning}");
+            }
+        }
+        if let Some(name) = self.screenshot.and_then(Path::file_name) {
+            let _ = writeln!(text, "**Screenshot:** {}", name.to_string_lossy());
+        }
+        text.push_str("\n**What happens:**\n\n\n**What should happen:**\n\n");
+        let _ = write!(
+            text,
+            "\n<details><summary>Settings</summary>\n\n```\n{}```\n</details>\n",
+            self.settings
+        );
+        text.push_str("\n<details><summary>Recent log</summary>\n\n```\n");
+        for line in &self.log.lines {
+            text.push_str(&redact(line, home.as_deref()));
+            text.push('\n');
+        }
+        text.push_str("```\n</details>\n");
+        text
+    }
+
+    /// Link to a new issue filled in with the report, cut short if it doesn't fit
+    pub fn issue_url(&self) -> String {
+        let mut url = format!(
+            "{NEW_ISSUE_URL}?title={}&body=",
+            percent_encode(&format!("Problem with {}", self.game))
+        );
+        let cut = percent_encode("...\n(cut short, see the saved report)");
+        for line in self.text().split_inclusive('\n') {
+            let line = percent_encode(line);
+            if url.len() + line.len() + cut.len() > MAX_URL_LEN {
+                url.push_str(&cut);
+                break;
+            }
+            url.push_str(&line);
+        }
+        url
+    }
+}
+
+fn redact(line: &str, home: Option<&str>) -> String {
+    match home {
+        Some(home) if !home.is_empty() => line.replace(home, "~"),
+        _ => line.to_owned(),
+    }
+}
+
+fn percent_encode(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-_.~".contains(&byte) {
+            out.push(byte as char);
+        } else {
+            let _ = write!(out, "%{byte:02X}");
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn report(log: &RecentLog) -> Report<'_> {
+        Report {
+            game: "Test Game",
+            region: Region::Ntsc,
+            rom: None,
+            settings: "scaler=none\n".to_owned(),
+            log,
+            screenshot: Some(Path::new("/home/user/roms/game.report.ppm")),
+        }
+    }
+
+    #[test]
+    fn test_redaction() {
+        assert_eq!(
+            redact("Saved to /home/user/roms/a.sav", Some("/home/user")),
+            "Saved to ~/roms/a.sav"
+        );
+        assert_eq!(redact("/home/user", None), "/home/user");
+        let text = report(&RecentLog::default()).text();
+        assert!(text.contains("**Screenshot:** game.report.ppm\n"));
+        assert!(!text.contains("/home/user"));
+    }
+
+    #[test]
+    fn test_issue_url() {
+        let mut log = RecentLog::default();
+        for idx in 0..LOG_LINES + 5 {
+            log.push(format!("line {idx}"));
+        }
+        assert_eq!(log.lines.len(), LOG_LINES);
+        assert_eq!(log.lines[0], "line 5");
+        let url = report(&log).issue_url();
+        assert!(url.starts_with(
+            "https://github.com/kolmone/rnes/issues/new?title=Problem%20with%20Test%20Game&body="
+        ));
+        assert!(url.contains("line%2054%0A"));
+
+        let filler = "x".repeat(1000);
+        for _ in 0..LOG_LINES {
+            log.push(filler.clone());
+        }
+        let url = report(&log).issue_url();
+        assert!(url.len() <= MAX_URL_LEN);
+        assert!(url.ends_with("see%20the%20saved%20report%29"));
+    }
+}
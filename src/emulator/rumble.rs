@@ -0,0 +1,43 @@
+use sdl2::controller::GameController;
+use sdl2::GameControllerSubsystem;
+
+/// Force feedback on the first connected game controller.
+/// Entry point for peripherals and scripts that want to give haptic feedback.
+pub struct Rumble {
+    subsystem: Option<GameControllerSubsystem>,
+    controller: Option<GameController>,
+}
+
+impl Rumble {
+    pub fn new(subsystem: Option<GameControllerSubsystem>) -> Self {
+        Self {
+            subsystem,
+            controller: None,
+        }
+    }
+
+    /// Rumbles both motors at `strength` (0.0 - 1.0) for `duration_ms`.
+    /// Does nothing if no controller with rumble support is connected.
+    pub fn rumble(&mut self, strength: f32, duration_ms: u32) {
+        let level = (strength.clamp(0.0, 1.0) * f32::from(u16::MAX)) as u16;
+        let Some(controller) = self.controller() else {
+            return;
+        };
+        if controller.set_rumble(level, level, duration_ms).is_err() {
+            // Unplugged or no rumble motors, look for another controller next time
+            self.controller = None;
+        }
+    }
+
+    // Opens the first game controller if none is open yet
+    fn controller(&mut self) -> Option<&mut GameController> {
+        if self.controller.is_none() {
+            let subsystem = self.subsystem.as_ref()?;
+            let count = subsystem.num_joysticks().unwrap_or(0);
+            self.controller = (0..count)
+                .filter(|&i| subsystem.is_game_controller(i))
+                .find_map(|i| subsystem.open(i).ok());
+        }
+        self.controller.as_mut()
+    }
+}
@@ -0,0 +1,192 @@
+mod hq2x;
+mod xbrz;
+
+use hq2x::Hq2x;
+use xbrz::Xbrz;
+
+/// RGBA picture passed between scaler stages
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub rgba: Vec<u8>,
+}
+
+impl Image {
+    fn pixel(&self, x: usize, y: usize) -> [u8; 4] {
+        let idx = (y * self.width + x) * 4;
+        [
+            self.rgba[idx],
+            self.rgba[idx + 1],
+            self.rgba[idx + 2],
+            self.rgba[idx + 3],
+        ]
+    }
+
+    /// Pixel at an offset from (x, y), edges repeat outwards
+    fn neighbor(&self, x: usize, y: usize, dx: isize, dy: isize) -> [u8; 4] {
+        let x = x.saturating_add_signed(dx).min(self.width - 1);
+        let y = y.saturating_add_signed(dy).min(self.height - 1);
+        self.pixel(x, y)
+    }
+}
+
+/// A CPU-side stage between the emulated frame and the texture upload
+pub trait Scaler {
+    /// Output is this many times wider and taller than the input
+    fn factor(&self) -> usize;
+
+    /// Writes `input` scaled by `factor` to `output`, which has the right size
+    fn scale(&mut self, input: &Image, output: &mut Image);
+}
+
+/// Scalers run one after another, each on the output of the previous one
+#[derive(Default)]
+pub struct FilterChain {
+    stages: Vec<Box<dyn Scaler>>,
+}
+
+impl FilterChain {
+    pub fn new(stages: Vec<Box<dyn Scaler>>) -> Self {
+        Self { stages }
+    }
+
+    pub fn apply(&mut self, image: Image) -> Image {
+        self.stages.iter_mut().fold(image, |input, stage| {
+            let factor = stage.factor();
+            let (width, height) = (input.width * factor, input.height * factor);
+            let mut output = Image {
+                width,
+                height,
+                rgba: vec![0; width * height * 4],
+            };
+            stage.scale(&input, &mut output);
+            output
+        })
+    }
+}
+
+/// Scalers offered in the menu
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScalerKind {
+    /// The frame goes to the screen as emulated
+    None,
+    Nearest,
+    Hq2x,
+    Xbrz,
+}
+
+impl ScalerKind {
+    pub const ALL: [Self; 4] = [Self::None, Self::Nearest, Self::Hq2x, Self::Xbrz];
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.name() == name)
+    }
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Nearest => "nearest",
+            Self::Hq2x => "hq2x",
+            Self::Xbrz => "xbrz",
+        }
+    }
+
+    pub fn chain(self) -> FilterChain {
+        let stage: Box<dyn Scaler> = match self {
+            Self::None => return FilterChain::default(),
+            Self::Nearest => Box::new(Nearest),
+            Self::Hq2x => Box::new(Hq2x),
+            Self::Xbrz => Box::new(Xbrz::default()),
+        };
+        FilterChain::new(vec![stage])
+    }
+}
+
+/// Doubles every pixel, so a smoothing texture filter keeps sharper edges
+pub struct Nearest;
+
+impl Scaler for Nearest {
+    fn factor(&self) -> usize {
+        2
+    }
+
+    fn scale(&mut self, input: &Image, output: &mut Image) {
+        for (y, row) in output.rgba.chunks_exact_mut(output.width * 4).enumerate() {
+            for (x, pixel) in row.chunks_exact_mut(4).enumerate() {
+                pixel.copy_from_slice(&input.pixel(x / 2, y / 2));
+            }
+        }
+    }
+}
+
+// Mixes colors by integer weights
+fn blend(colors: &[([u8; 4], u32)]) -> [u8; 4] {
+    let total: u32 = colors.iter().map(|&(_, weight)| weight).sum();
+    let mut out = [0; 4];
+    for (channel, value) in out.iter_mut().enumerate() {
+        let sum: u32 = colors
+            .iter()
+            .map(|&(color, weight)| color[channel] as u32 * weight)
+            .sum();
+        *value = ((sum + total / 2) / total) as u8;
+    }
+    out
+}
+
+// Quarter turns clockwise of an offset, with y growing downwards
+const fn rotate(dx: isize, dy: isize, turns: usize) -> (isize, isize) {
+    match turns % 4 {
+        0 => (dx, dy),
+        1 => (-dy, dx),
+        2 => (-dx, -dy),
+        _ => (dy, -dx),
+    }
+}
+
+// Sets one pixel of the 2x2 block that `(x, y)` of the input scales to. The
+// sub-pixel is given as the corner of the block it's in, (1, 1) for bottom right.
+fn put_2x(output: &mut Image, x: usize, y: usize, corner: (isize, isize), color: [u8; 4]) {
+    let ox = 2 * x + usize::from(corner.0 > 0);
+    let oy = 2 * y + usize::from(corner.1 > 0);
+    let idx = (oy * output.width + ox) * 4;
+    output.rgba[idx..idx + 4].copy_from_slice(&color);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    pub fn image(width: usize, height: usize, pixels: &[[u8; 4]]) -> Image {
+        Image {
+            width,
+            height,
+            rgba: pixels.concat(),
+        }
+    }
+
+    #[test]
+    fn test_chain() {
+        let black = [0, 0, 0, 255];
+        let white = [255; 4];
+        let input = image(2, 1, &[black, white]);
+        let output = ScalerKind::Nearest.chain().apply(input);
+        assert_eq!((output.width, output.height), (4, 2));
+        assert_eq!(output.pixel(1, 1), black);
+        assert_eq!(output.pixel(2, 0), white);
+
+        let input = image(1, 1, &[white]);
+        let mut chain = FilterChain::new(vec![Box::new(Nearest), Box::new(Nearest)]);
+        assert_eq!(chain.apply(input).width, 4);
+        assert_eq!(ScalerKind::parse("xbrz"), Some(ScalerKind::Xbrz));
+    }
+
+    // Flat areas must come out unchanged, whatever the scaler
+    #[test]
+    fn test_flat_input() {
+        let color = [10, 200, 30, 255];
+        for kind in ScalerKind::ALL {
+            let output = kind.chain().apply(image(3, 3, &[color; 9]));
+            assert!(output.rgba.chunks_exact(4).all(|pixel| pixel == color));
+        }
+    }
+}
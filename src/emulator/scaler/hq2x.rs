@@ -0,0 +1,96 @@
+use super::{blend, put_2x, Image, Scaler};
+
+/// YUV similarity test and blends of the hq2x scaler. Its 256 pattern table is
+/// reduced to rules for one output corner, which only look at the center pixel
+/// and the three neighbors touching that corner.
+pub struct Hq2x;
+
+// Largest differences in Y, U and V that still count as the same color
+const Y_THRESHOLD: i32 = 48;
+const U_THRESHOLD: i32 = 7;
+const V_THRESHOLD: i32 = 6;
+
+fn yuv([r, g, b, _]: [u8; 4]) -> [i32; 3] {
+    let (r, g, b) = (r as i32, g as i32, b as i32);
+    [
+        (299 * r + 587 * g + 114 * b) / 1000,
+        (-169 * r - 331 * g + 500 * b) / 1000 + 128,
+        (500 * r - 419 * g - 81 * b) / 1000 + 128,
+    ]
+}
+
+fn similar(a: [u8; 4], b: [u8; 4]) -> bool {
+    let (a, b) = (yuv(a), yuv(b));
+    (a[0] - b[0]).abs() <= Y_THRESHOLD
+        && (a[1] - b[1]).abs() <= U_THRESHOLD
+        && (a[2] - b[2]).abs() <= V_THRESHOLD
+}
+
+// Color of the output corner next to neighbors `side` (horizontal), `vert` and `diag`
+fn corner(center: [u8; 4], side: [u8; 4], vert: [u8; 4], diag: [u8; 4]) -> [u8; 4] {
+    let near_side = similar(center, side);
+    let near_vert = similar(center, vert);
+    match (near_side, near_vert) {
+        // An edge runs diagonally past the corner, unless the center continues along it
+        (false, false) if similar(side, vert) && !similar(center, diag) => {
+            blend(&[(center, 2), (side, 3), (vert, 3)])
+        }
+        (false, false) => blend(&[(center, 2), (side, 1), (vert, 1)]),
+        (false, true) => blend(&[(center, 3), (side, 1)]),
+        (true, false) => blend(&[(center, 3), (vert, 1)]),
+        (true, true) if !similar(center, diag) => blend(&[(center, 3), (diag, 1)]),
+        (true, true) => center,
+    }
+}
+
+impl Scaler for Hq2x {
+    fn factor(&self) -> usize {
+        2
+    }
+
+    fn scale(&mut self, input: &Image, output: &mut Image) {
+        for y in 0..input.height {
+            for x in 0..input.width {
+                let center = input.pixel(x, y);
+                for (dx, dy) in [(-1, -1), (1, -1), (-1, 1), (1, 1)] {
+                    let color = corner(
+                        center,
+                        input.neighbor(x, y, dx, 0),
+                        input.neighbor(x, y, 0, dy),
+                        input.neighbor(x, y, dx, dy),
+                    );
+                    put_2x(output, x, y, (dx, dy), color);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::test::image;
+    use super::*;
+
+    #[test]
+    fn test_diagonal_edge_is_smoothed() {
+        // Black below the diagonal, white above it
+        let (b, w) = ([0, 0, 0, 255], [255; 4]);
+        #[rustfmt::skip]
+        let input = image(3, 3, &[
+            w, w, w,
+            b, w, w,
+            b, b, w,
+        ]);
+        let mut output = Image {
+            width: 6,
+            height: 6,
+            rgba: vec![0; 6 * 6 * 4],
+        };
+        Hq2x.scale(&input, &mut output);
+        // The center's corner towards the black side is mostly black
+        let cut = output.pixel(2, 3);
+        assert!(cut[0] > 0 && cut[0] < 128);
+        // The corner away from the edge stays white
+        assert_eq!(output.pixel(3, 2), w);
+    }
+}
@@ -0,0 +1,263 @@
+use super::{put_2x, rotate, Image, Scaler};
+
+/// Zenju's xBRZ at 2x. Blending is decided for every 2x2 block of input pixels
+/// from color gradients around it, then applied to the corners of each pixel.
+#[derive(Default)]
+pub struct Xbrz {
+    /// Blend of each pixel's corners, see `corner_blend`
+    blends: Vec<[Blend; 4]>,
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Debug)]
+enum Blend {
+    #[default]
+    None,
+    Normal,
+    /// The gradient along one diagonal is much stronger than along the other
+    Dominant,
+}
+
+// Defaults of the reference implementation
+const LUMINANCE_WEIGHT: f64 = 1.0;
+const EQUAL_COLOR_TOLERANCE: f64 = 30.0;
+const DOMINANT_DIRECTION_THRESHOLD: f64 = 3.6;
+const STEEP_DIRECTION_THRESHOLD: f64 = 2.2;
+
+// Distance in YCbCr with BT.2020 weights
+#[allow(clippy::many_single_char_names)]
+fn distance(a: [u8; 4], b: [u8; 4]) -> f64 {
+    const K_B: f64 = 0.0593;
+    const K_R: f64 = 0.2627;
+    const K_G: f64 = 1.0 - K_B - K_R;
+    let r = a[0] as f64 - b[0] as f64;
+    let g = a[1] as f64 - b[1] as f64;
+    let b = a[2] as f64 - b[2] as f64;
+    let y = K_R * r + K_G * g + K_B * b;
+    let c_b = 0.5 / (1.0 - K_B) * (b - y);
+    let c_r = 0.5 / (1.0 - K_R) * (r - y);
+    ((LUMINANCE_WEIGHT * y).powi(2) + c_b.powi(2) + c_r.powi(2)).sqrt()
+}
+
+fn equal(a: [u8; 4], b: [u8; 4]) -> bool {
+    distance(a, b) < EQUAL_COLOR_TOLERANCE
+}
+
+// Corners in the order blends are stored: top left, top right, bottom right, bottom left
+const CORNERS: [(isize, isize); 4] = [(-1, -1), (1, -1), (1, 1), (-1, 1)];
+
+fn corner_index(corner: (isize, isize)) -> usize {
+    CORNERS.iter().position(|&c| c == corner).unwrap_or(0)
+}
+
+// Blends for the four pixels of the block with `(x, y)` at its top left,
+// with `a` at (x - 1, y - 1):
+//   a b c d
+//   e f g h    f, g, j and k make up the block
+//   i j k l
+//   m n o p
+// Returned in the order f, g, j, k
+#[allow(clippy::many_single_char_names)]
+fn block_blends(input: &Image, x: usize, y: usize) -> [Blend; 4] {
+    let at = |dx: isize, dy: isize| input.neighbor(x, y, dx, dy);
+    let (b, c) = (at(0, -1), at(1, -1));
+    let (e, f, g, h) = (at(-1, 0), at(0, 0), at(1, 0), at(2, 0));
+    let (i, j, k, l) = (at(-1, 1), at(0, 1), at(1, 1), at(2, 1));
+    let (n, o) = (at(0, 2), at(1, 2));
+
+    let mut blends = [Blend::None; 4];
+    if (f == g && j == k) || (f == j && g == k) {
+        return blends;
+    }
+    let jg =
+        distance(i, f) + distance(f, c) + distance(n, k) + distance(k, h) + 4.0 * distance(j, g);
+    let fk =
+        distance(e, j) + distance(j, o) + distance(b, g) + distance(g, l) + 4.0 * distance(f, k);
+    let kind = |strong: bool| {
+        if strong {
+            Blend::Dominant
+        } else {
+            Blend::Normal
+        }
+    };
+    if jg < fk {
+        let blend = kind(DOMINANT_DIRECTION_THRESHOLD * jg < fk);
+        if f != g && f != j {
+            blends[0] = blend;
+        }
+        if k != j && k != g {
+            blends[3] = blend;
+        }
+    } else if fk < jg {
+        let blend = kind(DOMINANT_DIRECTION_THRESHOLD * fk < jg);
+        if j != f && j != k {
+            blends[2] = blend;
+        }
+        if g != f && g != k {
+            blends[1] = blend;
+        }
+    }
+    blends
+}
+
+fn mix(dst: [u8; 4], color: [u8; 4], num: u32, den: u32) -> [u8; 4] {
+    let mut out = [0; 4];
+    for (channel, value) in out.iter_mut().enumerate() {
+        let sum = color[channel] as u32 * num + dst[channel] as u32 * (den - num);
+        *value = ((sum + den / 2) / den) as u8;
+    }
+    out
+}
+
+impl Xbrz {
+    fn compute_blends(&mut self, input: &Image) {
+        let (width, height) = (input.width, input.height);
+        self.blends.clear();
+        self.blends.resize(width * height, [Blend::None; 4]);
+        // Blocks hanging over the top and left edges too, their pixels repeat inwards
+        for y in -1..height as isize {
+            for x in -1..width as isize {
+                // Repeated edge pixels are equal, so those blocks have nothing to blend
+                let block = if x < 0 || y < 0 {
+                    [Blend::None; 4]
+                } else {
+                    block_blends(input, x as usize, y as usize)
+                };
+                // Each pixel of the block gets the blend of its corner inside it
+                for (idx, (px, py)) in [(0, 0), (1, 0), (0, 1), (1, 1)].into_iter().enumerate() {
+                    let (px, py) = (x + px, y + py);
+                    if px < 0 || py < 0 || px >= width as isize || py >= height as isize {
+                        continue;
+                    }
+                    let corner =
+                        corner_index((1 - 2 * (idx as isize % 2), 1 - 2 * (idx as isize / 2)));
+                    self.blends[py as usize * width + px as usize][corner] = block[idx];
+                }
+            }
+        }
+    }
+
+    fn corner_blend(&self, input: &Image, x: usize, y: usize, corner: (isize, isize)) -> Blend {
+        self.blends[y * input.width + x][corner_index(corner)]
+    }
+
+    // Blends the corner of `(x, y)` that `turns` quarter turns bring bottom right to
+    #[allow(clippy::many_single_char_names)]
+    fn scale_corner(&self, input: &Image, output: &mut Image, x: usize, y: usize, turns: usize) {
+        let at = |dx: isize, dy: isize| {
+            let (dx, dy) = rotate(dx, dy, turns);
+            input.neighbor(x, y, dx, dy)
+        };
+        let blend_at = |cx: isize, cy: isize| self.corner_blend(input, x, y, rotate(cx, cy, turns));
+        //   a b c
+        //   d e f    blending the corner of e towards i
+        //   g h i
+        let (b, c) = (at(0, -1), at(1, -1));
+        let (d, e, f) = (at(-1, 0), at(0, 0), at(1, 0));
+        let (g, h, i) = (at(-1, 1), at(0, 1), at(1, 1));
+
+        let blend = blend_at(1, 1);
+        if blend < Blend::Normal {
+            return;
+        }
+        let line_blend = if blend >= Blend::Dominant {
+            true
+        } else if blend_at(1, -1) != Blend::None && !equal(e, g) {
+            // A second blend from an adjacent corner would double up on insular pixels
+            false
+        } else if blend_at(-1, 1) != Blend::None && !equal(e, c) {
+            false
+        } else {
+            // L shapes only get their corner blended
+            !(!equal(e, i) && equal(g, h) && equal(h, i) && equal(i, f) && equal(f, c))
+        };
+        let color = if distance(e, f) <= distance(e, h) {
+            f
+        } else {
+            h
+        };
+
+        let mut put = |cx: isize, cy: isize, num: u32, den: u32| {
+            let corner = rotate(cx, cy, turns);
+            let sub_x = 2 * x + usize::from(corner.0 > 0);
+            let sub_y = 2 * y + usize::from(corner.1 > 0);
+            let dst = output.pixel(sub_x, sub_y);
+            put_2x(output, x, y, corner, mix(dst, color, num, den));
+        };
+        if !line_blend {
+            put(1, 1, 21, 100);
+            return;
+        }
+        let fg = distance(f, g);
+        let hc = distance(h, c);
+        let shallow = STEEP_DIRECTION_THRESHOLD * fg <= hc && e != g && d != g;
+        let steep = STEEP_DIRECTION_THRESHOLD * hc <= fg && e != c && b != c;
+        match (shallow, steep) {
+            (true, true) => {
+                put(-1, 1, 1, 4);
+                put(1, -1, 1, 4);
+                put(1, 1, 5, 6);
+            }
+            (true, false) => {
+                put(-1, 1, 1, 4);
+                put(1, 1, 3, 4);
+            }
+            (false, true) => {
+                put(1, -1, 1, 4);
+                put(1, 1, 3, 4);
+            }
+            (false, false) => put(1, 1, 1, 2),
+        }
+    }
+}
+
+impl Scaler for Xbrz {
+    fn factor(&self) -> usize {
+        2
+    }
+
+    fn scale(&mut self, input: &Image, output: &mut Image) {
+        self.compute_blends(input);
+        for y in 0..input.height {
+            for x in 0..input.width {
+                let center = input.pixel(x, y);
+                for corner in CORNERS {
+                    put_2x(output, x, y, corner, center);
+                }
+                for turns in 0..4 {
+                    self.scale_corner(input, output, x, y, turns);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::test::image;
+    use super::*;
+
+    #[test]
+    fn test_diagonal_edge_is_smoothed() {
+        let (b, w) = ([0, 0, 0, 255], [255; 4]);
+        #[rustfmt::skip]
+        let input = image(4, 4, &[
+            w, w, w, w,
+            b, w, w, w,
+            b, b, w, w,
+            b, b, b, w,
+        ]);
+        let mut output = Image {
+            width: 8,
+            height: 8,
+            rgba: vec![0; 8 * 8 * 4],
+        };
+        Xbrz::default().scale(&input, &mut output);
+        // The white pixel at (1, 1) gets its bottom left corner blended towards black
+        let cut = output.pixel(2, 3);
+        assert!(cut[0] < 255 && cut[0] > 0);
+        assert_eq!(output.pixel(3, 2), w);
+        // Pixels away from the edge are untouched
+        assert_eq!(output.pixel(0, 7), b);
+        assert_eq!(output.pixel(7, 0), w);
+    }
+}
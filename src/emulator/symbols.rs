@@ -0,0 +1,125 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use eyre::{eyre, Result, WrapErr};
+
+/// Labels for CPU addresses, from FCEUX `.nl` or ca65 `.dbg` files
+#[derive(Clone, Default, Debug, PartialEq, Eq)]
+pub struct Symbols {
+    labels: BTreeMap<u16, String>,
+}
+
+impl Symbols {
+    /// Reads a `.dbg` file as ca65 debug info and anything else as an FCEUX name list
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("Failed to read symbol file {}", path.display()))?;
+        let symbols = if path.extension().is_some_and(|ext| ext == "dbg") {
+            Self::parse_dbg(&text)
+        } else {
+            Self::parse_nl(&text)
+        };
+        if symbols.labels.is_empty() {
+            return Err(eyre!("No symbols in {}", path.display()));
+        }
+        Ok(symbols)
+    }
+
+    /// Symbol files FCEUX and ld65 would write for a ROM, that exist next to it
+    pub fn files_for_rom(rom: &Path) -> Vec<PathBuf> {
+        let mut ram_nl = rom.as_os_str().to_owned();
+        ram_nl.push(".ram.nl");
+        [PathBuf::from(ram_nl), rom.with_extension("dbg")]
+            .into_iter()
+            .filter(|path| path.exists())
+            .collect()
+    }
+
+    /// `$<addr>#<label>#<comment>` lines, `$<addr>/<size>` labels an array
+    fn parse_nl(text: &str) -> Self {
+        let labels = text
+            .lines()
+            .filter_map(|line| {
+                let (addr, rest) = line.strip_prefix('$')?.split_once('#')?;
+                let addr = addr.split('/').next()?;
+                let addr = u16::from_str_radix(addr, 16).ok()?;
+                let name = rest.split('#').next()?.trim();
+                (!name.is_empty()).then(|| (addr, name.to_owned()))
+            })
+            .collect();
+        Self { labels }
+    }
+
+    /// `sym` lines with a `name` and a `val` that fits in the CPU address space
+    fn parse_dbg(text: &str) -> Self {
+        let labels = text
+            .lines()
+            .filter_map(|line| {
+                let fields = line.strip_prefix("sym\t")?;
+                let (mut name, mut val) = (None, None);
+                for field in fields.split(',') {
+                    match field.split_once('=') {
+                        Some(("name", value)) => name = Some(value.trim_matches('"')),
+                        Some(("val", value)) => val = Some(value),
+                        _ => (),
+                    }
+                }
+                let val = val?;
+                let addr = match val.strip_prefix("0x") {
+                    Some(hex) => u16::from_str_radix(hex, 16).ok()?,
+                    None => val.parse().ok()?,
+                };
+                Some((addr, name?.to_owned()))
+            })
+            .collect();
+        Self { labels }
+    }
+
+    /// Adds the labels of `other`, which win where both have one
+    pub fn merge(&mut self, other: Self) {
+        self.labels.extend(other.labels);
+    }
+
+    pub fn len(&self) -> usize {
+        self.labels.len()
+    }
+
+    pub fn label(&self, addr: u16) -> Option<&str> {
+        self.labels.get(&addr).map(String::as_str)
+    }
+
+    pub fn find(&self, name: &str) -> Option<u16> {
+        self.labels
+            .iter()
+            .find(|(_, label)| *label == name)
+            .map(|(&addr, _)| addr)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_nl() {
+        let symbols = Symbols::parse_nl(
+            "$0010#player_x#Horizontal position\n$0300/10#buffer#\n$0400##unnamed\nbad line\n",
+        );
+        assert_eq!(symbols.len(), 2);
+        assert_eq!(symbols.label(0x10), Some("player_x"));
+        assert_eq!(symbols.find("buffer"), Some(0x300));
+    }
+
+    #[test]
+    fn test_parse_dbg() {
+        let symbols = Symbols::parse_dbg(
+            "version\tmajor=2,minor=0\n\
+             sym\tid=0,name=\"lives\",addrsize=zeropage,size=1,scope=0,def=1,val=0x3A,type=lab\n\
+             sym\tid=1,name=\"SPEED\",addrsize=zeropage,scope=0,def=2,val=12,type=equ\n\
+             sym\tid=2,name=\"far\",addrsize=far,scope=0,def=3,val=0x12345,type=lab\n",
+        );
+        assert_eq!(symbols.len(), 2);
+        assert_eq!(symbols.label(0x3A), Some("lives"));
+        assert_eq!(symbols.find("SPEED"), Some(12));
+    }
+}
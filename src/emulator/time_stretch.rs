@@ -0,0 +1,141 @@
+/// WSOLA time stretching: plays audio produced at `speed` times real time at its
+/// original pitch, by cutting it into overlapping segments and cross-fading each
+/// one into the spot of the input that continues the previous one most smoothly.
+pub struct TimeStretch {
+    input: Vec<f32>,
+    /// Nominal start of the next segment in `input`
+    position: f64,
+    /// Second half of the previous segment, faded out under the next one
+    tail: Vec<f32>,
+}
+
+impl TimeStretch {
+    // At 48 kHz: 10.7 ms of output per segment, searched over +-5.3 ms
+    const OVERLAP: usize = 512;
+    const SEGMENT_LEN: usize = 2 * Self::OVERLAP;
+    const TOLERANCE: usize = 256;
+    // Only every few samples are compared when searching, full resolution isn't needed
+    const SEARCH_STEP: usize = 4;
+
+    pub fn new() -> Self {
+        Self {
+            input: Vec::new(),
+            position: Self::TOLERANCE as f64,
+            tail: vec![0.0; Self::OVERLAP],
+        }
+    }
+
+    /// Returns about `input.len() / speed` samples. At normal speed the input is
+    /// passed through once the samples buffered from a speed change have played out.
+    pub fn process(&mut self, input: &[f32], speed: f32) -> Vec<f32> {
+        if (speed - 1.0).abs() < f32::EPSILON && self.input.is_empty() {
+            return input.to_vec();
+        }
+        self.input.extend_from_slice(input);
+
+        let hop = Self::OVERLAP as f64 * speed as f64;
+        let mut output = Vec::with_capacity((input.len() as f32 / speed) as usize + Self::OVERLAP);
+        while self.position as usize + Self::TOLERANCE + Self::SEGMENT_LEN <= self.input.len() {
+            let start = self.best_start(self.position as usize);
+            let segment = &self.input[start..start + Self::SEGMENT_LEN];
+            output.extend(
+                segment[..Self::OVERLAP]
+                    .iter()
+                    .zip(&self.tail)
+                    .enumerate()
+                    .map(|(idx, (new, old))| {
+                        let fade = Self::fade_in(idx);
+                        new * fade + old * (1.0 - fade)
+                    }),
+            );
+            self.tail.copy_from_slice(&segment[Self::OVERLAP..]);
+            self.position += hop;
+        }
+
+        // Keep only what the search can still reach
+        let consumed = (self.position as usize).saturating_sub(Self::TOLERANCE);
+        self.input.drain(..consumed.min(self.input.len()));
+        self.position -= consumed as f64;
+
+        if (speed - 1.0).abs() < f32::EPSILON {
+            // Back at normal speed, fade into the rest of the input and pass through after
+            let rest = self.input.get(self.position as usize..).unwrap_or_default();
+            output.extend(self.tail.iter().enumerate().map(|(idx, old)| {
+                let fade = Self::fade_in(idx);
+                rest.get(idx).map_or(0.0, |new| new * fade) + old * (1.0 - fade)
+            }));
+            output.extend(rest.iter().skip(Self::OVERLAP));
+            *self = Self::new();
+        }
+        output
+    }
+
+    // Raised cosine, sums with its mirror image to one
+    fn fade_in(idx: usize) -> f32 {
+        let phase = (idx as f32 + 0.5) / Self::OVERLAP as f32;
+        0.5 - 0.5 * (std::f32::consts::PI * phase).cos()
+    }
+
+    // Start within the tolerance of `nominal` whose beginning best matches the tail
+    fn best_start(&self, nominal: usize) -> usize {
+        let first = nominal.saturating_sub(Self::TOLERANCE);
+        let last = nominal + Self::TOLERANCE;
+        let score = |start: usize| -> f32 {
+            self.input[start..start + Self::OVERLAP]
+                .iter()
+                .zip(&self.tail)
+                .step_by(Self::SEARCH_STEP)
+                .map(|(a, b)| a * b)
+                .sum()
+        };
+        (first..=last)
+            .max_by(|&a, &b| score(a).total_cmp(&score(b)))
+            .unwrap_or(nominal)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sine(len: usize, period: f32) -> Vec<f32> {
+        (0..len)
+            .map(|i| (2.0 * std::f32::consts::PI * i as f32 / period).sin())
+            .collect()
+    }
+
+    // Average distance between rising zero crossings
+    fn period(samples: &[f32]) -> f32 {
+        let crossings: Vec<usize> = samples
+            .windows(2)
+            .enumerate()
+            .filter(|(_, w)| w[0] < 0.0 && w[1] >= 0.0)
+            .map(|(idx, _)| idx)
+            .collect();
+        (crossings[crossings.len() - 1] - crossings[0]) as f32 / (crossings.len() - 1) as f32
+    }
+
+    #[test]
+    fn test_passthrough_at_normal_speed() {
+        let mut stretch = TimeStretch::new();
+        let input = sine(800, 100.0);
+        assert_eq!(stretch.process(&input, 1.0), input);
+    }
+
+    #[test]
+    fn test_keeps_pitch_and_scales_length() {
+        for speed in [0.5, 2.0] {
+            let mut stretch = TimeStretch::new();
+            let input = sine(48_000, 109.0);
+            let output: Vec<f32> = input
+                .chunks(800)
+                .flat_map(|chunk| stretch.process(chunk, speed))
+                .collect();
+
+            let expected = input.len() as f32 / speed;
+            assert!((output.len() as f32 - expected).abs() < expected * 0.05);
+            // Skip the fade in from silence at the start
+            assert!((period(&output[2000..]) - 109.0).abs() < 1.0);
+        }
+    }
+}
@@ -1,17 +1,32 @@
-use std::collections::HashMap;
-use std::thread::yield_now;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 use std::time::SystemTime;
 
 use egui_sdl2_gl::egui::Color32;
 use eyre::Result;
 use sdl2::Sdl;
+use sdl2::TimerSubsystem;
 
+use super::bindings::{Bindings, BindingsWindow};
+use super::capture::{Capture, CaptureNaming, CaptureStage};
+use super::debugger::Debugger;
 use super::fw_error;
-use crate::console::controller::Button;
-use crate::console::controller::Controller;
+use super::gamepad::Gamepads;
+use super::latency::LatencyMeter;
+use super::layout::PanelLayout;
+use super::play_stats::GameStats;
+use super::rumble::Rumble;
+use super::scaler::{FilterChain, Image, ScalerKind};
+use super::GameInfo;
+use crate::console::apu::Apu;
+use crate::console::controller::{Controller, OpposingDirections};
+use crate::console::time::EmulatedTime;
 use crate::console::SCREEN_HEIGHT;
 use crate::console::SCREEN_WIDTH;
+use crate::romdb::RomInfo;
+use crate::update_check::Release;
+use egui_sdl2_gl::egui::plot::{Line, Plot, Value, Values};
+use egui_sdl2_gl::egui::ClippedMesh;
 use egui_sdl2_gl::egui::CtxRef;
 use egui_sdl2_gl::egui::TextureId;
 use egui_sdl2_gl::egui::Vec2;
@@ -19,39 +34,344 @@ use egui_sdl2_gl::egui::{self, Frame};
 use egui_sdl2_gl::painter::Painter;
 use egui_sdl2_gl::EguiStateHandler;
 use eyre::eyre;
+use sdl2::controller::Button as PadButton;
 use sdl2::event::Event;
+use sdl2::event::WindowEvent;
 use sdl2::keyboard::Keycode;
 use sdl2::mouse::MouseUtil;
+use sdl2::pixels::Color;
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::rect::Rect;
+use sdl2::surface::Surface;
 use sdl2::video::FullscreenType;
 use sdl2::video::GLContext;
 use sdl2::video::Window;
 use sdl2::EventPump;
+use sdl2::VideoSubsystem;
 
-const WINDOW_WIDTH: u32 = (SCREEN_WIDTH * 3) as u32;
-const WINDOW_HEIGHT: u32 = (SCREEN_HEIGHT * 3) as u32;
+const DEFAULT_SCALE: u32 = 3;
+const MAX_SCALE: u32 = 5;
+// Most TVs hid about 8 lines at the top and bottom of the picture
+const OVERSCAN_LINES: usize = 8;
+const SETTINGS_FILE: &str = "window.cfg";
+// How long a newer release is announced over the game
+const UPDATE_NOTICE_TIME: Duration = Duration::from_secs(10);
+const SCOPES_TITLE: &str = "Channel scopes";
 
 pub const RENDER_WIDTH: usize = SCREEN_WIDTH;
 pub const RENDER_HEIGHT: usize = SCREEN_HEIGHT;
 
-const ASPECT_RATIO: f32 = SCREEN_WIDTH as f32 / SCREEN_HEIGHT as f32;
+// 60.0988 frames per second, the display's refresh rate doesn't change it
+const FRAME_NANOS: u64 = 16_639_267;
+// Emulation speeds offered in the menu, and the one used while Tab is held
+const SPEEDS: [f32; 5] = [0.25, 0.5, 1.0, 1.5, 2.0];
+const FAST_FORWARD_SPEED: f32 = 2.0;
+// Sleeping is only accurate to a millisecond or so, spin for the rest
+const SPIN_NANOS: u64 = 1_500_000;
 
-pub struct Ui {
+/// How frames reach the screen
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Renderer {
+    /// OpenGL with the egui menus and debug windows
+    Gl,
+    /// Plain SDL surface blit for systems with broken GL drivers, without menus
+    Software,
+}
+
+impl Renderer {
+    pub fn parse(name: &str) -> Result<Self> {
+        match name {
+            "gl" => Ok(Self::Gl),
+            "software" => Ok(Self::Software),
+            _ => Err(eyre!("Unknown renderer {name}, expected gl or software")),
+        }
+    }
+}
+
+/// What the display shows on refreshes between two emulated frames
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interpolation {
+    /// Nothing new is presented, the display keeps the last frame
+    None,
+    /// The last frame is presented again on every refresh
+    Duplicate,
+}
+
+impl Interpolation {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "none" => Some(Self::None),
+            "duplicate" => Some(Self::Duplicate),
+            _ => None,
+        }
+    }
+
+    const fn name(self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Duplicate => "duplicate",
+        }
+    }
+}
+
+/// Largest size with the given aspect ratio that fits in `width` x `height`
+fn fit_size(width: f32, height: f32, aspect_ratio: f32) -> (f32, f32) {
+    if width / height > aspect_ratio {
+        // Screen wider than the picture
+        (height * aspect_ratio, height)
+    } else {
+        // Screen taller than the picture
+        (width, width / aspect_ratio)
+    }
+}
+
+/// Window options that are kept between runs
+#[derive(Debug, PartialEq)]
+struct WindowSettings {
+    width: u32,
+    height: u32,
+    keep_aspect: bool,
+    crop_overscan: bool,
+    /// Stop emulating while the window is minimized or another window has focus
+    pause_in_background: bool,
+    interpolation: Interpolation,
+    scaler: ScalerKind,
+    opposing_directions: OpposingDirections,
+    bindings: Bindings,
+    panels: PanelLayout,
+    /// SDL mappings for controllers without a built-in one
+    controller_mappings: Vec<String>,
+    capture: CaptureNaming,
+}
+
+impl Default for WindowSettings {
+    fn default() -> Self {
+        Self {
+            width: SCREEN_WIDTH as u32 * DEFAULT_SCALE,
+            height: SCREEN_HEIGHT as u32 * DEFAULT_SCALE,
+            keep_aspect: false,
+            crop_overscan: false,
+            pause_in_background: false,
+            interpolation: Interpolation::Duplicate,
+            scaler: ScalerKind::None,
+            opposing_directions: OpposingDirections::Allow,
+            bindings: Bindings::default(),
+            panels: PanelLayout::default(),
+            controller_mappings: Vec::new(),
+            capture: CaptureNaming::default(),
+        }
+    }
+}
+
+impl WindowSettings {
+    /// Reads settings from a file, missing files or keys fall back to defaults
+    fn load(file: &str) -> Self {
+        std::fs::read_to_string(file)
+            .map(|text| Self::parse(&text))
+            .unwrap_or_default()
+    }
+
+    fn parse(text: &str) -> Self {
+        let mut settings = Self::default();
+        for line in text.lines() {
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let value = value.trim();
+            match key.trim() {
+                "width" => settings.width = value.parse().unwrap_or(settings.width),
+                "height" => settings.height = value.parse().unwrap_or(settings.height),
+                "keep_aspect" => settings.keep_aspect = value == "true",
+                "crop_overscan" => settings.crop_overscan = value == "true",
+                "pause_in_background" => settings.pause_in_background = value == "true",
+                "interpolation" => {
+                    settings.interpolation =
+                        Interpolation::parse(value).unwrap_or(settings.interpolation);
+                }
+                "scaler" => settings.scaler = ScalerKind::parse(value).unwrap_or(settings.scaler),
+                "opposing_directions" => {
+                    settings.opposing_directions =
+                        OpposingDirections::parse(value).unwrap_or(settings.opposing_directions);
+                }
+                "controller_mapping" => settings.controller_mappings.push(value.to_owned()),
+                "capture_dir" => {
+                    settings.capture.dir = (!value.is_empty()).then(|| PathBuf::from(value));
+                }
+                "capture_name" if !value.is_empty() => {
+                    value.clone_into(&mut settings.capture.template);
+                }
+                key => {
+                    if !settings.bindings.parse_line(key, value) {
+                        settings.panels.parse_line(key, value);
+                    }
+                }
+            }
+        }
+        settings
+    }
+
+    fn save(&self, file: &str) -> Result<()> {
+        std::fs::write(file, self.text())?;
+        Ok(())
+    }
+
+    fn text(&self) -> String {
+        let mappings: String = self
+            .controller_mappings
+            .iter()
+            .flat_map(|mapping| ["controller_mapping=", mapping, "\n"])
+            .collect();
+        format!(
+            "width={}\nheight={}\nkeep_aspect={}\ncrop_overscan={}\npause_in_background={}\n\
+             interpolation={}\nscaler={}\nopposing_directions={}\ncapture_dir={}\n\
+             capture_name={}\n{}{}{mappings}",
+            self.width,
+            self.height,
+            self.keep_aspect,
+            self.crop_overscan,
+            self.pause_in_background,
+            self.interpolation.name(),
+            self.scaler.name(),
+            self.opposing_directions.name(),
+            self.capture
+                .dir
+                .as_deref()
+                .map(Path::display)
+                .map(|dir| dir.to_string())
+                .unwrap_or_default(),
+            self.capture.template,
+            self.bindings.lines(),
+            self.panels.lines()
+        )
+    }
+
+    /// Number of picture lines shown
+    const fn visible_height(&self) -> usize {
+        if self.crop_overscan {
+            SCREEN_HEIGHT - 2 * OVERSCAN_LINES
+        } else {
+            SCREEN_HEIGHT
+        }
+    }
+
+    fn aspect_ratio(&self) -> f32 {
+        SCREEN_WIDTH as f32 / self.visible_height() as f32
+    }
+
+    /// Resizes the window to an integer multiple of the visible picture
+    fn scale_window(&self, window: &mut Window, scale: u32) {
+        if window.fullscreen_state() != FullscreenType::Off {
+            return;
+        }
+        let width = SCREEN_WIDTH as u32 * scale;
+        let height = self.visible_height() as u32 * scale;
+        if let Err(e) = window.set_size(width, height) {
+            println!("Failed to resize window: {e}");
+        }
+    }
+
+    // Adjusts the window height to match the picture's aspect ratio
+    fn fit_window(&self, window: &mut Window) {
+        if window.fullscreen_state() != FullscreenType::Off {
+            return;
+        }
+        let (width, height) = window.size();
+        let fitted = (width as f32 / self.aspect_ratio()).round() as u32;
+        if fitted.abs_diff(height) > 1 {
+            if let Err(e) = window.set_size(width, fitted) {
+                println!("Failed to resize window: {e}");
+            }
+        }
+    }
+}
+
+/// GL context and egui state, only present with `Renderer::Gl`
+struct Gui {
     _gl_context: GLContext,
+    context: CtxRef,
+    painter: Painter,
+    state: EguiStateHandler,
+    texture: TextureId,
+    texture_size: (usize, usize),
+    /// Everything drawn for the last frame, to present it again
+    last_paint: Vec<ClippedMesh>,
+}
+
+#[allow(clippy::struct_excessive_bools)]
+pub struct Ui {
     mouse: MouseUtil,
     event_pump: EventPump,
     window: Window,
-    keymap: HashMap<Keycode, Button>,
-    egui_context: CtxRef,
-    egui_painter: Painter,
-    egui_state: EguiStateHandler,
-    egui_texture: TextureId,
-    next_render_time: SystemTime,
+    gui: Option<Gui>,
+    timer: TimerSubsystem,
+    /// When the next emulated frame is due
+    next_render_time: u64,
+    /// Display refresh period, if the display reports its rate
+    refresh_ticks: Option<u64>,
     menu_timeout_start: SystemTime,
     prev_cursor_pos: egui::Pos2,
+    pub game_info: GameInfo,
+    pub quit_requested: bool,
+    /// Names of the ROMs given on the command line or in a playlist
+    pub rom_list: Vec<String>,
+    pub current_rom: usize,
+    /// ROM list index to switch to
+    pub switch_to: Option<usize>,
+    pub reload_requested: bool,
+    pub save_state_requested: bool,
+    pub load_state_requested: bool,
+    pub trace_dump_requested: bool,
+    pub audio_info_requested: bool,
+    pub report_requested: bool,
+    /// Save a screenshot with the issue report
+    pub report_screenshot: bool,
+    /// Name of the anchor to save at the end of the frame
+    pub anchor_requested: Option<String>,
+    anchor_name: String,
+    /// Colors the picture by the layer each pixel came from
+    pub show_pixel_sources: bool,
+    /// Images to read back while drawing the next frame
+    pub capture_requests: Vec<CaptureStage>,
+    /// Images read back so far
+    pub captures: Vec<Capture>,
+    /// Address of the jam opcode the CPU is stuck on, until the next reset
+    pub jammed_at: Option<u16>,
+    /// Scalers the frame goes through before it's shown
+    filters: FilterChain,
+    /// Newer release found by the update check and when it was announced
+    update: Option<(Release, SystemTime)>,
+    fps_frames: usize,
+    fps_timer: SystemTime,
+    frame_count: u64,
+    pub debugger: Debugger,
+    rom_info: Option<RomInfo>,
+    /// Statistics of the loaded game, shown with the ROM info
+    pub game_stats: Option<GameStats>,
+    show_rom_info: bool,
+    bindings_window: BindingsWindow,
+    show_rom_warnings: bool,
+    /// Unsupported mapper of the running game, which is emulated as NROM
+    pub fallback_mapper: Option<u8>,
+    pub trim_requested: bool,
+    /// Set while the input latency test is running
+    latency: Option<LatencyMeter>,
+    settings: WindowSettings,
+    /// Texture of the comparison console's frame and a summary of the differences
+    compare: Option<(TextureId, String)>,
+    pub rumble: Rumble,
+    gamepads: Gamepads,
+    pub emulated_time: EmulatedTime,
+    show_play_time: bool,
+    speed: f32,
+    fast_forward: bool,
+    pub muted: bool,
+    focused: bool,
+    minimized: bool,
 }
 
 impl Ui {
-    pub fn new(sdl: &Sdl, fullscreen: bool) -> Result<Self> {
+    #[allow(clippy::too_many_lines)]
+    pub fn new(sdl: &Sdl, fullscreen: bool, renderer: Renderer) -> Result<Self> {
         let video = fw_error!(sdl.video());
 
         let gl_attr = video.gl_attr();
@@ -61,80 +381,286 @@ impl Ui {
         gl_attr.set_framebuffer_srgb_compatible(true);
         gl_attr.set_context_version(3, 2);
 
-        let mut window = video
-            .window("rN3S", WINDOW_WIDTH, WINDOW_HEIGHT)
-            .opengl()
-            .resizable()
-            .build()?;
-
-        let gl_context = fw_error!(window.gl_create_context());
-        assert_eq!(gl_attr.context_profile(), sdl2::video::GLProfile::Core);
-        assert_eq!(gl_attr.context_version(), (3, 2));
+        let settings = WindowSettings::load(SETTINGS_FILE);
+        let mut builder = video.window("rN3S", settings.width, settings.height);
+        if renderer == Renderer::Gl {
+            builder.opengl();
+        }
+        let mut window = builder.resizable().build()?;
 
-        fw_error!(window
-            .subsystem()
-            .gl_set_swap_interval(sdl2::video::SwapInterval::Immediate));
+        let gl_context = if renderer == Renderer::Gl {
+            let gl_context = fw_error!(window.gl_create_context());
+            assert_eq!(gl_attr.context_profile(), sdl2::video::GLProfile::Core);
+            assert_eq!(gl_attr.context_version(), (3, 2));
 
-        if fullscreen {
-            let mut mode = fw_error!(window.display_mode());
-            mode.refresh_rate = 60;
-            let desktop_mode = fw_error!(video.desktop_display_mode(0));
-            mode.w = desktop_mode.w;
-            mode.h = desktop_mode.h;
-            fw_error!(window.set_display_mode(mode));
-            fw_error!(window.set_fullscreen(sdl2::video::FullscreenType::True));
             fw_error!(window
                 .subsystem()
-                .gl_set_swap_interval(sdl2::video::SwapInterval::VSync));
+                .gl_set_swap_interval(sdl2::video::SwapInterval::Immediate));
+            Some(gl_context)
+        } else {
+            None
+        };
+
+        if fullscreen {
+            Self::enter_fullscreen(&video, &mut window, gl_context.is_some())?;
         }
 
-        let (mut egui_painter, egui_state) = egui_sdl2_gl::with_sdl2(
-            &window,
-            egui_sdl2_gl::ShaderVersion::Default,
-            egui_sdl2_gl::DpiScaling::Custom(1.25),
-        );
-        let egui_context = egui::CtxRef::default();
-        let srgba: Vec<Color32> = vec![Color32::TRANSPARENT; RENDER_WIDTH * RENDER_HEIGHT];
-        let egui_texture =
-            egui_painter.new_user_texture((RENDER_WIDTH, RENDER_HEIGHT), &srgba, false);
+        let gui = gl_context.map(|gl_context| {
+            let (mut painter, state) = egui_sdl2_gl::with_sdl2(
+                &window,
+                egui_sdl2_gl::ShaderVersion::Default,
+                egui_sdl2_gl::DpiScaling::Custom(1.25),
+            );
+            let srgba: Vec<Color32> = vec![Color32::TRANSPARENT; RENDER_WIDTH * RENDER_HEIGHT];
+            let texture = painter.new_user_texture((RENDER_WIDTH, RENDER_HEIGHT), &srgba, false);
+            Gui {
+                _gl_context: gl_context,
+                context: egui::CtxRef::default(),
+                painter,
+                state,
+                texture,
+                texture_size: (RENDER_WIDTH, RENDER_HEIGHT),
+                last_paint: Vec::new(),
+            }
+        });
 
+        let timer = fw_error!(sdl.timer());
+        let next_render_time =
+            timer.performance_counter() + Self::nanos_to_ticks(&timer, FRAME_NANOS);
+        let refresh_ticks = Self::refresh_ticks(&window, &timer);
+
+        let game_controller = sdl.game_controller().ok();
+        let gamepads = Gamepads::new(game_controller.clone(), &settings.controller_mappings);
+        let rumble = Rumble::new(game_controller);
         let mouse = sdl.mouse();
         let event_pump = fw_error!(sdl.event_pump());
 
         Ok(Self {
-            _gl_context: gl_context,
             mouse,
             event_pump,
-            keymap: Self::build_keymap(),
             window,
-            egui_context,
-            egui_painter,
-            egui_state,
-            egui_texture,
-            next_render_time: SystemTime::now() + Duration::from_nanos(16_666_666),
+            gui,
+            timer,
+            next_render_time,
+            refresh_ticks,
             menu_timeout_start: SystemTime::now(),
             prev_cursor_pos: egui::Pos2::default(),
+            game_info: GameInfo::default(),
+            quit_requested: false,
+            rom_list: Vec::new(),
+            current_rom: 0,
+            switch_to: None,
+            reload_requested: false,
+            save_state_requested: false,
+            load_state_requested: false,
+            trace_dump_requested: false,
+            audio_info_requested: false,
+            report_requested: false,
+            report_screenshot: true,
+            anchor_requested: None,
+            anchor_name: String::new(),
+            show_pixel_sources: false,
+            capture_requests: Vec::new(),
+            captures: Vec::new(),
+            jammed_at: None,
+            filters: settings.scaler.chain(),
+            update: None,
+            fps_frames: 0,
+            fps_timer: SystemTime::now(),
+            frame_count: 0,
+            debugger: Debugger::default(),
+            rom_info: None,
+            show_rom_info: false,
+            bindings_window: BindingsWindow::default(),
+            game_stats: None,
+            show_rom_warnings: false,
+            fallback_mapper: None,
+            trim_requested: false,
+            latency: None,
+            settings,
+            compare: None,
+            rumble,
+            gamepads,
+            emulated_time: EmulatedTime::default(),
+            show_play_time: false,
+            speed: 1.0,
+            fast_forward: false,
+            muted: false,
+            focused: true,
+            minimized: false,
         })
     }
 
-    fn scale_game(available_space: Vec2) -> Vec2 {
-        let (w, h) = (available_space.x, available_space.y);
-        if w / h > ASPECT_RATIO {
-            // Screen wider than default
-            let w = h * ASPECT_RATIO;
-            // let pos = egui::pos2((ww as f32 - w) / 2.0, 0.0);
-            Vec2::new(w, h)
+    fn enter_fullscreen(video: &VideoSubsystem, window: &mut Window, vsync: bool) -> Result<()> {
+        let mut mode = fw_error!(window.display_mode());
+        let desktop_mode = fw_error!(video.desktop_display_mode(0));
+        mode.w = desktop_mode.w;
+        mode.h = desktop_mode.h;
+        // Vsync runs at the display's own rate, the pacer fits 60 Hz frames into it
+        mode.refresh_rate = desktop_mode.refresh_rate;
+        fw_error!(window.set_display_mode(mode));
+        fw_error!(window.set_fullscreen(sdl2::video::FullscreenType::True));
+        if vsync {
+            fw_error!(window
+                .subsystem()
+                .gl_set_swap_interval(sdl2::video::SwapInterval::VSync));
+        }
+        Ok(())
+    }
+
+    /// True if a debugger panel is open and needs console state
+    pub fn debugger_active(&self) -> bool {
+        Debugger::active(&self.settings.panels)
+    }
+
+    /// Emulation speed relative to real time
+    pub fn speed(&self) -> f32 {
+        if self.fast_forward {
+            FAST_FORWARD_SPEED
         } else {
-            // Screen taller than default
-            let h = w / ASPECT_RATIO;
-            // let pos = egui::pos2(0.0, (wh as f32 - h) / 2.0);
-            Vec2::new(w, h)
+            self.speed
+        }
+    }
+
+    /// Muted audio needn't be generated when running fast, unless the scopes show it
+    /// True while the window is in the background and the user wants that to pause
+    pub const fn paused(&self) -> bool {
+        self.settings.pause_in_background && (self.minimized || !self.focused)
+    }
+
+    /// Starts frame pacing over, so time spent paused isn't caught up on
+    pub fn resume_pacing(&mut self) {
+        self.next_render_time = self.timer.performance_counter();
+    }
+
+    pub fn wants_audio(&self) -> bool {
+        !self.muted || self.speed() <= 1.0 || self.settings.panels.is_open(SCOPES_TITLE)
+    }
+
+    /// Window settings as they're saved to the settings file
+    pub fn settings_text(&self) -> String {
+        self.settings.text()
+    }
+
+    /// Where screenshots go and how they're named
+    pub const fn capture_naming(&self) -> &CaptureNaming {
+        &self.settings.capture
+    }
+
+    pub fn rom_info(&self) -> Option<&RomInfo> {
+        self.rom_info.as_ref()
+    }
+
+    pub fn copy_to_clipboard(&self, text: &str) -> Result<()> {
+        fw_error!(self.window.subsystem().clipboard().set_clipboard_text(text));
+        Ok(())
+    }
+
+    pub fn set_update(&mut self, release: Release) {
+        self.update = Some((release, SystemTime::now()));
+    }
+
+    pub fn set_rom_info(&mut self, info: RomInfo) {
+        self.show_rom_warnings = !info.warnings.is_empty();
+        self.rom_info = Some(info);
+    }
+
+    /// Adds a problem found while running, shown like the ones found when loading
+    pub fn add_rom_warning(&mut self, warning: String) {
+        if let Some(info) = self.rom_info.as_mut() {
+            info.warnings.push(warning);
+            self.show_rom_warnings = true;
         }
     }
 
-    pub fn update(&mut self, game_texture: Vec<u8>, controller: &mut Controller) {
+    /// Shows a second frame next to the game, see `compare.rs`
+    pub fn set_compare_frame(&mut self, rgba: Vec<u8>, diff_summary: String) {
+        let Some(gui) = self.gui.as_mut() else {
+            return;
+        };
+        let texture = self.compare.as_ref().map_or_else(
+            || {
+                let srgba = vec![Color32::TRANSPARENT; RENDER_WIDTH * RENDER_HEIGHT];
+                gui.painter
+                    .new_user_texture((RENDER_WIDTH, RENDER_HEIGHT), &srgba, false)
+            },
+            |(texture, _)| *texture,
+        );
+        gui.painter.update_user_texture_rgba8_data(texture, rgba);
+        self.compare = Some((texture, diff_summary));
+    }
+
+    fn scale_game(available_space: Vec2, aspect_ratio: f32) -> Vec2 {
+        let (w, h) = fit_size(available_space.x, available_space.y, aspect_ratio);
+        Vec2::new(w, h)
+    }
+
+    // Centered rectangle for the picture in a window of the given size
+    fn game_rect(&self, (width, height): (u32, u32)) -> Rect {
+        let (w, h) = fit_size(width as f32, height as f32, self.settings.aspect_ratio());
+        let (w, h) = (w as u32, h as u32);
+        Rect::new(
+            ((width - w) / 2) as i32,
+            ((height - h) / 2) as i32,
+            w.max(1),
+            h.max(1),
+        )
+    }
+
+    // Software renderer: scales the frame onto the window surface with SDL's blitter
+    fn present_software(&self, mut image: Image) -> Result<()> {
+        let scale = image.height / RENDER_HEIGHT;
+        let visible = self.settings.visible_height() * scale;
+        let frame = fw_error!(Surface::from_data(
+            &mut image.rgba,
+            image.width as u32,
+            image.height as u32,
+            image.width as u32 * 4,
+            PixelFormatEnum::ABGR8888,
+        ));
+        let source = Rect::new(
+            0,
+            ((image.height - visible) / 2) as i32,
+            image.width as u32,
+            visible as u32,
+        );
+
+        let mut surface = fw_error!(self.window.surface(&self.event_pump));
+        let dest = self.game_rect(surface.size());
+        fw_error!(surface.fill_rect(None, Color::BLACK));
+        fw_error!(frame.blit_scaled(source, &mut surface, dest));
+        fw_error!(surface.update_window());
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_lines)]
+    pub fn update(&mut self, game_texture: Vec<u8>, apu: &Apu, controller: &mut Controller) {
+        // Game captures take the frame as emulated, composited ones read the back buffer
+        // once everything is painted. Software rendering has no overlays to add.
+        let requests = std::mem::take(&mut self.capture_requests);
+        let read_back = self.gui.is_some() && requests.contains(&CaptureStage::Composited);
+        if requests
+            .iter()
+            .any(|&stage| stage == CaptureStage::Game || !read_back)
+        {
+            self.captures.push(Capture::game(&game_texture));
+        }
+        let image = self.filters.apply(Image {
+            width: RENDER_WIDTH,
+            height: RENDER_HEIGHT,
+            rgba: game_texture,
+        });
+        let Some(gui) = self.gui.as_mut() else {
+            if let Err(e) = self.present_software(image) {
+                println!("Failed to draw frame: {e}");
+            }
+            self.frame_count += 1;
+            self.wait_for_next_frame();
+            self.update_title();
+            return;
+        };
         // let start_time = SystemTime::now();
-        self.egui_context.begin_frame(self.egui_state.input.take());
+        gui.context.begin_frame(gui.state.input.take());
 
         unsafe {
             // Clear the screen
@@ -142,25 +668,141 @@ impl Ui {
             gl::Clear(gl::COLOR_BUFFER_BIT);
         }
 
-        self.egui_painter
-            .update_user_texture_rgba8_data(self.egui_texture, game_texture);
+        // User textures have a fixed size, a new scaler needs a new one
+        let size = (image.width, image.height);
+        if size == gui.texture_size {
+            gui.painter
+                .update_user_texture_rgba8_data(gui.texture, image.rgba);
+        } else {
+            gui.painter.free_user_texture(gui.texture);
+            gui.texture = gui.painter.new_user_texture_rgba8(size, image.rgba, false);
+            gui.texture_size = size;
+        }
+        let crop =
+            (SCREEN_HEIGHT - self.settings.visible_height()) as f32 / 2.0 / SCREEN_HEIGHT as f32;
+        let uv = egui::Rect::from_min_max(egui::pos2(0.0, crop), egui::pos2(1.0, 1.0 - crop));
+        let aspect_ratio = self.settings.aspect_ratio();
+        let main_texture = gui.texture;
+        let compare = self.compare.as_ref();
+        let debugger = &mut self.debugger;
+        let panels = &self.settings.panels;
+        let lines = self.settings.visible_height();
+        let first_line = (SCREEN_HEIGHT - lines) / 2;
         egui::CentralPanel::default()
             .frame(Frame::none())
-            .show(&self.egui_context, |ui| {
-                ui.centered_and_justified(|ui| {
-                    ui.image(self.egui_texture, Self::scale_game(ui.available_size()));
+            .show(&gui.context, |ui| {
+                let Some((compare_texture, summary)) = compare else {
+                    ui.centered_and_justified(|ui| {
+                        let size = Self::scale_game(ui.available_size(), aspect_ratio);
+                        let response = ui.add(egui::Image::new(main_texture, size).uv(uv));
+                        debugger.inspect_picture(response, panels, first_line, lines);
+                    });
+                    return;
+                };
+                ui.columns(2, |columns| {
+                    for (ui, texture) in columns.iter_mut().zip([main_texture, *compare_texture]) {
+                        ui.centered_and_justified(|ui| {
+                            let size = Self::scale_game(ui.available_size(), aspect_ratio);
+                            ui.add(egui::Image::new(texture, size).uv(uv));
+                        });
+                    }
                 });
+                egui::Area::new("compare diff")
+                    .anchor(egui::Align2::RIGHT_BOTTOM, egui::Vec2::ZERO)
+                    .show(ui.ctx(), |ui| {
+                        ui.label(summary.as_str());
+                    });
             });
 
         // Draw audio buffer depth graph
-        // egui::Window::new("audio buffer").show(&self.egui_context, |ui| {
+        // egui::Window::new("audio buffer").show(&gui.context, |ui| {
         //     let line = Line::new(Values::from_ys_f32(&self.audio_handler.average_history));
         //     Plot::new("buffer depth")
         //         .view_aspect(1.0)
         //         .show(ui, |plot_ui| plot_ui.line(line));
         // });
 
-        let cursor_pos = self.egui_state.pointer_pos;
+        Self::draw_scopes(&gui.context, apu, &mut self.settings.panels);
+        self.debugger.draw(&gui.context, &mut self.settings.panels);
+
+        if self.show_play_time {
+            let time = self.emulated_time;
+            egui::Area::new("play time")
+                .anchor(egui::Align2::LEFT_BOTTOM, egui::Vec2::ZERO)
+                .show(&gui.context, |ui| {
+                    ui.monospace(time.to_string());
+                });
+        }
+
+        self.bindings_window
+            .draw(&gui.context, &mut self.settings.bindings);
+        let released = self.gamepads.draw(&gui.context);
+        Self::release_pad_buttons(&self.settings.bindings, controller, &released);
+
+        if let Some(info) = self.rom_info.as_ref() {
+            if self.show_rom_info {
+                Self::draw_rom_info(&gui.context, info, self.game_stats, &mut self.show_rom_info);
+            }
+            if self.show_rom_warnings {
+                self.trim_requested |=
+                    Self::draw_rom_warnings(&gui.context, info, &mut self.show_rom_warnings);
+            }
+        }
+
+        self.frame_count += 1;
+        if let Some(meter) = self.latency.as_mut() {
+            meter.frame_done(
+                controller.latch_count(),
+                self.frame_count,
+                self.timer.ticks(),
+            );
+            Self::draw_latency(&gui.context, meter);
+        }
+
+        if let Some((release, since)) = self.update.as_ref() {
+            if since.elapsed().unwrap_or_default() < UPDATE_NOTICE_TIME {
+                egui::Area::new("update notice")
+                    .anchor(egui::Align2::RIGHT_BOTTOM, [-8.0, -8.0])
+                    .show(&gui.context, |ui| {
+                        Frame::popup(ui.style()).show(ui, |ui| {
+                            ui.label(format!(
+                                "rnes {} is available, see the File menu",
+                                release.version
+                            ));
+                        });
+                    });
+            }
+        }
+
+        // Stays up the whole run, glitches are expected and shouldn't be reported
+        if let Some(mapper) = self.fallback_mapper {
+            egui::Area::new("mapper fallback")
+                .anchor(egui::Align2::CENTER_TOP, [0.0, 24.0])
+                .show(&gui.context, |ui| {
+                    Frame::popup(ui.style()).show(ui, |ui| {
+                        ui.colored_label(
+                            Color32::YELLOW,
+                            format!("Mapper {mapper} isn't supported, running as NROM"),
+                        );
+                    });
+                });
+        }
+
+        if let Some(addr) = self.jammed_at {
+            egui::Window::new("CPU jammed")
+                .collapsible(false)
+                .resizable(false)
+                .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+                .show(&gui.context, |ui| {
+                    ui.label(format!("CPU jammed at ${addr:04X}"));
+                    if ui.button("Reset").clicked() {
+                        controller.reset();
+                        self.jammed_at = None;
+                    }
+                });
+        }
+
+        let cursor_pos = gui.state.pointer_pos;
         if cursor_pos != self.prev_cursor_pos {
             self.prev_cursor_pos = cursor_pos;
             self.menu_timeout_start = SystemTime::now();
@@ -175,31 +817,192 @@ impl Ui {
 
         self.mouse.show_cursor(!hide_panel);
 
+        let mut new_scale = None;
+        let mut fit_aspect = false;
         if !hide_panel {
-            egui::TopBottomPanel::top("top panel").show(&self.egui_context, |ui| {
+            egui::TopBottomPanel::top("top panel").show(&gui.context, |ui| {
                 egui::menu::bar(ui, |ui| {
                     ui.menu_button("File", |ui| {
                         if ui.button("Load ROM").clicked() {
                             println!("Loading ROM!");
                         }
+                        if ui.button("Reload ROM (F5)").clicked() {
+                            self.reload_requested = true;
+                            ui.close_menu();
+                        }
+                        if ui.button("Save state (F2)").clicked() {
+                            self.save_state_requested = true;
+                            ui.close_menu();
+                        }
+                        if ui.button("Load state (F3)").clicked() {
+                            self.load_state_requested = true;
+                            ui.close_menu();
+                        }
                         if ui.button("Reset").clicked() {
                             controller.reset();
+                            self.jammed_at = None;
+                            ui.close_menu();
+                        }
+                        if self.rom_list.len() > 1 {
+                            ui.menu_button("Switch ROM (F6)", |ui| {
+                                for (idx, name) in self.rom_list.iter().enumerate() {
+                                    if ui.radio(idx == self.current_rom, name).clicked() {
+                                        self.switch_to = (idx != self.current_rom).then_some(idx);
+                                        ui.close_menu();
+                                    }
+                                }
+                            });
+                        }
+                        if ui.button("Screenshot (F12)").clicked() {
+                            self.capture_requests.push(CaptureStage::Game);
+                            ui.close_menu();
+                        }
+                        if ui.button("Screenshot with overlays").clicked() {
+                            self.capture_requests.push(CaptureStage::Composited);
+                            ui.close_menu();
+                        }
+                        ui.horizontal(|ui| {
+                            ui.add(
+                                egui::TextEdit::singleline(&mut self.anchor_name)
+                                    .hint_text("anchor name")
+                                    .desired_width(100.0),
+                            );
+                            let name = self.anchor_name.trim();
+                            if ui.button("Mark anchor").clicked() && !name.is_empty() {
+                                self.anchor_requested = Some(name.to_owned());
+                                ui.close_menu();
+                            }
+                        });
+                        ui.horizontal(|ui| {
+                            if ui.button("Report issue with this game").clicked() {
+                                self.report_requested = true;
+                                ui.close_menu();
+                            }
+                            ui.checkbox(&mut self.report_screenshot, "with screenshot");
+                        });
+                        if ui.button("ROM info").clicked() {
+                            self.show_rom_info = true;
                             ui.close_menu();
                         }
+                        if let Some((release, _)) = self.update.as_ref() {
+                            let text = format!("Copy link to rnes {}", release.version);
+                            if ui.button(text).clicked() {
+                                ui.output().copied_text.clone_from(&release.url);
+                                ui.close_menu();
+                            }
+                        }
                         if ui.button("Quit").clicked() {
-                            std::process::exit(0);
+                            self.quit_requested = true;
+                        }
+                    });
+                    ui.menu_button("View", |ui| {
+                        for scale in 1..=MAX_SCALE {
+                            if ui.button(format!("Scale {scale}x ({scale})")).clicked() {
+                                new_scale = Some(scale);
+                                ui.close_menu();
+                            }
+                        }
+                        ui.checkbox(&mut self.settings.keep_aspect, "Keep aspect on resize");
+                        if ui
+                            .checkbox(&mut self.settings.crop_overscan, "Crop overscan")
+                            .changed()
+                        {
+                            fit_aspect = self.settings.keep_aspect;
+                        }
+                        ui.separator();
+                        self.settings.panels.checkbox(ui, SCOPES_TITLE);
+                        ui.checkbox(&mut self.show_play_time, "Play time");
+                        ui.separator();
+                        ui.label("Speed (hold Tab for 2x)");
+                        for speed in SPEEDS {
+                            let label = format!("{}%", (speed * 100.0) as u32);
+                            ui.radio_value(&mut self.speed, speed, label);
+                        }
+                        ui.checkbox(&mut self.muted, "Mute audio");
+                        if ui.button("Controls").clicked() {
+                            self.bindings_window.open = true;
+                            ui.close_menu();
+                        }
+                        if ui.button("Gamepads").clicked() {
+                            self.gamepads.window_open = true;
+                            ui.close_menu();
+                        }
+                        ui.label("Opposing directions");
+                        for opposing in OpposingDirections::ALL {
+                            ui.radio_value(
+                                &mut self.settings.opposing_directions,
+                                opposing,
+                                opposing.name(),
+                            );
+                        }
+                        ui.checkbox(
+                            &mut self.settings.pause_in_background,
+                            "Pause in background",
+                        );
+                        ui.label("Between frames");
+                        for interpolation in [Interpolation::None, Interpolation::Duplicate] {
+                            ui.radio_value(
+                                &mut self.settings.interpolation,
+                                interpolation,
+                                interpolation.name(),
+                            );
+                        }
+                        ui.label("Scaler");
+                        for scaler in ScalerKind::ALL {
+                            if ui
+                                .radio_value(&mut self.settings.scaler, scaler, scaler.name())
+                                .changed()
+                            {
+                                self.filters = scaler.chain();
+                            }
+                        }
+                        ui.separator();
+                        if ui.button("Test rumble").clicked() {
+                            self.rumble.rumble(1.0, 250);
+                        }
+                        let mut latency_test = self.latency.is_some();
+                        if ui
+                            .checkbox(&mut latency_test, "Input latency test")
+                            .changed()
+                        {
+                            self.latency = latency_test.then(LatencyMeter::default);
+                        }
+                    });
+                    ui.menu_button("Debug", |ui| {
+                        Debugger::menu(ui, &mut self.settings.panels);
+                        ui.checkbox(&mut self.show_pixel_sources, "Show pixel layers");
+                        if self.show_pixel_sources {
+                            Debugger::pixel_source_legend(ui);
+                        }
+                        if ui.button("Dump trace (F9)").clicked() {
+                            self.trace_dump_requested = true;
+                            ui.close_menu();
+                        }
+                        if ui.button("Save audio info").clicked() {
+                            self.audio_info_requested = true;
+                            ui.close_menu();
                         }
                     });
                 });
             });
         }
 
-        let (egui_output, paint_cmds) = self.egui_context.end_frame();
-        self.egui_state.process_output(&self.window, &egui_output);
+        if let Some(scale) = new_scale {
+            self.settings.scale_window(&mut self.window, scale);
+        } else if fit_aspect {
+            self.settings.fit_window(&mut self.window);
+        }
 
-        let paint_jobs = self.egui_context.tessellate(paint_cmds);
-        self.egui_painter
-            .paint_jobs(None, paint_jobs, &self.egui_context.font_image());
+        let (egui_output, paint_cmds) = gui.context.end_frame();
+        gui.state.process_output(&self.window, &egui_output);
+
+        gui.last_paint = gui.context.tessellate(paint_cmds);
+        gui.painter
+            .paint_jobs(None, gui.last_paint.clone(), &gui.context.font_image());
+        if read_back {
+            let (width, height) = self.window.drawable_size();
+            self.captures.push(Capture::read_back_buffer(width, height));
+        }
 
         // println!(
         //     "Rendering took {:?}",
@@ -207,69 +1010,504 @@ impl Ui {
         // );
 
         let minimized = self.window.window_flags() & 64 != 0;
-        if self.window.fullscreen_state() != FullscreenType::True || minimized {
-            let mut now = SystemTime::now();
-            if now < self.next_render_time {
-                while now < self.next_render_time {
-                    yield_now();
-                    now = SystemTime::now();
+        let vsync = self.window.fullscreen_state() == FullscreenType::True && !minimized;
+        if vsync {
+            // The swap waits for the display, frames are only counted here
+            self.window.gl_swap_window();
+            let now = self.timer.performance_counter();
+            self.next_render_time = self.next_render_time.max(now) + self.frame_ticks();
+        } else {
+            self.wait_for_next_frame();
+            self.window.gl_swap_window();
+        }
+        self.update_title();
+        self.repeat_until_due(vsync);
+    }
+
+    /// Fills the display refreshes before the next emulated frame is due, leaving
+    /// the last one to emulate that frame in
+    fn repeat_until_due(&mut self, vsync: bool) {
+        let Some(refresh) = self.refresh_ticks else {
+            return;
+        };
+        let duplicate = self.settings.interpolation == Interpolation::Duplicate;
+        // A vsynced swap lands on the next refresh, a free running one when called
+        let lead = if vsync {
+            refresh + refresh / 2
+        } else {
+            2 * refresh
+        };
+        let mut presented = self.timer.performance_counter();
+        while presented + lead <= self.next_render_time {
+            let Some(gui) = self.gui.as_mut().filter(|_| duplicate) else {
+                // Only vsync needs holding back, the windowed pacer already waited
+                if vsync {
+                    Self::sleep_until(&self.timer, self.next_render_time - lead);
                 }
-            } else {
-                println!("Frame rendering late");
+                return;
+            };
+            if !vsync {
+                Self::sleep_until(&self.timer, presented + refresh);
+            }
+            unsafe {
+                gl::ClearColor(0.0, 0.0, 0.0, 1.0);
+                gl::Clear(gl::COLOR_BUFFER_BIT);
             }
-            self.next_render_time = now + Duration::from_nanos(16_666_666);
+            gui.painter
+                .paint_jobs(None, gui.last_paint.clone(), &gui.context.font_image());
+            self.window.gl_swap_window();
+            presented = self.timer.performance_counter();
+        }
+    }
+
+    fn refresh_ticks(window: &Window, timer: &TimerSubsystem) -> Option<u64> {
+        let mode = window.display_mode().ok()?;
+        (mode.refresh_rate > 0)
+            .then(|| Self::nanos_to_ticks(timer, 1_000_000_000 / mode.refresh_rate as u64))
+    }
+
+    fn frame_ticks(&self) -> u64 {
+        Self::nanos_to_ticks(
+            &self.timer,
+            (FRAME_NANOS as f64 / self.speed() as f64) as u64,
+        )
+    }
+
+    fn draw_scopes(ctx: &CtxRef, apu: &Apu, panels: &mut PanelLayout) {
+        const SCOPE_SAMPLES: usize = 512;
+
+        panels.show(
+            ctx,
+            SCOPES_TITLE,
+            |window| window,
+            |ui| {
+                for (idx, (scope, name)) in apu.scopes.iter().zip(Apu::CHANNEL_NAMES).enumerate() {
+                    // DMC has a 7-bit output, the rest are 4-bit
+                    let max = if idx == 4 { 127.0 } else { 15.0 };
+                    let samples = scope.synced(SCOPE_SAMPLES);
+                    let line = Line::new(Values::from_values_iter(
+                        samples
+                            .iter()
+                            .enumerate()
+                            .map(|(x, y)| Value::new(x as f64, *y as f64)),
+                    ));
+                    let mute = apu.pulse_mutes().get(idx).copied().flatten();
+                    if let Some(mute) = mute {
+                        ui.horizontal(|ui| {
+                            ui.label(name);
+                            ui.colored_label(
+                                Color32::YELLOW,
+                                format!("muted: {}", mute.describe()),
+                            );
+                        });
+                    } else {
+                        ui.label(name);
+                    }
+                    Plot::new(name)
+                        .height(60.0)
+                        .width(300.0)
+                        .include_y(0.0)
+                        .include_y(max)
+                        .include_x(0.0)
+                        .include_x(SCOPE_SAMPLES as f64)
+                        .allow_drag(false)
+                        .allow_zoom(false)
+                        .show_axes([false, false])
+                        .show(ui, |plot_ui| plot_ui.line(line));
+                }
+            },
+        );
+    }
+
+    fn draw_rom_info(ctx: &CtxRef, info: &RomInfo, stats: Option<GameStats>, open: &mut bool) {
+        egui::Window::new("ROM info")
+            .open(open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                if let Some(name) = &info.db_name {
+                    ui.label(format!("Database: {name}"));
+                }
+                ui.label(format!("CRC32: {:08X}", info.crc32));
+                ui.label(format!("SHA1: {}", info.sha1));
+                ui.label(format!(
+                    "PRG ROM: {} kB, CHR ROM: {} kB, file: {} bytes",
+                    info.prg_len / 1024,
+                    info.chr_len / 1024,
+                    info.file_len
+                ));
+                for warning in &info.warnings {
+                    ui.colored_label(Color32::YELLOW, warning);
+                }
+                if let Some(stats) = stats {
+                    ui.separator();
+                    let secs = stats.play_time.as_secs();
+                    ui.label(format!(
+                        "Played for {}:{:02}:{:02}",
+                        secs / 3600,
+                        secs / 60 % 60,
+                        secs % 60
+                    ));
+                    ui.label(format!(
+                        "Resets: {}, save states loaded: {}",
+                        stats.resets, stats.states_loaded
+                    ));
+                }
+            });
+    }
+
+    // Returns true if the user asked for a trimmed copy
+    fn draw_rom_warnings(ctx: &CtxRef, info: &RomInfo, open: &mut bool) -> bool {
+        let mut trim = false;
+        egui::Window::new("ROM dump problems")
+            .open(open)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                for warning in &info.warnings {
+                    ui.label(warning);
+                }
+                ui.label("Emulation problems may be caused by the dump, not the emulator.");
+                trim = ui.button("Save trimmed copy").clicked();
+            });
+        trim
+    }
+
+    // Flashes the screen when the game sees a press, and shows the measurements
+    fn draw_latency(ctx: &CtxRef, meter: &LatencyMeter) {
+        if meter.flash() {
+            ctx.layer_painter(egui::LayerId::new(
+                egui::Order::Foreground,
+                egui::Id::new("latency flash"),
+            ))
+            .rect_filled(ctx.input().screen_rect(), 0.0, Color32::WHITE);
+        }
+        egui::Window::new("Input latency")
+            .resizable(false)
+            .show(ctx, |ui| ui.label(meter.summary()));
+    }
+
+    fn nanos_to_ticks(timer: &TimerSubsystem, nanos: u64) -> u64 {
+        (timer.performance_frequency() as u128 * nanos as u128 / 1_000_000_000) as u64
+    }
+
+    /// Sleeps until shortly before the next frame is due and spins for the rest,
+    /// so the pacer doesn't keep a core busy but still hits the deadline precisely
+    fn wait_for_next_frame(&mut self) {
+        let frame_ticks = self.frame_ticks();
+        let now = self.timer.performance_counter();
+        if now >= self.next_render_time {
+            println!("Frame rendering late");
+            self.next_render_time = now + frame_ticks;
+            return;
         }
-        self.window.gl_swap_window();
+        Self::sleep_until(&self.timer, self.next_render_time);
+        self.next_render_time += frame_ticks;
     }
 
+    fn sleep_until(timer: &TimerSubsystem, deadline: u64) {
+        let spin_ticks = Self::nanos_to_ticks(timer, SPIN_NANOS);
+        let remaining = deadline.saturating_sub(timer.performance_counter());
+        if remaining > spin_ticks {
+            let sleep_nanos = (remaining - spin_ticks) as u128 * 1_000_000_000
+                / timer.performance_frequency() as u128;
+            std::thread::sleep(Duration::from_nanos(sleep_nanos as u64));
+        }
+        while timer.performance_counter() < deadline {
+            std::hint::spin_loop();
+        }
+    }
+
+    // Refresh the window title with game info and measured FPS about once a second
+    fn update_title(&mut self) {
+        self.fps_frames += 1;
+        let elapsed = match SystemTime::now().duration_since(self.fps_timer) {
+            Ok(val) => val,
+            Err(_) => Duration::from_secs(0),
+        };
+        if elapsed < Duration::from_secs(1) {
+            return;
+        }
+
+        let fps = self.fps_frames as f64 / elapsed.as_secs_f64();
+        self.fps_frames = 0;
+        self.fps_timer = SystemTime::now();
+
+        let title = if self.game_info.name.is_empty() {
+            format!("rN3S - {fps:.1} FPS")
+        } else {
+            format!(
+                "rN3S - {} [{}] - {:.1} FPS",
+                self.game_info.name, self.game_info.region, fps
+            )
+        };
+        // Title can only fail on interior NUL bytes, not worth reporting
+        let _ = self.window.set_title(&title);
+    }
+
+    #[allow(clippy::too_many_lines)]
     pub fn handle_input(&mut self, controller: &mut Controller) {
+        controller.set_opposing_directions(self.settings.opposing_directions);
         for event in self.event_pump.poll_iter() {
+            if self
+                .bindings_window
+                .capture(&mut self.settings.bindings, &event)
+            {
+                continue;
+            }
             match event {
                 Event::KeyDown {
                     keycode: Some(Keycode::Escape),
                     ..
                 }
-                | Event::Quit { .. } => std::process::exit(0),
+                | Event::Quit { .. } => self.quit_requested = true,
                 Event::KeyDown {
                     keycode: Some(Keycode::R),
                     ..
                 } => {
                     controller.reset();
+                    self.jammed_at = None;
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F2),
+                    repeat: false,
+                    ..
+                } => self.save_state_requested = true,
+                Event::KeyDown {
+                    keycode: Some(Keycode::F3),
+                    repeat: false,
+                    ..
+                } => self.load_state_requested = true,
+                Event::KeyDown {
+                    keycode: Some(Keycode::F5),
+                    ..
+                } => self.reload_requested = true,
+                Event::KeyDown {
+                    keycode: Some(Keycode::F6),
+                    ..
+                } => {
+                    if self.rom_list.len() > 1 {
+                        self.switch_to = Some((self.current_rom + 1) % self.rom_list.len());
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F9),
+                    ..
+                } => self.trace_dump_requested = true,
+                Event::KeyDown {
+                    keycode: Some(Keycode::F12),
+                    repeat: false,
+                    ..
+                } => self.capture_requests.push(CaptureStage::Game),
+                Event::KeyDown {
+                    keycode: Some(Keycode::Tab),
+                    ..
+                } => self.fast_forward = true,
+                Event::KeyUp {
+                    keycode: Some(Keycode::Tab),
+                    ..
+                } => self.fast_forward = false,
+                Event::KeyDown {
+                    keycode:
+                        Some(
+                            key @ (Keycode::Num1
+                            | Keycode::Num2
+                            | Keycode::Num3
+                            | Keycode::Num4
+                            | Keycode::Num5),
+                        ),
+                    ..
+                } => self
+                    .settings
+                    .scale_window(&mut self.window, (key as i32 - Keycode::Num0 as i32) as u32),
+                Event::Window {
+                    win_event: WindowEvent::FocusGained,
+                    ..
+                } => self.focused = true,
+                Event::Window {
+                    win_event: WindowEvent::FocusLost,
+                    ..
+                } => self.focused = false,
+                Event::Window {
+                    win_event: WindowEvent::Minimized,
+                    ..
+                } => self.minimized = true,
+                Event::Window {
+                    win_event: WindowEvent::Restored,
+                    ..
+                } => self.minimized = false,
+                Event::Window {
+                    win_event: WindowEvent::Resized(..),
+                    ..
+                } => {
+                    if self.settings.keep_aspect {
+                        self.settings.fit_window(&mut self.window);
+                    }
+                    Self::forward(&mut self.gui, &self.window, event);
                 }
-                Event::KeyDown { keycode, .. } => {
-                    if let Some(key) = self.keymap.get(&keycode.unwrap_or(Keycode::Ampersand)) {
-                        controller.set_button_state(*key, true);
+                Event::KeyDown {
+                    keycode,
+                    timestamp,
+                    repeat,
+                    ..
+                } => {
+                    if let Some(key) = keycode.and_then(|k| self.settings.bindings.key_button(k)) {
+                        controller.set_button_state(key, true);
+                        if let (Some(meter), false) = (self.latency.as_mut(), repeat) {
+                            meter.button_pressed(
+                                timestamp,
+                                controller.latch_count(),
+                                self.frame_count,
+                            );
+                        }
                     } else {
-                        self.egui_state
-                            .process_input(&self.window, event, &mut self.egui_painter);
+                        Self::forward(&mut self.gui, &self.window, event);
                     }
                 }
                 Event::KeyUp { keycode, .. } => {
-                    if let Some(key) = self.keymap.get(&keycode.unwrap_or(Keycode::Ampersand)) {
-                        controller.set_button_state(*key, false);
+                    if let Some(key) = keycode.and_then(|k| self.settings.bindings.key_button(k)) {
+                        controller.set_button_state(key, false);
                     } else {
-                        self.egui_state
-                            .process_input(&self.window, event, &mut self.egui_painter);
+                        Self::forward(&mut self.gui, &self.window, event);
+                    }
+                }
+                Event::ControllerDeviceAdded { which, .. } => self.gamepads.added(which),
+                Event::ControllerDeviceRemoved { which, .. } => {
+                    let released = self.gamepads.removed(which);
+                    Self::release_pad_buttons(&self.settings.bindings, controller, &released);
+                }
+                Event::ControllerButtonDown {
+                    which,
+                    button,
+                    timestamp,
+                    ..
+                } => {
+                    if !self.gamepads.button(which, button, true) {
+                        continue;
+                    }
+                    if let Some(key) = self.settings.bindings.pad_button(button) {
+                        controller.set_button_state(key, true);
+                        if let Some(meter) = self.latency.as_mut() {
+                            meter.button_pressed(
+                                timestamp,
+                                controller.latch_count(),
+                                self.frame_count,
+                            );
+                        }
+                    }
+                }
+                Event::ControllerButtonUp { which, button, .. } => {
+                    if !self.gamepads.button(which, button, false) {
+                        continue;
+                    }
+                    if let Some(key) = self.settings.bindings.pad_button(button) {
+                        controller.set_button_state(key, false);
                     }
                 }
                 _ => {
-                    self.egui_state
-                        .process_input(&self.window, event, &mut self.egui_painter);
+                    Self::forward(&mut self.gui, &self.window, event);
                 }
             }
         }
     }
 
-    fn build_keymap() -> HashMap<Keycode, Button> {
-        HashMap::from([
-            (Keycode::Down, Button::Down),
-            (Keycode::Up, Button::Up),
-            (Keycode::Right, Button::Right),
-            (Keycode::Left, Button::Left),
-            (Keycode::Q, Button::Select),
-            (Keycode::W, Button::Start),
-            (Keycode::S, Button::A),
-            (Keycode::A, Button::B),
-        ])
+    fn release_pad_buttons(
+        bindings: &Bindings,
+        controller: &mut Controller,
+        buttons: &[PadButton],
+    ) {
+        for &button in buttons {
+            if let Some(key) = bindings.pad_button(button) {
+                controller.set_button_state(key, false);
+            }
+        }
+    }
+
+    // Passes events the emulator doesn't handle itself on to egui
+    fn forward(gui: &mut Option<Gui>, window: &Window, event: Event) {
+        if let Some(gui) = gui.as_mut() {
+            gui.state.process_input(window, event, &mut gui.painter);
+        }
+    }
+}
+
+impl Drop for Ui {
+    fn drop(&mut self) {
+        if self.window.fullscreen_state() == FullscreenType::Off {
+            (self.settings.width, self.settings.height) = self.window.size();
+        }
+        if let Err(e) = self.settings.save(SETTINGS_FILE) {
+            println!("Failed to save window settings: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_window_settings_parse() {
+        let settings = WindowSettings::parse(
+            "width=512\nheight = 448\ncrop_overscan=true\nscaler=xbrz\nbogus\n",
+        );
+        assert_eq!(
+            settings,
+            WindowSettings {
+                width: 512,
+                height: 448,
+                keep_aspect: false,
+                crop_overscan: true,
+                pause_in_background: false,
+                interpolation: Interpolation::Duplicate,
+                scaler: ScalerKind::Xbrz,
+                opposing_directions: OpposingDirections::Allow,
+                bindings: Bindings::default(),
+                panels: PanelLayout::default(),
+                controller_mappings: Vec::new(),
+                capture: CaptureNaming::default(),
+            }
+        );
+        assert_eq!(settings.visible_height(), 224);
+    }
+
+    #[test]
+    fn test_controller_mappings() {
+        let mapping = "03000000000000000000000000000000,Pad,a:b1,b:b0,platform:Linux,";
+        let settings = WindowSettings::parse(&format!("controller_mapping = {mapping}\n"));
+        assert_eq!(settings.controller_mappings, [mapping]);
+    }
+
+    #[test]
+    fn test_capture_settings() {
+        let settings = WindowSettings::parse("capture_dir = shots\ncapture_name={game}_{frame}\n");
+        assert_eq!(settings.capture.dir.as_deref(), Some(Path::new("shots")));
+        assert_eq!(settings.capture.template, "{game}_{frame}");
+        assert_eq!(WindowSettings::parse(&settings.text()), settings);
+
+        let settings = WindowSettings::parse("capture_dir=\ncapture_name=\n");
+        assert_eq!(settings.capture, CaptureNaming::default());
+    }
+
+    #[test]
+    fn test_interpolation_setting() {
+        let settings = WindowSettings::parse("interpolation=none\n");
+        assert_eq!(settings.interpolation, Interpolation::None);
+        let settings = WindowSettings::parse("interpolation=blend\n");
+        assert_eq!(settings.interpolation, Interpolation::Duplicate);
+    }
+
+    #[test]
+    fn test_window_settings_defaults() {
+        assert_eq!(
+            WindowSettings::parse("width=abc"),
+            WindowSettings::default()
+        );
+    }
+
+    #[test]
+    fn test_fit_size() {
+        // Wide window gets pillarboxed, tall window letterboxed
+        assert_eq!(fit_size(1000.0, 300.0, 2.0), (600.0, 300.0));
+        assert_eq!(fit_size(512.0, 1000.0, 2.0), (512.0, 256.0));
     }
 }
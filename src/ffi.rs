@@ -0,0 +1,354 @@
+//! C ABI for embedding the core in frontends written in other languages. Build with
+//! `cargo rustc --lib --release --features ffi --crate-type cdylib` and include
+//! `include/rnes.h`, which is generated from this file with `cbindgen`.
+//!
+//! Every console is driven one frame at a time from a single thread.
+
+use std::cell::RefCell;
+use std::ffi::{c_char, CString};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::rc::Rc;
+
+use eyre::{eyre, Result};
+
+use crate::console::{
+    apu::Apu, controller::Controller, video::Frame, Console, Frontend, SCREEN_HEIGHT, SCREEN_WIDTH,
+};
+
+/// Bytes in the RGBA framebuffer
+pub const RNES_FRAMEBUFFER_LEN: usize = SCREEN_WIDTH * SCREEN_HEIGHT * 4;
+
+thread_local! {
+    static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+}
+
+fn set_last_error(message: &str) {
+    let message = CString::new(message.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|error| *error.borrow_mut() = message);
+}
+
+// Turns an error or a panic into `false` and the message for `rnes_last_error`
+fn report(run: impl FnOnce() -> Result<()>) -> bool {
+    match panic::catch_unwind(AssertUnwindSafe(run)) {
+        Ok(Ok(())) => true,
+        Ok(Err(e)) => {
+            set_last_error(&e.to_string());
+            false
+        }
+        Err(_) => {
+            set_last_error("Emulator panicked");
+            false
+        }
+    }
+}
+
+/// What the frontend and the C side both see
+struct Shared {
+    framebuffer: Vec<u8>,
+    buttons: u8,
+    frame_done: bool,
+}
+
+/// Stops the console after every frame so `rnes_console_run_frame` can return
+struct FfiFrontend {
+    shared: Rc<RefCell<Shared>>,
+}
+
+impl Frontend for FfiFrontend {
+    fn handle_io(&mut self, frame: &Frame, _apu: &Apu, controller: &mut Controller) {
+        let mut shared = self.shared.borrow_mut();
+        shared.framebuffer.copy_from_slice(frame.rgba);
+        controller.set_buttons(shared.buttons);
+        shared.frame_done = true;
+    }
+
+    fn handle_audio(&mut self, _apu: &Apu) -> Result<()> {
+        Ok(())
+    }
+
+    fn wants_audio(&self) -> bool {
+        false
+    }
+
+    fn poll_input(&mut self, controller: &mut Controller) {
+        controller.set_buttons(self.shared.borrow().buttons);
+    }
+
+    fn quit_requested(&self) -> bool {
+        self.shared.borrow().frame_done
+    }
+}
+
+/// A console and the frontend it borrows, opaque to C
+pub struct RnesConsole {
+    console: Option<Console<'static>>,
+    /// Owned, freed once the console borrowing it is gone
+    frontend: *mut FfiFrontend,
+    shared: Rc<RefCell<Shared>>,
+}
+
+impl RnesConsole {
+    fn load(&mut self, rom: &[u8]) -> Result<()> {
+        self.console = None;
+        // Safety: the previous console and its borrow were dropped above
+        let frontend = unsafe { &mut *self.frontend };
+        self.console = Some(Console::new(rom, frontend)?);
+        Ok(())
+    }
+
+    fn console(&mut self) -> Result<&mut Console<'static>> {
+        self.console.as_mut().ok_or_else(|| eyre!("No ROM loaded"))
+    }
+}
+
+impl Drop for RnesConsole {
+    fn drop(&mut self) {
+        self.console = None;
+        // Safety: created by Box::into_raw in rnes_console_create, nothing borrows it now
+        drop(unsafe { Box::from_raw(self.frontend) });
+    }
+}
+
+unsafe fn byte_slice<'a>(data: *const u8, len: usize, what: &str) -> Result<&'a [u8]> {
+    if data.is_null() {
+        return Err(eyre!("{what} pointer is null"));
+    }
+    Ok(std::slice::from_raw_parts(data, len))
+}
+
+/// Powers on a console with an iNES image. Returns null on failure, see
+/// `rnes_last_error`.
+///
+/// # Safety
+/// `rom` must point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn rnes_console_create(rom: *const u8, len: usize) -> *mut RnesConsole {
+    let shared = Rc::new(RefCell::new(Shared {
+        framebuffer: vec![0; RNES_FRAMEBUFFER_LEN],
+        buttons: 0,
+        frame_done: false,
+    }));
+    let frontend = Box::into_raw(Box::new(FfiFrontend {
+        shared: shared.clone(),
+    }));
+    let mut console = Box::new(RnesConsole {
+        console: None,
+        frontend,
+        shared,
+    });
+    if report(|| console.load(byte_slice(rom, len, "ROM")?)) {
+        Box::into_raw(console)
+    } else {
+        ptr::null_mut()
+    }
+}
+
+/// Frees a console from `rnes_console_create`, null is ignored
+///
+/// # Safety
+/// `console` must be null or a console that hasn't been destroyed yet.
+#[no_mangle]
+pub unsafe extern "C" fn rnes_console_destroy(console: *mut RnesConsole) {
+    if !console.is_null() {
+        drop(Box::from_raw(console));
+    }
+}
+
+/// Replaces the ROM and powers the console on again
+///
+/// # Safety
+/// `console` must be valid and `rom` must point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn rnes_console_load_rom(
+    console: *mut RnesConsole,
+    rom: *const u8,
+    len: usize,
+) -> bool {
+    let console = &mut *console;
+    report(|| console.load(byte_slice(rom, len, "ROM")?))
+}
+
+/// Emulates until the next frame is complete
+///
+/// # Safety
+/// `console` must be valid.
+#[no_mangle]
+pub unsafe extern "C" fn rnes_console_run_frame(console: *mut RnesConsole) -> bool {
+    let console = &mut *console;
+    console.shared.borrow_mut().frame_done = false;
+    report(|| console.console()?.run_with_callback(|_| {}))
+}
+
+/// Saves the console state into `buffer`, which holds `*len` bytes. The state's
+/// size is stored in `*len`; if `buffer` is null only the size is stored, and if
+/// it is too small the call fails.
+///
+/// # Safety
+/// `console` and `len` must be valid and `buffer` must be null or point to `*len`
+/// writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn rnes_console_save_state(
+    console: *mut RnesConsole,
+    buffer: *mut u8,
+    len: *mut usize,
+) -> bool {
+    let console = &mut *console;
+    report(|| {
+        let state = console.console()?.save_state();
+        let capacity = std::mem::replace(&mut *len, state.len());
+        if buffer.is_null() {
+            return Ok(());
+        }
+        if capacity < state.len() {
+            return Err(eyre!(
+                "State needs {} bytes, buffer holds {capacity}",
+                state.len()
+            ));
+        }
+        ptr::copy_nonoverlapping(state.as_ptr(), buffer, state.len());
+        Ok(())
+    })
+}
+
+/// Continues from a state saved with `rnes_console_save_state` for the same ROM.
+/// On failure the console is left as it was.
+///
+/// # Safety
+/// `console` must be valid and `state` must point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn rnes_console_load_state(
+    console: *mut RnesConsole,
+    state: *const u8,
+    len: usize,
+) -> bool {
+    let console = &mut *console;
+    report(|| {
+        let state = byte_slice(state, len, "State")?;
+        console.console()?.load_state(state)
+    })
+}
+
+/// The last completed frame, `RNES_FRAMEBUFFER_LEN` bytes of 256x240 RGBA pixels.
+/// Stays valid until the console is destroyed.
+///
+/// # Safety
+/// `console` must be valid.
+#[no_mangle]
+pub unsafe extern "C" fn rnes_console_framebuffer(console: *const RnesConsole) -> *const u8 {
+    (*(*console).shared.as_ptr()).framebuffer.as_ptr()
+}
+
+/// Sets controller 1 buttons, one bit each: A, B, Select, Start, Up, Down, Left, Right
+/// from bit 0
+///
+/// # Safety
+/// `console` must be valid.
+#[no_mangle]
+pub unsafe extern "C" fn rnes_console_set_input(console: *mut RnesConsole, buttons: u8) {
+    (*console).shared.borrow_mut().buttons = buttons;
+}
+
+/// Message of the last failed call on this thread, valid until the next failure
+#[no_mangle]
+pub extern "C" fn rnes_last_error() -> *const c_char {
+    LAST_ERROR.with(|error| error.borrow().as_ptr())
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod test {
+    use super::*;
+    use crate::console::ines_image;
+    use std::ffi::CStr;
+
+    // NROM image looping on JMP $8000 with rendering off
+    fn rom() -> Vec<u8> {
+        let mut prg = vec![0; 0x4000];
+        prg[..3].copy_from_slice(&[0x4C, 0x00, 0x80]);
+        prg[0x3FFC..0x3FFE].copy_from_slice(&[0x00, 0x80]);
+        ines_image(0, &prg, &[0; 0x2000])
+    }
+
+    #[test]
+    fn test_run_frames() {
+        let rom = rom();
+        unsafe {
+            let console = rnes_console_create(rom.as_ptr(), rom.len());
+            assert!(!console.is_null());
+            rnes_console_set_input(console, 0x08);
+            for _ in 0..3 {
+                assert!(rnes_console_run_frame(console));
+            }
+            assert_eq!((*console).console.as_ref().unwrap().time().frames, 3);
+            let framebuffer =
+                std::slice::from_raw_parts(rnes_console_framebuffer(console), RNES_FRAMEBUFFER_LEN);
+            assert!(framebuffer.chunks(4).all(|pixel| pixel[3] == 255));
+
+            assert!(!rnes_console_load_rom(console, rom.as_ptr(), 3));
+            assert!(!rnes_console_run_frame(console));
+            assert_eq!(
+                CStr::from_ptr(rnes_last_error()).to_str().unwrap(),
+                "No ROM loaded"
+            );
+            assert!(rnes_console_load_rom(console, rom.as_ptr(), rom.len()));
+            rnes_console_destroy(console);
+        }
+    }
+
+    #[test]
+    fn test_save_and_load_state() {
+        let rom = rom();
+        unsafe {
+            let console = rnes_console_create(rom.as_ptr(), rom.len());
+            assert!(rnes_console_run_frame(console));
+
+            let mut len = 0;
+            assert!(rnes_console_save_state(
+                console,
+                ptr::null_mut(),
+                &raw mut len
+            ));
+            let mut state = vec![0; len];
+            let mut short = len - 1;
+            assert!(!rnes_console_save_state(
+                console,
+                state.as_mut_ptr(),
+                &raw mut short
+            ));
+            assert_eq!(short, len);
+            assert!(rnes_console_save_state(
+                console,
+                state.as_mut_ptr(),
+                &raw mut len
+            ));
+            assert_eq!(len, state.len());
+
+            assert!(rnes_console_run_frame(console));
+            assert!(rnes_console_load_state(
+                console,
+                state.as_ptr(),
+                state.len()
+            ));
+            assert_eq!((*console).console.as_ref().unwrap().time().frames, 1);
+            assert!(!rnes_console_load_state(console, state.as_ptr(), 8));
+            assert!(!rnes_console_load_state(console, ptr::null(), 0));
+            assert_eq!(
+                CStr::from_ptr(rnes_last_error()).to_str().unwrap(),
+                "State pointer is null"
+            );
+            rnes_console_destroy(console);
+        }
+    }
+
+    #[test]
+    fn test_invalid_rom() {
+        unsafe {
+            assert!(rnes_console_create(ptr::null(), 0).is_null());
+            assert_eq!(
+                CStr::from_ptr(rnes_last_error()).to_str().unwrap(),
+                "ROM pointer is null"
+            );
+        }
+    }
+}
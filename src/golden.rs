@@ -0,0 +1,123 @@
+use std::path::{Path, PathBuf};
+
+use eyre::{eyre, Result, WrapErr};
+
+use crate::console::video::diff;
+use crate::console::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+/// Saves an RGBA frame as a binary PPM image, which most image viewers open
+pub fn save_ppm(path: &Path, rgba: &[u8]) -> Result<()> {
+    write_ppm(path, SCREEN_WIDTH, SCREEN_HEIGHT, rgba)
+}
+
+/// Saves an RGBA image of any size as a binary PPM image
+pub fn write_ppm(path: &Path, width: usize, height: usize, rgba: &[u8]) -> Result<()> {
+    let mut data = format!("P6\n{width} {height}\n255\n").into_bytes();
+    for pixel in rgba.chunks_exact(4) {
+        data.extend_from_slice(&pixel[..3]);
+    }
+    std::fs::write(path, data).wrap_err_with(|| format!("Failed to write {}", path.display()))
+}
+
+/// Loads a binary PPM image of a full frame as RGBA
+pub fn load_ppm(path: &Path) -> Result<Vec<u8>> {
+    let data =
+        std::fs::read(path).wrap_err_with(|| format!("Failed to open {}", path.display()))?;
+    parse_ppm(&data).wrap_err_with(|| format!("Invalid frame image {}", path.display()))
+}
+
+fn parse_ppm(data: &[u8]) -> Result<Vec<u8>> {
+    // Header is four whitespace separated fields followed by a single whitespace byte
+    let mut fields = Vec::new();
+    let mut pos = 0;
+    while fields.len() < 4 {
+        while data.get(pos).is_some_and(u8::is_ascii_whitespace) {
+            pos += 1;
+        }
+        let start = pos;
+        while data.get(pos).is_some_and(|b| !b.is_ascii_whitespace()) {
+            pos += 1;
+        }
+        if start == pos {
+            return Err(eyre!("Truncated PPM header"));
+        }
+        fields.push(String::from_utf8_lossy(&data[start..pos]).into_owned());
+    }
+    let expected = [
+        "P6".to_owned(),
+        SCREEN_WIDTH.to_string(),
+        SCREEN_HEIGHT.to_string(),
+        "255".to_owned(),
+    ];
+    if fields != expected {
+        return Err(eyre!(
+            "Expected a {SCREEN_WIDTH}x{SCREEN_HEIGHT} 8-bit P6 image, got header {}",
+            fields.join(" ")
+        ));
+    }
+    let pixels = data
+        .get(pos + 1..pos + 1 + SCREEN_WIDTH * SCREEN_HEIGHT * 3)
+        .ok_or_else(|| eyre!("Truncated PPM pixel data"))?;
+    Ok(pixels
+        .chunks_exact(3)
+        .flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 255])
+        .collect())
+}
+
+/// Compares a frame with a golden image. On a mismatch the actual frame and a
+/// highlighted diff are written next to the golden image for inspection.
+pub fn check(golden: &Path, rgba: &[u8], tolerance: u8) -> Result<()> {
+    let expected = load_ppm(golden)?;
+    let diff = diff::compare_rgb(&expected, rgba, tolerance);
+    if diff.is_empty() {
+        return Ok(());
+    }
+
+    let actual_file = artifact_path(golden, "actual");
+    let diff_file = artifact_path(golden, "diff");
+    save_ppm(&actual_file, rgba)?;
+    let mut highlighted = rgba.to_vec();
+    diff.highlight(&mut highlighted);
+    save_ppm(&diff_file, &highlighted)?;
+    Err(eyre!(
+        "Frame differs from {}: {diff}, see {} and {}",
+        golden.display(),
+        actual_file.display(),
+        diff_file.display()
+    ))
+}
+
+// golden.ppm -> golden.<suffix>.ppm
+fn artifact_path(golden: &Path, suffix: &str) -> PathBuf {
+    let stem = golden
+        .file_stem()
+        .map_or_else(String::new, |s| s.to_string_lossy().into_owned());
+    golden.with_file_name(format!("{stem}.{suffix}.ppm"))
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ppm_round_trip() {
+        let rgba: Vec<u8> = (0..SCREEN_WIDTH * SCREEN_HEIGHT)
+            .flat_map(|i| [i as u8, (i >> 8) as u8, 7, 255])
+            .collect();
+        let path = std::env::temp_dir().join("rnes_golden_test.ppm");
+        save_ppm(&path, &rgba).unwrap();
+        assert_eq!(load_ppm(&path).unwrap(), rgba);
+        std::fs::remove_file(path).unwrap();
+
+        assert!(parse_ppm(b"P6\n16 16\n255\n").is_err());
+    }
+
+    #[test]
+    fn test_artifact_path() {
+        assert_eq!(
+            artifact_path(Path::new("tests/golden/smb.ppm"), "diff"),
+            Path::new("tests/golden/smb.diff.ppm")
+        );
+    }
+}
@@ -0,0 +1,94 @@
+use std::cell::Cell;
+use std::rc::Rc;
+
+use eyre::Result;
+
+use crate::anchor::Anchor;
+use crate::console::{
+    apu::Apu,
+    controller::Controller,
+    debug::{DebugSnapshot, Fnv1a},
+    video::Frame,
+    Frontend,
+};
+use crate::movie::Movie;
+
+/// Runs the console without a window or audio, optionally replaying a movie,
+/// and keeps a hash of the last completed frame for regression checks.
+pub struct Headless {
+    movie: Option<Movie>,
+    frame_limit: usize,
+    frames_done: usize,
+    pub frame_hash: u64,
+    /// Every pixel of the last frame had the same color
+    pub frame_blank: bool,
+    /// RGBA pixels of the last frame, only kept if set to `Some` before running
+    pub last_frame: Option<Vec<u8>>,
+    /// Set to stop before the frame limit, e.g. by a `TestWatch`
+    stop: Rc<Cell<bool>>,
+    /// Checked against the state once the run reaches it, the movie should start
+    /// with the anchor's input
+    pub anchor: Option<Anchor>,
+    pub anchor_result: Option<Result<()>>,
+}
+
+impl Headless {
+    pub fn new(movie: Option<Movie>, frame_limit: usize) -> Self {
+        Self {
+            movie,
+            frame_limit,
+            frames_done: 0,
+            frame_hash: 0,
+            frame_blank: false,
+            last_frame: None,
+            stop: Rc::new(Cell::new(false)),
+            anchor: None,
+            anchor_result: None,
+        }
+    }
+
+    pub fn stop_handle(&self) -> Rc<Cell<bool>> {
+        self.stop.clone()
+    }
+
+    pub const fn frames_done(&self) -> usize {
+        self.frames_done
+    }
+}
+
+impl Frontend for Headless {
+    fn handle_io(&mut self, frame: &Frame, _apu: &Apu, controller: &mut Controller) {
+        let mut hasher = Fnv1a::default();
+        hasher.write_u16s(frame.indices);
+        self.frame_hash = hasher.finish();
+        self.frame_blank = frame.indices.iter().all(|&p| p == frame.indices[0]);
+        if let Some(last_frame) = self.last_frame.as_mut() {
+            last_frame.clear();
+            last_frame.extend_from_slice(frame.rgba);
+        }
+        if let Some(buttons) = self.movie.as_ref().and_then(|m| m.frame(self.frames_done)) {
+            controller.set_buttons(buttons);
+        }
+        self.frames_done += 1;
+    }
+
+    fn handle_audio(&mut self, _apu: &Apu) -> Result<()> {
+        Ok(())
+    }
+
+    fn wants_debug(&self) -> bool {
+        self.anchor
+            .as_ref()
+            .is_some_and(|anchor| anchor.frames() == self.frames_done + 1)
+    }
+
+    fn debug_snapshot(&mut self, snapshot: &DebugSnapshot) {
+        if let Some(anchor) = self.anchor.as_ref() {
+            self.anchor_result = Some(anchor.verify(&snapshot.hashes));
+        }
+    }
+
+    fn quit_requested(&self) -> bool {
+        self.frames_done >= self.frame_limit || self.stop.get()
+    }
+}
@@ -0,0 +1,73 @@
+#![warn(trivial_numeric_casts)]
+#![warn(clippy::pedantic)]
+#![warn(clippy::unwrap_used)]
+#![warn(clippy::expect_used)]
+#![allow(clippy::cast_sign_loss)]
+#![allow(clippy::cast_lossless)]
+#![allow(clippy::cast_possible_truncation)]
+#![allow(clippy::cast_possible_wrap)]
+#![allow(clippy::cast_precision_loss)]
+#![allow(clippy::bad_bit_mask)]
+// The library only exists for the binary and benchmarks, not as a public API
+#![allow(clippy::must_use_candidate)]
+#![allow(clippy::missing_errors_doc)]
+#![allow(clippy::missing_panics_doc)]
+#![allow(clippy::new_without_default)]
+
+// Emulation core, kept in a library so benchmarks can drive it directly
+
+pub mod console;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+
+pub mod macros {
+    #[macro_export]
+    macro_rules! bit_bool {
+        ($value:ident, $bit:literal) => {
+            ($value >> $bit) & 0x1 == 1
+        };
+    }
+    #[macro_export]
+    macro_rules! bool_u8 {
+        ($value:expr, $bit:literal) => {
+            (($value as u8) << $bit)
+        };
+    }
+
+    #[macro_export]
+    macro_rules! fw_error {
+        ( $x:expr ) => {
+            match $x {
+                Ok(v) => v,
+                Err(e) => return Err(eyre!(e)),
+            }
+        };
+    }
+
+    /// Implements `Snapshot::state_fields` with the given fields, in that order
+    #[macro_export]
+    macro_rules! state_fields {
+        ($($field:ident),* $(,)?) => {
+            fn state_fields(
+                &mut self,
+            ) -> Vec<(&'static str, &mut dyn $crate::console::state::StateField)> {
+                vec![$((
+                    stringify!($field),
+                    &mut self.$field as &mut dyn $crate::console::state::StateField,
+                )),*]
+            }
+        };
+    }
+
+    pub use crate::bit_bool;
+    pub use crate::bool_u8;
+    pub use crate::fw_error;
+    pub use crate::state_fields;
+}
+
+// 21441960 / 12 = 1786830 - if NES ran at exactly 60 Hz
+// const MAIN_FREQ: usize = 21441960;
+pub const MAIN_FREQ: usize = 21_442_080; // 89342 PPU cycles * 60 * 4
+pub const CPU_FREQ: usize = MAIN_FREQ / 12;
+pub const APU_FREQ: usize = CPU_FREQ;
+pub const _PPU_FREQ: usize = MAIN_FREQ / 4;
@@ -9,62 +9,498 @@
 #![allow(clippy::cast_precision_loss)]
 #![allow(clippy::bad_bit_mask)]
 
-mod console;
+mod anchor;
+mod checksum;
+mod compare;
+mod determinism;
 mod emulator;
+mod golden;
+mod headless;
+mod movie;
+mod nsf;
+mod playlist;
+mod raw_prg;
+mod rom_source;
+mod romdb;
+mod run_info;
+mod scan;
+mod test_rom;
+mod thread_tuning;
+mod update_check;
 
-use console::cpu::Cpu;
-use console::ppu::Ppu;
+// The emulation core lives in the library, see lib.rs
+use rnes::{console, macros, APU_FREQ};
+
+use console::access_trace::AccessFilter;
+use console::cpu::{Cpu, JamBehavior};
+use console::video::palette::Palette;
+use eyre::eyre;
 use eyre::Context;
 use eyre::Result;
 use std::env;
+use std::path::Path;
+use std::time::Duration;
+
+// Enough for most games to get past their boot code
+const SCAN_FRAMES: usize = 300;
+
+// Reloaded while running when edited
+const PALETTE_FILE: &str = "cxa.pal";
+// --palette value that generates the palette instead of loading a file
+const NTSC_PALETTE: &str = "ntsc";
+
+// Minutes between battery RAM saves unless --autosave says otherwise
+const AUTOSAVE_MINUTES: u64 = 1;
+
+// Audio is handed from the console to the output in chunks of this many milliseconds,
+// unless --audio-chunk says otherwise. Longer chunks overflow the output buffer target.
+const AUDIO_CHUNK_MS: f64 = 1000.0 / 120.0;
+const MAX_AUDIO_CHUNK_MS: f64 = 20.0;
+
+// Optional list of known good and bad dumps, see romdb.rs
+const ROM_DB_FILE: &str = "romdb.txt";
 
-mod macros {
-    macro_rules! bit_bool {
-        ($value:ident, $bit:literal) => {
-            ($value >> $bit) & 0x1 == 1
+/// Command line options
+#[allow(clippy::struct_excessive_bools)]
+struct Options<'a> {
+    rom_file: &'a str,
+    /// Every ROM given before the first option, the first being `rom_file`
+    rom_files: Vec<&'a str>,
+    trace: bool,
+    fullscreen: bool,
+    renderer: emulator::Renderer,
+    jam_behavior: JamBehavior,
+    sync: run_info::SyncSettings,
+    palette_file: &'a str,
+    autosave_minutes: u64,
+    audio_chunk: Duration,
+    access_filters: Option<Vec<AccessFilter>>,
+    coverage_file: Option<&'a str>,
+    apu_log_file: Option<&'a str>,
+    record_file: Option<&'a str>,
+    movie_file: Option<&'a str>,
+    compare_file: Option<&'a str>,
+    frames: Option<usize>,
+    expect_hash: Option<u64>,
+    save_frame: Option<&'a str>,
+    expect_frame: Option<&'a str>,
+    tolerance: u8,
+    check_every: Option<usize>,
+    hash_log: Option<&'a str>,
+    against_log: Option<&'a str>,
+    audio_info: bool,
+    check_updates: bool,
+    priority: Option<thread_tuning::Priority>,
+    core: Option<usize>,
+    start_from: Option<&'a str>,
+}
+
+impl<'a> Options<'a> {
+    fn parse(args: &'a [String]) -> Result<Self> {
+        let jam_behavior = if args.contains(&"--jam-break".to_owned()) {
+            JamBehavior::Break
+        } else {
+            JamBehavior::Hang
         };
+        Ok(Self {
+            rom_file: &args[1],
+            rom_files: args[1..]
+                .iter()
+                .take_while(|arg| !arg.starts_with("--"))
+                .map(String::as_str)
+                .collect(),
+            trace: args.contains(&"--trace".to_owned()),
+            fullscreen: args.contains(&"--fs".to_owned()),
+            renderer: arg_value(args, "--renderer")
+                .map_or(Ok(emulator::Renderer::Gl), emulator::Renderer::parse)?,
+            jam_behavior,
+            sync: run_info::SyncSettings {
+                alignment: arg_value(args, "--alignment")
+                    .map(console::Alignment::parse)
+                    .transpose()?,
+                dpcm_conflicts: args.contains(&"--dpcm-conflicts".to_owned()),
+                mapper_fallback: args.contains(&"--mapper-fallback".to_owned()),
+                accuracy: arg_value(args, "--accuracy")
+                    .map_or(Ok(console::Accuracy::default()), console::Accuracy::parse)?,
+            },
+            palette_file: arg_value(args, "--palette").unwrap_or(PALETTE_FILE),
+            autosave_minutes: arg_value(args, "--autosave")
+                .map(str::parse::<u64>)
+                .transpose()
+                .wrap_err("Invalid --autosave value")?
+                .unwrap_or(AUTOSAVE_MINUTES),
+            audio_chunk: audio_chunk(arg_value(args, "--audio-chunk"))?,
+            access_filters: arg_value(args, "--trace-access")
+                .map(AccessFilter::parse_list)
+                .transpose()?,
+            coverage_file: arg_value(args, "--coverage"),
+            apu_log_file: arg_value(args, "--apu-log"),
+            record_file: arg_value(args, "--record"),
+            movie_file: arg_value(args, "--play"),
+            compare_file: arg_value(args, "--compare"),
+            frames: arg_value(args, "--frames")
+                .map(str::parse::<usize>)
+                .transpose()
+                .wrap_err("Invalid --frames value")?,
+            expect_hash: arg_value(args, "--expect-hash")
+                .map(|h| u64::from_str_radix(h.trim_start_matches("0x"), 16))
+                .transpose()
+                .wrap_err("Invalid --expect-hash value")?,
+            save_frame: arg_value(args, "--save-frame"),
+            expect_frame: arg_value(args, "--expect-frame"),
+            tolerance: arg_value(args, "--tolerance")
+                .map_or(Ok(0), str::parse)
+                .wrap_err("Invalid --tolerance value")?,
+            check_every: arg_value(args, "--check-determinism")
+                .map(str::parse::<usize>)
+                .transpose()
+                .wrap_err("Invalid --check-determinism value")?,
+            hash_log: arg_value(args, "--hash-log"),
+            against_log: arg_value(args, "--against"),
+            audio_info: args.contains(&"--audio-info".to_owned()),
+            check_updates: args.contains(&"--check-updates".to_owned()),
+            priority: arg_value(args, "--priority")
+                .map(thread_tuning::Priority::parse)
+                .transpose()?,
+            start_from: arg_value(args, "--start-from"),
+            core: arg_value(args, "--core")
+                .map(str::parse::<usize>)
+                .transpose()
+                .wrap_err("Invalid --core value")?,
+        })
+    }
+
+    const fn headless(&self) -> bool {
+        self.movie_file.is_some() || self.frames.is_some()
     }
-    macro_rules! bool_u8 {
-        ($value:expr, $bit:literal) => {
-            (($value as u8) << $bit)
+
+    /// Movie to replay and number of frames to run without a window
+    fn headless_input(&self) -> Result<(Option<movie::Movie>, usize)> {
+        let movie = self
+            .movie_file
+            .map(|f| movie::Movie::load(Path::new(f)))
+            .transpose()?;
+        let frames = match (self.frames, &movie) {
+            (Some(frames), _) => frames,
+            (None, Some(movie)) => movie.len(),
+            (None, None) => return Err(eyre!("Headless run needs --frames or --play")),
         };
+        Ok((movie, frames))
     }
 
-    macro_rules! fw_error {
-        ( $x:expr ) => {
-            match $x {
-                Ok(v) => v,
-                Err(e) => return Err(eyre!(e)),
-            }
+    fn anchor(&self) -> Result<Option<anchor::Anchor>> {
+        self.start_from
+            .map(|file| anchor::Anchor::load(Path::new(file)))
+            .transpose()
+    }
+
+    /// Settings to replay a movie with, those not given taken from the recording.
+    /// Prints how the replay differs from the recording.
+    fn replay_settings(&self, rom: &[u8], movie: Option<&movie::Movie>) -> run_info::SyncSettings {
+        let Some(recorded) = movie.and_then(|movie| movie.info.as_ref()) else {
+            return self.sync;
         };
+        let sync = self.sync.or_recorded(&recorded.settings);
+        let current = run_info::RunInfo::new(checksum::crc32(rom), recorded.region, sync);
+        for warning in recorded.differences(&current) {
+            println!("Movie warning: {warning}");
+        }
+        sync
     }
 
-    pub(crate) use bit_bool;
-    pub(crate) use bool_u8;
-    pub(crate) use fw_error;
+    // Settings shared by windowed and headless runs
+    fn configure(&self, console: &mut console::Console, sync: &run_info::SyncSettings) {
+        console.set_jam_behavior(self.jam_behavior);
+        if let Some(alignment) = sync.alignment {
+            console.set_alignment(alignment);
+        }
+        console.set_dpcm_conflicts(sync.dpcm_conflicts);
+        console.set_accuracy(sync.accuracy);
+        if let Some(filters) = self.access_filters.as_ref() {
+            console.set_access_trace(filters.clone());
+        }
+        if self.coverage_file.is_some() {
+            console.enable_coverage();
+        }
+        if self.apu_log_file.is_some() {
+            console.enable_apu_log();
+        }
+    }
+
+    // Not being allowed a higher priority shouldn't stop the game from running
+    fn tune_thread(&self) {
+        if let Some(priority) = self.priority {
+            if let Err(e) = priority.apply() {
+                println!("{e}");
+            }
+        }
+        if let Some(core) = self.core {
+            if let Err(e) = thread_tuning::pin_to_core(core) {
+                println!("{e}");
+            }
+        }
+    }
+
+    fn export_coverage(&self, console: &console::Console) -> Result<()> {
+        if let (Some(file), Some(coverage)) = (self.coverage_file, console.coverage()) {
+            println!("{}", coverage.summary());
+            coverage
+                .export_cdl(file)
+                .wrap_err_with(|| format!("Failed to write coverage file {file}"))?;
+        }
+        Ok(())
+    }
+
+    fn export_apu_log(&self, console: &console::Console) -> Result<()> {
+        if let (Some(file), Some(log)) = (self.apu_log_file, console.apu_log()) {
+            log.export(file, console.time().cpu_cycles)?;
+        }
+        Ok(())
+    }
+}
+
+fn read_rom(file: &str) -> Result<Vec<u8>> {
+    let rom = rom_source::read(file)?;
+    if nsf::is_nsf(&rom) {
+        print_nsf_info(&nsf::NsfInfo::parse(&rom)?);
+        return Err(eyre!(
+            "{file} is an NSF music file, which can't be played yet"
+        ));
+    }
+    Ok(rom)
 }
 
-// 21441960 / 12 = 1786830 - if NES ran at exactly 60 Hz
-// const MAIN_FREQ: usize = 21441960;
-const MAIN_FREQ: usize = 21_442_080; // 89342 PPU cycles * 60 * 4
-const CPU_FREQ: usize = MAIN_FREQ / 12;
-const APU_FREQ: usize = CPU_FREQ;
-const _PPU_FREQ: usize = MAIN_FREQ / 4;
+fn power_on<'f>(
+    rom: &[u8],
+    frontend: &'f mut dyn console::Frontend,
+    sync: &run_info::SyncSettings,
+) -> Result<console::Console<'f>> {
+    if sync.mapper_fallback {
+        console::Console::with_mapper_fallback(rom, frontend)
+    } else {
+        console::Console::new(rom, frontend)
+    }
+}
 
-fn run_rom(file: &str, do_trace: bool, fullscreen: bool) -> Result<()> {
-    let rom: Vec<u8> =
-        std::fs::read(file).wrap_err_with(|| format!("Failed to open ROM file {}", file))?;
+fn print_nsf_info(info: &nsf::NsfInfo) {
+    println!("{} - {} {}", info.artist, info.title, info.copyright);
+    for (idx, track) in info.tracks.iter().enumerate() {
+        let secs = track.play_time_ms() / 1000;
+        let marker = if idx == info.start_track { '*' } else { ' ' };
+        println!(
+            "{marker}{:3}. {} ({}:{:02})",
+            idx + 1,
+            track.title.as_deref().unwrap_or("<untitled>"),
+            secs / 60,
+            secs % 60
+        );
+    }
+}
 
-    let mut emulator = emulator::Emulator::new(fullscreen)?;
-    let mut console = console::Console::new(&rom, &mut emulator)?;
+fn run_rom(options: &Options) -> Result<()> {
+    let files = playlist::expand(&options.rom_files)?;
+    if files.is_empty() {
+        return Err(eyre!("No ROMs to run"));
+    }
 
+    let mut emulator =
+        emulator::Emulator::new(options.fullscreen, options.renderer, options.audio_chunk)?;
+    options.tune_thread();
+    let palette = if options.palette_file == NTSC_PALETTE {
+        Palette::ntsc()
+    } else {
+        emulator.watch_palette(options.palette_file);
+        Palette::new(options.palette_file)?
+    };
+    if Path::new(ROM_DB_FILE).exists() {
+        emulator.set_rom_db(romdb::RomDb::load(ROM_DB_FILE)?);
+    }
+    #[cfg(feature = "presence")]
+    emulator.set_presence_hook(Box::new(emulator::presence::LogPresence));
+    emulator.set_sync_settings(options.sync);
+    if let Some(record_file) = options.record_file {
+        emulator.start_recording(record_file);
+    }
+    if options.check_updates {
+        emulator.check_for_updates();
+    }
+    if let Some(anchor) = options.anchor()? {
+        emulator.start_from(anchor);
+    }
+    if let Some(compare_file) = options.compare_file {
+        let compare_rom = read_rom(compare_file)?;
+        emulator.start_comparison(compare::Comparison::spawn(compare_rom, palette.clone()));
+    }
+    emulator.set_rom_list(files.iter().map(|file| rom_source::name(file)).collect());
+
+    // Switching ROMs ends the console run and powers on the chosen one
+    let mut current = 0;
+    loop {
+        run_cartridge(options, &mut emulator, &files[current], palette.clone())?;
+        match emulator.take_rom_switch() {
+            Some(next) => current = next,
+            None => break,
+        }
+    }
+
+    if options.audio_info {
+        println!("{}", emulator.audio_report());
+    }
+    emulator.finish_recording()
+}
+
+fn run_cartridge(
+    options: &Options,
+    emulator: &mut emulator::Emulator,
+    file: &str,
+    palette: Palette,
+) -> Result<()> {
+    let rom = read_rom(file)?;
+    if let Some(anchor) = emulator.anchor() {
+        anchor.check_rom(&rom)?;
+    }
+    // Piped and downloaded ROMs can't be reloaded or get a save file
+    let battery_ram = if rom_source::is_file(file) {
+        emulator.set_rom_path(file);
+        let autosave_interval = (options.autosave_minutes > 0)
+            .then(|| Duration::from_secs(60 * options.autosave_minutes));
+        emulator.enable_battery_saves(autosave_interval)?
+    } else {
+        emulator.clear_rom_path();
+        emulator.set_game_name(rom_source::name(file));
+        None
+    };
+    emulator.identify_rom(&rom);
+
+    let mut console = power_on(&rom, emulator, &options.sync)?;
+    options.configure(&mut console, &options.sync);
+    console.set_palette(palette);
+    if let Some(ram) = battery_ram {
+        console.load_battery_ram(&ram);
+    }
+
+    // Traces show every instruction, so idle loops are only skipped without one
+    console.set_idle_skip(!options.trace);
+
+    let do_trace = options.trace;
     console.run_with_callback(move |cpu| {
         if do_trace {
             trace(cpu);
         }
+    })?;
+
+    options.export_coverage(&console)?;
+    options.export_apu_log(&console)
+}
+
+/// Runs without a window for the given number of frames, or the length of the movie,
+/// and prints the hash of the last frame. Fails if it differs from the expected hash.
+fn run_headless(options: &Options) -> Result<()> {
+    let rom = read_rom(options.rom_file)?;
+    let (mut movie, mut frames) = options.headless_input()?;
+    let sync = options.replay_settings(&rom, movie.as_ref());
+    // The run starts with the input that leads to the anchor
+    let anchor = options.anchor()?;
+    if let Some(anchor) = anchor.as_ref() {
+        anchor.check_rom(&rom)?;
+        let mut input = anchor.movie().clone();
+        if let Some(movie) = movie.as_ref() {
+            input.append(movie);
+        }
+        movie = Some(input);
+        frames += anchor.frames();
+    }
+
+    let mut headless = headless::Headless::new(movie, frames);
+    headless.anchor = anchor;
+    if options.save_frame.is_some() || options.expect_frame.is_some() {
+        headless.last_frame = Some(Vec::new());
+    }
+    let mut watch = test_rom::TestWatch::new(headless.stop_handle());
+    let mut console = power_on(&rom, &mut headless, &sync)?;
+    options.configure(&mut console, &sync);
+    let do_trace = options.trace;
+    console.run_with_callback(|cpu| {
+        if do_trace {
+            trace(cpu);
+        }
+        watch.check(cpu);
+    })?;
+    options.export_coverage(&console)?;
+    options.export_apu_log(&console)?;
+    let time = console.time();
+    drop(console);
+    if let Some(result) = headless.anchor_result.take() {
+        result?;
+    } else if let Some(anchor) = headless.anchor.as_ref() {
+        return Err(eyre!("Run stopped before reaching anchor {}", anchor.name));
+    }
+
+    println!(
+        "Frame {} hash {:016X}, emulated time {time}",
+        headless.frames_done(),
+        headless.frame_hash
+    );
+    let last_frame = headless.last_frame.unwrap_or_default();
+    if let Some(file) = options.save_frame {
+        golden::save_ppm(Path::new(file), &last_frame)?;
+    }
+    if let Some(file) = options.expect_frame {
+        golden::check(Path::new(file), &last_frame, options.tolerance)?;
+    }
+    // Test ROMs that report their result need no expected hash
+    if let Some(status) = watch.status {
+        println!("{}", status.summary());
+        if !status.passed() {
+            return Err(eyre!("Test ROM didn't pass"));
+        }
+    }
+    match options.expect_hash {
+        Some(expected) if expected != headless.frame_hash => Err(eyre!(
+            "Frame hash mismatch, expected {expected:016X} got {:016X}",
+            headless.frame_hash
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Runs twice, or against a log from another build, comparing state hashes
+fn check_determinism(options: &Options, interval: usize) -> Result<()> {
+    let rom = read_rom(options.rom_file)?;
+    let (movie, frames) = options.headless_input()?;
+    let sync = options.replay_settings(&rom, movie.as_ref());
+    let check = determinism::CheckOptions {
+        interval,
+        log_file: options.hash_log,
+        against: options.against_log,
+    };
+    determinism::run(&rom, movie.as_ref(), frames, &check, |console| {
+        options.configure(console, &sync);
     })
 }
 
+fn arg_value<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|arg| arg == flag)
+        .and_then(|idx| args.get(idx + 1))
+        .map(String::as_str)
+}
+
+// Parses --audio-chunk, rejecting lengths the audio output can't keep up with
+fn audio_chunk(value: Option<&str>) -> Result<Duration> {
+    let ms = value
+        .map(str::parse::<f64>)
+        .transpose()
+        .wrap_err("Invalid --audio-chunk value")?
+        .unwrap_or(AUDIO_CHUNK_MS);
+    if !(ms > 0.0 && ms <= MAX_AUDIO_CHUNK_MS) {
+        return Err(eyre!(
+            "--audio-chunk must be more than 0 and at most {MAX_AUDIO_CHUNK_MS} ms"
+        ));
+    }
+    Ok(Duration::from_secs_f64(ms / 1000.0))
+}
+
 fn trace(cpu: &mut Cpu) {
     println!(
         "{:04X}  {:02X}  {:3} {:02X} {:02X}  A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}",
@@ -97,13 +533,89 @@ fn main() -> Result<()> {
 
     if args.len() < 2 {
         println!("Must provide at least one parameter!");
-        println!("  <file>         -- runs given rom");
+        println!("  <file>                -- runs given rom, - reads it from stdin");
+        println!("  <file> <file>...      -- runs the first rom, F6 switches to the next");
+        println!("  <list.m3u>            -- runs the roms in a playlist, one path per line");
+        println!("  <url>                 -- downloads and runs a rom, needs the url feature");
+        println!(
+            "  --scan <dir>          -- run every ROM in a directory and report compatibility"
+        );
+        println!("  --scan-report <file>  -- write the scan report as .csv or .html");
+        println!(
+            "  --raw-prg <addr>      -- run a headerless 6502 program loaded at addr, CPU only"
+        );
+        println!("  --start <addr>        -- where --raw-prg starts, the load address by default");
+        println!("  --expect-pc <addr>    -- fail unless --raw-prg stops at addr");
+        println!("  --dump-memory <file>  -- write the 64 kB of --raw-prg memory when it stops");
+        println!("  --trace               -- print trace of executed instructions");
+        println!("  --trace-access <list> -- log bus accesses, e.g. w:2000-2007,r:4016");
+        println!("  --fs                  -- run in fullscreen");
+        println!("  --renderer <name>     -- gl (default) or software, which has no menus");
+        println!("  --coverage <out.cdl>  -- log PRG ROM code/data coverage on exit");
+        println!("  --apu-log <out.vgm>   -- log APU register writes as VGM, or text if not .vgm");
+        println!("  --record <out.rmov>   -- record controller input to a movie");
+        println!("  --play <movie.rmov>   -- replay a movie without a window");
+        println!("  --frames <n>          -- run n frames without a window");
+        println!("  --expect-hash <hash>  -- fail if the last frame's hash differs");
+        println!("  --save-frame <f.ppm>  -- save the last frame as a golden image");
+        println!("  --expect-frame <f.ppm> -- fail if the last frame differs, writing diff images");
+        println!("  --tolerance <n>       -- allowed per-channel difference for --expect-frame");
+        println!(
+            "  --check-determinism <n> -- run twice with the same input, comparing state every n frames"
+        );
+        println!("  --hash-log <file>     -- save the state hashes of a --check-determinism run");
+        println!("  --against <file>      -- compare --check-determinism with another build's log");
+        println!("  --jam-break           -- stop with an error when the CPU jams");
+        println!(
+            "  --alignment <phase>   -- PPU/CPU power-on alignment: 0-2, random, random:<seed>"
+        );
+        println!("  --palette <file.pal>  -- 64 or 512 color palette, or ntsc to generate one");
+        println!("  --dpcm-conflicts      -- let DMC sample fetches corrupt controller reads");
+        println!("  --mapper-fallback     -- run games with an unsupported mapper as NROM");
+        println!(
+            "  --accuracy <quirks>   -- ppu-warmup, nmi-delay, no-bus-conflicts or all, comma separated"
+        );
+        println!("  --autosave <minutes>  -- battery RAM save interval, 0 saves only on exit");
+        println!("  --audio-chunk <ms>    -- audio handed to the output at a time, up to 20 ms");
+        println!("  --audio-info          -- print audio output format and rates on exit");
+        println!("  --check-updates       -- tell when a newer release is out, never downloads");
+        println!("  --start-from <f.state> -- replay to an anchor and check its state first");
+        println!("  --priority <level>    -- emulation thread priority: normal, high or critical");
+        println!("  --core <n>            -- run the emulation thread on CPU core n only");
+        println!(
+            "  --compare <file>      -- run a second console side by side and show differences"
+        );
         return Ok(());
     }
 
-    let trace = args.contains(&"--trace".to_owned());
-    let fullscreen = args.contains(&"--fs".to_owned());
+    if let Some(addr) = arg_value(&args, "--raw-prg") {
+        let options = raw_prg::RawPrgOptions {
+            load_addr: raw_prg::parse_addr(addr)?,
+            start: arg_value(&args, "--start")
+                .map(raw_prg::parse_addr)
+                .transpose()?,
+            expect_pc: arg_value(&args, "--expect-pc")
+                .map(raw_prg::parse_addr)
+                .transpose()?,
+            dump_file: arg_value(&args, "--dump-memory"),
+        };
+        return raw_prg::run(&args[1], &options);
+    }
+
+    if let Some(dir) = arg_value(&args, "--scan") {
+        let frames = arg_value(&args, "--frames")
+            .map_or(Ok(SCAN_FRAMES), str::parse)
+            .wrap_err("Invalid --frames value")?;
+        return scan::run(Path::new(dir), frames, arg_value(&args, "--scan-report"));
+    }
 
-    run_rom(&args[1], trace, fullscreen)?;
+    let options = Options::parse(&args)?;
+    if let Some(interval) = options.check_every {
+        return check_determinism(&options, interval);
+    }
+    if options.headless() {
+        return run_headless(&options);
+    }
+    run_rom(&options)?;
     Ok(())
 }
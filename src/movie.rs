@@ -0,0 +1,158 @@
+use std::path::Path;
+
+use eyre::{eyre, Result, WrapErr};
+
+use crate::run_info::RunInfo;
+
+/// Recorded controller input, one button byte per frame.
+///
+/// Stored as text: a `rmov 1` header line followed by one line per frame,
+/// each listing the buttons A, B, Select, Start, Up, Down, Left, Right as a
+/// letter when held and `.` when released, e.g. `A..SU...`. Movies that know
+/// what they were recorded with have a `rmov 2` header and the `RunInfo` lines
+/// before the first frame.
+#[derive(Clone, Default)]
+pub struct Movie {
+    frames: Vec<u8>,
+    pub info: Option<RunInfo>,
+}
+
+impl Movie {
+    const HEADER: &'static str = "rmov 1";
+    const INFO_HEADER: &'static str = "rmov 2";
+    const BUTTON_CHARS: [char; 8] = ['A', 'B', 's', 'S', 'U', 'D', 'L', 'R'];
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("Failed to open movie file {}", path.display()))?;
+        Self::parse(&text).wrap_err_with(|| format!("Invalid movie file {}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, self.to_text())
+            .wrap_err_with(|| format!("Failed to write movie file {}", path.display()))
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut lines = text.lines().peekable();
+        let info = match lines.next().map(str::trim) {
+            Some(Self::HEADER) => None,
+            // Frames never hold a space, info lines always do
+            Some(Self::INFO_HEADER) => {
+                let mut info_lines = Vec::new();
+                while let Some(line) = lines.next_if(|line| line.contains(' ')) {
+                    info_lines.push(line);
+                }
+                Some(RunInfo::parse(info_lines)?)
+            }
+            _ => return Err(eyre!("Missing '{}' header", Self::HEADER)),
+        };
+
+        let mut frames = Vec::new();
+        for (idx, line) in lines.enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            if line.chars().count() != 8 {
+                return Err(eyre!("Frame {idx} must list exactly 8 buttons"));
+            }
+            let mut buttons = 0;
+            for (bit, (c, name)) in line.chars().zip(Self::BUTTON_CHARS).enumerate() {
+                match c {
+                    '.' => (),
+                    c if c == name => buttons |= 1 << bit,
+                    c => return Err(eyre!("Unexpected '{c}' for button {bit} on frame {idx}")),
+                }
+            }
+            frames.push(buttons);
+        }
+        Ok(Self { frames, info })
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = String::with_capacity((self.frames.len() + 1) * 9);
+        if let Some(info) = &self.info {
+            text.push_str(Self::INFO_HEADER);
+            text.push('\n');
+            for line in info.to_lines() {
+                text.push_str(&line);
+                text.push('\n');
+            }
+        } else {
+            text.push_str(Self::HEADER);
+            text.push('\n');
+        }
+        for buttons in &self.frames {
+            for (bit, name) in Self::BUTTON_CHARS.iter().enumerate() {
+                text.push(if buttons >> bit & 1 != 0 { *name } else { '.' });
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    pub fn push(&mut self, buttons: u8) {
+        self.frames.push(buttons);
+    }
+
+    /// Adds the frames of `other` after these
+    pub fn append(&mut self, other: &Self) {
+        self.frames.extend_from_slice(&other.frames);
+    }
+
+    /// Buttons applied after the given frame has been completed
+    pub fn frame(&self, idx: usize) -> Option<u8> {
+        self.frames.get(idx).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_text_round_trip() {
+        let mut movie = Movie::default();
+        movie.push(0x00);
+        movie.push(0x09);
+        movie.push(0xFF);
+        let text = movie.to_text();
+        assert_eq!(text, "rmov 1\n........\nA..S....\nABsSUDLR\n");
+
+        let parsed = Movie::parse(&text).unwrap();
+        assert_eq!(parsed.frames, vec![0x00, 0x09, 0xFF]);
+    }
+
+    #[test]
+    fn test_run_info() {
+        let mut movie = Movie::default();
+        movie.push(0x01);
+        movie.info = Some(RunInfo::new(
+            0xCAFE_F00D,
+            crate::console::Region::Ntsc,
+            crate::run_info::SyncSettings::default(),
+        ));
+        let text = movie.to_text();
+        assert!(text.starts_with("rmov 2\nversion "));
+        assert!(text.ends_with("\nmapper-fallback off\nA.......\n"));
+
+        let parsed = Movie::parse(&text).unwrap();
+        assert_eq!(parsed.info, movie.info);
+        assert_eq!(parsed.frames, vec![0x01]);
+        assert!(Movie::parse("rmov 2\nA.......\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_rejects_bad_input() {
+        assert!(Movie::parse("........\n").is_err());
+        assert!(Movie::parse("rmov 1\nA.......X\n").is_err());
+        assert!(Movie::parse("rmov 1\n.B......\n").is_ok());
+        assert!(Movie::parse("rmov 1\nB.......\n").is_err());
+    }
+}
@@ -0,0 +1,209 @@
+use eyre::{eyre, Result};
+
+const NSF_MAGIC: &[u8] = b"NESM\x1A";
+const NSFE_MAGIC: &[u8] = b"NSFE";
+const NSF_HEADER_LEN: usize = 0x80;
+const NSF_TEXT_LEN: usize = 32;
+
+// Used for tracks without a length, as most players do
+const DEFAULT_LENGTH_MS: u32 = 150_000;
+const DEFAULT_FADE_MS: u32 = 8_000;
+
+/// Metadata of a single track
+#[derive(Clone, Default, Debug, PartialEq, Eq)]
+pub struct Track {
+    pub title: Option<String>,
+    pub length_ms: Option<u32>,
+    pub fade_ms: Option<u32>,
+}
+
+impl Track {
+    /// Time until the player should move on to the next track, fade included
+    pub fn play_time_ms(&self) -> u32 {
+        self.length_ms.unwrap_or(DEFAULT_LENGTH_MS) + self.fade_ms.unwrap_or(DEFAULT_FADE_MS)
+    }
+}
+
+/// Album and track information from an NSF, NSF2 or `NSFe` file
+#[derive(Default, Debug)]
+pub struct NsfInfo {
+    pub title: String,
+    pub artist: String,
+    pub copyright: String,
+    /// Zero-based index of the track to start from
+    pub start_track: usize,
+    pub tracks: Vec<Track>,
+}
+
+pub fn is_nsf(data: &[u8]) -> bool {
+    data.starts_with(NSF_MAGIC) || data.starts_with(NSFE_MAGIC)
+}
+
+impl NsfInfo {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if data.starts_with(NSFE_MAGIC) {
+            let mut info = Self::default();
+            info.parse_chunks(&data[NSFE_MAGIC.len()..])?;
+            Ok(info)
+        } else if data.starts_with(NSF_MAGIC) {
+            Self::parse_nsf(data)
+        } else {
+            Err(eyre!("Not an NSF or NSFe file"))
+        }
+    }
+
+    fn parse_nsf(data: &[u8]) -> Result<Self> {
+        let header = data
+            .get(..NSF_HEADER_LEN)
+            .ok_or_else(|| eyre!("NSF header is truncated"))?;
+        let text = |offset: usize| c_string(&header[offset..offset + NSF_TEXT_LEN]);
+        let mut info = Self {
+            title: text(0x0E),
+            artist: text(0x2E),
+            copyright: text(0x4E),
+            start_track: (header[0x07] as usize).saturating_sub(1),
+            tracks: vec![Track::default(); header[0x06] as usize],
+        };
+
+        // NSF2 may append NSFe metadata chunks after the program data
+        let data_len = u32::from_le_bytes([header[0x7D], header[0x7E], header[0x7F], 0]) as usize;
+        if header[0x05] >= 2 && data_len > 0 {
+            let metadata = data
+                .get(NSF_HEADER_LEN + data_len..)
+                .ok_or_else(|| eyre!("NSF2 program data is truncated"))?;
+            info.parse_chunks(metadata)?;
+        }
+        Ok(info)
+    }
+
+    // Chunks are a 32-bit length, a four letter ID and the data. IDs starting with
+    // an uppercase letter are required to play the file, others can be skipped.
+    fn parse_chunks(&mut self, mut data: &[u8]) -> Result<()> {
+        while data.len() >= 8 {
+            let len = u32::from_le_bytes([data[0], data[1], data[2], data[3]]) as usize;
+            let id = &data[4..8];
+            let chunk = data
+                .get(8..8 + len)
+                .ok_or_else(|| eyre!("Chunk {} is truncated", String::from_utf8_lossy(id)))?;
+            data = &data[8 + len..];
+
+            match id {
+                b"INFO" => {
+                    if let Some(&count) = chunk.get(8) {
+                        self.tracks.resize(count as usize, Track::default());
+                    }
+                    self.start_track = chunk.get(9).map_or(0, |&s| s as usize);
+                }
+                b"auth" => {
+                    let mut fields = chunk.split(|&b| b == 0).map(c_string);
+                    self.title = fields.next().unwrap_or_default();
+                    self.artist = fields.next().unwrap_or_default();
+                    self.copyright = fields.next().unwrap_or_default();
+                }
+                b"tlbl" => {
+                    for (track, title) in self.tracks.iter_mut().zip(chunk.split(|&b| b == 0)) {
+                        track.title = Some(c_string(title));
+                    }
+                }
+                b"time" => {
+                    for (track, ms) in self.tracks.iter_mut().zip(ms_values(chunk)) {
+                        track.length_ms = ms;
+                    }
+                }
+                b"fade" => {
+                    for (track, ms) in self.tracks.iter_mut().zip(ms_values(chunk)) {
+                        track.fade_ms = ms;
+                    }
+                }
+                b"NEND" => break,
+                _ if id[0].is_ascii_uppercase() && id != b"DATA" && id != b"BANK" => {
+                    return Err(eyre!(
+                        "Unsupported required chunk {}",
+                        String::from_utf8_lossy(id)
+                    ));
+                }
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+}
+
+// Text fields are NUL padded, with no specified encoding
+fn c_string(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+// Signed 32-bit millisecond values, negative meaning "use the default"
+fn ms_values(chunk: &[u8]) -> impl Iterator<Item = Option<u32>> + '_ {
+    chunk
+        .chunks_exact(4)
+        .map(|b| u32::try_from(i32::from_le_bytes([b[0], b[1], b[2], b[3]])).ok())
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod test {
+    use super::*;
+
+    fn chunk(id: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut out = (data.len() as u32).to_le_bytes().to_vec();
+        out.extend_from_slice(id);
+        out.extend_from_slice(data);
+        out
+    }
+
+    #[test]
+    fn test_nsfe() {
+        let mut file = NSFE_MAGIC.to_vec();
+        file.extend(chunk(b"INFO", &[0, 0x80, 0, 0x80, 3, 0x80, 0, 0, 2, 1]));
+        file.extend(chunk(b"DATA", &[0x60]));
+        file.extend(chunk(b"auth", b"Album\0Artist\0(c) 1990\0Ripper\0"));
+        file.extend(chunk(b"tlbl", b"Intro\0Stage 1\0"));
+        file.extend(chunk(b"time", &[0x10, 0x27, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF]));
+        file.extend(chunk(b"xtra", &[1, 2, 3]));
+        file.extend(chunk(b"NEND", &[]));
+
+        let info = NsfInfo::parse(&file).unwrap();
+        assert_eq!(info.title, "Album");
+        assert_eq!(info.artist, "Artist");
+        assert_eq!(info.start_track, 1);
+        assert_eq!(
+            info.tracks[0],
+            Track {
+                title: Some("Intro".to_owned()),
+                length_ms: Some(10_000),
+                fade_ms: None,
+            }
+        );
+        assert_eq!(info.tracks[1].length_ms, None);
+        assert_eq!(info.tracks[1].play_time_ms(), 158_000);
+    }
+
+    #[test]
+    fn test_unknown_required_chunk() {
+        let mut file = NSFE_MAGIC.to_vec();
+        file.extend(chunk(b"VRC7", &[]));
+        assert!(NsfInfo::parse(&file).is_err());
+    }
+
+    #[test]
+    fn test_nsf2_metadata() {
+        let mut file = NSF_MAGIC.to_vec();
+        file.resize(NSF_HEADER_LEN, 0);
+        file[0x05] = 2;
+        file[0x06] = 2;
+        file[0x07] = 1;
+        file[0x0E..0x13].copy_from_slice(b"Title");
+        file[0x7D] = 4;
+        file.extend_from_slice(&[0xEA; 4]);
+        file.extend(chunk(b"fade", &[0xE8, 0x03, 0, 0]));
+
+        let info = NsfInfo::parse(&file).unwrap();
+        assert_eq!(info.title, "Title");
+        assert_eq!(info.start_track, 0);
+        assert_eq!(info.tracks.len(), 2);
+        assert_eq!(info.tracks[0].fade_ms, Some(1000));
+    }
+}
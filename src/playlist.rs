@@ -0,0 +1,62 @@
+use std::path::Path;
+
+use eyre::{Result, WrapErr};
+
+/// True for .m3u playlists, which list one ROM per line
+pub fn is_playlist(file: &str) -> bool {
+    Path::new(file)
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("m3u") || ext.eq_ignore_ascii_case("m3u8"))
+}
+
+// Skips blank lines and # comments, relative paths are relative to the playlist
+fn parse(text: &str, dir: &Path) -> Vec<String> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            if Path::new(line).is_absolute() || line.contains("://") {
+                line.to_owned()
+            } else {
+                dir.join(line).to_string_lossy().into_owned()
+            }
+        })
+        .collect()
+}
+
+/// ROM sources with any playlists among them replaced by their entries
+pub fn expand(sources: &[&str]) -> Result<Vec<String>> {
+    let mut roms = Vec::new();
+    for &source in sources {
+        if !is_playlist(source) {
+            roms.push(source.to_owned());
+            continue;
+        }
+        let text = std::fs::read_to_string(source)
+            .wrap_err_with(|| format!("Failed to read playlist {source}"))?;
+        let dir = Path::new(source).parent().unwrap_or_else(|| Path::new(""));
+        roms.extend(parse(&text, dir));
+    }
+    Ok(roms)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let text = "#EXTM3U\nbuild1.nes\n\n  sub/build2.nes \n/abs/test.nes\nhttps://x.org/a.nes\n";
+        assert_eq!(
+            parse(text, Path::new("roms")),
+            [
+                "roms/build1.nes",
+                "roms/sub/build2.nes",
+                "/abs/test.nes",
+                "https://x.org/a.nes"
+            ]
+        );
+        assert!(is_playlist("tests.M3U"));
+        assert!(!is_playlist("game.nes"));
+    }
+}
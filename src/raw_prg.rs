@@ -0,0 +1,129 @@
+use std::cell::Cell;
+use std::rc::Rc;
+
+use eyre::{eyre, Result, WrapErr};
+
+use crate::console::{apu::Apu, controller::Controller, video::Frame, Console, Frontend};
+use crate::rom_source;
+
+// Far more than any functional test suite needs, stops programs that never finish
+const MAX_INSTRUCTIONS: u64 = 500_000_000;
+
+/// Where a raw program should be loaded and run
+pub struct RawPrgOptions<'a> {
+    pub load_addr: u16,
+    /// Defaults to the load address
+    pub start: Option<u16>,
+    /// Fail unless the program stops here, e.g. the success trap of a test suite
+    pub expect_pc: Option<u16>,
+    /// Write all 64 kB of memory here when the program stops
+    pub dump_file: Option<&'a str>,
+}
+
+// Only asks the CPU to stop, nothing else reaches the frontend on a flat bus
+struct StopFrontend {
+    stop: Rc<Cell<bool>>,
+}
+
+impl Frontend for StopFrontend {
+    fn handle_io(&mut self, _frame: &Frame, _apu: &Apu, _controller: &mut Controller) {}
+
+    fn handle_audio(&mut self, _apu: &Apu) -> Result<()> {
+        Ok(())
+    }
+
+    fn quit_requested(&self) -> bool {
+        self.stop.get()
+    }
+}
+
+/// Parses an address like `0400`, `$0400` or `0x0400`
+pub fn parse_addr(text: &str) -> Result<u16> {
+    let digits = text.trim_start_matches('$').trim_start_matches("0x");
+    u16::from_str_radix(digits, 16).wrap_err_with(|| format!("Invalid address '{text}'"))
+}
+
+/// Runs a headerless 6502 program with only the CPU and RAM, until it executes BRK,
+/// jams or jumps to itself, then prints the registers and zero page
+pub fn run(file: &str, options: &RawPrgOptions) -> Result<()> {
+    let image = rom_source::read(file)?;
+    let stop = Rc::new(Cell::new(false));
+    let mut frontend = StopFrontend { stop: stop.clone() };
+    let start = options.start.unwrap_or(options.load_addr);
+    let mut console = Console::raw_prg(&image, options.load_addr, start, &mut frontend)?;
+    console.set_jam_behavior(crate::console::cpu::JamBehavior::Break);
+
+    let mut last_pc = None;
+    let mut instructions = 0;
+    let mut trapped = false;
+    let result = console.run_with_callback(|cpu| {
+        instructions += 1;
+        // Test suites signal success or failure by jumping to the same instruction
+        trapped = last_pc == Some(cpu.program_counter);
+        last_pc = Some(cpu.program_counter);
+        stop.set(trapped || instructions >= MAX_INSTRUCTIONS);
+    });
+
+    let regs = console.cpu_regs();
+    // Address of the instruction the program stopped on
+    let stop_pc = match result {
+        Ok(()) if trapped => {
+            println!("Trapped at ${:04X}", regs.pc);
+            regs.pc
+        }
+        Ok(()) if instructions >= MAX_INSTRUCTIONS => {
+            println!("Stopped after {instructions} instructions");
+            regs.pc
+        }
+        Ok(()) => {
+            // The CPU is past the BRK opcode
+            let pc = regs.pc.wrapping_sub(1);
+            println!("BRK at ${pc:04X}");
+            pc
+        }
+        Err(e) => {
+            println!("{e}");
+            regs.pc
+        }
+    };
+    println!(
+        "A={:02X} X={:02X} Y={:02X} P={:02X} SP={:02X} PC={:04X} cycles={}",
+        regs.a,
+        regs.x,
+        regs.y,
+        regs.p,
+        regs.sp,
+        regs.pc,
+        console.time().cpu_cycles
+    );
+
+    let memory = console
+        .flat_memory()
+        .ok_or_else(|| eyre!("Raw console has no flat memory"))?;
+    for (row, bytes) in memory[..0x100].chunks(16).enumerate() {
+        let hex: Vec<_> = bytes.iter().map(|b| format!("{b:02X}")).collect();
+        println!("{:04X}: {}", row * 16, hex.join(" "));
+    }
+    if let Some(dump_file) = options.dump_file {
+        std::fs::write(dump_file, memory)
+            .wrap_err_with(|| format!("Failed to write memory dump {dump_file}"))?;
+    }
+
+    match options.expect_pc {
+        Some(pc) if pc != stop_pc => Err(eyre!("Expected to stop at ${pc:04X}")),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_addr() {
+        assert_eq!(parse_addr("$0400").ok(), Some(0x400));
+        assert_eq!(parse_addr("0xC000").ok(), Some(0xC000));
+        assert_eq!(parse_addr("8000").ok(), Some(0x8000));
+        assert!(parse_addr("10000").is_err());
+    }
+}
@@ -0,0 +1,82 @@
+use std::io::Read;
+use std::path::Path;
+
+use eyre::{Result, WrapErr};
+
+/// ROM argument that reads the image from stdin, e.g. piped from an assembler
+pub const STDIN: &str = "-";
+
+// Larger than any real cartridge, stops a wrong URL from filling memory
+#[cfg(feature = "url")]
+const MAX_DOWNLOAD_LEN: u64 = 16 * 1024 * 1024;
+
+fn is_url(source: &str) -> bool {
+    source.starts_with("http://") || source.starts_with("https://")
+}
+
+/// True if the ROM comes from a file on disk, which can be reloaded and saved next to
+pub fn is_file(source: &str) -> bool {
+    source != STDIN && !is_url(source)
+}
+
+/// Name to show for the game, the file name without its extension
+pub fn name(source: &str) -> String {
+    if source == STDIN {
+        return "stdin".to_owned();
+    }
+    let path = source.rsplit('/').next().unwrap_or(source);
+    Path::new(path)
+        .file_stem()
+        .map_or_else(|| source.to_owned(), |s| s.to_string_lossy().into_owned())
+}
+
+/// Reads a ROM image from a file, stdin or a URL
+pub fn read(source: &str) -> Result<Vec<u8>> {
+    if source == STDIN {
+        let mut rom = Vec::new();
+        std::io::stdin()
+            .read_to_end(&mut rom)
+            .wrap_err("Failed to read ROM from stdin")?;
+        return Ok(rom);
+    }
+    if is_url(source) {
+        return download(source);
+    }
+    std::fs::read(source).wrap_err_with(|| format!("Failed to open ROM file {source}"))
+}
+
+#[cfg(feature = "url")]
+fn download(url: &str) -> Result<Vec<u8>> {
+    let response = ureq::get(url)
+        .call()
+        .wrap_err_with(|| format!("Failed to download {url}"))?;
+    let mut rom = Vec::new();
+    response
+        .into_reader()
+        .take(MAX_DOWNLOAD_LEN)
+        .read_to_end(&mut rom)
+        .wrap_err_with(|| format!("Failed to download {url}"))?;
+    Ok(rom)
+}
+
+#[cfg(not(feature = "url"))]
+fn download(url: &str) -> Result<Vec<u8>> {
+    Err(eyre::eyre!(
+        "Can't open {url}, loading ROMs from URLs needs a build with the url feature"
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sources() {
+        assert!(is_file("roms/smb.nes"));
+        assert!(!is_file(STDIN));
+        assert!(!is_file("https://example.com/jam/entry.nes"));
+        assert_eq!(name("roms/smb.nes"), "smb");
+        assert_eq!(name("https://example.com/jam/entry.nes"), "entry");
+        assert_eq!(name(STDIN), "stdin");
+    }
+}
@@ -0,0 +1,229 @@
+use std::collections::HashMap;
+
+use eyre::{eyre, Result, WrapErr};
+
+use crate::checksum::{crc32, sha1, to_hex};
+
+const HEADER_LEN: usize = 16;
+const TRAINER_LEN: usize = 512;
+const PRG_BANK_LEN: usize = 0x4000;
+const CHR_BANK_LEN: usize = 0x2000;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DumpStatus {
+    Good,
+    Bad,
+    Overdump,
+}
+
+struct DbEntry {
+    status: DumpStatus,
+    name: String,
+}
+
+/// Known dumps keyed by the CRC32 of the ROM data without the iNES header.
+///
+/// Read from a text file with one `<crc32> <good|bad|overdump> <name>` entry per line,
+/// `#` starting a comment.
+#[derive(Default)]
+pub struct RomDb {
+    entries: HashMap<u32, DbEntry>,
+}
+
+impl RomDb {
+    pub fn load(file: &str) -> Result<Self> {
+        let text = std::fs::read_to_string(file)
+            .wrap_err_with(|| format!("Failed to open ROM database {file}"))?;
+        Self::parse(&text).wrap_err_with(|| format!("Invalid ROM database {file}"))
+    }
+
+    fn parse(text: &str) -> Result<Self> {
+        let mut entries = HashMap::new();
+        for (idx, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut parts = line.splitn(3, char::is_whitespace);
+            let (Some(crc), Some(status)) = (parts.next(), parts.next()) else {
+                return Err(eyre!("Line {} is missing fields", idx + 1));
+            };
+            let crc = u32::from_str_radix(crc, 16)
+                .wrap_err_with(|| format!("Invalid CRC on line {}", idx + 1))?;
+            let status = match status {
+                "good" => DumpStatus::Good,
+                "bad" => DumpStatus::Bad,
+                "overdump" => DumpStatus::Overdump,
+                s => return Err(eyre!("Unknown status '{s}' on line {}", idx + 1)),
+            };
+            let name = parts.next().unwrap_or_default().trim().to_owned();
+            entries.insert(crc, DbEntry { status, name });
+        }
+        Ok(Self { entries })
+    }
+}
+
+/// Checksums of a ROM file and problems found with the dump
+pub struct RomInfo {
+    pub crc32: u32,
+    pub sha1: String,
+    /// iNES header as it is in the file
+    pub header: Vec<u8>,
+    pub prg_len: usize,
+    pub chr_len: usize,
+    pub file_len: usize,
+    /// Name of the game in the database, if it is known
+    pub db_name: Option<String>,
+    pub warnings: Vec<String>,
+}
+
+// Sizes declared by the header: (data start, PRG length, CHR length)
+fn declared_layout(rom: &[u8]) -> (usize, usize, usize) {
+    let data_start = HEADER_LEN + if rom[6] & 0b100 != 0 { TRAINER_LEN } else { 0 };
+    (
+        data_start,
+        rom[4] as usize * PRG_BANK_LEN,
+        rom[5] as usize * CHR_BANK_LEN,
+    )
+}
+
+// Overdumps of small ROMs often repeat the same data to fill a bigger chip
+fn is_mirrored(data: &[u8]) -> bool {
+    data.len() >= 2 * PRG_BANK_LEN && {
+        let (first, second) = data.split_at(data.len() / 2);
+        first == second
+    }
+}
+
+impl RomInfo {
+    pub fn new(rom: &[u8], db: &RomDb) -> Self {
+        let data = rom.get(HEADER_LEN..).unwrap_or_default();
+        let crc32 = crc32(data);
+        let mut info = Self {
+            crc32,
+            sha1: to_hex(&sha1(data)),
+            header: rom[..rom.len().min(HEADER_LEN)].to_vec(),
+            prg_len: 0,
+            chr_len: 0,
+            file_len: rom.len(),
+            db_name: None,
+            warnings: Vec::new(),
+        };
+
+        if let Some(entry) = db.entries.get(&crc32) {
+            info.db_name = Some(entry.name.clone());
+            match entry.status {
+                DumpStatus::Good => (),
+                DumpStatus::Bad => info
+                    .warnings
+                    .push(format!("Known bad dump of {}", entry.name)),
+                DumpStatus::Overdump => {
+                    info.warnings
+                        .push(format!("Known overdump of {}", entry.name));
+                }
+            }
+        }
+
+        if rom.len() < HEADER_LEN {
+            info.warnings
+                .push("File is too short for an iNES header".to_owned());
+            return info;
+        }
+
+        let (data_start, prg_len, chr_len) = declared_layout(rom);
+        info.prg_len = prg_len;
+        info.chr_len = chr_len;
+        let expected_len = data_start + prg_len + chr_len;
+        if rom.len() < expected_len {
+            info.warnings.push(format!(
+                "File is {} bytes shorter than the header declares",
+                expected_len - rom.len()
+            ));
+        } else {
+            if rom.len() > expected_len {
+                info.warnings.push(format!(
+                    "{} extra bytes after the declared ROM data",
+                    rom.len() - expected_len
+                ));
+            }
+            if is_mirrored(&rom[data_start..data_start + prg_len]) {
+                info.warnings
+                    .push("PRG ROM halves are identical, likely overdumped".to_owned());
+            }
+        }
+        info
+    }
+}
+
+/// Removes data past the declared ROM size and repeated PRG ROM halves,
+/// fixing the header to match
+pub fn trim(rom: &[u8]) -> Result<Vec<u8>> {
+    if rom.len() < HEADER_LEN {
+        return Err(eyre!("File is too short for an iNES header"));
+    }
+    let (data_start, mut prg_len, chr_len) = declared_layout(rom);
+    if rom.len() < data_start + prg_len + chr_len {
+        return Err(eyre!("ROM is truncated, nothing to trim"));
+    }
+
+    let prg_start = data_start;
+    while is_mirrored(&rom[prg_start..prg_start + prg_len]) {
+        prg_len /= 2;
+    }
+
+    let chr_start = data_start + rom[4] as usize * PRG_BANK_LEN;
+    let mut out = rom[..prg_start + prg_len].to_vec();
+    out.extend_from_slice(&rom[chr_start..chr_start + chr_len]);
+    out[4] = (prg_len / PRG_BANK_LEN) as u8;
+    Ok(out)
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod test {
+    use super::*;
+    use crate::console::ines_image;
+
+    #[test]
+    fn test_database_lookup() {
+        let good = ines_image(0, &[1; PRG_BANK_LEN], &[2; CHR_BANK_LEN]);
+        let crc = crc32(&good[HEADER_LEN..]);
+        let db = RomDb::parse(&format!(
+            "# comment\n{crc:08x} bad Some Game (bad)\n\nDEADBEEF good Other\n"
+        ))
+        .unwrap();
+
+        let info = RomInfo::new(&good, &db);
+        assert_eq!(info.db_name.as_deref(), Some("Some Game (bad)"));
+        assert_eq!(info.warnings, vec!["Known bad dump of Some Game (bad)"]);
+
+        assert!(RomDb::parse("1234 great Name").is_err());
+    }
+
+    #[test]
+    fn test_overdump_detected_and_trimmed() {
+        let prg: Vec<u8> = (0..PRG_BANK_LEN).map(|i| i as u8).collect();
+        let mut rom = ines_image(0, &[prg.clone(), prg.clone()].concat(), &[7; CHR_BANK_LEN]);
+        rom.extend_from_slice(&[0xFF; 100]);
+
+        let info = RomInfo::new(&rom, &RomDb::default());
+        assert_eq!(info.warnings.len(), 2);
+
+        let trimmed = trim(&rom).unwrap();
+        assert_eq!(trimmed[4], 1);
+        assert_eq!(&trimmed[HEADER_LEN..HEADER_LEN + PRG_BANK_LEN], &prg[..]);
+        assert_eq!(trimmed.len(), HEADER_LEN + PRG_BANK_LEN + CHR_BANK_LEN);
+        assert!(RomInfo::new(&trimmed, &RomDb::default())
+            .warnings
+            .is_empty());
+    }
+
+    #[test]
+    fn test_truncated_rom_warns() {
+        let mut rom = ines_image(0, &[0; PRG_BANK_LEN], &[]);
+        rom.truncate(100);
+        let info = RomInfo::new(&rom, &RomDb::default());
+        assert_eq!(info.warnings.len(), 1);
+        assert!(trim(&rom).is_err());
+    }
+}
@@ -0,0 +1,277 @@
+// What a recording needs to play back the same way: the build that made it, the
+// ROM and the console settings that change timing. Movies and save states carry it
+// so a replay can warn about a different setup, or take the settings over.
+
+use eyre::{eyre, Result, WrapErr};
+
+use crate::console::state::{SaveState, SectionFormat};
+use crate::console::{Accuracy, Alignment, Region};
+
+/// Save state section written by the frontend, the console skips it on load
+const STATE_SECTION: SectionFormat = SectionFormat {
+    tag: *b"INFO",
+    version: 1,
+    migrations: &[],
+};
+
+/// Console settings from the command line that a run's timing depends on
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct SyncSettings {
+    pub accuracy: Accuracy,
+    /// None leaves the console's default alignment
+    pub alignment: Option<Alignment>,
+    pub dpcm_conflicts: bool,
+    pub mapper_fallback: bool,
+}
+
+impl SyncSettings {
+    /// These settings, with the ones left at their default taken from a recording
+    pub fn or_recorded(self, recorded: &Self) -> Self {
+        Self {
+            accuracy: if self.accuracy == Accuracy::default() {
+                recorded.accuracy
+            } else {
+                self.accuracy
+            },
+            alignment: self.alignment.or(recorded.alignment),
+            dpcm_conflicts: self.dpcm_conflicts || recorded.dpcm_conflicts,
+            mapper_fallback: self.mapper_fallback || recorded.mapper_fallback,
+        }
+    }
+}
+
+/// Environment a movie or save state was made in.
+///
+/// Stored as `key value` lines: `version`, `rom` (CRC32 of the whole file),
+/// `region`, `accuracy`, `alignment`, `dpcm-conflicts` and `mapper-fallback`.
+/// Unknown keys are skipped so later builds can add their own.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct RunInfo {
+    pub version: String,
+    pub rom_crc32: u32,
+    /// Follows from the ROM, kept for whoever reads the file
+    pub region: Region,
+    pub settings: SyncSettings,
+}
+
+impl RunInfo {
+    /// Info for a run of this build
+    pub fn new(rom_crc32: u32, region: Region, settings: SyncSettings) -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            rom_crc32,
+            region,
+            settings,
+        }
+    }
+
+    pub fn parse<'a>(lines: impl IntoIterator<Item = &'a str>) -> Result<Self> {
+        let mut version = None;
+        let mut rom_crc32 = None;
+        let mut region = Region::Ntsc;
+        let mut settings = SyncSettings::default();
+        for line in lines {
+            let Some((key, value)) = line.trim().split_once(' ') else {
+                return Err(eyre!("Expected a key and a value in '{line}'"));
+            };
+            let value = value.trim();
+            match key {
+                "version" => version = Some(value.to_owned()),
+                "rom" => {
+                    rom_crc32 = Some(u32::from_str_radix(value, 16).wrap_err("Invalid ROM CRC32")?);
+                }
+                "region" => {
+                    region = match value {
+                        "NTSC" => Region::Ntsc,
+                        "PAL" => Region::Pal,
+                        _ => return Err(eyre!("Unknown region '{value}'")),
+                    };
+                }
+                "accuracy" => settings.accuracy = Accuracy::parse(value)?,
+                "alignment" => {
+                    settings.alignment = match value {
+                        "default" => None,
+                        _ => Some(Alignment::parse(value)?),
+                    };
+                }
+                "dpcm-conflicts" => settings.dpcm_conflicts = parse_switch(value)?,
+                "mapper-fallback" => settings.mapper_fallback = parse_switch(value)?,
+                _ => (),
+            }
+        }
+        Ok(Self {
+            version: version.ok_or_else(|| eyre!("Missing version line"))?,
+            rom_crc32: rom_crc32.ok_or_else(|| eyre!("Missing rom line"))?,
+            region,
+            settings,
+        })
+    }
+
+    pub fn to_lines(&self) -> Vec<String> {
+        let settings = &self.settings;
+        vec![
+            format!("version {}", self.version),
+            format!("rom {:08X}", self.rom_crc32),
+            format!("region {}", self.region),
+            format!("accuracy {}", settings.accuracy),
+            format!("alignment {}", alignment_text(settings.alignment)),
+            format!("dpcm-conflicts {}", switch(settings.dpcm_conflicts)),
+            format!("mapper-fallback {}", switch(settings.mapper_fallback)),
+        ]
+    }
+
+    /// Ways a replay in `current` differs from the recording, as warnings
+    pub fn differences(&self, current: &Self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        let mut compare = |what: &str, recorded: String, now: String| {
+            if recorded != now {
+                warnings.push(format!(
+                    "Recorded with {what} {recorded}, running with {now}"
+                ));
+            }
+        };
+        compare("rnes", self.version.clone(), current.version.clone());
+        compare(
+            "ROM",
+            format!("{:08X}", self.rom_crc32),
+            format!("{:08X}", current.rom_crc32),
+        );
+        compare(
+            "region",
+            self.region.to_string(),
+            current.region.to_string(),
+        );
+        let (recorded, now) = (&self.settings, &current.settings);
+        compare(
+            "accuracy",
+            recorded.accuracy.to_string(),
+            now.accuracy.to_string(),
+        );
+        compare(
+            "alignment",
+            alignment_text(recorded.alignment),
+            alignment_text(now.alignment),
+        );
+        compare(
+            "DPCM conflicts",
+            switch(recorded.dpcm_conflicts).to_owned(),
+            switch(now.dpcm_conflicts).to_owned(),
+        );
+        compare(
+            "mapper fallback",
+            switch(recorded.mapper_fallback).to_owned(),
+            switch(now.mapper_fallback).to_owned(),
+        );
+        if recorded.alignment == Some(Alignment::Random(None)) {
+            warnings.push(
+                "Recorded with a random alignment without a seed, the replay may not sync"
+                    .to_owned(),
+            );
+        }
+        warnings
+    }
+
+    /// Save state file with this info added
+    pub fn add_to_state(&self, state: &[u8]) -> Result<Vec<u8>> {
+        let mut state = SaveState::decode(state)?;
+        state.put(&STATE_SECTION, self.to_lines().join("\n").into_bytes());
+        Ok(state.encode())
+    }
+
+    /// Info kept in a save state file, None for states saved without it
+    pub fn from_state(state: &[u8]) -> Result<Option<Self>> {
+        let Some(data) = SaveState::decode(state)?.get(&STATE_SECTION)? else {
+            return Ok(None);
+        };
+        let text = String::from_utf8(data).wrap_err("Save state info isn't text")?;
+        Self::parse(text.lines()).map(Some)
+    }
+}
+
+fn alignment_text(alignment: Option<Alignment>) -> String {
+    alignment.map_or_else(|| "default".to_owned(), |a| a.to_string())
+}
+
+const fn switch(on: bool) -> &'static str {
+    if on {
+        "on"
+    } else {
+        "off"
+    }
+}
+
+fn parse_switch(value: &str) -> Result<bool> {
+    match value {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => Err(eyre!("Expected on or off, got '{value}'")),
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod test {
+    use super::*;
+
+    fn info() -> RunInfo {
+        RunInfo::new(
+            0x1234_ABCD,
+            Region::Pal,
+            SyncSettings {
+                accuracy: Accuracy::parse("nmi-delay").unwrap(),
+                alignment: Some(Alignment::Random(Some(7))),
+                dpcm_conflicts: true,
+                mapper_fallback: false,
+            },
+        )
+    }
+
+    #[test]
+    fn test_text_round_trip() {
+        let info = info();
+        let lines = info.to_lines();
+        assert_eq!(lines[1..3], ["rom 1234ABCD", "region PAL"]);
+        let parsed = RunInfo::parse(lines.iter().map(String::as_str)).unwrap();
+        assert_eq!(parsed, info);
+        assert!(RunInfo::parse(["rom 00000000"]).is_err());
+        assert!(RunInfo::parse(["version 1.0", "rom 00000000", "later-key x"]).is_ok());
+    }
+
+    #[test]
+    fn test_differences_and_recorded_settings() {
+        let recorded = info();
+        assert!(recorded.differences(&recorded).is_empty());
+
+        let mut current = info();
+        current.version = "0.0.1".to_owned();
+        current.settings.accuracy = Accuracy::default();
+        assert_eq!(
+            recorded.differences(&current),
+            [
+                format!(
+                    "Recorded with rnes {}, running with 0.0.1",
+                    recorded.version
+                ),
+                "Recorded with accuracy nmi-delay, running with none".to_owned(),
+            ]
+        );
+
+        // Defaults are filled in from the recording, given settings are kept
+        let given = SyncSettings {
+            alignment: Some(Alignment::Fixed(1)),
+            ..SyncSettings::default()
+        };
+        let settings = given.or_recorded(&recorded.settings);
+        assert_eq!(settings.accuracy, recorded.settings.accuracy);
+        assert_eq!(settings.alignment, Some(Alignment::Fixed(1)));
+        assert!(settings.dpcm_conflicts);
+    }
+
+    #[test]
+    fn test_save_state_section() {
+        let state = SaveState::new().encode();
+        assert_eq!(RunInfo::from_state(&state).unwrap(), None);
+        let state = info().add_to_state(&state).unwrap();
+        assert_eq!(RunInfo::from_state(&state).unwrap(), Some(info()));
+    }
+}
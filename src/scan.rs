@@ -0,0 +1,230 @@
+use std::fmt::Write;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+
+use eyre::{Result, WrapErr};
+
+use crate::console::{cpu::JamBehavior, Console};
+use crate::headless::Headless;
+use crate::test_rom::{TestStatus, TestWatch};
+
+/// How a ROM fared in a compatibility scan
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Outcome {
+    /// Ran all frames and showed something on screen
+    Ok,
+    /// Ran all frames but the last one was a single color
+    BlackScreen,
+    UnsupportedMapper(String),
+    LoadFailed(String),
+    /// Emulation stopped with an error or a panic
+    Crashed(String),
+    /// A test ROM reported passing, or failing with the given text
+    TestPassed(String),
+    TestFailed(String),
+}
+
+impl Outcome {
+    const fn label(&self) -> &'static str {
+        match self {
+            Self::Ok => "ok",
+            Self::BlackScreen => "black screen",
+            Self::UnsupportedMapper(_) => "unsupported mapper",
+            Self::LoadFailed(_) => "load failed",
+            Self::Crashed(_) => "crashed",
+            Self::TestPassed(_) => "test passed",
+            Self::TestFailed(_) => "test failed",
+        }
+    }
+
+    fn detail(&self) -> &str {
+        match self {
+            Self::Ok | Self::BlackScreen => "",
+            Self::UnsupportedMapper(e)
+            | Self::LoadFailed(e)
+            | Self::Crashed(e)
+            | Self::TestPassed(e)
+            | Self::TestFailed(e) => e,
+        }
+    }
+}
+
+pub struct ScanResult {
+    pub file: String,
+    pub frames: usize,
+    pub outcome: Outcome,
+}
+
+/// Runs every .nes file in `dir` headlessly for `frames` frames and writes a report,
+/// as HTML if the report file ends in .html and CSV otherwise, or CSV to stdout
+pub fn run(dir: &Path, frames: usize, report_file: Option<&str>) -> Result<()> {
+    let mut roms: Vec<PathBuf> = std::fs::read_dir(dir)
+        .wrap_err_with(|| format!("Failed to read directory {}", dir.display()))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("nes"))
+        })
+        .collect();
+    roms.sort();
+
+    // Panics are reported in the results, keep them from cluttering the output
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+    let results: Vec<ScanResult> = roms
+        .iter()
+        .map(|path| {
+            let result = scan_rom(path, frames);
+            println!("{}: {}", result.file, result.outcome.label());
+            result
+        })
+        .collect();
+    panic::set_hook(hook);
+
+    let ok = results
+        .iter()
+        .filter(|r| matches!(r.outcome, Outcome::Ok | Outcome::TestPassed(_)))
+        .count();
+    println!("{ok} of {} ROMs ran without problems", results.len());
+
+    let Some(file) = report_file else {
+        print!("{}", csv_report(&results));
+        return Ok(());
+    };
+    let html = Path::new(file)
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("html"));
+    let report = if html {
+        html_report(&results)
+    } else {
+        csv_report(&results)
+    };
+    std::fs::write(file, report).wrap_err_with(|| format!("Failed to write scan report {file}"))
+}
+
+fn scan_rom(path: &Path, frames: usize) -> ScanResult {
+    let file = path
+        .file_name()
+        .map_or_else(String::new, |f| f.to_string_lossy().into_owned());
+    let mut headless = Headless::new(None, frames);
+    let outcome = match std::fs::read(path) {
+        Err(e) => Outcome::LoadFailed(e.to_string()),
+        Ok(rom) => {
+            let run = panic::catch_unwind(AssertUnwindSafe(|| run_rom(&rom, &mut headless)));
+            match run {
+                Ok(outcome) => outcome,
+                Err(panic) => Outcome::Crashed(panic_message(&*panic)),
+            }
+        }
+    };
+    let outcome = match outcome {
+        Outcome::Ok if headless.frame_blank => Outcome::BlackScreen,
+        outcome => outcome,
+    };
+    ScanResult {
+        file,
+        frames: headless.frames_done(),
+        outcome,
+    }
+}
+
+fn run_rom(rom: &[u8], headless: &mut Headless) -> Outcome {
+    let stop = headless.stop_handle();
+    let mut console = match Console::new(rom, headless) {
+        Ok(console) => console,
+        Err(e) if e.to_string().starts_with("Unsupported mapper") => {
+            return Outcome::UnsupportedMapper(e.to_string())
+        }
+        Err(e) => return Outcome::LoadFailed(e.to_string()),
+    };
+    console.set_jam_behavior(JamBehavior::Break);
+    let mut watch = TestWatch::new(stop);
+    match console.run_with_callback(|cpu| watch.check(cpu)) {
+        Ok(()) => match watch.status {
+            Some(TestStatus::Done { code: 0, text }) => Outcome::TestPassed(text),
+            Some(status) => Outcome::TestFailed(status.summary()),
+            None => Outcome::Ok,
+        },
+        Err(e) => Outcome::Crashed(e.to_string()),
+    }
+}
+
+fn panic_message(panic: &(dyn std::any::Any + Send)) -> String {
+    let message = panic
+        .downcast_ref::<String>()
+        .map(String::as_str)
+        .or_else(|| panic.downcast_ref::<&str>().copied())
+        .unwrap_or("unknown panic");
+    format!("panic: {message}")
+}
+
+fn csv_report(results: &[ScanResult]) -> String {
+    let quote = |s: &str| format!("\"{}\"", s.replace('"', "\"\""));
+    let mut out = "file,result,frames,detail\n".to_owned();
+    for r in results {
+        let _ = writeln!(
+            out,
+            "{},{},{},{}",
+            quote(&r.file),
+            r.outcome.label(),
+            r.frames,
+            quote(r.outcome.detail())
+        );
+    }
+    out
+}
+
+fn html_report(results: &[ScanResult]) -> String {
+    let escape = |s: &str| {
+        s.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+    };
+    let mut out = "<!DOCTYPE html>\n<html><head><title>rnes compatibility</title></head><body>\n\
+                   <table border=\"1\">\n<tr><th>File</th><th>Result</th><th>Frames</th><th>Detail</th></tr>\n"
+        .to_owned();
+    for r in results {
+        let color = match r.outcome {
+            Outcome::Ok | Outcome::TestPassed(_) => "#cfc",
+            Outcome::BlackScreen => "#ffc",
+            _ => "#fcc",
+        };
+        let _ = writeln!(
+            out,
+            "<tr style=\"background:{color}\"><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            escape(&r.file),
+            r.outcome.label(),
+            r.frames,
+            escape(r.outcome.detail())
+        );
+    }
+    out.push_str("</table>\n</body></html>\n");
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_csv_report() {
+        let results = [
+            ScanResult {
+                file: "a.nes".to_owned(),
+                frames: 300,
+                outcome: Outcome::Ok,
+            },
+            ScanResult {
+                file: "b \"x\".nes".to_owned(),
+                frames: 0,
+                outcome: Outcome::UnsupportedMapper("Unsupported mapper 5".to_owned()),
+            },
+        ];
+        assert_eq!(
+            csv_report(&results),
+            "file,result,frames,detail\n\
+             \"a.nes\",ok,300,\"\"\n\
+             \"b \"\"x\"\".nes\",unsupported mapper,0,\"Unsupported mapper 5\"\n"
+        );
+    }
+}
@@ -0,0 +1,132 @@
+use std::cell::Cell;
+use std::rc::Rc;
+
+use crate::console::cpu::Cpu;
+
+const STATUS_ADDR: u16 = 0x6000;
+const SIGNATURE_ADDR: u16 = 0x6001;
+const SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+const TEXT_ADDR: u16 = 0x6004;
+const TEXT_END: u16 = 0x7FFF;
+
+const STATUS_RUNNING: u8 = 0x80;
+const STATUS_NEEDS_RESET: u8 = 0x81;
+// Tests want the button pressed at least 100 ms after asking
+const RESET_DELAY_FRAMES: u64 = 10;
+
+/// What a blargg style test ROM reports in PRG RAM
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum TestStatus {
+    Running,
+    NeedsReset,
+    /// Result code, 0 for passed, and the text the test printed
+    Done {
+        code: u8,
+        text: String,
+    },
+}
+
+impl TestStatus {
+    /// Reads the status, `None` unless the signature at $6001 is there
+    pub fn read(mut peek: impl FnMut(u16) -> Option<u8>) -> Option<Self> {
+        let signature = [0, 1, 2].map(|i| peek(SIGNATURE_ADDR + i));
+        if signature != SIGNATURE.map(Some) {
+            return None;
+        }
+        let status = match peek(STATUS_ADDR)? {
+            STATUS_RUNNING => Self::Running,
+            STATUS_NEEDS_RESET => Self::NeedsReset,
+            code => {
+                let bytes: Vec<u8> = (TEXT_ADDR..=TEXT_END)
+                    .map_while(|addr| peek(addr).filter(|&b| b != 0))
+                    .collect();
+                Self::Done {
+                    code,
+                    text: String::from_utf8_lossy(&bytes).trim().to_owned(),
+                }
+            }
+        };
+        Some(status)
+    }
+
+    pub const fn passed(&self) -> bool {
+        matches!(self, Self::Done { code: 0, .. })
+    }
+
+    pub fn summary(&self) -> String {
+        match self {
+            Self::Running | Self::NeedsReset => "test didn't finish".to_owned(),
+            Self::Done { code: 0, text } => format!("test passed: {text}"),
+            Self::Done { code, text } => format!("test failed with code {code}: {text}"),
+        }
+    }
+}
+
+/// Checks a test ROM's status once per frame from the CPU callback, pressing reset
+/// when the test asks for it and stopping the console once it's done
+pub struct TestWatch {
+    stop: Rc<Cell<bool>>,
+    last_frame: u64,
+    reset_frame: Option<u64>,
+    pub status: Option<TestStatus>,
+}
+
+impl TestWatch {
+    /// `stop` is set when the test is done, the frontend should quit then
+    pub const fn new(stop: Rc<Cell<bool>>) -> Self {
+        Self {
+            stop,
+            last_frame: 0,
+            reset_frame: None,
+            status: None,
+        }
+    }
+
+    pub fn check(&mut self, cpu: &mut Cpu) {
+        let frame = cpu.bus.time().frames;
+        if frame == self.last_frame {
+            return;
+        }
+        self.last_frame = frame;
+        self.status = TestStatus::read(|addr| cpu.bus.peek(addr));
+        match self.status {
+            Some(TestStatus::NeedsReset) => {
+                let asked = *self.reset_frame.get_or_insert(frame);
+                if frame >= asked + RESET_DELAY_FRAMES {
+                    cpu.bus.press_reset();
+                    self.reset_frame = None;
+                }
+            }
+            Some(TestStatus::Done { .. }) => self.stop.set(true),
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn peek_memory(memory: &[u8]) -> impl FnMut(u16) -> Option<u8> + '_ {
+        |addr| memory.get(addr.checked_sub(STATUS_ADDR)? as usize).copied()
+    }
+
+    #[test]
+    fn test_read_status() {
+        assert_eq!(TestStatus::read(peek_memory(&[0x80, 0, 0, 0])), None);
+        let running = [0x80, 0xDE, 0xB0, 0x61];
+        assert_eq!(
+            TestStatus::read(peek_memory(&running)),
+            Some(TestStatus::Running)
+        );
+
+        let mut failed = vec![0x03, 0xDE, 0xB0, 0x61];
+        failed.extend(b"\n02-branch\n\nFailed #3\n\0junk");
+        let status = TestStatus::read(peek_memory(&failed));
+        assert_eq!(
+            status.as_ref().map(TestStatus::summary).as_deref(),
+            Some("test failed with code 3: 02-branch\n\nFailed #3")
+        );
+        assert!(!status.is_some_and(|s| s.passed()));
+    }
+}
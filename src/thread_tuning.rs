@@ -0,0 +1,97 @@
+// Scheduling of the thread that runs the console. The frontend isn't split into
+// threads, so this is the main thread, which also draws and queues audio.
+
+use eyre::{eyre, Result};
+use sdl2::sys::{SDL_SetThreadPriority, SDL_ThreadPriority};
+
+/// OS scheduling priority, raising it usually needs extra privileges
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Priority {
+    Normal,
+    High,
+    Critical,
+}
+
+impl Priority {
+    pub fn parse(name: &str) -> Result<Self> {
+        match name {
+            "normal" => Ok(Self::Normal),
+            "high" => Ok(Self::High),
+            "critical" => Ok(Self::Critical),
+            _ => Err(eyre!(
+                "Unknown priority {name}, expected normal, high or critical"
+            )),
+        }
+    }
+
+    /// Applies to the calling thread
+    pub fn apply(self) -> Result<()> {
+        let priority = match self {
+            Self::Normal => SDL_ThreadPriority::SDL_THREAD_PRIORITY_NORMAL,
+            Self::High => SDL_ThreadPriority::SDL_THREAD_PRIORITY_HIGH,
+            Self::Critical => SDL_ThreadPriority::SDL_THREAD_PRIORITY_TIME_CRITICAL,
+        };
+        if unsafe { SDL_SetThreadPriority(priority) } != 0 {
+            return Err(eyre!(
+                "Failed to set thread priority: {}",
+                sdl2::get_error()
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Keeps the calling thread on one CPU core, numbered from 0
+#[cfg(target_os = "linux")]
+pub fn pin_to_core(core: usize) -> Result<()> {
+    if core >= libc::CPU_SETSIZE as usize {
+        return Err(eyre!("No CPU core {core}"));
+    }
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_SET(core, &mut set);
+        // Thread ID 0 is the calling thread
+        if libc::sched_setaffinity(0, std::mem::size_of_val(&set), &raw const set) != 0 {
+            return Err(eyre!(
+                "Failed to pin to core {core}: {}",
+                std::io::Error::last_os_error()
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+pub fn pin_to_core(core: usize) -> Result<()> {
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetCurrentThread() -> isize;
+        fn SetThreadAffinityMask(thread: isize, mask: usize) -> usize;
+    }
+    if core >= usize::BITS as usize {
+        return Err(eyre!("No CPU core {core}"));
+    }
+    if unsafe { SetThreadAffinityMask(GetCurrentThread(), 1 << core) } == 0 {
+        return Err(eyre!(
+            "Failed to pin to core {core}: {}",
+            std::io::Error::last_os_error()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+pub fn pin_to_core(_core: usize) -> Result<()> {
+    Err(eyre!("Pinning to a core isn't supported on this platform"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Priority::parse("high").ok(), Some(Priority::High));
+        assert!(Priority::parse("realtime").is_err());
+    }
+}
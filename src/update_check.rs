@@ -0,0 +1,111 @@
+// Opt-in check for a newer release on GitHub. Only the version and the release
+// page are fetched, updating is left to the user.
+
+use std::sync::mpsc::{self, Receiver};
+
+use eyre::Result;
+
+#[cfg(feature = "update-check")]
+const LATEST_RELEASE_URL: &str = "https://api.github.com/repos/kolmone/rnes/releases/latest";
+
+/// A release newer than the running build
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Release {
+    pub version: String,
+    /// Release page to download from
+    pub url: String,
+}
+
+/// Checks in the background, the receiver gets a release only if it's newer
+pub fn start() -> Receiver<Release> {
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || match fetch_latest() {
+        Ok(release) if is_newer(&release.version, env!("CARGO_PKG_VERSION")) => {
+            let _ = sender.send(release);
+        }
+        Ok(_) => (),
+        Err(e) => println!("Update check failed: {e}"),
+    });
+    receiver
+}
+
+#[cfg(feature = "update-check")]
+fn fetch_latest() -> Result<Release> {
+    use eyre::{eyre, WrapErr};
+
+    let json = ureq::get(LATEST_RELEASE_URL)
+        .set("User-Agent", concat!("rnes/", env!("CARGO_PKG_VERSION")))
+        .call()
+        .wrap_err("Failed to fetch the latest release")?
+        .into_string()
+        .wrap_err("Failed to read the latest release")?;
+    parse_release(&json).ok_or_else(|| eyre!("Unexpected reply from GitHub"))
+}
+
+#[cfg(not(feature = "update-check"))]
+fn fetch_latest() -> Result<Release> {
+    Err(eyre::eyre!(
+        "checking for updates needs a build with the update-check feature"
+    ))
+}
+
+// Picks the tag and page out of the release JSON, neither has escaped characters
+#[cfg(any(feature = "update-check", test))]
+fn parse_release(json: &str) -> Option<Release> {
+    let field = |key: &str| {
+        let start = json.find(&format!("\"{key}\""))?;
+        let rest = json[start + key.len() + 2..]
+            .trim_start()
+            .strip_prefix(':')?;
+        let value = rest.trim_start().strip_prefix('"')?;
+        value.split('"').next().map(str::to_owned)
+    };
+    Some(Release {
+        version: field("tag_name")?.trim_start_matches('v').to_owned(),
+        url: field("html_url")?,
+    })
+}
+
+// Compares dotted version numbers, anything after a number like `-rc1` is ignored
+fn is_newer(version: &str, current: &str) -> bool {
+    let parts = |version: &str| -> Vec<u32> {
+        version
+            .split('.')
+            .map(|part| {
+                let digits = part
+                    .find(|c: char| !c.is_ascii_digit())
+                    .unwrap_or(part.len());
+                part[..digits].parse().unwrap_or(0)
+            })
+            .collect()
+    };
+    parts(version) > parts(current)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_release() {
+        let json = r#"{"url":"https://api.github.com/x","html_url": "https://github.com/kolmone/rnes/releases/tag/v0.2.0","id":1,"tag_name" : "v0.2.0"}"#;
+        let release = parse_release(json);
+        assert_eq!(
+            release,
+            Some(Release {
+                version: "0.2.0".to_owned(),
+                url: "https://github.com/kolmone/rnes/releases/tag/v0.2.0".to_owned(),
+            })
+        );
+        assert_eq!(parse_release(r#"{"message":"Not Found"}"#), None);
+    }
+
+    #[test]
+    fn test_is_newer() {
+        assert!(is_newer("0.2.0", "0.1.0"));
+        assert!(is_newer("0.1.10", "0.1.9"));
+        assert!(is_newer("1.0", "0.9.9"));
+        assert!(!is_newer("0.1.0", "0.1.0"));
+        assert!(!is_newer("0.1.0-rc1", "0.1.0"));
+    }
+}